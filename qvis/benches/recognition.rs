@@ -0,0 +1,161 @@
+//! Baselines for the hot paths of recognition. Every input is generated from a fixed seed so that runs can be compared with each other.
+
+use std::{collections::BTreeMap, hint::black_box, sync::Arc};
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use internment::ArcIntern;
+use itertools::Itertools;
+use puzzle_theory::{
+    permutations::{Permutation, schreier_sims::StabilizerChain},
+    puzzle_geometry::{PuzzleGeometry, parsing::puzzle},
+};
+use qvis::{CVProcessor, Pixel, puzzle_matching::Matcher};
+use rand::{Rng, SeedableRng, rngs::SmallRng};
+
+/// The side length of the frame that inference is measured on
+const FRAME_SIDE: usize = 350;
+
+const PALETTE: [(f64, f64, f64); 6] = [
+    (1., 0.2, 0.2),
+    (1., 0.6, 0.2),
+    (1., 1., 1.),
+    (0.8, 0.8, 0.2),
+    (0.2, 0.5, 1.),
+    (0.3, 1., 0.5),
+];
+
+/// Simulate the classifier's log-likelihoods for a picture of the puzzle in the given state: the true color of every sticker is the most likely, give or take some noise
+fn observe(
+    geometry: &PuzzleGeometry,
+    state: &Permutation,
+    rng: &mut SmallRng,
+) -> Vec<BTreeMap<ArcIntern<str>, f64>> {
+    let group = geometry.permutation_group();
+    let colors = group.facelet_colors().iter().unique().collect_vec();
+
+    (0..group.facelet_count())
+        .map(|sticker| {
+            let actual = &group.facelet_colors()[state.state().get(sticker)];
+
+            colors
+                .iter()
+                .map(|&color| {
+                    let ll = if color == actual { 0. } else { -100. };
+                    (ArcIntern::clone(color), ll + rng.random_range(-15.0..15.))
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn most_likely(c: &mut Criterion) {
+    let mut rng = SmallRng::from_seed(*b"Benchmarks need the same cubes!!");
+
+    for name in ["3x3", "4x4"] {
+        let geometry = puzzle(name).into_inner();
+        let stabchain = StabilizerChain::new(&geometry.permutation_group());
+        let matcher = Matcher::new(Arc::clone(&geometry));
+
+        let observations = (0..10)
+            .map(|_| observe(&geometry, &stabchain.random(&mut rng), &mut rng))
+            .collect_vec();
+
+        c.bench_function(&format!("most likely {name}"), |b| {
+            b.iter(|| {
+                for observation in &observations {
+                    black_box(matcher.most_likely(black_box(observation)));
+                }
+            });
+        });
+    }
+}
+
+fn k_best(c: &mut Criterion) {
+    let mut rng = SmallRng::from_seed(*b"One orbit, one hundred matchings");
+
+    // A 2x2 has nothing but corners, so its unfiltered candidates are exactly the matchings of a single orbit
+    let geometry = puzzle("2x2").into_inner();
+    let stabchain = StabilizerChain::new(&geometry.permutation_group());
+    let matcher = Matcher::new(Arc::clone(&geometry));
+    let observation = observe(&geometry, &stabchain.random(&mut rng), &mut rng);
+
+    c.bench_function("first 100 matchings of one orbit", |b| {
+        b.iter(|| {
+            black_box(
+                matcher
+                    .unfiltered_candidates(black_box(&observation))
+                    .take(100)
+                    .count(),
+            )
+        });
+    });
+}
+
+fn inference(c: &mut Criterion) {
+    let mut rng = SmallRng::from_seed(*b"A frame the size of a real photo");
+
+    let geometry = puzzle("3x3").into_inner();
+    let group = geometry.permutation_group();
+    let stabchain = StabilizerChain::new(&group);
+    let image_size = FRAME_SIDE * FRAME_SIDE;
+
+    let calibration = (0..10)
+        .map(|_| {
+            let state = stabchain.random(&mut rng);
+            (picture(&geometry, &state, &mut rng), state)
+        })
+        .collect_vec();
+
+    // Calibrating with every image at once adds the samples of each kd-tree in a random order, while calibrating one image at a time adds them in the order of the images
+    let assignment = (0..image_size)
+        .map(|pixel| Pixel::Sticker(band(pixel, group.facelet_count())))
+        .collect::<Box<[_]>>();
+    let mut incremental =
+        CVProcessor::new(Arc::clone(&geometry), image_size, assignment.clone()).unwrap();
+    let mut batch = CVProcessor::new(Arc::clone(&geometry), image_size, assignment).unwrap();
+    for (image, state) in &calibration {
+        incremental.calibrate(image, state.clone()).unwrap();
+    }
+    batch.calibrate_batch(&calibration).unwrap();
+
+    let frame = picture(&geometry, &stabchain.random(&mut rng), &mut rng);
+
+    // Rendering is inference plus painting every pixel, which is negligible next to the nearest neighbor searches
+    for (name, cv) in [("incremental", &incremental), ("batch", &batch)] {
+        c.bench_function(&format!("inference on a 350x350 frame ({name})"), |b| {
+            b.iter(|| black_box(cv.render_inferred_colors(black_box(&frame)).unwrap()));
+        });
+    }
+}
+
+/// Split the frame into one horizontal band per sticker and return the sticker that the pixel belongs to
+fn band(pixel: usize, stickers: usize) -> usize {
+    pixel * stickers / (FRAME_SIDE * FRAME_SIDE)
+}
+
+/// Render a noisy frame of the puzzle in the given state, coloring each sticker by the index of its color in a fixed palette
+fn picture(
+    geometry: &PuzzleGeometry,
+    state: &Permutation,
+    rng: &mut SmallRng,
+) -> Box<[(f64, f64, f64)]> {
+    let group = geometry.permutation_group();
+    let colors = group.facelet_colors().iter().unique().collect_vec();
+    let sticker_colors = (0..group.facelet_count())
+        .map(|sticker| {
+            let color = &group.facelet_colors()[state.state().get(sticker)];
+            PALETTE[colors.iter().position(|v| *v == color).unwrap()]
+        })
+        .collect_vec();
+
+    (0..FRAME_SIDE * FRAME_SIDE)
+        .map(|pixel| {
+            let (r, g, b) = sticker_colors[band(pixel, sticker_colors.len())];
+            let mut noise = || rng.random_range(0.95..1.05);
+            (r * noise(), g * noise(), b * noise())
+        })
+        .collect()
+}
+
+criterion_group!(benches, most_likely, k_best, inference);
+criterion_main!(benches);
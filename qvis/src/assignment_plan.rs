@@ -0,0 +1,220 @@
+use std::{error::Error, fmt};
+
+use internment::ArcIntern;
+use puzzle_theory::puzzle_geometry::PuzzleGeometry;
+
+use crate::Pixel;
+
+/// A sticker that has to be found in the image when assigning the pixels of the puzzle to its stickers
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StickerToAssign {
+    /// The color of the face that the sticker is on in the solved state
+    pub color: String,
+    /// The names of the faces that the piece of the sticker touches, which together say where the sticker is on that face
+    pub labels: Vec<String>,
+}
+
+/// Every sticker that has to be assigned for the puzzle, in the order that `Pixel::Sticker` numbers them. Stickers that are fixed in place, like the centers of the 3x3, aren't facelets of the puzzle and aren't included.
+pub fn assignment_plan(puzzle: &PuzzleGeometry) -> Vec<StickerToAssign> {
+    puzzle
+        .stickers()
+        .iter()
+        .map(|(face, labels)| StickerToAssign {
+            color: face.color.to_string(),
+            labels: labels.iter().map(ToString::to_string).collect(),
+        })
+        .collect()
+}
+
+/// Check that every sticker of the puzzle has at least one pixel assigned to it. A sticker without any pixels contributes nothing to the recognition, so forgetting one goes unnoticed until states that differ only there get mixed up.
+///
+/// # Errors
+///
+/// Returns the stickers that have no pixels, in increasing order.
+pub fn validate_assignment(
+    assignment: &[Pixel],
+    puzzle: &PuzzleGeometry,
+) -> Result<(), Vec<usize>> {
+    let mut assigned = vec![false; puzzle.permutation_group().facelet_count()];
+    for pixel in assignment {
+        if let Pixel::Sticker(sticker) = pixel
+            && let Some(assigned) = assigned.get_mut(*sticker)
+        {
+            *assigned = true;
+        }
+    }
+
+    let missing = (0..assigned.len())
+        .filter(|sticker| !assigned[*sticker])
+        .collect::<Vec<_>>();
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(missing)
+    }
+}
+
+/// Why `CVProcessor::new` can't use an assignment
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InvalidAssignment {
+    /// The assignment doesn't have one entry per pixel of the image, or per super-pixel for an assignment of super-pixels
+    Size { expected: usize, found: usize },
+    /// The entry at `pixel` is a sticker that the puzzle doesn't have
+    Sticker { pixel: usize, sticker: usize },
+    /// The entry at `pixel` is white balance for a face of a color that the puzzle doesn't have
+    Face { pixel: usize, color: ArcIntern<str> },
+}
+
+impl fmt::Display for InvalidAssignment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvalidAssignment::Size { expected, found } => {
+                write!(
+                    f,
+                    "The assignment has {found} entries instead of {expected}"
+                )
+            }
+            InvalidAssignment::Sticker { pixel, sticker } => {
+                write!(
+                    f,
+                    "Pixel {pixel} is assigned to sticker {sticker}, which the puzzle doesn't have"
+                )
+            }
+            InvalidAssignment::Face { pixel, color } => {
+                write!(
+                    f,
+                    "Pixel {pixel} is white balance for the {color} face, which the puzzle doesn't have"
+                )
+            }
+        }
+    }
+}
+
+impl Error for InvalidAssignment {}
+
+/// Check that the assignment has `expected` entries and only assigns pixels to stickers and faces that the puzzle has, which is what `CVProcessor::new` checks of its assignment
+///
+/// # Errors
+///
+/// Returns the first problem with the assignment, if it has any.
+pub fn check_assignment(
+    assignment: &[Pixel],
+    expected: usize,
+    puzzle: &PuzzleGeometry,
+) -> Result<(), InvalidAssignment> {
+    if assignment.len() != expected {
+        return Err(InvalidAssignment::Size {
+            expected,
+            found: assignment.len(),
+        });
+    }
+
+    let group = puzzle.permutation_group();
+    for (pixel, entry) in assignment.iter().enumerate() {
+        match entry {
+            Pixel::Unassigned => {}
+            Pixel::Sticker(sticker) if *sticker >= group.facelet_count() => {
+                return Err(InvalidAssignment::Sticker {
+                    pixel,
+                    sticker: *sticker,
+                });
+            }
+            Pixel::Sticker(_) => {}
+            Pixel::WhiteBalance(color) => {
+                if !group.facelet_colors().contains(color) {
+                    return Err(InvalidAssignment::Face {
+                        pixel,
+                        color: ArcIntern::clone(color),
+                    });
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use itertools::Itertools;
+    use puzzle_theory::puzzle_geometry::parsing::puzzle;
+
+    use super::{InvalidAssignment, assignment_plan, check_assignment, validate_assignment};
+    use crate::Pixel;
+
+    #[test]
+    fn cube() {
+        let geometry = puzzle("3x3").into_inner();
+        let plan = assignment_plan(&geometry);
+        assert_eq!(plan.len(), geometry.permutation_group().facelet_count());
+
+        // The centers are fixed in place, so each face has the eight stickers around its center
+        assert_eq!(plan.len(), 48);
+        let faces = plan.iter().counts_by(|sticker| sticker.color.as_str());
+        assert_eq!(faces.len(), 6);
+        assert!(faces.values().all(|count| *count == 8), "{faces:?}");
+
+        // Each sticker is on a different piece or a different side of it
+        assert!(
+            plan.iter()
+                .map(|sticker| (&sticker.color, &sticker.labels))
+                .all_unique()
+        );
+    }
+
+    #[test]
+    fn validate() {
+        let geometry = puzzle("3x3").into_inner();
+
+        let mut assignment = (0..48).map(Pixel::Sticker).collect::<Vec<_>>();
+        assignment.push(Pixel::Unassigned);
+        assignment.push(Pixel::WhiteBalance("white".into()));
+        assert_eq!(validate_assignment(&assignment, &geometry), Ok(()));
+
+        // Forgetting two stickers, which the pixels that are left alone or used for white balance don't make up for
+        assignment[7] = Pixel::Unassigned;
+        assignment[30] = Pixel::WhiteBalance("white".into());
+        assert_eq!(
+            validate_assignment(&assignment, &geometry),
+            Err(vec![7, 30])
+        );
+
+        assert_eq!(validate_assignment(&[], &geometry), Err((0..48).collect()));
+    }
+
+    #[test]
+    fn check() {
+        let geometry = puzzle("3x3").into_inner();
+
+        let mut assignment = (0..48).map(Pixel::Sticker).collect::<Vec<_>>();
+        assignment.push(Pixel::WhiteBalance("white".into()));
+        assert_eq!(check_assignment(&assignment, 49, &geometry), Ok(()));
+
+        assert_eq!(
+            check_assignment(&assignment, 50, &geometry),
+            Err(InvalidAssignment::Size {
+                expected: 50,
+                found: 49
+            })
+        );
+
+        assignment[3] = Pixel::Sticker(48);
+        assert_eq!(
+            check_assignment(&assignment, 49, &geometry),
+            Err(InvalidAssignment::Sticker {
+                pixel: 3,
+                sticker: 48
+            })
+        );
+
+        assignment[3] = Pixel::WhiteBalance("pink".into());
+        assert_eq!(
+            check_assignment(&assignment, 49, &geometry),
+            Err(InvalidAssignment::Face {
+                pixel: 3,
+                color: "pink".into()
+            })
+        );
+    }
+}
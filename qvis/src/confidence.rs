@@ -0,0 +1,164 @@
+//! Turning the log-likelihood that recognition reports into the probability that the recognition is right. The log-likelihood is a sum over every pixel, so its scale depends on the size of the image and the puzzle, and it means nothing to someone who has to decide whether to trust it.
+
+/// A logistic mapping from the log-likelihood that `CVProcessor::process_image` reports to the probability that the state it reports is right, as in Platt scaling
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConfidenceCalibration {
+    /// How quickly the probability rises with the log-likelihood. This is never negative, so a higher log-likelihood never means a lower probability.
+    pub scale: f64,
+    /// The log-odds of being right at a log-likelihood of zero
+    pub offset: f64,
+}
+
+impl ConfidenceCalibration {
+    /// Fit the mapping to examples of log-likelihoods along with whether the state recognized was right, by logistic regression.
+    ///
+    /// Like Platt's method, the examples are fit to probabilities slightly inside of zero and one that depend on how many there are, so that a set of examples that are all right or all wrong doesn't make the probabilities reach exactly one or zero. With no examples at all, every log-likelihood gets a probability of one half.
+    pub fn fit(examples: &[(f64, bool)]) -> ConfidenceCalibration {
+        if examples.is_empty() {
+            return ConfidenceCalibration {
+                scale: 0.,
+                offset: 0.,
+            };
+        }
+
+        let right = examples.iter().filter(|(_, right)| *right).count() as f64;
+        let wrong = examples.len() as f64 - right;
+        let target_right = (right + 1.) / (right + 2.);
+        let target_wrong = 1. / (wrong + 2.);
+
+        let targets = examples
+            .iter()
+            .map(|(_, right)| if *right { target_right } else { target_wrong })
+            .collect::<Vec<_>>();
+        let prior = ConfidenceCalibration {
+            scale: 0.,
+            offset: logit(targets.iter().sum::<f64>() / targets.len() as f64),
+        };
+
+        // The log-likelihoods are in the thousands, so they are standardized to keep Newton's method well conditioned
+        let n = examples.len() as f64;
+        let mean = examples.iter().map(|(score, _)| score).sum::<f64>() / n;
+        let std_dev = (examples
+            .iter()
+            .map(|(score, _)| (score - mean).powi(2))
+            .sum::<f64>()
+            / n)
+            .sqrt();
+        if !(std_dev.is_finite() && std_dev > 0.) {
+            return prior;
+        }
+
+        let xs = examples
+            .iter()
+            .map(|(score, _)| (score - mean) / std_dev)
+            .collect::<Vec<_>>();
+
+        let (mut a, mut b) = (0., prior.offset);
+        for _ in 0..100 {
+            // The gradient and Hessian of the cross-entropy, with a little ridge to keep the Hessian invertible when the examples are separable
+            let (mut g_a, mut g_b) = (RIDGE * a, 0.);
+            let (mut h_aa, mut h_ab, mut h_bb) = (RIDGE, 0., RIDGE);
+            for (x, t) in xs.iter().zip(&targets) {
+                let p = sigmoid(a * x + b);
+                let w = p * (1. - p);
+                g_a += (p - t) * x;
+                g_b += p - t;
+                h_aa += w * x * x;
+                h_ab += w * x;
+                h_bb += w;
+            }
+
+            let det = h_aa * h_bb - h_ab * h_ab;
+            let step_a = (h_bb * g_a - h_ab * g_b) / det;
+            let step_b = (h_aa * g_b - h_ab * g_a) / det;
+            a -= step_a;
+            b -= step_b;
+
+            if step_a.abs().max(step_b.abs()) < 1e-10 {
+                break;
+            }
+        }
+
+        // A higher log-likelihood meaning a lower probability can only be noise
+        if !(a > 0. && b.is_finite()) {
+            return prior;
+        }
+
+        ConfidenceCalibration {
+            scale: a / std_dev,
+            offset: b - a * mean / std_dev,
+        }
+    }
+
+    /// The probability that a state recognized with the given log-likelihood is right
+    pub fn probability(&self, log_likelihood: f64) -> f64 {
+        // Don't multiply an infinite log-likelihood by a scale of zero
+        if self.scale == 0. {
+            sigmoid(self.offset)
+        } else {
+            sigmoid(self.scale * log_likelihood + self.offset)
+        }
+    }
+}
+
+const RIDGE: f64 = 1e-6;
+
+fn sigmoid(v: f64) -> f64 {
+    1. / (1. + (-v).exp())
+}
+
+fn logit(p: f64) -> f64 {
+    (p / (1. - p)).ln()
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng};
+
+    use super::ConfidenceCalibration;
+
+    #[test]
+    fn monotonic_and_bounded() {
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"How sure are you? Show your work");
+
+        // Recognitions are more often right the higher their log-likelihood is, like they are on real images
+        let examples = (0..500)
+            .map(|_| {
+                let score = rng.random_range(-3000.0..-1000.);
+                let right = rng.random_bool((score + 3000.) / 2000.);
+                (score, right)
+            })
+            .collect::<Vec<_>>();
+
+        let calibration = ConfidenceCalibration::fit(&examples);
+        assert!(calibration.scale > 0.);
+        assert!(calibration.probability(-2900.) < 0.2);
+        assert!(calibration.probability(-1100.) > 0.8);
+
+        let mut last = 0.;
+        for i in -100..=100 {
+            let probability = calibration.probability(f64::from(i) * 100.);
+            assert!((0. ..=1.).contains(&probability));
+            assert!(probability >= last);
+            last = probability;
+        }
+
+        for (score, expected) in [(f64::NEG_INFINITY, 0.), (f64::INFINITY, 1.)] {
+            assert_eq!(calibration.probability(score), expected);
+        }
+
+        // Examples that are all right don't make the recognition certain
+        let all_right = examples
+            .iter()
+            .map(|(score, _)| (*score, true))
+            .collect::<Vec<_>>();
+        let calibration = ConfidenceCalibration::fit(&all_right);
+        for score in [-3000., -1000., 0.] {
+            let probability = calibration.probability(score);
+            assert!(probability > 0.5 && probability < 1., "{probability}");
+        }
+
+        let calibration = ConfidenceCalibration::fit(&[]);
+        assert_eq!(calibration.probability(f64::NEG_INFINITY), 0.5);
+    }
+}
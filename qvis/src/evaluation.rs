@@ -0,0 +1,152 @@
+//! Measuring how well a `CVProcessor` recognizes the puzzle on a labeled dataset, so that changes to the inference or matching can be compared by their numbers rather than by eye.
+
+use puzzle_theory::permutations::Permutation;
+
+use crate::{CVProcessor, Image, QvisError, facelets::colors_in};
+
+/// How well a `CVProcessor` did on a set of labeled images
+#[derive(Debug, Clone, PartialEq)]
+pub struct EvalReport {
+    /// The number of images evaluated
+    pub samples: usize,
+    /// The number of images where every sticker was recognized as the right color. Pieces with identical colors can't be told apart, so this compares what the puzzle looks like rather than the permutation itself.
+    pub exact_matches: usize,
+    /// The number of stickers, across every image, that were recognized as the right color
+    pub correct_stickers: usize,
+    /// The number of stickers across every image
+    pub total_stickers: usize,
+    /// The mean difference in log-likelihood between the most likely state and the runner up. Larger margins mean that the recognition is less likely to flip with a bit of noise.
+    pub mean_confidence_margin: f64,
+}
+
+impl EvalReport {
+    /// The fraction of images where every sticker was recognized correctly, or zero if there were no images
+    pub fn exact_match_rate(&self) -> f64 {
+        ratio(self.exact_matches, self.samples)
+    }
+
+    /// The fraction of stickers that were recognized as the right color, or zero if there were no images
+    pub fn sticker_accuracy(&self) -> f64 {
+        ratio(self.correct_stickers, self.total_stickers)
+    }
+}
+
+fn ratio(numerator: usize, denominator: usize) -> f64 {
+    if denominator == 0 {
+        0.
+    } else {
+        numerator as f64 / denominator as f64
+    }
+}
+
+/// Run `processor` over every image and compare what it recognizes with the true state of the puzzle in that image.
+///
+/// # Errors
+///
+/// Returns an error if `processor` can't process one of the images; see `CVProcessor::process_image`.
+pub fn evaluate(
+    processor: &CVProcessor,
+    samples: &[(Image, Permutation)],
+) -> Result<EvalReport, QvisError> {
+    let group = processor.puzzle.permutation_group();
+    let mut report = EvalReport {
+        samples: samples.len(),
+        exact_matches: 0,
+        correct_stickers: 0,
+        total_stickers: samples.len() * group.facelet_count(),
+        mean_confidence_margin: 0.,
+    };
+
+    let mut total_margin = 0.;
+
+    for (image, truth) in samples {
+        let confidences = processor.observe(image)?;
+        let mut candidates = processor.matcher.candidates(&confidences);
+        let (found, likelihood) = candidates.next().ok_or(QvisError::NoValidState)?;
+        // Every puzzle has more than one state, so there's always a runner up
+        let (_, runner_up) = candidates.next().unwrap();
+        total_margin += likelihood - runner_up;
+
+        let correct = colors_in(&group, &found)
+            .iter()
+            .zip(&colors_in(&group, truth))
+            .filter(|(found, truth)| found == truth)
+            .count();
+        report.correct_stickers += correct;
+        if correct == group.facelet_count() {
+            report.exact_matches += 1;
+        }
+    }
+
+    if !samples.is_empty() {
+        report.mean_confidence_margin = total_margin / samples.len() as f64;
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use puzzle_theory::{
+        permutations::schreier_sims::StabilizerChain, puzzle_geometry::parsing::puzzle,
+    };
+    use rand::SeedableRng;
+
+    use crate::{
+        CVProcessor,
+        evaluation::evaluate,
+        tests::{PIXELS_PER_STICKER, assignment, picture},
+    };
+
+    #[test]
+    fn evaluate_synthetic() {
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"Grading my own homework, again!!");
+
+        let geometry = puzzle("3x3").into_inner();
+        let group = geometry.permutation_group();
+        let stabchain = StabilizerChain::new(&group);
+
+        let mut cv = CVProcessor::new(
+            Arc::clone(&geometry),
+            group.facelet_count() * PIXELS_PER_STICKER,
+            assignment(&group),
+        )
+        .unwrap();
+
+        for _ in 0..30 {
+            let state = stabchain.random(&mut rng);
+            cv.calibrate(&picture(&group, &state, &mut rng), state)
+                .unwrap();
+        }
+
+        let samples = (0..10)
+            .map(|_| {
+                let state = stabchain.random(&mut rng);
+                (picture(&group, &state, &mut rng), state)
+            })
+            .collect::<Vec<_>>();
+
+        let report = evaluate(&cv, &samples).unwrap();
+        assert_eq!(report.samples, 10);
+        assert_eq!(report.total_stickers, 10 * group.facelet_count());
+        assert_eq!(report.exact_match_rate(), 1.);
+        assert_eq!(report.sticker_accuracy(), 1.);
+        assert!(report.mean_confidence_margin > 0.);
+
+        // Mislabel every image so that nothing matches exactly
+        let mislabeled = samples
+            .iter()
+            .map(|(image, _)| (image.clone(), stabchain.random(&mut rng)))
+            .collect::<Vec<_>>();
+
+        let report = evaluate(&cv, &mislabeled).unwrap();
+        assert_eq!(report.exact_matches, 0);
+        assert!(report.sticker_accuracy() < 1.);
+
+        let report = evaluate(&cv, &[]).unwrap();
+        assert_eq!(report.exact_match_rate(), 0.);
+        assert_eq!(report.sticker_accuracy(), 0.);
+    }
+}
@@ -0,0 +1,251 @@
+use internment::ArcIntern;
+use puzzle_theory::{
+    permutations::{Permutation, PermutationGroup},
+    puzzle_geometry::PuzzleGeometry,
+};
+
+/// The faces of a cube in the order that Kociemba's facelet notation lists them, along with the color of each face in the standard color scheme
+const KOCIEMBA_FACES: [(char, &str); 6] = [
+    ('U', "white"),
+    ('R', "red"),
+    ('F', "green"),
+    ('D', "yellow"),
+    ('L', "orange"),
+    ('B', "blue"),
+];
+
+/// The faces around each face of `KOCIEMBA_FACES` as the notation lays it out, as the indices of the faces above, below, left of, and right of it:
+///
+/// ```text
+///   U
+/// L F R B
+///   D
+/// ```
+const KOCIEMBA_NEIGHBOURS: [[usize; 4]; 6] = [
+    [5, 2, 4, 1],
+    [0, 3, 2, 5],
+    [0, 3, 4, 1],
+    [2, 5, 4, 1],
+    [0, 3, 5, 2],
+    [0, 3, 1, 4],
+];
+
+/// The color that a letter of Kociemba's facelet notation stands for in the standard color scheme, which is the color scheme that `facelet_string` writes the notation for
+pub fn facelet_color(letter: char) -> Option<&'static str> {
+    KOCIEMBA_FACES
+        .iter()
+        .find(|(v, _)| *v == letter)
+        .map(|(_, color)| *color)
+}
+
+/// The color at each facelet of the puzzle in the given state, indexed by facelet.
+///
+/// A state says which facelet *comes from* where: `state.state().get(i)` is the facelet that the state moves into position `i`, so the color at position `i` is the color that facelet has on the solved puzzle. `state.mapping()` goes the other way and says where each facelet *goes to*; looking colors up through it gives the colors of the inverse state instead, which is an easy mistake to make since both are permutations of the same facelets.
+pub fn state_colors(puzzle: &PuzzleGeometry, state: &Permutation) -> Box<[ArcIntern<str>]> {
+    colors_in(&puzzle.permutation_group(), state)
+}
+
+/// `state_colors` for when only the permutation group is at hand
+pub(crate) fn colors_in(group: &PermutationGroup, state: &Permutation) -> Box<[ArcIntern<str>]> {
+    (0..group.facelet_count())
+        .map(|facelet| ArcIntern::clone(&group.facelet_colors()[state.state().get(facelet)]))
+        .collect()
+}
+
+/// Write the state of a cube in Kociemba's facelet notation: the faces are listed in URFDLB order and each sticker is written as the letter of the face whose color it has. Stickers within a face are listed in reading order with the face laid out like the notation lays it out, and centers that are fixed in place (and therefore not facelets of the puzzle) are inserted in the middle of their face.
+///
+/// Where a sticker is on its face is worked out from the faces that the other stickers of its piece are on, since the puzzle geometry numbers the stickers in an order of its own. That tells every sticker of a 2x2 or 3x3 apart, but not the edges and centers of bigger cubes.
+///
+/// Returns `None` if the puzzle isn't a 2x2 or 3x3 cube with the standard color scheme.
+pub fn facelet_string(puzzle: &PuzzleGeometry, state: &Permutation) -> Option<String> {
+    let group = puzzle.permutation_group();
+
+    let face_idx = |color: &str| KOCIEMBA_FACES.iter().position(|(_, v)| *v == color);
+    let solved_faces = group
+        .facelet_colors()
+        .iter()
+        .map(|color| face_idx(color))
+        .collect::<Option<Vec<_>>>()?;
+
+    // The faces that each sticker's piece touches besides its own
+    let mut touches = vec![Vec::new(); group.facelet_count()];
+    for piece in puzzle
+        .pieces_data()
+        .orbits()
+        .iter()
+        .flat_map(|orbit| orbit.pieces())
+    {
+        for sticker in piece.stickers() {
+            touches[*sticker].extend(piece.stickers().iter().map(|v| solved_faces[*v]));
+        }
+    }
+
+    // Where each sticker is on its face, as its row and column from 0 to 2, where 1 is anywhere between the edges of the face
+    let position = |sticker: usize| {
+        let [above, below, left, right] = KOCIEMBA_NEIGHBOURS[solved_faces[sticker]];
+        let along = |before: usize, after: usize| {
+            if touches[sticker].contains(&before) {
+                0
+            } else if touches[sticker].contains(&after) {
+                2
+            } else {
+                1
+            }
+        };
+
+        (along(above, below), along(left, right))
+    };
+
+    let mut faces: [Vec<usize>; 6] = Default::default();
+    for sticker in 0..group.facelet_count() {
+        faces[solved_faces[sticker]].push(sticker);
+    }
+
+    let colors = colors_in(&group, state);
+    let mut letters: [Vec<char>; 6] = Default::default();
+    for ((stickers, face), (letter, _)) in faces.iter_mut().zip(&mut letters).zip(KOCIEMBA_FACES) {
+        stickers.sort_by_key(|sticker| position(*sticker));
+        if stickers
+            .windows(2)
+            .any(|pair| position(pair[0]) == position(pair[1]))
+        {
+            return None;
+        }
+
+        for sticker in stickers {
+            face.push(KOCIEMBA_FACES[face_idx(&colors[*sticker])?].0);
+        }
+
+        let len = face.len();
+
+        if !is_square(len) && is_square(len + 1) && len % 2 == 0 {
+            face.insert(len / 2, letter);
+        }
+
+        if !is_square(face.len()) {
+            return None;
+        }
+    }
+
+    let per_face = letters[0].len();
+    if letters.iter().any(|face| face.len() != per_face) {
+        return None;
+    }
+
+    let string = letters.into_iter().flatten().collect::<String>();
+    assert_eq!(string.len(), per_face * 6);
+
+    Some(string)
+}
+
+fn is_square(n: usize) -> bool {
+    let root = n.isqrt();
+    root * root == n
+}
+
+#[cfg(test)]
+mod tests {
+    use puzzle_theory::{
+        permutations::{Algorithm, Permutation},
+        puzzle_geometry::parsing::puzzle,
+    };
+
+    use super::{facelet_color, facelet_string, is_square, state_colors};
+
+    #[test]
+    fn solved() {
+        let geometry = puzzle("3x3").into_inner();
+
+        assert_eq!(
+            facelet_string(&geometry, &Permutation::from_cycles(Vec::new())).as_deref(),
+            Some("UUUUUUUUURRRRRRRRRFFFFFFFFFDDDDDDDDDLLLLLLLLLBBBBBBBBB")
+        );
+
+        let geometry = puzzle("2x2").into_inner();
+
+        assert_eq!(
+            facelet_string(&geometry, &Permutation::from_cycles(Vec::new())).as_deref(),
+            Some("UUUURRRRFFFFDDDDLLLLBBBB")
+        );
+    }
+
+    #[test]
+    fn turned() {
+        let geometry = puzzle("3x3").into_inner();
+        let r = Algorithm::parse_from_string(geometry.permutation_group(), "R")
+            .unwrap()
+            .permutation()
+            .clone();
+
+        // The front comes up onto the right of the top face, in the reading order of each face rather than whatever order the puzzle geometry numbers the stickers in
+        assert_eq!(
+            facelet_string(&geometry, &r).as_deref(),
+            Some("UUFUUFUUFRRRRRRRRRFFDFFDFFDDDBDDBDDBLLLLLLLLLUBBUBBUBB")
+        );
+
+        // The edges of bigger cubes can't be told apart by the faces their pieces touch
+        let geometry = puzzle("4x4").into_inner();
+        assert_eq!(
+            facelet_string(&geometry, &Permutation::from_cycles(Vec::new())),
+            None
+        );
+    }
+
+    #[test]
+    fn letters() {
+        assert_eq!(facelet_color('U'), Some("white"));
+        assert_eq!(facelet_color('B'), Some("blue"));
+        assert_eq!(facelet_color('X'), None);
+    }
+
+    #[test]
+    fn squares() {
+        assert!(is_square(0));
+        assert!(is_square(1));
+        assert!(is_square(9));
+        assert!(!is_square(8));
+        assert!(!is_square(24));
+        assert!(is_square(25));
+    }
+
+    #[test]
+    fn colors() {
+        let geometry = puzzle("3x3").into_inner();
+        let group = geometry.permutation_group();
+
+        assert_eq!(
+            &*state_colors(&geometry, &Permutation::from_cycles(Vec::new())),
+            group.facelet_colors()
+        );
+
+        let r = Algorithm::parse_from_string(geometry.permutation_group(), "R")
+            .unwrap()
+            .permutation()
+            .clone();
+        let colors = state_colors(&geometry, &r);
+        let changed = group
+            .facelet_colors()
+            .iter()
+            .zip(&colors)
+            .filter(|(solved, now)| solved != now)
+            .map(|(solved, now)| (&**solved, &**now))
+            .collect::<Vec<_>>();
+
+        // R turns the stickers of the three columns nearest the red face, but the red face keeps its color
+        assert_eq!(changed.len(), 12);
+        assert!(
+            !changed
+                .iter()
+                .any(|(solved, _)| ["red", "orange"].contains(solved))
+        );
+        // R brings the front of the cube to the top, so the white stickers that changed are now green. Looking the colors up the other way around would make them blue.
+        assert_eq!(
+            changed
+                .iter()
+                .filter(|(solved, _)| *solved == "white")
+                .map(|(_, now)| *now)
+                .collect::<Vec<_>>(),
+            ["green"; 3]
+        );
+    }
+}
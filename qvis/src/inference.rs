@@ -1,4 +1,13 @@
-use std::{cmp::Ordering, collections::HashMap, sync::Arc};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    fmt,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
 
 use internment::ArcIntern;
 use itertools::Itertools;
@@ -7,11 +16,121 @@ use puzzle_theory::{
     permutations::{Permutation, PermutationGroup},
     puzzle_geometry::PuzzleGeometry,
 };
-use rand::Rng;
+
+use rand::{Rng, SeedableRng, rngs::SmallRng, seq::SliceRandom};
+
+use crate::{
+    cmp_ll,
+    facelets::colors_in,
+    select::{select_nth, top_k},
+    super_pixels::SuperPixels,
+};
 
 const CONFIDENCE_PERCENTILE: f64 = 0.2;
 const MAX_NEAREST_N: usize = 10;
 const MAX_FRACTION: usize = 8;
+/// How many standard deviations apart, added together, two colors' centroids must be at every pixel for `Scoring::Centroid` to tell them apart
+const MIN_CENTROID_SEPARATION: f64 = 3.;
+/// The least variance that a centroid is taken to have, which is about the noise of an 8-bit camera. A color that was only calibrated with one sample, or with identical ones, would otherwise have a peak so narrow that every pixel is infinitely unlikely to be it.
+const MIN_CENTROID_VARIANCE: f64 = 1e-4;
+/// How many times further than its calibration samples typically are from their mean a pixel can be from a color and still plausibly show it, for `Inference::plausible_fraction`
+const MAX_PLAUSIBLE_DEVIATIONS: f64 = 4.;
+/// The least distance that the calibration samples of a color are typically taken to be from their mean by `Inference::plausible_fraction`, as a fraction of how bright the color is. A color calibrated with one picture or a few barely spreads, but the light drifts by several percent between pictures, and by more for brighter colors, without the puzzle looking any less like itself.
+const MIN_PLAUSIBLE_SPREAD: f64 = 0.05;
+/// A color or sticker with less than this fraction of the median number of calibration samples of every color or sticker is under-sampled, for `Inference::under_sampled`
+const UNDER_SAMPLED_FRACTION: f64 = 0.5;
+/// The `reject_distance` of `InferenceParams::stickerless`
+const STICKERLESS_REJECT_DISTANCE: f64 = 0.5;
+/// The `min_observed_fraction` of `InferenceParams::stickerless`
+const STICKERLESS_MIN_OBSERVED_FRACTION: f64 = 0.25;
+/// The format of the calibrations that `Inference::calibration_to_text` writes, which they record so that older ones are turned away
+#[cfg(feature = "serde")]
+const CALIBRATION_FORMAT: &str = "qvis calibration 2";
+
+/// How `Inference` scores how much a pixel looks like each color
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Scoring {
+    /// Estimate the density of each color's calibration samples around the pixel, separately for each color
+    #[default]
+    Density,
+    /// Find the nearest calibration samples across every color and score each color by the fraction of them that it has. This is standard k-NN classification; the scores are probabilities, and they hold up better when some colors have far more calibration samples than others.
+    Vote,
+    /// Summarize each color's calibration samples at each pixel by their mean and spread, and score the pixel by the density of a normal distribution with them. This skips the kd-trees entirely, so it is much faster, and it is nearly as accurate when the colors are far apart. If any two colors are too close together at some pixel to be told apart this way, `Density` is used instead.
+    Centroid,
+}
+
+/// Tuning for how `Inference` turns the calibration samples into confidences
+#[derive(Debug, Clone, PartialEq)]
+pub struct InferenceParams {
+    /// A sticker's confidence in a color is the score that this fraction of its pixels, weighted, score above. This must be less than one.
+    pub confidence_percentile: f64,
+    /// The most calibration samples that are looked at around each pixel
+    pub max_nearest_n: usize,
+    /// At most this fraction (as its reciprocal) of the calibration samples are looked at around each pixel, so that the estimate stays local while there are few samples
+    pub max_fraction: usize,
+    pub scoring: Scoring,
+    /// Convert pixels from sRGB, which is how images and cameras encode them, to linear light before doing anything else with them. Distances between linear colors correspond to physical differences in light, and white balancing by dividing by the neutral color is only physically meaningful in linear light. Changing this after calibrating mixes samples from both spaces, so it should be set first.
+    pub linearize_srgb: bool,
+    /// A sticker with less than this fraction of its pixels observed is left unobserved, with no estimates, rather than being judged from the few pixels that are left. Those are usually at the edge of whatever is covering the sticker and are as likely to show the finger or its shadow as the sticker.
+    pub min_observed_fraction: f64,
+    /// A pixel whose color is farther than this from every calibration sample of its own, of any color, is left out of its sticker's confidences instead of voting for whichever color happens to be least far. This keeps the table or the hand showing past the edge of a sticker from bleeding into it. The distance is in the white balanced space that the samples are in, so it depends on `linearize_srgb`. Every pixel counts by default.
+    pub reject_distance: f64,
+    /// Undo a color cast over the whole picture, like the one a camera's auto white balance gives it when it changes its mind, before classifying its pixels. The cast is estimated by comparing the average color of the white balance pixels with their average in the calibration pictures; see `Inference::color_cast`. This complements the white balance of each face, which only corrects faces with white balance pixels of their own, so it needs white balance pixels of at least one face. Correcting copies each picture, and is off by default.
+    pub correct_color_cast: bool,
+}
+
+impl Default for InferenceParams {
+    fn default() -> Self {
+        InferenceParams {
+            confidence_percentile: CONFIDENCE_PERCENTILE,
+            max_nearest_n: MAX_NEAREST_N,
+            max_fraction: MAX_FRACTION,
+            scoring: Scoring::default(),
+            linearize_srgb: false,
+            min_observed_fraction: 0.,
+            reject_distance: f64::INFINITY,
+            correct_color_cast: false,
+        }
+    }
+}
+
+impl InferenceParams {
+    /// Parameters for stickerless puzzles, whose colors are molded into glossy plastic instead of printed on flat stickers. The plastic reflects the light in sharp highlights that can cover much of a face, so pixels far from every calibration sample are rejected as glare rather than counted, and a face that is mostly highlight is left unobserved for the rest of the puzzle to decide. Select these with `CVProcessor::set_inference_params` before calibrating.
+    pub fn stickerless() -> InferenceParams {
+        InferenceParams {
+            reject_distance: STICKERLESS_REJECT_DISTANCE,
+            min_observed_fraction: STICKERLESS_MIN_OBSERVED_FRACTION,
+            ..InferenceParams::default()
+        }
+    }
+}
+
+/// Convert a channel from sRGB's nonlinear encoding to linear light with the standard sRGB transfer function
+pub(crate) fn srgb_to_linear(v: f64) -> f64 {
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// The inverse of `srgb_to_linear`
+pub(crate) fn linear_to_srgb(v: f64) -> f64 {
+    if v <= 0.003_130_8 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(2.4_f64.recip()) - 0.055
+    }
+}
+
+/// Read a pixel of an image into the space that the calibration samples are in
+fn decode((r, g, b): (f64, f64, f64), linearize_srgb: bool) -> (f64, f64, f64) {
+    if linearize_srgb {
+        (srgb_to_linear(r), srgb_to_linear(g), srgb_to_linear(b))
+    } else {
+        (r, g, b)
+    }
+}
 
 fn white_balance(mut color: (f64, f64, f64), neutral: (f64, f64, f64)) -> (f64, f64, f64) {
     color.0 /= neutral.0;
@@ -21,23 +140,621 @@ fn white_balance(mut color: (f64, f64, f64), neutral: (f64, f64, f64)) -> (f64,
     color
 }
 
-struct Pixel {
+/// The gain on each channel that takes the `observed` neutral color to the `expected` one. Channels that are black in either are left alone, since nothing tells how they were cast.
+fn cast_correction(expected: (f64, f64, f64), observed: (f64, f64, f64)) -> (f64, f64, f64) {
+    let gain = |expected: f64, observed: f64| {
+        if expected > 0. && observed > 0. {
+            expected / observed
+        } else {
+            1.
+        }
+    };
+
+    (
+        gain(expected.0, observed.0),
+        gain(expected.1, observed.1),
+        gain(expected.2, observed.2),
+    )
+}
+
+/// The sum of some decoded colors, along with how many there were
+type ColorSum = ((f64, f64, f64), usize);
+
+/// The average color of a sum of `count` colors, or `None` if there are none
+fn average(((r, g, b), count): ColorSum) -> Option<(f64, f64, f64)> {
+    (count > 0).then(|| {
+        let count = count as f64;
+        (r / count, g / count, b / count)
+    })
+}
+
+/// Add a sum of colors and how many there were to another
+fn add_sum(sum: &mut ColorSum, ((r, g, b), count): ColorSum) {
+    sum.0.0 += r;
+    sum.0.1 += g;
+    sum.0.2 += b;
+    sum.1 += count;
+}
+
+/// Multiply the decoded color of `value` by `gain` and encode it again, leaving its other channels alone
+fn correct_cast<const K: usize, P: Channels<K>>(
+    value: P,
+    gain: (f64, f64, f64),
+    linearize_srgb: bool,
+) -> P {
+    let mut channels = value.channels();
+    for (channel, gain) in channels.iter_mut().zip([gain.0, gain.1, gain.2]) {
+        *channel = if linearize_srgb {
+            linear_to_srgb(srgb_to_linear(*channel) * gain)
+        } else {
+            *channel * gain
+        };
+    }
+
+    P::from_channels(channels)
+}
+
+/// A pixel of a picture with `K` channels: its color, followed by whatever else the camera captures along with it, like depth or infrared. Stickers that are glossy or nearly the same color can be told apart by the other channels when their colors alone are ambiguous. Only the color is decoded and white balanced; the other channels are compared as they are, so they should be scaled like the color, from zero to one.
+///
+/// `(r, g, b)` tuples are pixels with only a color, which is what `Inference` takes by default, and `[f64; K]` arrays hold the color in their first three channels.
+pub trait Channels<const K: usize>: Copy + Send + Sync {
+    /// Every channel of the pixel, starting with its red, green, and blue
+    fn channels(&self) -> [f64; K];
+
+    /// The pixel with the given channels
+    fn from_channels(channels: [f64; K]) -> Self;
+
+    /// The color of the pixel as `(r, g, b)`
+    fn color(&self) -> (f64, f64, f64) {
+        let channels = self.channels();
+        (channels[0], channels[1], channels[2])
+    }
+}
+
+impl Channels<3> for (f64, f64, f64) {
+    fn channels(&self) -> [f64; 3] {
+        [self.0, self.1, self.2]
+    }
+
+    fn from_channels([r, g, b]: [f64; 3]) -> Self {
+        (r, g, b)
+    }
+
+    fn color(&self) -> (f64, f64, f64) {
+        *self
+    }
+}
+
+impl<const K: usize> Channels<K> for [f64; K] {
+    fn channels(&self) -> [f64; K] {
+        *self
+    }
+
+    fn from_channels(channels: [f64; K]) -> Self {
+        channels
+    }
+}
+
+/// The point in the space of the calibration samples that a pixel is compared at: its color read with `decode` and white balanced with `wb`, followed by its other channels
+fn point<const K: usize, P: Channels<K>>(
+    value: P,
+    linearize_srgb: bool,
+    wb: (f64, f64, f64),
+) -> [f64; K] {
+    let (r, g, b) = white_balance(decode(value.color(), linearize_srgb), wb);
+
+    let mut point = value.channels();
+    point[..3].copy_from_slice(&[r, g, b]);
+    point
+}
+
+/// Whether the pixel at `idx` was observed, where `None` means that every pixel was
+fn is_observed(observed: Option<&[bool]>, idx: usize) -> bool {
+    observed.is_none_or(|observed| observed[idx])
+}
+
+struct Pixel<const K: usize> {
     idx: usize,
-    kdtrees: HashMap<ArcIntern<str>, KdTree<f64, 3>>,
+    // How much the pixel counts towards its sticker's confidences relative to the other pixels of the sticker
+    weight: f64,
+    // The white balanced calibration samples of each color, which are only kept here and read back out with `samples`
+    kdtrees: BTreeMap<ArcIntern<str>, KdTree<f64, K>>,
+    centroids: BTreeMap<ArcIntern<str>, Centroid<K>>,
+    // Whether every pair of calibrated colors' centroids are far enough apart for `Scoring::Centroid`
+    centroids_separate: bool,
+}
+
+impl<const K: usize> Pixel<K> {
+    /// The pixel at `idx` with a weight of one and no calibration samples of any of `colors`
+    fn uncalibrated(idx: usize, colors: &[ArcIntern<str>]) -> Pixel<K> {
+        Pixel {
+            idx,
+            weight: 1.,
+            kdtrees: colors
+                .iter()
+                .map(|color| (ArcIntern::clone(color), KdTree::new()))
+                .collect(),
+            centroids: colors
+                .iter()
+                .map(|color| (ArcIntern::clone(color), Centroid::default()))
+                .collect(),
+            centroids_separate: true,
+        }
+    }
+
+    /// Whether some calibration sample of the pixel, of any color, is within `distance` of `color`
+    fn has_sample_within(&self, color: [f64; K], distance: f64) -> bool {
+        self.kdtrees
+            .values()
+            .filter(|kdtree| kdtree.size() > 0)
+            .any(|kdtree| {
+                kdtree.nearest_one::<SquaredEuclidean>(&color).distance <= distance * distance
+            })
+    }
+
+    /// The calibration samples of each color, in no particular order
+    fn samples(&self) -> impl Iterator<Item = (&ArcIntern<str>, impl Iterator<Item = [f64; K]>)> {
+        self.kdtrees
+            .iter()
+            .map(|(color, kdtree)| (color, kdtree.iter().map(|(_, sample)| sample)))
+    }
+
+    /// Add calibration samples of the given color, building the kd-tree with `shuffled_kdtree` if it is still empty
+    fn add_samples<R: Rng + ?Sized>(
+        &mut self,
+        rng: &mut R,
+        color: &ArcIntern<str>,
+        samples: &mut [[f64; K]],
+    ) {
+        let kdtree = self.kdtrees.get_mut(color).unwrap();
+
+        if kdtree.size() == 0 {
+            *kdtree = shuffled_kdtree(rng, samples);
+        } else {
+            for sample in samples.iter() {
+                kdtree.add(sample, 0);
+            }
+        }
+
+        let centroid = self.centroids.get_mut(color).unwrap();
+        for sample in samples.iter() {
+            centroid.add(sample);
+        }
+        self.centroids_separate = self
+            .centroids
+            .values()
+            .filter(|centroid| centroid.count > 0)
+            .tuple_combinations()
+            .all(|(a, b)| a.is_separate_from(b));
+    }
+}
+
+/// The calibration samples of a color at a pixel, summarized by their sums so that their mean and spread can be kept up to date as samples are added
+#[derive(Debug, Clone)]
+struct Centroid<const K: usize> {
+    sum: [f64; K],
+    // The sum of the squared lengths of the samples
+    sum_squares: f64,
+    count: usize,
+}
+
+impl<const K: usize> Default for Centroid<K> {
+    fn default() -> Self {
+        Centroid {
+            sum: [0.; K],
+            sum_squares: 0.,
+            count: 0,
+        }
+    }
+}
+
+impl<const K: usize> Centroid<K> {
+    fn add(&mut self, sample: &[f64; K]) {
+        for (sum, v) in self.sum.iter_mut().zip(sample) {
+            *sum += v;
+        }
+        self.sum_squares += sample.iter().map(|v| v * v).sum::<f64>();
+        self.count += 1;
+    }
+
+    fn mean(&self) -> [f64; K] {
+        let count = self.count as f64;
+        self.sum.map(|v| v / count)
+    }
+
+    /// The variance of the samples in each channel, taking the spread to be the same in every direction
+    fn variance(&self) -> f64 {
+        let mean = self.mean();
+        let mean_squares = self.sum_squares / self.count as f64;
+        let total = mean_squares - mean.iter().map(|v| v * v).sum::<f64>();
+
+        (total / K as f64).max(MIN_CENTROID_VARIANCE)
+    }
+
+    /// The density at `color` of the normal distribution with the samples' mean and variance
+    fn density(&self, color: [f64; K]) -> f64 {
+        let variance = self.variance();
+        let squared_distance = squared_distance(color, self.mean());
+
+        (2. * core::f64::consts::PI * variance).powf(-(K as f64) / 2.)
+            * (-squared_distance / (2. * variance)).exp()
+    }
+
+    /// Add the samples summarized by `other` to these
+    fn merge(&mut self, other: &Centroid<K>) {
+        for (sum, other) in self.sum.iter_mut().zip(other.sum) {
+            *sum += other;
+        }
+        self.sum_squares += other.sum_squares;
+        self.count += other.count;
+    }
+
+    /// Whether `color` is within `MAX_PLAUSIBLE_DEVIATIONS` of the typical distance of the samples from their mean. In `K` channels with the same spread, that typical distance is the standard deviation times the square root of `K`, and it is at least `MIN_PLAUSIBLE_SPREAD` of the length of the mean.
+    fn is_plausible(&self, color: [f64; K]) -> bool {
+        if self.count == 0 {
+            return false;
+        }
+
+        let mean = self.mean();
+        let spread = (K as f64 * self.variance())
+            .max(MIN_PLAUSIBLE_SPREAD.powi(2) * squared_distance(mean, [0.; K]));
+
+        squared_distance(color, mean) <= MAX_PLAUSIBLE_DEVIATIONS.powi(2) * spread
+    }
+
+    fn is_separate_from(&self, other: &Centroid<K>) -> bool {
+        let distance = squared_distance(self.mean(), other.mean()).sqrt();
+        let spread = self.variance().sqrt() + other.variance().sqrt();
+
+        distance >= MIN_CENTROID_SEPARATION * spread
+    }
+}
+
+/// Score the pixel by the density of each calibrated color's centroid at it
+fn push_centroid_densities<const K: usize>(
+    pixel: &Pixel<K>,
+    color: [f64; K],
+    scores: &mut BTreeMap<ArcIntern<str>, Vec<(f64, f64)>>,
+) {
+    for (color_name, centroid) in &pixel.centroids {
+        if centroid.count > 0 {
+            scores
+                .get_mut(color_name)
+                .unwrap()
+                .push((centroid.density(color), pixel.weight));
+        }
+    }
+}
+
+fn squared_distance<const K: usize>(a: [f64; K], b: [f64; K]) -> f64 {
+    a.iter().zip(&b).map(|(a, b)| (a - b).powi(2)).sum()
+}
+
+/// Pick the largest density such that more than `percentile` of the total weight belongs to densities at or above it. When every weight is the same, this is the element `percentile` of the way through the densities sorted in descending order, which is found without sorting.
+fn weighted_percentile<R: Rng + ?Sized>(
+    rng: &mut R,
+    densities: &mut [(f64, f64)],
+    percentile: f64,
+) -> f64 {
+    let by_density = |a: &(f64, f64), b: &(f64, f64)| cmp_ll(a.0, b.0);
+
+    if densities
+        .iter()
+        .all(|(_, weight)| *weight == densities[0].1)
+    {
+        let n = (percentile * densities.len() as f64).floor() as usize;
+        // `n` is in range because `percentile` is less than one
+        return select_nth(rng, densities, by_density, n).unwrap().0;
+    }
+
+    densities.sort_unstable_by(|a, b| by_density(b, a));
+
+    let threshold = percentile * densities.iter().map(|(_, weight)| weight).sum::<f64>();
+    let mut cumulative = 0.;
+
+    for (density, weight) in densities.iter() {
+        cumulative += weight;
+        if cumulative > threshold {
+            return *density;
+        }
+    }
+
+    densities.last().unwrap().0
+}
+
+/// The items whose counts are less than `UNDER_SAMPLED_FRACTION` of the median count, in the order they were given
+fn below_median<T>(counts: Vec<(T, usize)>) -> Vec<T> {
+    let sorted = counts
+        .iter()
+        .map(|(_, count)| *count)
+        .sorted()
+        .collect_vec();
+    if sorted.is_empty() {
+        return Vec::new();
+    }
+
+    let median = (sorted[(sorted.len() - 1) / 2] + sorted[sorted.len() / 2]) as f64 / 2.;
+
+    counts
+        .into_iter()
+        .filter(|(_, count)| (*count as f64) < UNDER_SAMPLED_FRACTION * median)
+        .map(|(item, _)| item)
+        .collect()
+}
+
+/// Build a kd-tree by adding every sample to it in a random order, which is how trees should be built when all of their samples are known up front, like when they are rebuilt from saved samples. `kiddo`'s mutable trees split a bucket at its median when it fills up, so the shape of the tree depends on the order that the samples arrive in; shuffling them first puts the splits near the medians of the whole set rather than of whichever samples happened to come first, which keeps the tree balanced and its queries fast. `kiddo`'s immutable trees are built from a slice in one go, but they can't be added to after that, which calibrating again needs.
+fn shuffled_kdtree<R: Rng + ?Sized, const K: usize>(
+    rng: &mut R,
+    samples: &mut [[f64; K]],
+) -> KdTree<f64, K> {
+    samples.shuffle(rng);
+
+    let mut kdtree = KdTree::with_capacity(samples.len());
+    for sample in samples.iter() {
+        kdtree.add(sample, 0);
+    }
+
+    kdtree
+}
+
+/// Why two calibrations couldn't be merged
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IncompatibleCalibration {
+    /// The calibrations are of different puzzles
+    Puzzle,
+    /// The calibrations assign the pixels of the image differently
+    Assignment,
+    /// One calibration linearized the pixels from sRGB and the other didn't, so their samples are in different color spaces. See `InferenceParams::linearize_srgb`.
+    Linearization,
+}
+
+impl fmt::Display for IncompatibleCalibration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IncompatibleCalibration::Puzzle => {
+                write!(f, "The calibrations are of different puzzles")
+            }
+            IncompatibleCalibration::Assignment => {
+                write!(f, "The calibrations assign the pixels differently")
+            }
+            IncompatibleCalibration::Linearization => {
+                write!(f, "Only one of the calibrations linearized the pixels")
+            }
+        }
+    }
+}
+
+impl Error for IncompatibleCalibration {}
+
+/// A calibration as `Inference::calibration_to_text` writes it
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SavedCalibration {
+    format: String,
+    colors: Box<[String]>,
+    linearize_srgb: bool,
+    stickers: Box<[Box<[usize]>]>,
+    white_balance: BTreeMap<String, Box<[usize]>>,
+    super_pixels: Option<SuperPixels>,
+    appearances: BTreeMap<String, ColorSum>,
+    neutral: ColorSum,
+    samples: Vec<SavedSamples>,
+}
+
+/// The calibration samples of one color at one pixel
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SavedSamples {
+    sticker: usize,
+    // The index of the pixel in the pictures
+    pixel: usize,
+    color: String,
+    // Every channel of every sample, one sample after another
+    values: Vec<f64>,
+}
+
+/// A facelet label that `Inference::calibrate_facelets` couldn't use
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InvalidFaceletLabel {
+    /// The puzzle has no facelet with this index
+    Index(usize),
+    /// None of the puzzle's faces have this color
+    Color(ArcIntern<str>),
+}
+
+impl fmt::Display for InvalidFaceletLabel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvalidFaceletLabel::Index(index) => {
+                write!(f, "The puzzle has no facelet {index}")
+            }
+            InvalidFaceletLabel::Color(color) => {
+                write!(f, "The puzzle has no {color} face")
+            }
+        }
+    }
+}
+
+impl Error for InvalidFaceletLabel {}
+
+/// Pixel weights that `Inference::set_weights` couldn't use
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvalidWeights {
+    /// There isn't one weight per pixel of the assignment
+    Size { expected: usize, found: usize },
+    /// The weight of this pixel is negative or NaN
+    Negative(usize),
+    /// Every pixel of this sticker has a weight of zero, so nothing would count towards its confidences
+    ZeroSticker(usize),
+}
+
+impl fmt::Display for InvalidWeights {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvalidWeights::Size { expected, found } => {
+                write!(f, "There are {found} weights instead of {expected}")
+            }
+            InvalidWeights::Negative(pixel) => {
+                write!(f, "The weight of pixel {pixel} is negative")
+            }
+            InvalidWeights::ZeroSticker(sticker) => {
+                write!(f, "Every pixel of sticker {sticker} has a weight of zero")
+            }
+        }
+    }
+}
+
+impl Error for InvalidWeights {}
+
+/// The colors and stickers that `Inference::under_sampled` found to have far fewer calibration samples than the others, which are what the next calibration pictures should show
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct UnderSampled {
+    /// The under-sampled colors, in the order that the puzzle's stickers first show them
+    pub colors: Vec<ArcIntern<str>>,
+    /// The under-sampled stickers, in increasing order
+    pub stickers: Vec<usize>,
+}
+
+/// Buffers that `Inference::infer_into` reuses from one call to the next. A scratch can be used with any `Inference`, but it is sized for the last one it was used with.
+#[derive(Debug, Clone, Default)]
+pub struct InferenceScratch {
+    white_balances: HashMap<ArcIntern<str>, (f64, f64, f64)>,
+    sticker: StickerScratch,
+    confidences: Vec<BTreeMap<ArcIntern<str>, f64>>,
+}
+
+impl InferenceScratch {
+    pub fn new() -> InferenceScratch {
+        InferenceScratch::default()
+    }
 }
 
-pub struct Inference {
-    pixels_by_sticker: Box<[Box<[Pixel]>]>,
+/// The buffers for inferring a single sticker, which every thread needs its own of when stickers are inferred in parallel
+#[derive(Debug, Clone, Default)]
+struct StickerScratch {
+    // The scores of each pixel of the sticker being inferred along with its weight, for each color
+    scores: BTreeMap<ArcIntern<str>, Vec<(f64, f64)>>,
+    // The nearest calibration samples of every color around the pixel being voted on
+    neighbours: Vec<(f64, ArcIntern<str>)>,
+}
+
+impl StickerScratch {
+    /// Make an empty list of scores for each of `colors` and no others
+    fn reset(&mut self, colors: &[ArcIntern<str>]) {
+        // The scratch may have been used with a different puzzle
+        self.scores.retain(|color, _| colors.contains(color));
+        for color in colors {
+            self.scores
+                .entry(ArcIntern::clone(color))
+                .or_default()
+                .clear();
+        }
+    }
+}
+
+/// A picture as the assignment sees it, along with which of its entries were observed, borrowed from the picture unless its super-pixels had to be averaged
+type Aggregated<'a, P> = (Cow<'a, [P]>, Option<Cow<'a, [bool]>>);
+
+/// Scores how much each sticker of a puzzle looks like each of its colors, from the pixels assigned to the sticker. This is the part of `CVProcessor` that deals with colors, on its own, for experimenting with other ways of matching the scores to a state of the puzzle.
+///
+/// ```
+/// use puzzle_theory::{
+///     permutations::{Algorithm, Permutation},
+///     puzzle_geometry::parsing::puzzle,
+/// };
+/// use qvis::{Inference, Pixel};
+///
+/// let geometry = puzzle("3x3").into_inner();
+/// let group = geometry.permutation_group();
+///
+/// // One pixel per sticker
+/// let assignment = (0..group.facelet_count()).map(Pixel::Sticker).collect();
+/// let mut inference = Inference::new(assignment, &geometry);
+///
+/// // Every color is its own shade of gray
+/// let picture = |state: &Permutation, brightness: f64| {
+///     (0..group.facelet_count())
+///         .map(|sticker| {
+///             let color = &group.facelet_colors()[state.state().get(sticker)];
+///             let shade = group.facelet_colors().iter().position(|c| c == color).unwrap();
+///             let v = brightness * (shade + 1) as f64 / 50.;
+///             (v, v, v)
+///         })
+///         .collect::<Vec<_>>()
+/// };
+///
+/// let solved = Permutation::from_cycles(Vec::new());
+/// let turned = Algorithm::parse_from_string(geometry.permutation_group(), "U R")
+///     .unwrap()
+///     .permutation()
+///     .clone();
+/// inference.calibrate(&picture(&solved, 1.), &solved);
+/// inference.calibrate(&picture(&turned, 1.), &turned);
+///
+/// let observation = inference.infer(&picture(&turned, 1.01));
+/// for (sticker, scores) in observation.iter().enumerate() {
+///     let (best, _) = scores.iter().max_by(|(_, a), (_, b)| a.total_cmp(b)).unwrap();
+///     assert_eq!(*best, group.facelet_colors()[turned.state().get(sticker)]);
+/// }
+/// ```
+///
+/// Pictures whose pixels have more channels than their color, like depth or infrared, are classified on every channel by an `Inference<K>` made with `with_channels`. See `Channels`.
+pub struct Inference<const K: usize = 3> {
+    pixels_by_sticker: Box<[Box<[Pixel<K>]>]>,
     white_balance_by_face: HashMap<ArcIntern<str>, Box<[usize]>>,
     group: Arc<PermutationGroup>,
     colors: Box<[ArcIntern<str>]>,
+    // The sum of every calibrated pixel of each color before white balancing, along with how many there were. The pixels are linearized first if the parameters say so.
+    appearances: HashMap<ArcIntern<str>, ColorSum>,
+    // The sum of every white balance pixel of the calibration pictures, decoded like `appearances`, along with how many there were
+    neutral: ColorSum,
+    params: InferenceParams,
+    seed: Option<u64>,
+    // If the assignment is of super-pixels, how the pixels of the pictures are grouped into them
+    super_pixels: Option<SuperPixels>,
+    // Whether it has been logged that `Scoring::Centroid` fell back to `Scoring::Density`, so that it isn't logged again for every frame
+    centroid_fallback_logged: AtomicBool,
 }
 
 impl Inference {
+    /// Create an `Inference` for the given puzzle with nothing calibrated yet. The assignment has one entry per pixel of the pictures, like the one `CVProcessor::new` takes. This method panics if a pixel is assigned to a sticker that the puzzle doesn't have or is white balance for a color that it doesn't have, and warns about stickers that no pixel is assigned to, since they can't be recognized.
     pub fn new(assignment: Box<[super::Pixel]>, puzzle: &PuzzleGeometry) -> Inference {
+        Inference::with_channels(assignment, puzzle)
+    }
+
+    /// Like `new`, but the assignment has one entry per super-pixel rather than per pixel. Pictures still have every pixel, and the pixels of each super-pixel are averaged before they are scored or calibrated with. This method panics if the assignment doesn't have one entry per super-pixel.
+    pub fn with_super_pixels(
+        assignment: Box<[super::Pixel]>,
+        super_pixels: SuperPixels,
+        puzzle: &PuzzleGeometry,
+    ) -> Inference {
+        assert_eq!(assignment.len(), super_pixels.len());
+
+        let mut inference = Inference::new(assignment, puzzle);
+        inference.super_pixels = Some(super_pixels);
+        inference
+    }
+}
+
+impl<const K: usize> Inference<K> {
+    /// Like `new`, but for pictures whose pixels have `K` channels, the first three of which are the color. `K` less than three doesn't compile.
+    pub fn with_channels(assignment: Box<[super::Pixel]>, puzzle: &PuzzleGeometry) -> Inference<K> {
+        const {
+            assert!(
+                K >= 3,
+                "Pixels need at least the three channels of their color"
+            );
+        }
+
+        if let Err(missing) = crate::validate_assignment(&assignment, puzzle) {
+            log::warn!(
+                "No pixels are assigned to stickers {missing:?}, so they can't be recognized"
+            );
+        }
+
         let group = puzzle.permutation_group();
 
-        let mut pixels_by_sticker: Vec<Vec<Pixel>> = Vec::new();
+        let mut pixels_by_sticker: Vec<Vec<Pixel<K>>> = Vec::new();
 
         for _ in 0..group.facelet_count() {
             pixels_by_sticker.push(Vec::new());
@@ -51,12 +768,6 @@ impl Inference {
             .cloned()
             .collect();
 
-        let empty_kdtrees: HashMap<ArcIntern<str>, KdTree<f64, 3>> = colors
-            .iter()
-            .cloned()
-            .map(|a| (a, KdTree::<f64, 3>::new()))
-            .collect();
-
         let mut white_balance_by_face = colors
             .iter()
             .cloned()
@@ -71,10 +782,7 @@ impl Inference {
                     .unwrap()
                     .push(idx),
                 crate::Pixel::Sticker(sticker) => {
-                    pixels_by_sticker[sticker].push(Pixel {
-                        idx,
-                        kdtrees: empty_kdtrees.clone(),
-                    });
+                    pixels_by_sticker[sticker].push(Pixel::uncalibrated(idx, &colors));
                 }
             }
         }
@@ -86,337 +794,2449 @@ impl Inference {
                 .map(|(k, v)| (k, v.into()))
                 .collect(),
             group,
+            appearances: colors
+                .iter()
+                .cloned()
+                .map(|v| (v, ((0., 0., 0.), 0)))
+                .collect(),
+            neutral: ((0., 0., 0.), 0),
             colors,
+            params: InferenceParams::default(),
+            seed: None,
+            super_pixels: None,
+            centroid_fallback_logged: AtomicBool::new(false),
         }
     }
 
-    fn white_balance(
-        &self,
-        picture: &[(f64, f64, f64)],
-    ) -> HashMap<ArcIntern<str>, (f64, f64, f64)> {
-        self.white_balance_by_face
-            .iter()
-            .map(|(k, v)| {
-                let white = v
-                    .iter()
-                    .map(|idx| picture[*idx])
-                    .tree_reduce(|(r1, g1, b1), (r2, g2, b2)| (r1 + r2, g1 + g2, b1 + b2));
+    /// How the pixels of the pictures are grouped into the super-pixels of the assignment, if they are
+    pub fn super_pixels(&self) -> Option<&SuperPixels> {
+        self.super_pixels.as_ref()
+    }
 
+    /// The picture as the assignment sees it, which averages each super-pixel if there are any, along with which of its entries were observed
+    fn aggregate<'a, P: Channels<K>>(
+        &self,
+        picture: &'a [P],
+        observed: Option<&'a [bool]>,
+    ) -> Aggregated<'a, P> {
+        match &self.super_pixels {
+            Some(super_pixels) => {
+                let (picture, observed) = super_pixels.average(picture, observed);
                 (
-                    ArcIntern::clone(k),
-                    match white {
-                        Some((r, g, b)) => {
-                            let len = v.len() as f64;
-
-                            (r / len, g / len, b / len)
-                        }
-                        None => (1., 1., 1.),
-                    },
+                    Cow::Owned(picture.into_vec()),
+                    observed.map(|observed| Cow::Owned(observed.into_vec())),
                 )
-            })
-            .collect()
+            }
+            None => (Cow::Borrowed(picture), observed.map(Cow::Borrowed)),
+        }
     }
 
-    pub fn infer(&self, picture: &[(f64, f64, f64)]) -> Box<[HashMap<ArcIntern<str>, f64>]> {
-        let mut rng = rand::rng();
-
-        let mut confidences_by_pixel = self
-            .colors
-            .iter()
-            .cloned()
-            .map(|v| (v, Vec::<f64>::new()))
-            .collect::<HashMap<_, _>>();
-
-        let wb = self.white_balance(picture);
-
-        self.pixels_by_sticker
-            .iter()
-            .enumerate()
-            .map(|(idx, v)| {
-                let wb = *wb.get(&self.group.facelet_colors()[idx]).unwrap();
-
-                // Maybe pick random subset
-                for pixel in v {
-                    for (color, kdtree) in &pixel.kdtrees {
-                        let (r, g, b) = white_balance(picture[pixel.idx], wb);
-                        let n = MAX_NEAREST_N
-                            .min(kdtree.size() as usize / MAX_FRACTION)
-                            .max(1);
-                        let nn = kdtree.nearest_n::<SquaredEuclidean>(&[r, g, b], n);
-
-                        // https://faculty.washington.edu/yenchic/18W_425/Lec7_knn_basis.pdf
-                        // TODO: Try to account for non uniform distributions?
-                        const UNIT_SPHERE: f64 = 4. / 3. * core::f64::consts::PI;
-
-                        if let Some(last) = nn.last() {
-                            let density = n as f64 / kdtree.size() as f64
-                                * (last.distance.powi(3) * UNIT_SPHERE).recip();
-
-                            confidences_by_pixel.get_mut(color).unwrap().push(density);
-                        }
-                    }
-                }
-
-                confidences_by_pixel
-                    .iter_mut()
-                    .map(|(k, v)| {
-                        if v.is_empty() {
-                            return (ArcIntern::clone(k), 0.);
-                        }
-
-                        let n = (CONFIDENCE_PERCENTILE * v.len() as f64).floor() as usize;
-                        quickselect(&mut rng, v, f64::total_cmp, n);
-                        let confidence = v[n];
-                        v.drain(..);
-                        (ArcIntern::clone(k), confidence)
-                    })
-                    .collect()
-            })
-            .collect()
+    /// A random number generator for the work numbered `stream`, seeded from the seed given to `set_seed` if there is one and from entropy otherwise. Each sticker is its own stream so that the results don't depend on how the stickers are split between threads.
+    fn rng(&self, stream: usize) -> SmallRng {
+        match self.seed {
+            Some(seed) => SmallRng::seed_from_u64(seed.wrapping_add(stream as u64)),
+            None => SmallRng::from_rng(&mut rand::rng()),
+        }
     }
 
-    pub fn calibrate(&mut self, image: &[(f64, f64, f64)], state: &Permutation) {
-        let wb = self.white_balance(image);
-
-        for (sticker, pixels) in self.pixels_by_sticker.iter_mut().enumerate() {
-            let wb = *wb.get(&self.group.facelet_colors()[sticker]).unwrap();
-            let color = &self.group.facelet_colors()[state.state().get(sticker)];
+    fn white_balance<P: Channels<K>>(
+        &self,
+        picture: &[P],
+    ) -> HashMap<ArcIntern<str>, (f64, f64, f64)> {
+        let mut white_balances = HashMap::new();
+        self.white_balance_into(picture, None, &mut white_balances);
+        white_balances
+    }
 
-            for pixel in pixels {
-                let (r, g, b) = white_balance(image[pixel.idx], wb);
-                pixel.kdtrees.get_mut(color).unwrap().add(&[r, g, b], 0);
-            }
+    /// Find the white balance of each face from the pixels that were observed, or from every pixel if `observed` is `None`
+    fn white_balance_into<P: Channels<K>>(
+        &self,
+        picture: &[P],
+        observed: Option<&[bool]>,
+        white_balances: &mut HashMap<ArcIntern<str>, (f64, f64, f64)>,
+    ) {
+        white_balances.clear();
+
+        for (k, v) in &self.white_balance_by_face {
+            let pixels = v.iter().filter(|idx| is_observed(observed, **idx));
+            let white = pixels
+                .clone()
+                .map(|idx| decode(picture[*idx].color(), self.params.linearize_srgb))
+                .tree_reduce(|(r1, g1, b1), (r2, g2, b2)| (r1 + r2, g1 + g2, b1 + b2));
+
+            white_balances.insert(
+                ArcIntern::clone(k),
+                match white {
+                    Some((r, g, b)) => {
+                        let len = pixels.count() as f64;
+
+                        (r / len, g / len, b / len)
+                    }
+                    None => (1., 1., 1.),
+                },
+            );
         }
     }
-}
 
-// This quickselect code is copied from <https://gitlab.com/hrovnyak/nmr-schedule>
+    /// The sum of the decoded colors of the white balance pixels that were observed, of every face together, along with how many there were
+    fn neutral_sum<P: Channels<K>>(&self, picture: &[P], observed: Option<&[bool]>) -> ColorSum {
+        self.white_balance_by_face
+            .values()
+            .flatten()
+            .filter(|idx| is_observed(observed, **idx))
+            .map(|idx| decode(picture[*idx].color(), self.params.linearize_srgb))
+            .fold(
+                ((0., 0., 0.), 0_usize),
+                |((r, g, b), count), (pr, pg, pb)| ((r + pr, g + pg, b + pb), count + 1),
+            )
+    }
 
-fn partition<T, R: Rng + ?Sized>(
-    rng: &mut R,
-    slice: &mut [T],
-    by: &impl Fn(&T, &T) -> Ordering,
-) -> usize {
-    slice.swap(0, rng.random_range(0..slice.len()));
+    /// The average decoded color of the white balance pixels that were observed, or `None` if there are none
+    fn neutral<P: Channels<K>>(
+        &self,
+        picture: &[P],
+        observed: Option<&[bool]>,
+    ) -> Option<(f64, f64, f64)> {
+        average(self.neutral_sum(picture, observed))
+    }
 
-    let mut i = 1;
-    let mut j = slice.len() - 1;
+    /// How much each channel of the decoded colors of `picture` has to be multiplied by to undo its color cast, as `(r, g, b)`. The cast is how far the average color of the white balance pixels is from their average in the calibration pictures, channel by channel. They show the same neutral surfaces whatever state the puzzle is in, so the averages only differ when the light or the camera does. The stickers can't be used for this, since which colors the visible faces show depends on the state. No cast, `(1, 1, 1)`, is found if nothing is calibrated or no pixels are assigned to white balance.
+    ///
+    /// The picture is only corrected before it is classified if `InferenceParams::correct_color_cast` says so.
+    pub fn color_cast<P: Channels<K>>(&self, picture: &[P]) -> (f64, f64, f64) {
+        let (picture, _) = self.aggregate(picture, None);
+        self.color_cast_of(&picture, None)
+    }
 
-    loop {
-        while i < slice.len() && !matches!(by(&slice[i], &slice[0]), Ordering::Less) {
-            i += 1;
+    /// Like `color_cast`, but from the pixels that were observed of an already aggregated picture
+    fn color_cast_of<P: Channels<K>>(
+        &self,
+        picture: &[P],
+        observed: Option<&[bool]>,
+    ) -> (f64, f64, f64) {
+        match (average(self.neutral), self.neutral(picture, observed)) {
+            (Some(expected), Some(observed)) => cast_correction(expected, observed),
+            _ => (1., 1., 1.),
         }
+    }
 
-        while matches!(by(&slice[j], &slice[0]), Ordering::Less) {
-            j -= 1;
+    /// The aggregated picture with its color cast undone, if the parameters say to correct it
+    fn uncast<'a, P: Channels<K>>(
+        &self,
+        picture: Cow<'a, [P]>,
+        observed: Option<&[bool]>,
+    ) -> Cow<'a, [P]> {
+        if !self.params.correct_color_cast {
+            return picture;
         }
 
-        // If the indices crossed, return
-        if i > j {
-            slice.swap(0, j);
-            return j;
+        let gain = self.color_cast_of(&picture, observed);
+        if gain == (1., 1., 1.) {
+            return picture;
         }
 
-        // Swap the elements at the left and right indices
-        slice.swap(i, j);
-        i += 1;
+        let linearize_srgb = self.params.linearize_srgb;
+        Cow::Owned(
+            picture
+                .iter()
+                .map(|value| correct_cast(*value, gain, linearize_srgb))
+                .collect(),
+        )
+    }
+
+    /// The fraction of the pixels assigned to stickers whose color is plausibly one of the calibrated colors, which tells a picture of the puzzle apart from one of something else, like a black frame before the camera starts or the room behind the puzzle. A puzzle in any state, even one that can't be recognized, still shows the calibrated colors.
+    ///
+    /// A pixel is compared with the calibration samples of each color at that pixel, and with the samples of every pixel together for colors that weren't calibrated there, since a pixel that was only calibrated with the solved puzzle has only seen one color. Returns zero if no pixels are assigned to stickers.
+    pub fn plausible_fraction<P: Channels<K>>(&self, picture: &[P]) -> f64 {
+        let (picture, _) = self.aggregate(picture, None);
+        let picture = self.uncast(picture, None);
+        let white_balances = self.white_balance(&picture);
+
+        let mut overall = BTreeMap::<&ArcIntern<str>, Centroid<K>>::new();
+        for pixel in self.pixels_by_sticker.iter().flatten() {
+            for (color, centroid) in &pixel.centroids {
+                overall.entry(color).or_default().merge(centroid);
+            }
+        }
+
+        let mut pixels = 0_usize;
+        let mut plausible = 0_usize;
+        for (idx, v) in self.pixels_by_sticker.iter().enumerate() {
+            let wb = white_balances[&self.group.facelet_colors()[idx]];
+
+            for pixel in v {
+                let color = point(picture[pixel.idx], self.params.linearize_srgb, wb);
+
+                pixels += 1;
+                if pixel.centroids.iter().any(|(name, centroid)| {
+                    if centroid.count > 0 {
+                        centroid.is_plausible(color)
+                    } else {
+                        overall[name].is_plausible(color)
+                    }
+                }) {
+                    plausible += 1;
+                }
+            }
+        }
+
+        if pixels == 0 {
+            0.
+        } else {
+            plausible as f64 / pixels as f64
+        }
+    }
+
+    /// Estimate how much each sticker looks like each color. `picture` has one pixel per pixel of the assignment, like an `(r, g, b)` tuple, with each channel ranging from zero to one.
+    ///
+    /// The estimates are indexed by sticker, and map each of the puzzle's colors to a score where higher means more likely. What the score is depends on `InferenceParams::scoring`, and `puzzle_matching::Matcher` adds them up across stickers like log-likelihoods. Stickers that no pixel is assigned to can't be seen and have no estimates at all. Colors that a sticker hasn't been calibrated with score zero.
+    pub fn infer<P: Channels<K>>(&self, picture: &[P]) -> Box<[BTreeMap<ArcIntern<str>, f64>]> {
+        let mut scratch = InferenceScratch::default();
+        self.infer_with(picture, None, &mut scratch);
+
+        scratch.confidences.into()
+    }
+
+    /// Like `infer`, but only the pixels marked in `observed` are looked at, as if the others weren't assigned to anything. Stickers and white balance whose pixels are all unobserved are treated like ones that no pixel is assigned to, and so are stickers with less than `InferenceParams::min_observed_fraction` of their pixels observed.
+    pub fn infer_masked<P: Channels<K>>(
+        &self,
+        picture: &[P],
+        observed: &[bool],
+    ) -> Box<[BTreeMap<ArcIntern<str>, f64>]> {
+        let mut scratch = InferenceScratch::default();
+        self.infer_with(picture, Some(observed), &mut scratch);
+
+        scratch.confidences.into()
+    }
+
+    /// Like `infer`, but reuses the buffers in `scratch` instead of allocating new ones, so that processing a video frame by frame doesn't allocate once the buffers have grown to size. The estimates are left in `scratch` and returned.
+    pub fn infer_into<'a, P: Channels<K>>(
+        &self,
+        picture: &[P],
+        scratch: &'a mut InferenceScratch,
+    ) -> &'a [BTreeMap<ArcIntern<str>, f64>] {
+        self.infer_with(picture, None, scratch)
+    }
+
+    fn infer_with<'a, P: Channels<K>>(
+        &self,
+        picture: &[P],
+        observed: Option<&[bool]>,
+        scratch: &'a mut InferenceScratch,
+    ) -> &'a [BTreeMap<ArcIntern<str>, f64>] {
+        let (picture, observed) = self.aggregate(picture, observed);
+        let picture = self.uncast(picture, observed.as_deref());
+        let (picture, observed) = (&*picture, observed.as_deref());
+
+        self.white_balance_into(picture, observed, &mut scratch.white_balances);
+        scratch
+            .confidences
+            .resize_with(self.pixels_by_sticker.len(), BTreeMap::new);
+
+        let scoring = self.scoring();
+
+        // The browser has no threads to spread the stickers over
+        #[cfg(all(feature = "parallel", not(target_arch = "wasm32")))]
+        self.infer_stickers_parallel(scoring, picture, observed, scratch);
+        #[cfg(not(all(feature = "parallel", not(target_arch = "wasm32"))))]
+        self.infer_stickers(scoring, picture, observed, scratch);
+
+        &scratch.confidences
+    }
+
+    /// How the pixels are actually scored, which is `Density` in place of `Centroid` when the centroids can't tell the colors apart. The fallback is logged as a warning once for each time the params are set.
+    fn scoring(&self) -> Scoring {
+        match self.params.scoring {
+            Scoring::Centroid if !self.centroids_separate() => {
+                if !self.centroid_fallback_logged.swap(true, Ordering::Relaxed) {
+                    log::warn!(
+                        "Some colors are too close together to tell apart by their centroids, so pixels are scored by density instead"
+                    );
+                }
+                Scoring::Density
+            }
+            scoring => scoring,
+        }
+    }
+
+    /// Whether the centroids of every pair of colors are far enough apart at every pixel for `Scoring::Centroid` to tell the colors apart. If they aren't, `Scoring::Centroid` falls back to `Scoring::Density`.
+    pub fn centroids_separate(&self) -> bool {
+        self.pixels_by_sticker
+            .iter()
+            .flatten()
+            .all(|pixel| pixel.centroids_separate)
+    }
+
+    /// Infer every sticker one after another, reusing the buffers in `scratch`
+    #[cfg_attr(
+        all(feature = "parallel", not(target_arch = "wasm32")),
+        allow(dead_code)
+    )]
+    fn infer_stickers<P: Channels<K>>(
+        &self,
+        scoring: Scoring,
+        picture: &[P],
+        observed: Option<&[bool]>,
+        scratch: &mut InferenceScratch,
+    ) {
+        let InferenceScratch {
+            white_balances,
+            sticker,
+            confidences,
+        } = scratch;
+        sticker.reset(&self.colors);
+
+        for (idx, confidences) in confidences.iter_mut().enumerate() {
+            self.infer_sticker(
+                &mut self.rng(idx),
+                scoring,
+                idx,
+                picture,
+                observed,
+                white_balances,
+                sticker,
+                confidences,
+            );
+        }
+    }
+
+    /// Infer the stickers on every core at once. Stickers don't depend on each other, and querying the kd-trees of their pixels is most of the work of a frame.
+    #[cfg(all(feature = "parallel", not(target_arch = "wasm32")))]
+    fn infer_stickers_parallel<P: Channels<K>>(
+        &self,
+        scoring: Scoring,
+        picture: &[P],
+        observed: Option<&[bool]>,
+        scratch: &mut InferenceScratch,
+    ) {
+        use rayon::prelude::*;
+
+        let InferenceScratch {
+            white_balances,
+            confidences,
+            ..
+        } = scratch;
+
+        confidences.par_iter_mut().enumerate().for_each_init(
+            || {
+                let mut sticker = StickerScratch::default();
+                sticker.reset(&self.colors);
+                sticker
+            },
+            |sticker, (idx, confidences)| {
+                self.infer_sticker(
+                    &mut self.rng(idx),
+                    scoring,
+                    idx,
+                    picture,
+                    observed,
+                    white_balances,
+                    sticker,
+                    confidences,
+                );
+            },
+        );
+    }
+
+    /// Estimate how much the sticker at `idx` looks like each color into `confidences`, leaving them empty if too few of its pixels were observed. `scratch` must have an empty list of scores for every color, and is left that way.
+    #[allow(clippy::too_many_arguments)]
+    fn infer_sticker<R: Rng + ?Sized, P: Channels<K>>(
+        &self,
+        rng: &mut R,
+        scoring: Scoring,
+        idx: usize,
+        picture: &[P],
+        observed: Option<&[bool]>,
+        white_balances: &HashMap<ArcIntern<str>, (f64, f64, f64)>,
+        scratch: &mut StickerScratch,
+        confidences: &mut BTreeMap<ArcIntern<str>, f64>,
+    ) {
+        let StickerScratch { scores, neighbours } = scratch;
+        let v = &self.pixels_by_sticker[idx];
+        confidences.clear();
+
+        let seen = v
+            .iter()
+            .filter(|pixel| is_observed(observed, pixel.idx))
+            .count();
+        if seen == 0 || (seen as f64) < self.params.min_observed_fraction * v.len() as f64 {
+            return;
+        }
+
+        let wb = *white_balances
+            .get(&self.group.facelet_colors()[idx])
+            .unwrap();
+
+        // Maybe pick random subset
+        // Every pixel has its own kd-trees, so pixels with identical colors still ask different questions and there's nothing to memoize across them; only the white balancing is shared between a pixel's colors
+        for pixel in v.iter().filter(|pixel| is_observed(observed, pixel.idx)) {
+            self.push_scores(
+                rng,
+                scoring,
+                pixel,
+                picture[pixel.idx],
+                wb,
+                neighbours,
+                scores,
+            );
+        }
+
+        for (k, v) in scores.iter_mut() {
+            let confidence = if v.is_empty() {
+                0.
+            } else {
+                weighted_percentile(rng, v, self.params.confidence_percentile)
+            };
+            v.clear();
+
+            confidences.insert(ArcIntern::clone(k), confidence);
+        }
+    }
+
+    /// Score how much the pixel, whose value in the picture is `value`, looks like each color, pushing the scores onto `scores`
+    #[allow(clippy::too_many_arguments)]
+    fn push_scores<R: Rng + ?Sized, P: Channels<K>>(
+        &self,
+        rng: &mut R,
+        scoring: Scoring,
+        pixel: &Pixel<K>,
+        value: P,
+        wb: (f64, f64, f64),
+        neighbours: &mut Vec<(f64, ArcIntern<str>)>,
+        scores: &mut BTreeMap<ArcIntern<str>, Vec<(f64, f64)>>,
+    ) {
+        let color = point(value, self.params.linearize_srgb, wb);
+
+        if self.params.reject_distance.is_finite()
+            && !pixel.has_sample_within(color, self.params.reject_distance)
+        {
+            return;
+        }
+
+        match scoring {
+            Scoring::Density => {
+                self.push_densities(pixel, color, scores);
+            }
+            Scoring::Vote => {
+                self.push_votes(rng, pixel, color, neighbours, scores);
+            }
+            Scoring::Centroid => {
+                push_centroid_densities(pixel, color, scores);
+            }
+        }
+    }
+
+    /// Score the pixel by estimating the density of each color's calibration samples around it separately
+    fn push_densities(
+        &self,
+        pixel: &Pixel<K>,
+        value: [f64; K],
+        scores: &mut BTreeMap<ArcIntern<str>, Vec<(f64, f64)>>,
+    ) {
+        for (color, kdtree) in &pixel.kdtrees {
+            let n = self
+                .params
+                .max_nearest_n
+                .min(kdtree.size() as usize / self.params.max_fraction)
+                .max(1);
+            let nn = kdtree.nearest_n::<SquaredEuclidean>(&value, n);
+
+            // https://faculty.washington.edu/yenchic/18W_425/Lec7_knn_basis.pdf
+            // TODO: Try to account for non uniform distributions?
+            const UNIT_SPHERE: f64 = 4. / 3. * core::f64::consts::PI;
+
+            if let Some(last) = nn.last() {
+                let density = n as f64 / kdtree.size() as f64
+                    * (last.distance.powi(K as i32) * UNIT_SPHERE).recip();
+
+                scores.get_mut(color).unwrap().push((density, pixel.weight));
+            }
+        }
+    }
+
+    /// Score the pixel by the fraction of its nearest calibration samples, across every color, that each color has
+    fn push_votes<R: Rng + ?Sized>(
+        &self,
+        rng: &mut R,
+        pixel: &Pixel<K>,
+        color: [f64; K],
+        neighbours: &mut Vec<(f64, ArcIntern<str>)>,
+        scores: &mut BTreeMap<ArcIntern<str>, Vec<(f64, f64)>>,
+    ) {
+        let size = pixel
+            .kdtrees
+            .values()
+            .map(|kdtree| kdtree.size() as usize)
+            .sum::<usize>();
+        let n = self
+            .params
+            .max_nearest_n
+            .min(size / self.params.max_fraction)
+            .max(1);
+
+        // The nearest `n` of all colors are among the nearest `n` of each color
+        neighbours.clear();
+        neighbours.extend(pixel.kdtrees.iter().flat_map(|(color_name, kdtree)| {
+            kdtree
+                .nearest_n::<SquaredEuclidean>(&color, n)
+                .into_iter()
+                .map(move |nn| (nn.distance, ArcIntern::clone(color_name)))
+        }));
+        // `top_k` picks the greatest, so the distances are compared backwards
+        let nearest = top_k(rng, neighbours, |a, b| b.0.total_cmp(&a.0), n);
+
+        if nearest.is_empty() {
+            return;
+        }
+
+        for color_name in pixel.kdtrees.keys() {
+            let votes = nearest.iter().filter(|(_, v)| v == color_name).count();
+            let probability = votes as f64 / nearest.len() as f64;
+
+            scores
+                .get_mut(color_name)
+                .unwrap()
+                .push((probability, pixel.weight));
+        }
+    }
+
+    /// Configure how the pixels are scored
+    pub fn set_params(&mut self, params: InferenceParams) {
+        self.params = params;
+        // Asking for centroid scoring again is worth another warning if it still can't be used
+        *self.centroid_fallback_logged.get_mut() = false;
+    }
+
+    /// How the pixels are scored
+    pub fn params(&self) -> &InferenceParams {
+        &self.params
+    }
+
+    /// Seed the random number generators that the inference uses, so that the same calibration and picture always give exactly the same confidences. `None`, the default, seeds them from entropy every time. The randomness only picks the pivots for selecting percentiles and the order that kd-trees are built in, so it doesn't change much either way.
+    pub fn set_seed(&mut self, seed: Option<u64>) {
+        self.seed = seed;
+    }
+
+    /// Set how much each pixel of the image counts towards the confidences of its sticker, one weight per pixel of the assignment. Every pixel starts out with a weight of one. This method panics if there are fewer weights than pixels of the assignment.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a weight is negative or NaN, or if every pixel of a sticker has a weight of zero. The weights are left as they were in that case.
+    pub fn set_weights(&mut self, weights: &[f64]) -> Result<(), InvalidWeights> {
+        if let Some(pixel) = weights.iter().position(|weight| !(0.0..).contains(weight)) {
+            return Err(InvalidWeights::Negative(pixel));
+        }
+        if let Some(sticker) = self.pixels_by_sticker.iter().position(|pixels| {
+            !pixels.is_empty() && pixels.iter().all(|pixel| weights[pixel.idx] == 0.)
+        }) {
+            return Err(InvalidWeights::ZeroSticker(sticker));
+        }
+
+        for pixel in self.pixels_by_sticker.iter_mut().flatten() {
+            pixel.weight = weights[pixel.idx];
+        }
+
+        Ok(())
+    }
+
+    /// Calibrate with a picture of the puzzle in the given state, which teaches every pixel what the color that the state puts on its sticker looks like there
+    pub fn calibrate<P: Channels<K>>(&mut self, image: &[P], state: &Permutation) {
+        self.calibrate_batch(&[(image, state)]);
+    }
+
+    /// Calibrate with many images at once. Each pixel's samples are the same as calibrating with the images one at a time would give it, so the results are the same, but the pixels are only gone through once, and kd-trees that were still empty are built from all of their samples with `shuffled_kdtree`.
+    pub fn calibrate_batch<P: Channels<K>>(&mut self, images: &[(&[P], &Permutation)]) {
+        let aggregated = images
+            .iter()
+            .map(|(image, state)| (self.aggregate(image, None).0, *state))
+            .collect_vec();
+        let images = aggregated
+            .iter()
+            .map(|(image, state)| (&**image, *state))
+            .collect_vec();
+
+        let white_balances = images
+            .iter()
+            .map(|(image, _)| self.white_balance(image))
+            .collect_vec();
+
+        for (image, _) in &images {
+            let sum = self.neutral_sum(image, None);
+            add_sum(&mut self.neutral, sum);
+        }
+
+        let mut samples = self
+            .colors
+            .iter()
+            .cloned()
+            .map(|v| (v, Vec::<[f64; K]>::new()))
+            .collect::<HashMap<_, _>>();
+
+        let colors = images
+            .iter()
+            .map(|(_, state)| colors_in(&self.group, state))
+            .collect_vec();
+
+        let linearize_srgb = self.params.linearize_srgb;
+        // Calibration gets the stream after every sticker's
+        let mut rng = self.rng(self.pixels_by_sticker.len());
+
+        for (sticker, pixels) in self.pixels_by_sticker.iter_mut().enumerate() {
+            let face = &self.group.facelet_colors()[sticker];
+
+            for pixel in pixels {
+                for ((image, _), (wb, colors)) in
+                    images.iter().zip(white_balances.iter().zip(&colors))
+                {
+                    let color = &colors[sticker];
+                    let ((sum_r, sum_g, sum_b), count) = self.appearances.get_mut(color).unwrap();
+
+                    let value = image[pixel.idx];
+                    let (r, g, b) = decode(value.color(), linearize_srgb);
+                    *sum_r += r;
+                    *sum_g += g;
+                    *sum_b += b;
+                    *count += 1;
+
+                    samples
+                        .get_mut(color)
+                        .unwrap()
+                        .push(point(value, linearize_srgb, wb[face]));
+                }
+
+                for (color, samples) in &mut samples {
+                    pixel.add_samples(&mut rng, color, samples);
+                    samples.clear();
+                }
+            }
+        }
+    }
+
+    /// Calibrate with only some facelets of an image, each labeled with the color that it shows, for when the state of the rest of the puzzle isn't known. Every pixel of a labeled facelet gets one sample of its color, just like calibrating with a whole state would give it. This is handy for correcting a single color that calibrated badly.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a facelet index is out of range or a color isn't one of the puzzle's. Nothing is calibrated in that case.
+    pub fn calibrate_facelets<P: Channels<K>>(
+        &mut self,
+        image: &[P],
+        labels: &[(usize, ArcIntern<str>)],
+    ) -> Result<(), InvalidFaceletLabel> {
+        for (facelet, color) in labels {
+            if *facelet >= self.group.facelet_count() {
+                return Err(InvalidFaceletLabel::Index(*facelet));
+            }
+
+            if !self.colors.contains(color) {
+                return Err(InvalidFaceletLabel::Color(ArcIntern::clone(color)));
+            }
+        }
+
+        let (image, _) = self.aggregate(image, None);
+        let wb = self.white_balance(&image);
+        let neutral = self.neutral_sum(&image, None);
+        add_sum(&mut self.neutral, neutral);
+        let linearize_srgb = self.params.linearize_srgb;
+        let mut rng = self.rng(self.pixels_by_sticker.len());
+
+        for (facelet, color) in labels {
+            let face = &self.group.facelet_colors()[*facelet];
+            let ((sum_r, sum_g, sum_b), count) = self.appearances.get_mut(color).unwrap();
+
+            for pixel in &mut self.pixels_by_sticker[*facelet] {
+                let value = image[pixel.idx];
+                let (r, g, b) = decode(value.color(), linearize_srgb);
+                *sum_r += r;
+                *sum_g += g;
+                *sum_b += b;
+                *count += 1;
+
+                pixel.add_samples(
+                    &mut rng,
+                    color,
+                    &mut [point(value, linearize_srgb, wb[face])],
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Move `sticker` to the pixels that `assignment` gives it and forget everything that it was calibrated with, for when a single sticker keeps being misclassified because of where its pixels were picked, like on a scratch or a reflection. `assignment` is the whole assignment again with only that sticker's pixels changed. Pixels that it gives the sticker stop counting for whatever they were assigned to before, and the calibration of every other sticker is kept, so only this sticker has to be calibrated again, for example with `calibrate_facelets`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the puzzle doesn't have the sticker. Nothing is reassigned in that case.
+    pub fn reassign_sticker(
+        &mut self,
+        sticker: usize,
+        assignment: &[super::Pixel],
+    ) -> Result<(), InvalidFaceletLabel> {
+        if sticker >= self.pixels_by_sticker.len() {
+            return Err(InvalidFaceletLabel::Index(sticker));
+        }
+
+        let is_sticker = |idx: usize, sticker: usize| matches!(assignment.get(idx), Some(super::Pixel::Sticker(v)) if *v == sticker);
+
+        for (other, pixels) in self.pixels_by_sticker.iter_mut().enumerate() {
+            let mut kept = std::mem::take(pixels).into_vec();
+            if other == sticker {
+                kept.clear();
+            } else {
+                kept.retain(|pixel| is_sticker(pixel.idx, other));
+            }
+            *pixels = kept.into();
+        }
+
+        self.pixels_by_sticker[sticker] = assignment
+            .iter()
+            .enumerate()
+            .filter(|(idx, _)| is_sticker(*idx, sticker))
+            .map(|(idx, _)| Pixel::uncalibrated(idx, &self.colors))
+            .collect();
+
+        for (face, pixels) in &mut self.white_balance_by_face {
+            let mut kept = std::mem::take(pixels).into_vec();
+            kept.retain(|idx| {
+                matches!(assignment.get(*idx), Some(super::Pixel::WhiteBalance(v)) if v == face)
+            });
+            *pixels = kept.into();
+        }
+
+        Ok(())
+    }
+
+    /// Add the calibration of `other` to this one, as if this had also been calibrated with every image that `other` was. The weights and parameters of this `Inference` are kept. Colors that only one of them has calibration samples for end up with those samples.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `other` is for a different puzzle or assigns the pixels differently, since its samples wouldn't mean the same thing. Nothing is merged in that case.
+    pub fn merge(&mut self, other: &Inference<K>) -> Result<(), IncompatibleCalibration> {
+        if self.group.facelet_colors() != other.group.facelet_colors() {
+            return Err(IncompatibleCalibration::Puzzle);
+        }
+
+        if self.params.linearize_srgb != other.params.linearize_srgb {
+            return Err(IncompatibleCalibration::Linearization);
+        }
+
+        if self.sticker_layout() != other.sticker_layout()
+            || self.white_balance_by_face != other.white_balance_by_face
+            || self.super_pixels != other.super_pixels
+        {
+            return Err(IncompatibleCalibration::Assignment);
+        }
+
+        let mut rng = self.rng(self.pixels_by_sticker.len());
+        let pixels = self.pixels_by_sticker.iter_mut().flatten();
+        let other_pixels = other.pixels_by_sticker.iter().flatten();
+        for (pixel, other_pixel) in pixels.zip(other_pixels) {
+            for (color, samples) in other_pixel.samples() {
+                pixel.add_samples(&mut rng, color, &mut samples.collect_vec());
+            }
+        }
+
+        for (color, ((r, g, b), count)) in &other.appearances {
+            let ((sum_r, sum_g, sum_b), sum_count) = self.appearances.get_mut(color).unwrap();
+            *sum_r += r;
+            *sum_g += g;
+            *sum_b += b;
+            *sum_count += count;
+        }
+        add_sum(&mut self.neutral, other.neutral);
+
+        Ok(())
+    }
+
+    /// Write the calibration as JSON that `load_calibration` reads back. Along with the samples of each color at each pixel and the summed appearances, it records the puzzle's colors, which pixels are assigned to each sticker and to the white balance of each face, the super-pixels, and whether the pixels were linearized, so that it is only loaded where its samples mean the same thing.
+    ///
+    /// The numbers are written with as many digits as it takes to read back the exact same values, so a loaded calibration infers exactly what this one does. Samples that aren't finite, like those of a face whose white balance pixels were black, can't be written as JSON and are left out.
+    #[cfg(feature = "serde")]
+    pub fn calibration_to_text(&self) -> String {
+        let samples = self
+            .pixels_by_sticker
+            .iter()
+            .enumerate()
+            .flat_map(|(sticker, pixels)| {
+                pixels.iter().flat_map(move |pixel| {
+                    pixel.samples().filter_map(move |(color, samples)| {
+                        let values = samples
+                            .filter(|sample| sample.iter().all(|v| v.is_finite()))
+                            .flatten()
+                            .collect_vec();
+
+                        (!values.is_empty()).then(|| SavedSamples {
+                            sticker,
+                            pixel: pixel.idx,
+                            color: color.to_string(),
+                            values,
+                        })
+                    })
+                })
+            })
+            .collect();
+
+        let saved = SavedCalibration {
+            format: CALIBRATION_FORMAT.to_owned(),
+            colors: self.colors.iter().map(|color| color.to_string()).collect(),
+            linearize_srgb: self.params.linearize_srgb,
+            stickers: self.sticker_layout(),
+            white_balance: self.white_balance_layout(),
+            super_pixels: self.super_pixels.clone(),
+            appearances: self
+                .appearances
+                .iter()
+                .map(|(color, appearance)| (color.to_string(), *appearance))
+                .collect(),
+            neutral: self.neutral,
+            samples,
+        };
+
+        serde_json::to_string(&saved).unwrap()
+    }
+
+    /// Replace the calibration with one written by `calibration_to_text`. The weights and parameters of this `Inference` are kept, and the kd-trees are rebuilt from the samples.
+    ///
+    /// # Errors
+    ///
+    /// Returns a description of what is wrong with the text if it isn't a calibration of this puzzle with this assignment, or its pixels were linearized and these aren't or the other way around. The calibration is left as it was in that case.
+    #[cfg(feature = "serde")]
+    pub fn load_calibration(&mut self, text: &str) -> Result<(), String> {
+        let saved: SavedCalibration =
+            serde_json::from_str(text).map_err(|err| format!("Not a calibration: {err}"))?;
+
+        if saved.format != CALIBRATION_FORMAT {
+            return Err(format!("Unknown calibration format `{}`", saved.format));
+        }
+        if !saved
+            .colors
+            .iter()
+            .map(String::as_str)
+            .eq(self.colors.iter().map(|color| &**color))
+        {
+            return Err(IncompatibleCalibration::Puzzle.to_string());
+        }
+        if saved.linearize_srgb != self.params.linearize_srgb {
+            return Err(IncompatibleCalibration::Linearization.to_string());
+        }
+        if saved.stickers != self.sticker_layout()
+            || saved.white_balance != self.white_balance_layout()
+            || saved.super_pixels != self.super_pixels
+        {
+            return Err(IncompatibleCalibration::Assignment.to_string());
+        }
+
+        let find_color = |name: &str| {
+            self.colors
+                .iter()
+                .find(|color| ***color == *name)
+                .cloned()
+                .ok_or_else(|| format!("The puzzle has no {name} face"))
+        };
+
+        let mut appearances = self
+            .colors
+            .iter()
+            .cloned()
+            .map(|v| (v, ((0., 0., 0.), 0)))
+            .collect::<HashMap<_, _>>();
+        for (color, appearance) in saved.appearances {
+            appearances.insert(find_color(&color)?, appearance);
+        }
+
+        // The position within its sticker of each pixel that samples are for, along with its color and samples
+        let mut samples = Vec::new();
+        for SavedSamples {
+            sticker,
+            pixel: idx,
+            color,
+            values,
+        } in saved.samples
+        {
+            let position = self
+                .pixels_by_sticker
+                .get(sticker)
+                .and_then(|pixels| pixels.iter().position(|pixel| pixel.idx == idx))
+                .ok_or_else(|| format!("Pixel {idx} isn't assigned to sticker {sticker}"))?;
+            let color = find_color(&color)?;
+
+            let (pixel_samples, []) = values.as_chunks::<K>() else {
+                return Err(format!("Pixel {idx} has an incomplete sample"));
+            };
+
+            samples.push((sticker, position, color, pixel_samples.to_vec()));
+        }
+
+        for pixel in self.pixels_by_sticker.iter_mut().flatten() {
+            for kdtree in pixel.kdtrees.values_mut() {
+                *kdtree = KdTree::new();
+            }
+            for centroid in pixel.centroids.values_mut() {
+                *centroid = Centroid::default();
+            }
+            pixel.centroids_separate = true;
+        }
+
+        let mut rng = self.rng(self.pixels_by_sticker.len());
+        for (sticker, position, color, mut samples) in samples {
+            self.pixels_by_sticker[sticker][position].add_samples(&mut rng, &color, &mut samples);
+        }
+        self.appearances = appearances;
+        self.neutral = saved.neutral;
+
+        Ok(())
+    }
+
+    /// The pixels assigned to each sticker
+    fn sticker_layout(&self) -> Box<[Box<[usize]>]> {
+        self.pixels_by_sticker
+            .iter()
+            .map(|pixels| pixels.iter().map(|pixel| pixel.idx).collect())
+            .collect()
+    }
+
+    /// The pixels assigned to the white balance of each face, by the name of its color
+    #[cfg(feature = "serde")]
+    fn white_balance_layout(&self) -> BTreeMap<String, Box<[usize]>> {
+        self.white_balance_by_face
+            .iter()
+            .map(|(face, pixels)| (face.to_string(), pixels.clone()))
+            .collect()
+    }
+
+    /// Whether anything has been calibrated with yet. Until then there are no samples to compare pixels with and every color is as likely as every other.
+    pub fn is_calibrated(&self) -> bool {
+        self.appearances.values().any(|(_, count)| *count > 0)
+    }
+
+    /// Every calibration sample of each color, across every pixel, for plotting where the colors lie and seeing which of them overlap. The samples are white balanced, and linearized if the parameters say so, which makes them exactly what pixels are compared against. The samples of a color are in no particular order, and colors without samples map to an empty list.
+    pub fn color_points(&self) -> HashMap<ArcIntern<str>, Vec<[f64; K]>> {
+        let mut points = self
+            .colors
+            .iter()
+            .map(|color| (ArcIntern::clone(color), Vec::new()))
+            .collect::<HashMap<_, _>>();
+
+        for pixel in self.pixels_by_sticker.iter().flatten() {
+            for (color, samples) in pixel.samples() {
+                points.get_mut(color).unwrap().extend(samples);
+            }
+        }
+
+        points
+    }
+
+    /// How many calibration samples each sticker has of each color, keyed by sticker and color, which is how many times it has been calibrated showing that color. A sticker's count is the fewest that any of its pixels has, so pixels that `reassign_sticker` gave it and that still have to catch up are counted as well. Every sticker that has pixels has an entry for every color, even when it is zero; stickers without pixels have none.
+    pub fn calibration_coverage(&self) -> HashMap<(usize, ArcIntern<str>), usize> {
+        self.pixels_by_sticker
+            .iter()
+            .enumerate()
+            .filter(|(_, pixels)| !pixels.is_empty())
+            .flat_map(|(sticker, pixels)| {
+                self.colors.iter().map(move |color| {
+                    let count = pixels
+                        .iter()
+                        .map(|pixel| pixel.kdtrees[color].size() as usize)
+                        .min()
+                        .unwrap();
+
+                    ((sticker, ArcIntern::clone(color)), count)
+                })
+            })
+            .collect()
+    }
+
+    /// Find the colors and stickers whose calibration samples in `calibration_coverage`, added up over every sticker or color, are fewer than half the median of every color or sticker. Those are the conditions that the calibration pictures didn't show enough of, like a color that was always on the face turned away from the light. Nothing is under-sampled before anything is calibrated, since there is no median to fall short of.
+    pub fn under_sampled(&self) -> UnderSampled {
+        let coverage = self.calibration_coverage();
+
+        let by_color = self
+            .colors
+            .iter()
+            .map(|color| {
+                let count = coverage
+                    .iter()
+                    .filter(|((_, v), _)| v == color)
+                    .map(|(_, count)| count)
+                    .sum::<usize>();
+                (ArcIntern::clone(color), count)
+            })
+            .collect_vec();
+
+        let by_sticker = (0..self.pixels_by_sticker.len())
+            .filter(|sticker| !self.pixels_by_sticker[*sticker].is_empty())
+            .map(|sticker| {
+                let count = coverage
+                    .iter()
+                    .filter(|((v, _), _)| *v == sticker)
+                    .map(|(_, count)| count)
+                    .sum::<usize>();
+                (sticker, count)
+            })
+            .collect_vec();
+
+        UnderSampled {
+            colors: below_median(by_color),
+            stickers: below_median(by_sticker),
+        }
+    }
+
+    /// The average appearance of the given color in the calibration images, or black if it was never calibrated
+    fn appearance(&self, color: &ArcIntern<str>) -> (f64, f64, f64) {
+        match self.appearances.get(color) {
+            Some(&((r, g, b), count)) if count > 0 => {
+                let count = count as f64;
+                let (r, g, b) = (r / count, g / count, b / count);
+
+                // The average was taken in linear light, so it has to be encoded again to be shown
+                if self.params.linearize_srgb {
+                    (linear_to_srgb(r), linear_to_srgb(g), linear_to_srgb(b))
+                } else {
+                    (r, g, b)
+                }
+            }
+            _ => (0., 0., 0.),
+        }
+    }
+
+    /// Paint every pixel assigned to a sticker with the average calibrated appearance of the color that the sticker most likely has. Pixels not assigned to a sticker are black. With super-pixels, every pixel of a super-pixel gets the super-pixel's color.
+    pub fn render<P: Channels<K>>(&self, picture: &[P]) -> Box<[(f64, f64, f64)]> {
+        let assigned = self
+            .super_pixels
+            .as_ref()
+            .map_or(picture.len(), SuperPixels::len);
+        let mut rendered = vec![(0., 0., 0.); assigned].into_boxed_slice();
+
+        for (pixels, confidences) in self.pixels_by_sticker.iter().zip(self.infer(picture)) {
+            let Some((color, _)) = confidences.iter().max_by(|(_, a), (_, b)| cmp_ll(**a, **b))
+            else {
+                continue;
+            };
+
+            let appearance = self.appearance(color);
+
+            for pixel in pixels {
+                rendered[pixel.idx] = appearance;
+            }
+        }
+
+        self.expand(rendered, picture.len())
+    }
+
+    /// How confidently each pixel picks the color that its sticker most likely has over the other colors, as the fraction of the pixel's scores across every color that goes to that color. The map has one value per pixel of the picture, in the same order, and ranges from zero to one: one means that the pixel looks like nothing but the sticker's color, and values near one over the number of colors mean that the pixel can't tell the colors apart. Pixels not assigned to a sticker, white balance, and pixels of stickers that can't be seen are zero. With super-pixels, every pixel of a super-pixel gets the super-pixel's value.
+    ///
+    /// Showing this as a heatmap over the picture points out the pixels that hold the calibration back, like ones on a sticker's edge or in a reflection, which the per-sticker scores of `infer` average away.
+    pub fn confidence_map<P: Channels<K>>(&self, picture: &[P]) -> Box<[f64]> {
+        let observation = self.infer(picture);
+
+        let (aggregated, _) = self.aggregate(picture, None);
+        let aggregated = self.uncast(aggregated, None);
+        let white_balances = self.white_balance(&aggregated);
+        let scoring = self.scoring();
+
+        let mut map = vec![0.; aggregated.len()].into_boxed_slice();
+        let mut scratch = StickerScratch::default();
+
+        for (idx, (pixels, confidences)) in
+            self.pixels_by_sticker.iter().zip(&observation).enumerate()
+        {
+            let Some((top, _)) = confidences.iter().max_by(|(_, a), (_, b)| cmp_ll(**a, **b))
+            else {
+                continue;
+            };
+
+            let wb = white_balances[&self.group.facelet_colors()[idx]];
+            let mut rng = self.rng(idx);
+
+            for pixel in pixels {
+                scratch.reset(&self.colors);
+                let StickerScratch { scores, neighbours } = &mut scratch;
+                self.push_scores(
+                    &mut rng,
+                    scoring,
+                    pixel,
+                    aggregated[pixel.idx],
+                    wb,
+                    neighbours,
+                    scores,
+                );
+
+                let score = |color: &ArcIntern<str>| scores[color].first().map_or(0., |(v, _)| *v);
+                let total = self.colors.iter().map(score).sum::<f64>();
+
+                map[pixel.idx] = if total.is_infinite() {
+                    // A pixel that exactly matches a calibration sample has an infinite density for its color
+                    if score(top).is_infinite() { 1. } else { 0. }
+                } else if total > 0. {
+                    score(top) / total
+                } else {
+                    0.
+                };
+            }
+        }
+
+        self.expand(map, picture.len())
+    }
+
+    /// Spread values for each entry of the assignment over the `len` pixels of the picture, which only does something with super-pixels
+    fn expand<T: Copy>(&self, values: Box<[T]>, len: usize) -> Box<[T]> {
+        match &self.super_pixels {
+            Some(super_pixels) => (0..len)
+                .map(|idx| values[super_pixels.label(idx)])
+                .collect(),
+            None => values,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{BTreeMap, HashMap},
+        sync::{Arc, LazyLock},
+    };
+
+    use internment::ArcIntern;
+    use itertools::Itertools;
+    use kiddo::{KdTree, SquaredEuclidean};
+    use puzzle_theory::{
+        permutations::{Algorithm, Permutation, PermutationGroup, schreier_sims::StabilizerChain},
+        puzzle_geometry::parsing::puzzle,
+    };
+    use rand::{Rng, SeedableRng};
+
+    use crate::{
+        inference::{
+            IncompatibleCalibration, Inference, InferenceParams, InferenceScratch,
+            InvalidFaceletLabel, InvalidWeights, Scoring, UnderSampled, linear_to_srgb,
+            shuffled_kdtree, srgb_to_linear,
+        },
+        puzzle_matching::Matcher,
+    };
+
+    static NATURAL_COLORS: LazyLock<HashMap<ArcIntern<str>, (f64, f64, f64)>> =
+        LazyLock::new(|| {
+            let mut map = HashMap::new();
+
+            map.insert(ArcIntern::from("red"), (1., 0.2, 0.2));
+            map.insert(ArcIntern::from("orange"), (1., 0.6, 0.2));
+            map.insert(ArcIntern::from("white"), (1., 1., 1.));
+            map.insert(ArcIntern::from("yellow"), (0.8, 0.8, 0.2));
+            map.insert(ArcIntern::from("blue"), (0.2, 0.5, 1.));
+            map.insert(ArcIntern::from("green"), (0.3, 1., 0.5));
+
+            map
+        });
+
+    /// Assign 20 pixels to each sticker of a 3x3 followed by 20 pixels of white balance for each face, which is the layout that `simulate_picture` renders
+    fn standard_assignment() -> Vec<crate::Pixel> {
+        let mut assignment = Vec::new();
+
+        for i in 0..48 {
+            for _ in 0..20 {
+                assignment.push(crate::Pixel::Sticker(i));
+            }
+        }
+
+        for color in ["white", "orange", "green", "red", "blue", "yellow"].map(ArcIntern::from) {
+            for _ in 0..20 {
+                assignment.push(crate::Pixel::WhiteBalance(ArcIntern::clone(&color)))
+            }
+        }
+
+        assignment
+    }
+
+    /// The calibration samples of each color in `Inference::color_points`, sorted so that calibrations with the same samples compare equal however their kd-trees were built
+    #[cfg(feature = "serde")]
+    fn sorted_points<const K: usize>(
+        inference: &Inference<K>,
+    ) -> HashMap<ArcIntern<str>, Vec<[f64; K]>> {
+        let mut points = inference.color_points();
+        for samples in points.values_mut() {
+            samples.sort_by(|a, b| {
+                a.iter()
+                    .zip(b)
+                    .map(|(a, b)| a.total_cmp(b))
+                    .find(|ordering| ordering.is_ne())
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            });
+        }
+        points
+    }
+
+    fn simulate_picture<R: Rng + ?Sized>(
+        perm: &Permutation,
+        group: &PermutationGroup,
+        shadowful_noise: f64,
+        colorful_noise: f64,
+        rng: &mut R,
+        out: &mut [(f64, f64, f64)],
+    ) {
+        assert_eq!(out.len(), (48 + 6) * 20);
+
+        for face in 0..6 {
+            // Simulate random lighting on this face to test our white balance code
+            let white = (
+                rng.random_range(0.2..1.2),
+                rng.random_range(0.2..1.2),
+                rng.random_range(0.2..1.2),
+            );
+
+            for spot in 0..8 {
+                let idx = spot + face * 8;
+
+                let is = perm.state().get(idx);
+                let mut color = *NATURAL_COLORS.get(&group.facelet_colors()[is]).unwrap();
+                color.0 *= white.0;
+                color.1 *= white.1;
+                color.2 *= white.2;
+
+                out[idx * 20..(idx + 1) * 20].fill(color);
+            }
+
+            out[(48 + face) * 20..(48 + face + 1) * 20].fill(white);
+        }
+
+        for (r, g, b) in out.iter_mut() {
+            // Add random noise to simulate differences in shadowing
+            let noise = rng.random_range(((1. + shadowful_noise).recip())..(1. + shadowful_noise));
+            *r *= noise;
+            *g *= noise;
+            *b *= noise;
+        }
+
+        for v in out.iter_mut().flat_map(|(r, g, b)| [r, g, b]) {
+            // Add some random noise to simulate camera noise
+            *v *= rng.random_range(((1. + colorful_noise).recip())..(1. + colorful_noise));
+            // Clamp everything to be less than one to simulate overexposure
+            *v = v.min(1.);
+        }
+    }
+
+    #[test]
+    fn test_inference() {
+        let assignment = standard_assignment();
+
+        let puzzle = puzzle("3x3");
+        let group = puzzle.permutation_group();
+        let stabchain = StabilizerChain::new(&group);
+
+        let mut inference = Inference::new(assignment.into(), &puzzle);
+
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"Buying black on the black market");
+
+        let mut img = [(0., 0., 0.); (48 + 6) * 20];
+
+        for _ in 0..30 {
+            let perm = stabchain.random(&mut rng);
+            simulate_picture(&perm, &group, 0.2, 0.1, &mut rng, &mut img);
+            inference.calibrate(&img, &perm);
+        }
+
+        let matcher = Matcher::new(Arc::clone(&puzzle));
+
+        for _ in 0..100 {
+            let perm = stabchain.random(&mut rng);
+            simulate_picture(&perm, &group, 0.2, 0.1, &mut rng, &mut img);
+            assert_eq!(matcher.most_likely(&inference.infer(&img)).unwrap().0, perm);
+        }
+    }
+
+    #[test]
+    fn confidence_map() {
+        let mut assignment = standard_assignment();
+
+        // Off to the side of the puzzle
+        assignment.push(crate::Pixel::Unassigned);
+
+        let puzzle = puzzle("3x3");
+        let group = puzzle.permutation_group();
+        let stabchain = StabilizerChain::new(&group);
+
+        let mut inference = Inference::new(assignment.into(), &puzzle);
+
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"Hot where it's sure, cold if not");
+
+        let mut img = [(0., 0., 0.); (48 + 6) * 20 + 1];
+
+        for _ in 0..30 {
+            let perm = stabchain.random(&mut rng);
+            simulate_picture(&perm, &group, 0.2, 0.1, &mut rng, &mut img[..(48 + 6) * 20]);
+            inference.calibrate(&img, &perm);
+        }
+
+        // Every sticker is an almost flat patch of its color
+        let perm = stabchain.random(&mut rng);
+        simulate_picture(
+            &perm,
+            &group,
+            0.01,
+            0.01,
+            &mut rng,
+            &mut img[..(48 + 6) * 20],
+        );
+        img[(48 + 6) * 20] = (0.5, 0.5, 0.5);
+
+        let map = inference.confidence_map(&img);
+        assert_eq!(map.len(), img.len());
+
+        let stickers = &map[..48 * 20];
+        assert!(
+            stickers.iter().all(|v| (0.5..=1.).contains(v)),
+            "{stickers:?}"
+        );
+        assert!(stickers.iter().sum::<f64>() / stickers.len() as f64 > 0.9);
+
+        // White balance and unassigned pixels
+        assert!(map[48 * 20..].iter().all(|v| *v == 0.));
+    }
+
+    #[test]
+    fn weights() {
+        let assignment = standard_assignment();
+
+        // The last 8 pixels of each sticker are on its edge
+        let is_edge = |idx: usize| idx < 48 * 20 && idx % 20 >= 12;
+        let weights = (0..(48 + 6) * 20)
+            .map(|idx| if is_edge(idx) { 0. } else { 1. })
+            .collect::<Vec<_>>();
+
+        let puzzle = puzzle("3x3");
+        let group = puzzle.permutation_group();
+        let stabchain = StabilizerChain::new(&group);
+
+        let mut unweighted = Inference::new(assignment.clone().into(), &puzzle);
+        let mut weighted = Inference::new(assignment.into(), &puzzle);
+        weighted.set_weights(&weights).unwrap();
+
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"Hold still, the picture's blurry");
+
+        let mut img = [(0., 0., 0.); (48 + 6) * 20];
+
+        for _ in 0..30 {
+            let perm = stabchain.random(&mut rng);
+            simulate_picture(&perm, &group, 0.2, 0.1, &mut rng, &mut img);
+            unweighted.calibrate(&img, &perm);
+            weighted.calibrate(&img, &perm);
+        }
+
+        let perm = stabchain.random(&mut rng);
+        simulate_picture(&perm, &group, 0.2, 0.1, &mut rng, &mut img);
+        let color_of = |sticker: usize| &group.facelet_colors()[perm.state().get(sticker)];
+
+        // Blur each sticker's edge into a differently colored sticker on the same face, which is lit the same way
+        let mut blurred = img;
+        let mut bleeding_colors = Vec::new();
+        for sticker in 0..48 {
+            let face = sticker / 8 * 8..sticker / 8 * 8 + 8;
+            let neighbour = face.into_iter().find(|v| color_of(*v) != color_of(sticker));
+            bleeding_colors.push(neighbour.map(color_of));
+
+            if let Some(neighbour) = neighbour {
+                let core = img[neighbour * 20];
+                blurred[sticker * 20 + 12..(sticker + 1) * 20].fill(core);
+            }
+        }
+
+        // The edge pixels don't count at all, so corrupting them changes nothing
+        assert_eq!(weighted.infer(&blurred), weighted.infer(&img));
+
+        let clean = unweighted.infer(&img);
+        let corrupted = unweighted.infer(&blurred);
+        let mut checked = 0;
+        for (sticker, bleeding_color) in bleeding_colors.into_iter().enumerate() {
+            // The sticker can't look like a color that it was never calibrated with
+            let Some(color) = bleeding_color.filter(|color| {
+                unweighted.pixels_by_sticker[sticker][0].kdtrees[*color].size() > 0
+            }) else {
+                continue;
+            };
+
+            assert!(corrupted[sticker][color] > clean[sticker][color]);
+            checked += 1;
+        }
+        assert!(checked > 0);
+    }
+
+    #[test]
+    fn invalid_weights() {
+        let mut inference = Inference::new(standard_assignment().into(), &puzzle("3x3"));
+
+        // The second sticker's pixels don't count at all
+        let mut weights = (0..(48 + 6) * 20)
+            .map(|idx| if (20..40).contains(&idx) { 0. } else { 1. })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            inference.set_weights(&weights),
+            Err(InvalidWeights::ZeroSticker(1))
+        );
+
+        weights[25] = 1.;
+        weights[3] = -1.;
+        assert_eq!(
+            inference.set_weights(&weights),
+            Err(InvalidWeights::Negative(3))
+        );
+        weights[3] = f64::NAN;
+        assert_eq!(
+            inference.set_weights(&weights),
+            Err(InvalidWeights::Negative(3))
+        );
+
+        // Nothing was set by the weights that couldn't be used
+        assert!(
+            inference
+                .pixels_by_sticker
+                .iter()
+                .flatten()
+                .all(|pixel| pixel.weight == 1.)
+        );
+
+        weights[3] = 0.;
+        assert_eq!(inference.set_weights(&weights), Ok(()));
+    }
+
+    #[test]
+    fn scoring_modes() {
+        let assignment = standard_assignment();
+
+        let puzzle = puzzle("3x3");
+        let group = puzzle.permutation_group();
+        let stabchain = StabilizerChain::new(&group);
+        let solved = Permutation::from_cycles(Vec::new());
+
+        let mut density = Inference::new(assignment.clone().into(), &puzzle);
+        let mut vote = Inference::new(assignment.into(), &puzzle);
+        vote.set_params(InferenceParams {
+            scoring: Scoring::Vote,
+            ..InferenceParams::default()
+        });
+
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"Some colors get all the samples!");
+
+        let mut img = [(0., 0., 0.); (48 + 6) * 20];
+
+        // Every sticker sees its solved color far more often than any other
+        for i in 0..80 {
+            let perm = if i % 2 == 0 {
+                solved.clone()
+            } else {
+                stabchain.random(&mut rng)
+            };
+            simulate_picture(&perm, &group, 0.2, 0.1, &mut rng, &mut img);
+            density.calibrate(&img, &perm);
+            vote.calibrate(&img, &perm);
+        }
+
+        let matcher = Matcher::new(Arc::clone(&puzzle));
+
+        for _ in 0..5 {
+            simulate_picture(&solved, &group, 0.2, 0.1, &mut rng, &mut img);
+            assert_eq!(matcher.most_likely(&density.infer(&img)).unwrap().0, solved);
+
+            let confidences = vote.infer(&img);
+            assert_eq!(matcher.most_likely(&confidences).unwrap().0, solved);
+
+            // Votes are probabilities, unlike densities
+            for (sticker, confidences) in confidences.iter().enumerate() {
+                assert!(confidences.values().all(|v| (0. ..=1.).contains(v)));
+                assert!(confidences[&group.facelet_colors()[sticker]] > 0.5);
+            }
+        }
+    }
+
+    #[test]
+    fn centroid_scoring() {
+        let assignment = standard_assignment();
+
+        let puzzle = puzzle("3x3");
+        let group = puzzle.permutation_group();
+        let stabchain = StabilizerChain::new(&group);
+
+        let mut density = Inference::new(assignment.clone().into(), &puzzle);
+        let mut centroid = Inference::new(assignment.into(), &puzzle);
+        centroid.set_params(InferenceParams {
+            scoring: Scoring::Centroid,
+            ..InferenceParams::default()
+        });
+
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"Far apart colors need no k-NN!!!");
+
+        let mut img = [(0., 0., 0.); (48 + 6) * 20];
+
+        // Little enough noise that the colors are well separated
+        for _ in 0..30 {
+            let perm = stabchain.random(&mut rng);
+            simulate_picture(&perm, &group, 0.02, 0.02, &mut rng, &mut img);
+            density.calibrate(&img, &perm);
+            centroid.calibrate(&img, &perm);
+        }
+        assert!(centroid.centroids_separate());
+        assert_eq!(centroid.scoring(), Scoring::Centroid);
+
+        let matcher = Matcher::new(Arc::clone(&puzzle));
+
+        let mut right = (0, 0);
+        for _ in 0..20 {
+            let perm = stabchain.random(&mut rng);
+            simulate_picture(&perm, &group, 0.02, 0.02, &mut rng, &mut img);
+
+            right.0 += usize::from(matcher.most_likely(&density.infer(&img)).unwrap().0 == perm);
+            right.1 += usize::from(matcher.most_likely(&centroid.infer(&img)).unwrap().0 == perm);
+        }
+        assert_eq!(right, (20, 20));
+
+        // Every sticker looks the same no matter what color it is, so the centroids can't tell the colors apart
+        let mut gray = Inference::new(
+            (0..48).map(crate::Pixel::Sticker).collect_vec().into(),
+            &puzzle,
+        );
+        gray.set_params(InferenceParams {
+            scoring: Scoring::Centroid,
+            ..InferenceParams::default()
+        });
+        let img = [(0.5, 0.5, 0.5); 48];
+        gray.calibrate(&img, &Permutation::from_cycles(Vec::new()));
+        assert!(gray.centroids_separate());
+        gray.calibrate(&img, &stabchain.random(&mut rng));
+        assert!(!gray.centroids_separate());
+        assert_eq!(gray.scoring(), Scoring::Density);
+    }
+
+    #[test]
+    fn reused_scratch() {
+        let mut assignment = Vec::new();
+
+        // The last sticker isn't visible
+        for i in 0..47 {
+            for _ in 0..20 {
+                assignment.push(crate::Pixel::Sticker(i));
+            }
+        }
+        assignment.extend((0..20).map(|_| crate::Pixel::Unassigned));
+
+        for color in ["white", "orange", "green", "red", "blue", "yellow"].map(ArcIntern::from) {
+            for _ in 0..20 {
+                assignment.push(crate::Pixel::WhiteBalance(ArcIntern::clone(&color)))
+            }
+        }
+
+        let puzzle = puzzle("3x3");
+        let group = puzzle.permutation_group();
+        let stabchain = StabilizerChain::new(&group);
+
+        let mut density = Inference::new(assignment.clone().into(), &puzzle);
+        let mut vote = Inference::new(assignment.into(), &puzzle);
+        vote.set_params(InferenceParams {
+            scoring: Scoring::Vote,
+            ..InferenceParams::default()
+        });
+
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"Same buffers, frame after frame!");
+
+        let mut img = [(0., 0., 0.); (48 + 6) * 20];
+
+        for _ in 0..20 {
+            let perm = stabchain.random(&mut rng);
+            simulate_picture(&perm, &group, 0.2, 0.1, &mut rng, &mut img);
+            density.calibrate(&img, &perm);
+            vote.calibrate(&img, &perm);
+        }
+
+        // One scratch shared between both, so that it is also reused across differently configured inferences
+        let mut scratch = InferenceScratch::new();
+
+        for _ in 0..5 {
+            let perm = stabchain.random(&mut rng);
+            simulate_picture(&perm, &group, 0.2, 0.1, &mut rng, &mut img);
+
+            for inference in [&density, &vote] {
+                let expected = inference.infer(&img);
+                assert_eq!(inference.infer_into(&img, &mut scratch), &*expected);
+                assert!(expected[47].is_empty());
+            }
+        }
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel() {
+        let mut assignment = Vec::new();
+
+        // The last sticker isn't visible
+        for i in 0..47 {
+            for _ in 0..20 {
+                assignment.push(crate::Pixel::Sticker(i));
+            }
+        }
+        assignment.extend((0..20).map(|_| crate::Pixel::Unassigned));
+
+        for color in ["white", "orange", "green", "red", "blue", "yellow"].map(ArcIntern::from) {
+            for _ in 0..20 {
+                assignment.push(crate::Pixel::WhiteBalance(ArcIntern::clone(&color)))
+            }
+        }
+
+        let puzzle = puzzle("3x3");
+        let group = puzzle.permutation_group();
+        let stabchain = StabilizerChain::new(&group);
+
+        let mut density = Inference::new(assignment.clone().into(), &puzzle);
+        let mut vote = Inference::new(assignment.into(), &puzzle);
+        vote.set_params(InferenceParams {
+            scoring: Scoring::Vote,
+            ..InferenceParams::default()
+        });
+
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"Many hands make light work, too!");
+
+        let mut img = [(0., 0., 0.); (48 + 6) * 20];
+
+        for _ in 0..20 {
+            let perm = stabchain.random(&mut rng);
+            simulate_picture(&perm, &group, 0.2, 0.1, &mut rng, &mut img);
+            density.calibrate(&img, &perm);
+            vote.calibrate(&img, &perm);
+        }
+
+        let serial = |inference: &Inference, img: &[(f64, f64, f64)], observed| {
+            let mut scratch = InferenceScratch::new();
+            inference.white_balance_into(img, observed, &mut scratch.white_balances);
+            scratch
+                .confidences
+                .resize_with(48, std::collections::BTreeMap::new);
+            inference.infer_stickers(inference.scoring(), img, observed, &mut scratch);
+            scratch.confidences
+        };
+
+        // Only some of the pixels of the first stickers are seen
+        let observed = (0..img.len())
+            .map(|idx| idx >= 5 * 20 || idx % 3 == 0)
+            .collect::<Vec<_>>();
+
+        for _ in 0..5 {
+            let perm = stabchain.random(&mut rng);
+            simulate_picture(&perm, &group, 0.2, 0.1, &mut rng, &mut img);
+
+            for inference in [&density, &vote] {
+                assert_eq!(*inference.infer(&img), *serial(inference, &img, None));
+                assert_eq!(
+                    *inference.infer_masked(&img, &observed),
+                    *serial(inference, &img, Some(&observed))
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn calibrate_facelets() {
+        let assignment = standard_assignment();
+
+        let puzzle = puzzle("3x3");
+        let group = puzzle.permutation_group();
+        let mut inference = Inference::new(assignment.into(), &puzzle);
+
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"Those five pixels are surely red");
+        let mut img = [(0., 0., 0.); (48 + 6) * 20];
+        simulate_picture(
+            &Permutation::from_cycles(Vec::new()),
+            &group,
+            0.2,
+            0.1,
+            &mut rng,
+            &mut img,
+        );
+
+        let red = ArcIntern::from("red");
+        let tree_sizes = |inference: &Inference, sticker: usize, color: &ArcIntern<str>| {
+            inference.pixels_by_sticker[sticker]
+                .iter()
+                .map(|pixel| pixel.kdtrees[color].size() as usize)
+                .sum::<usize>()
+        };
+
+        inference
+            .calibrate_facelets(&img, &[(5, ArcIntern::clone(&red))])
+            .unwrap();
+
+        // Every pixel of the facelet gained one sample of the color, and nothing else changed
+        assert_eq!(tree_sizes(&inference, 5, &red), 20);
+        for sticker in 0..48 {
+            for color in group.facelet_colors() {
+                if (sticker, color) != (5, &red) {
+                    assert_eq!(tree_sizes(&inference, sticker, color), 0);
+                }
+            }
+        }
+        assert_eq!(inference.appearances[&red].1, 20);
+
+        // Labelling the same facelet again adds to its samples
+        inference
+            .calibrate_facelets(
+                &img,
+                &[(5, ArcIntern::clone(&red)), (5, ArcIntern::clone(&red))],
+            )
+            .unwrap();
+        assert_eq!(tree_sizes(&inference, 5, &red), 60);
+
+        assert_eq!(
+            inference.calibrate_facelets(&img, &[(5, ArcIntern::clone(&red)), (48, red)]),
+            Err(InvalidFaceletLabel::Index(48))
+        );
+        assert_eq!(
+            inference.calibrate_facelets(&img, &[(5, ArcIntern::from("purple"))]),
+            Err(InvalidFaceletLabel::Color(ArcIntern::from("purple")))
+        );
+        assert_eq!(tree_sizes(&inference, 5, &ArcIntern::from("red")), 60);
     }
-}
 
-/// Standard quickselect algorithm: https://en.wikipedia.org/wiki/Quickselect
-/// Sorts in descending order
-///
-/// After calling this function, the value at index `find_spot` is guaranteed to be at the correctly sorted position and all values at indices less than `find_spot` are guaranteed to be greater than the value at `find_spot` and vice versa for indices greater.
-pub(crate) fn quickselect<T, R: Rng + ?Sized>(
-    rng: &mut R,
-    mut slice: &mut [T],
-    by: impl Fn(&T, &T) -> Ordering,
-    mut find_spot: usize,
-) {
-    loop {
-        let len = slice.len();
+    #[test]
+    fn calibration_coverage() {
+        let assignment = standard_assignment();
 
-        if len < 2 {
-            return;
+        let puzzle = puzzle("3x3");
+        let group = puzzle.permutation_group();
+        let colors = group.facelet_colors();
+        let mut inference = Inference::new(assignment.into(), &puzzle);
+
+        // Nothing to fall short of yet
+        assert_eq!(inference.under_sampled(), UnderSampled::default());
+
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"Is that enough pictures yet, no?");
+        let mut img = [(0., 0., 0.); (48 + 6) * 20];
+        let solved = Permutation::from_cycles(Vec::new());
+        for _ in 0..2 {
+            simulate_picture(&solved, &group, 0.2, 0.1, &mut rng, &mut img);
+            inference.calibrate(&img, &solved);
+        }
+
+        let coverage = inference.calibration_coverage();
+        assert_eq!(coverage.len(), 48 * 6);
+        for ((sticker, color), count) in &coverage {
+            let expected = if *color == colors[*sticker] { 2 } else { 0 };
+            assert_eq!(*count, expected);
+        }
+        assert_eq!(inference.under_sampled(), UnderSampled::default());
+
+        // Every sticker but the red ones is labelled four more times
+        let red = ArcIntern::from("red");
+        let labels = (0..48)
+            .filter(|sticker| colors[*sticker] != red)
+            .map(|sticker| (sticker, ArcIntern::clone(&colors[sticker])))
+            .collect_vec();
+        for _ in 0..4 {
+            inference.calibrate_facelets(&img, &labels).unwrap();
         }
 
-        let spot_found = partition(rng, slice, &by);
+        for ((sticker, color), count) in inference.calibration_coverage() {
+            let expected = match colors[sticker] == color {
+                true if color == red => 2,
+                true => 6,
+                false => 0,
+            };
+            assert_eq!(count, expected);
+        }
 
-        match find_spot.cmp(&spot_found) {
-            Ordering::Less => slice = &mut slice[0..spot_found],
-            Ordering::Equal => return,
-            Ordering::Greater => {
-                slice = &mut slice[spot_found + 1..len];
-                find_spot = find_spot - spot_found - 1;
+        // Red has 16 samples and every other color 48, and each red sticker has 2 and every other sticker 6
+        let red_stickers = (0..48)
+            .filter(|sticker| colors[*sticker] == red)
+            .collect_vec();
+        assert_eq!(red_stickers.len(), 8);
+        assert_eq!(
+            inference.under_sampled(),
+            UnderSampled {
+                colors: vec![red],
+                stickers: red_stickers,
             }
-        }
+        );
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use std::{
-        collections::HashMap,
-        sync::{Arc, LazyLock},
-    };
 
-    use internment::ArcIntern;
-    use puzzle_theory::{
-        permutations::{Permutation, PermutationGroup, schreier_sims::StabilizerChain},
-        puzzle_geometry::parsing::puzzle,
-    };
-    use rand::{Rng, SeedableRng};
+    #[test]
+    fn reassign_sticker() {
+        let mut assignment = standard_assignment();
 
-    use crate::{inference::Inference, puzzle_matching::Matcher};
+        let puzzle = puzzle("3x3");
+        let group = puzzle.permutation_group();
+        let stabchain = StabilizerChain::new(&group);
+        let mut inference = Inference::new(assignment.clone().into(), &puzzle);
 
-    use super::quickselect;
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"That one sticker has a scratch!!");
+        let mut img = [(0., 0., 0.); (48 + 6) * 20];
 
-    static NATURAL_COLORS: LazyLock<HashMap<ArcIntern<str>, (f64, f64, f64)>> =
-        LazyLock::new(|| {
-            let mut map = HashMap::new();
+        for _ in 0..30 {
+            let perm = stabchain.random(&mut rng);
+            simulate_picture(&perm, &group, 0.2, 0.1, &mut rng, &mut img);
+            inference.calibrate(&img, &perm);
+        }
 
-            map.insert(ArcIntern::from("red"), (1., 0.2, 0.2));
-            map.insert(ArcIntern::from("orange"), (1., 0.6, 0.2));
-            map.insert(ArcIntern::from("white"), (1., 1., 1.));
-            map.insert(ArcIntern::from("yellow"), (0.8, 0.8, 0.2));
-            map.insert(ArcIntern::from("blue"), (0.2, 0.5, 1.));
-            map.insert(ArcIntern::from("green"), (0.3, 1., 0.5));
+        let samples = |inference: &Inference, sticker: usize| {
+            inference.pixels_by_sticker[sticker]
+                .iter()
+                .flat_map(|pixel| pixel.kdtrees.values())
+                .map(|kdtree| kdtree.size() as usize)
+                .sum::<usize>()
+        };
+        let pixels = |inference: &Inference, sticker: usize| {
+            inference.pixels_by_sticker[sticker]
+                .iter()
+                .map(|pixel| pixel.idx)
+                .collect_vec()
+        };
+
+        // The first few pixels of sticker 7 are on the scratch
+        assignment[7 * 20..7 * 20 + 5].fill(crate::Pixel::Unassigned);
+        inference.reassign_sticker(7, &assignment).unwrap();
+
+        assert_eq!(pixels(&inference, 7), (7 * 20 + 5..8 * 20).collect_vec());
+        assert_eq!(samples(&inference, 7), 0);
+        for sticker in (0..48).filter(|sticker| *sticker != 7) {
+            assert_eq!(pixels(&inference, sticker).len(), 20);
+            assert_eq!(samples(&inference, sticker), 20 * 30);
+        }
 
-            map
-        });
+        // Calibrating only sticker 7 brings it back
+        for _ in 0..30 {
+            let perm = stabchain.random(&mut rng);
+            simulate_picture(&perm, &group, 0.2, 0.1, &mut rng, &mut img);
+            let color = ArcIntern::clone(&group.facelet_colors()[perm.state().get(7)]);
+            inference.calibrate_facelets(&img, &[(7, color)]).unwrap();
+        }
+        assert_eq!(samples(&inference, 7), 15 * 30);
+        assert_eq!(samples(&inference, 8), 20 * 30);
 
-    fn simulate_picture<R: Rng + ?Sized>(
-        perm: &Permutation,
-        group: &PermutationGroup,
-        shadowful_noise: f64,
-        colorful_noise: f64,
-        rng: &mut R,
-        out: &mut [(f64, f64, f64)],
-    ) {
-        assert_eq!(out.len(), (48 + 6) * 20);
+        let matcher = Matcher::new(Arc::clone(&puzzle));
+        for _ in 0..10 {
+            let perm = stabchain.random(&mut rng);
+            simulate_picture(&perm, &group, 0.2, 0.1, &mut rng, &mut img);
+            assert_eq!(matcher.most_likely(&inference.infer(&img)).unwrap().0, perm);
+        }
 
-        for face in 0..6 {
-            // Simulate random lighting on this face to test our white balance code
-            let white = (
-                rng.random_range(0.2..1.2),
-                rng.random_range(0.2..1.2),
-                rng.random_range(0.2..1.2),
-            );
+        // Pixels given to a sticker are taken from the sticker and the white balance that had them
+        assignment[9 * 20..9 * 20 + 3].fill(crate::Pixel::Sticker(10));
+        assignment[48 * 20] = crate::Pixel::Sticker(10);
+        inference.reassign_sticker(10, &assignment).unwrap();
+
+        assert_eq!(pixels(&inference, 9), (9 * 20 + 3..10 * 20).collect_vec());
+        assert_eq!(samples(&inference, 9), 17 * 30);
+        assert_eq!(
+            pixels(&inference, 10),
+            (9 * 20..9 * 20 + 3)
+                .chain(10 * 20..11 * 20)
+                .chain([48 * 20])
+                .collect_vec()
+        );
+        assert_eq!(samples(&inference, 10), 0);
+        assert_eq!(
+            inference.white_balance_by_face[&ArcIntern::from("white")].len(),
+            19
+        );
+
+        assert_eq!(
+            inference.reassign_sticker(48, &assignment),
+            Err(InvalidFaceletLabel::Index(48))
+        );
+        assert_eq!(pixels(&inference, 10).len(), 24);
+    }
 
-            for spot in 0..8 {
-                let idx = spot + face * 8;
+    #[test]
+    fn color_order() {
+        let assignment = standard_assignment();
 
-                let is = perm.state().get(idx);
-                let mut color = *NATURAL_COLORS.get(&group.facelet_colors()[is]).unwrap();
-                color.0 *= white.0;
-                color.1 *= white.1;
-                color.2 *= white.2;
+        let puzzle = puzzle("3x3");
+        let group = puzzle.permutation_group();
+        let solved = Permutation::from_cycles(Vec::new());
+        let mut inference = Inference::new(assignment.into(), &puzzle);
 
-                out[idx * 20..(idx + 1) * 20].fill(color);
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"Blue, green, orange, red, white.");
+        let mut img = [(0., 0., 0.); (48 + 6) * 20];
+        simulate_picture(&solved, &group, 0.2, 0.1, &mut rng, &mut img);
+        inference.calibrate(&img, &solved);
+
+        // Every sticker lists the colors in the same, sorted order, no matter which map it came from
+        let mut expected = group.facelet_colors().to_vec();
+        expected.sort();
+        expected.dedup();
+
+        for _ in 0..3 {
+            simulate_picture(&solved, &group, 0.2, 0.1, &mut rng, &mut img);
+            for confidences in inference.infer(&img) {
+                assert!(confidences.keys().eq(&expected));
             }
+        }
+    }
 
-            out[(48 + face) * 20..(48 + face + 1) * 20].fill(white);
+    #[test]
+    fn masked() {
+        let assignment = standard_assignment();
+
+        let puzzle = puzzle("3x3");
+        let group = puzzle.permutation_group();
+        let stabchain = StabilizerChain::new(&group);
+        let mut inference = Inference::new(assignment.into(), &puzzle);
+
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"Nothing to see here, move along!");
+        let mut img = [(0., 0., 0.); (48 + 6) * 20];
+
+        for _ in 0..20 {
+            let perm = stabchain.random(&mut rng);
+            simulate_picture(&perm, &group, 0.2, 0.1, &mut rng, &mut img);
+            inference.calibrate(&img, &perm);
         }
 
-        for (r, g, b) in out.iter_mut() {
-            // Add random noise to simulate differences in shadowing
-            let noise = rng.random_range(((1. + shadowful_noise).recip())..(1. + shadowful_noise));
-            *r *= noise;
-            *g *= noise;
-            *b *= noise;
+        let perm = stabchain.random(&mut rng);
+        simulate_picture(&perm, &group, 0.2, 0.1, &mut rng, &mut img);
+
+        // Every pixel observed is the same as no mask at all
+        let everything = [true; (48 + 6) * 20];
+        assert_eq!(
+            inference.infer_masked(&img, &everything),
+            inference.infer(&img)
+        );
+
+        // Half of every sticker and white balance is transparent, and the whole of sticker 0
+        let observed = (0..(48 + 6) * 20)
+            .map(|idx| idx >= 20 && idx % 20 < 10)
+            .collect::<Vec<_>>();
+        let mut transparent = img;
+        for (pixel, observed) in transparent.iter_mut().zip(&observed) {
+            if !observed {
+                *pixel = (0., 0., 0.);
+            }
         }
 
-        for v in out.iter_mut().flat_map(|(r, g, b)| [r, g, b]) {
-            // Add some random noise to simulate camera noise
-            *v *= rng.random_range(((1. + colorful_noise).recip())..(1. + colorful_noise));
-            // Clamp everything to be less than one to simulate overexposure
-            *v = v.min(1.);
+        let confidences = inference.infer_masked(&img, &observed);
+        assert_eq!(inference.infer_masked(&transparent, &observed), confidences);
+        assert!(confidences[0].is_empty());
+        assert!(confidences[1..].iter().all(|v| !v.is_empty()));
+        assert_eq!(
+            Matcher::new(Arc::clone(&puzzle))
+                .most_likely(&confidences)
+                .unwrap()
+                .0,
+            perm
+        );
+    }
+
+    #[test]
+    fn stickerless() {
+        let stickerless = InferenceParams::stickerless();
+        let default = InferenceParams::default();
+
+        // Glare is rejected and mostly glare stickers are left unobserved, which the default never does
+        assert_eq!(stickerless.reject_distance, 0.5);
+        assert!(default.reject_distance.is_infinite());
+        assert_eq!(stickerless.min_observed_fraction, 0.25);
+        assert_eq!(default.min_observed_fraction, 0.);
+
+        // The scoring is the same
+        assert_eq!(
+            InferenceParams {
+                reject_distance: default.reject_distance,
+                min_observed_fraction: default.min_observed_fraction,
+                ..stickerless
+            },
+            default
+        );
+    }
+
+    #[test]
+    fn reject_distance() {
+        let assignment = standard_assignment();
+
+        let puzzle = puzzle("3x3");
+        let group = puzzle.permutation_group();
+        let stabchain = StabilizerChain::new(&group);
+        let mut inference = Inference::new(assignment.into(), &puzzle);
+        inference.set_params(InferenceParams {
+            reject_distance: 0.5,
+            ..InferenceParams::default()
+        });
+
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"Mind the table behind the cube!!");
+        let mut img = [(0., 0., 0.); (48 + 6) * 20];
+
+        for _ in 0..20 {
+            let perm = stabchain.random(&mut rng);
+            simulate_picture(&perm, &group, 0.2, 0.1, &mut rng, &mut img);
+            inference.calibrate(&img, &perm);
         }
+
+        let perm = stabchain.random(&mut rng);
+        simulate_picture(&perm, &group, 0.2, 0.1, &mut rng, &mut img);
+
+        // The assignment of sticker 5 bleeds past its edge, so half of its pixels show the dark table
+        let table = 100..110;
+        img[table.clone()].fill((0.02, 0.02, 0.02));
+
+        // The table is far from every color, so it counts for nothing, as if it weren't there at all
+        let observed = (0..(48 + 6) * 20)
+            .map(|idx| !table.contains(&idx))
+            .collect::<Vec<_>>();
+        let confidences = inference.infer(&img);
+        assert_eq!(confidences[5], inference.infer_masked(&img, &observed)[5]);
+
+        let (top, _) = confidences[5]
+            .iter()
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .unwrap();
+        assert_eq!(*top, group.facelet_colors()[perm.state().get(5)]);
+        assert_eq!(
+            Matcher::new(Arc::clone(&puzzle))
+                .most_likely(&confidences)
+                .unwrap()
+                .0,
+            perm
+        );
     }
 
     #[test]
-    fn test_inference() {
-        let mut assignment = Vec::new();
+    fn color_cast() {
+        let puzzle = puzzle("3x3");
+        let group = puzzle.permutation_group();
+        let stabchain = StabilizerChain::new(&group);
 
-        for i in 0..48 {
+        // The camera only sees three faces, and only the first of them has white balance pixels, so the white balance of the faces can't undo a cast on the other two
+        let mut assignment = Vec::new();
+        for i in 0..24 {
             for _ in 0..20 {
                 assignment.push(crate::Pixel::Sticker(i));
             }
         }
+        for _ in 0..20 {
+            assignment.push(crate::Pixel::WhiteBalance(ArcIntern::clone(
+                &group.facelet_colors()[0],
+            )));
+        }
+        let mut inference = Inference::new(assignment.into(), &puzzle);
 
-        for color in ["white", "orange", "green", "red", "blue", "yellow"].map(ArcIntern::from) {
-            for _ in 0..20 {
-                assignment.push(crate::Pixel::WhiteBalance(ArcIntern::clone(&color)))
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"The camera decided it was dusk!!");
+        let render = |perm: &Permutation, cast: (f64, f64, f64), rng: &mut rand::rngs::SmallRng| {
+            let mut img = [(0., 0., 0.); 25 * 20];
+            for (sticker, pixels) in img.chunks_mut(20).enumerate() {
+                let (r, g, b) = match sticker {
+                    24 => (1., 1., 1.),
+                    _ => NATURAL_COLORS[&group.facelet_colors()[perm.state().get(sticker)]],
+                };
+                pixels.fill((r * cast.0, g * cast.1, b * cast.2));
+            }
+
+            for (r, g, b) in &mut img {
+                let noise = rng.random_range(0.95..1.05);
+                *r *= noise;
+                *g *= noise;
+                *b *= noise;
             }
+
+            img
+        };
+        let is_uncast = |(r, g, b): (f64, f64, f64), cast: (f64, f64, f64)| {
+            [r * cast.0, g * cast.1, b * cast.2]
+                .iter()
+                .all(|v| (v - 1.).abs() < 0.03)
+        };
+
+        // Nothing to compare with yet
+        let solved = Permutation::from_cycles(Vec::new());
+        let img = render(&solved, (1., 1., 1.), &mut rng);
+        assert_eq!(inference.color_cast(&img), (1., 1., 1.));
+
+        // Enough pictures that every sticker has seen every color
+        for _ in 0..60 {
+            let perm = stabchain.random(&mut rng);
+            inference.calibrate(&render(&perm, (1., 1., 1.), &mut rng), &perm);
+        }
+
+        // The visible faces show a different mix of colors in every state, which isn't a cast
+        for _ in 0..5 {
+            let perm = stabchain.random(&mut rng);
+            let cast = inference.color_cast(&render(&perm, (1., 1., 1.), &mut rng));
+            assert!(is_uncast(cast, (1., 1., 1.)), "{cast:?}");
         }
+        let cast = inference.color_cast(&render(&solved, (1., 1., 1.), &mut rng));
+        assert!(is_uncast(cast, (1., 1., 1.)), "{cast:?}");
+
+        // The camera turns the whole picture blue, which makes white look like blue and orange like yellow
+        let cast = (0.45, 0.7, 1.);
+        let perm = stabchain.random(&mut rng);
+        let img = render(&perm, cast, &mut rng);
+        assert!(is_uncast(inference.color_cast(&img), cast));
+
+        // Only the faces without white balance pixels are thrown off by it
+        let misclassified = |confidences: &[BTreeMap<ArcIntern<str>, f64>]| {
+            (8..24)
+                .filter(|sticker| {
+                    let (top, _) = confidences[*sticker]
+                        .iter()
+                        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+                        .unwrap();
+                    *top != group.facelet_colors()[perm.state().get(*sticker)]
+                })
+                .count()
+        };
+        assert!(misclassified(&inference.infer(&img)) > 0);
+
+        inference.set_params(InferenceParams {
+            correct_color_cast: true,
+            ..InferenceParams::default()
+        });
+        assert_eq!(misclassified(&inference.infer(&img)), 0);
+
+        // And correcting a picture without a cast leaves it alone
+        let img = render(&perm, (1., 1., 1.), &mut rng);
+        assert_eq!(misclassified(&inference.infer(&img)), 0);
+    }
+
+    #[test]
+    fn extra_channels() {
+        let assignment = standard_assignment();
 
         let puzzle = puzzle("3x3");
         let group = puzzle.permutation_group();
         let stabchain = StabilizerChain::new(&group);
+        let mut inference = Inference::<4>::with_channels(assignment.clone().into(), &puzzle);
 
-        let mut inference = Inference::new(assignment.into(), &puzzle);
-
-        let mut rng = rand::rngs::SmallRng::from_seed(*b"Buying black on the black market");
-
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"Orange glows under the infrared!");
         let mut img = [(0., 0., 0.); (48 + 6) * 20];
 
+        // Orange looks exactly like red, and only the fourth channel tells them apart
+        let with_infrared =
+            |perm: &Permutation, img: &[(f64, f64, f64)], rng: &mut rand::rngs::SmallRng| {
+                img.iter()
+                    .enumerate()
+                    .map(|(idx, &(r, g, b))| {
+                        let is_orange = idx < 48 * 20
+                            && *group.facelet_colors()[perm.state().get(idx / 20)] == *"orange";
+                        let infrared = if is_orange { 0.9 } else { 0.3 };
+
+                        [
+                            r,
+                            if is_orange { g / 3. } else { g },
+                            b,
+                            infrared * rng.random_range(0.95..1.05),
+                        ]
+                    })
+                    .collect::<Vec<_>>()
+            };
+
         for _ in 0..30 {
             let perm = stabchain.random(&mut rng);
             simulate_picture(&perm, &group, 0.2, 0.1, &mut rng, &mut img);
-            inference.calibrate(&img, &perm);
+            inference.calibrate(&with_infrared(&perm, &img, &mut rng), &perm);
         }
 
         let matcher = Matcher::new(Arc::clone(&puzzle));
 
-        for _ in 0..100 {
+        for _ in 0..20 {
             let perm = stabchain.random(&mut rng);
             simulate_picture(&perm, &group, 0.2, 0.1, &mut rng, &mut img);
-            assert_eq!(matcher.most_likely(&inference.infer(&img)).0, perm);
+            let confidences = inference.infer(&with_infrared(&perm, &img, &mut rng));
+
+            for (sticker, confidences) in confidences.iter().enumerate() {
+                let color = &group.facelet_colors()[perm.state().get(sticker)];
+                if !["red", "orange"].contains(&&**color) {
+                    continue;
+                }
+
+                let (top, _) = confidences
+                    .iter()
+                    .max_by(|(_, a), (_, b)| a.total_cmp(b))
+                    .unwrap();
+                assert_eq!(top, color);
+            }
+            assert_eq!(matcher.most_likely(&confidences).unwrap().0, perm);
+        }
+
+        // Every channel of the samples is saved
+        #[cfg(feature = "serde")]
+        {
+            let mut loaded = Inference::<4>::with_channels(assignment.into(), &puzzle);
+            loaded
+                .load_calibration(&inference.calibration_to_text())
+                .unwrap();
+            assert_eq!(sorted_points(&loaded), sorted_points(&inference));
         }
     }
 
     #[test]
-    fn test_quickselect() {
-        fn verify<R: Rng + ?Sized>(rng: &mut R, pos: usize, slice: &[f64]) {
-            let mut slice = slice
-                .iter()
-                .enumerate()
-                .map(|(a, b)| (*b, a))
-                .collect::<Vec<_>>();
+    fn srgb() {
+        for byte in 0..=255_u8 {
+            let v = f64::from(byte) / 255.;
+            assert!((linear_to_srgb(srgb_to_linear(v)) - v).abs() < 1e-12);
+        }
+        assert_eq!(srgb_to_linear(0.), 0.);
+        assert!((srgb_to_linear(1.) - 1.).abs() < 1e-12);
+        // Middle gray is encoded far brighter than the light it stands for
+        assert!((srgb_to_linear(0.5) - 0.214).abs() < 1e-3);
 
-            quickselect(rng, &mut slice, |a, b| a.0.total_cmp(&b.0), pos);
+        let assignment = standard_assignment();
 
-            for i in 0..pos {
-                assert!(
-                    slice[i].0 >= slice[pos].0,
-                    "Pos: {pos}, Index: {i} - {slice:?}"
-                );
+        let puzzle = puzzle("3x3");
+        let group = puzzle.permutation_group();
+        let stabchain = StabilizerChain::new(&group);
+        let mut inference = Inference::new(assignment.into(), &puzzle);
+        inference.set_params(InferenceParams {
+            linearize_srgb: true,
+            ..InferenceParams::default()
+        });
+
+        // The white and yellow faces are a light and a dark gray, so they only differ in brightness
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"Light gray, dark gray, same hue!");
+        let render = |perm: &Permutation, rng: &mut rand::rngs::SmallRng| {
+            let mut img = [(1., 1., 1.); (48 + 6) * 20];
+            for (sticker, pixels) in img.chunks_mut(20).take(48).enumerate() {
+                let color = &group.facelet_colors()[perm.state().get(sticker)];
+                let color = match &**color {
+                    "white" => (0.8, 0.8, 0.8),
+                    "yellow" => (0.4, 0.4, 0.4),
+                    _ => NATURAL_COLORS[color],
+                };
+                pixels.fill(color);
             }
 
-            for i in pos + 1..slice.len() {
-                assert!(
-                    slice[i].0 <= slice[pos].0,
-                    "Pos: {pos}, Index: {i} - {slice:?}"
-                );
+            for (r, g, b) in &mut img {
+                let noise = rng.random_range(0.95..1.05);
+                *r *= noise;
+                *g *= noise;
+                *b *= noise;
+            }
+
+            img
+        };
+
+        for _ in 0..20 {
+            let perm = stabchain.random(&mut rng);
+            inference.calibrate(&render(&perm, &mut rng), &perm);
+        }
+
+        let matcher = Matcher::new(Arc::clone(&puzzle));
+        let white = ArcIntern::from("white");
+        let yellow = ArcIntern::from("yellow");
+
+        for _ in 0..5 {
+            let perm = stabchain.random(&mut rng);
+            let confidences = inference.infer(&render(&perm, &mut rng));
+            assert_eq!(matcher.most_likely(&confidences).unwrap().0, perm);
+
+            for (sticker, confidences) in confidences.iter().enumerate() {
+                let color = &group.facelet_colors()[perm.state().get(sticker)];
+                if *color == white {
+                    assert!(confidences[&white] > confidences[&yellow]);
+                } else if *color == yellow {
+                    assert!(confidences[&yellow] > confidences[&white]);
+                }
             }
+        }
+    }
+
+    #[test]
+    fn color_points() {
+        let puzzle = puzzle("3x3");
+        let group = puzzle.permutation_group();
+        let colors = group
+            .facelet_colors()
+            .iter()
+            .unique()
+            .cloned()
+            .collect_vec();
+
+        // One pixel per sticker, and a white balance pixel that leaves the colors as they are on every face
+        let assignment = (0..48)
+            .map(crate::Pixel::Sticker)
+            .chain(colors.iter().cloned().map(crate::Pixel::WhiteBalance))
+            .collect_vec();
+        let mut inference = Inference::new(assignment.into(), &puzzle);
+
+        let img = (0..48)
+            .map(|i| (f64::from(i) / 48., 0.5, 0.25))
+            .chain([(1., 1., 1.); 6])
+            .collect_vec();
+        let solved = Permutation::from_cycles(Vec::new());
+        inference.calibrate(&img, &solved);
+
+        let points = inference.color_points();
+        assert_eq!(points.len(), 6);
+        for color in &colors {
+            let expected = (0..48)
+                .filter(|i| group.facelet_colors()[*i] == *color)
+                .map(|i| [img[i].0, img[i].1, img[i].2])
+                .collect_vec();
+            assert_eq!(points[color], expected);
+        }
+
+        // Every calibration adds a sample to every sticker
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"Plot the points, find the mixups");
+        let perm = StabilizerChain::new(&group).random(&mut rng);
+        inference.calibrate(&img, &perm);
+        for points in inference.color_points().values() {
+            assert_eq!(points.len(), 16);
+        }
+    }
+
+    #[test]
+    fn shuffled_kdtree_neighbours() {
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"Same neighbours, different trees");
+
+        let mut samples = (0..2000)
+            .map(|_| {
+                [
+                    rng.random_range(0. ..1.),
+                    rng.random_range(0. ..1.),
+                    rng.random_range(0. ..1.),
+                ]
+            })
+            .collect::<Vec<_>>();
+
+        let mut incremental = KdTree::<f64, 3>::new();
+        for sample in &samples {
+            incremental.add(sample, 0);
+        }
+        let shuffled = shuffled_kdtree(&mut rng, &mut samples);
+        assert_eq!(shuffled.size(), incremental.size());
+
+        for _ in 0..200 {
+            let query = [
+                rng.random_range(0. ..1.),
+                rng.random_range(0. ..1.),
+                rng.random_range(0. ..1.),
+            ];
+
+            let distances = |kdtree: &KdTree<f64, 3>| {
+                kdtree
+                    .nearest_n::<SquaredEuclidean>(&query, 10)
+                    .into_iter()
+                    .map(|nn| nn.distance)
+                    .collect::<Vec<_>>()
+            };
+            assert_eq!(distances(&shuffled), distances(&incremental));
+        }
+    }
+
+    #[test]
+    fn merge() {
+        let assignment = standard_assignment();
+
+        let cube2x2 = puzzle("2x2");
+        let puzzle = puzzle("3x3");
+        let group = puzzle.permutation_group();
+        let solved = Permutation::from_cycles(Vec::new());
+        // Every sticker that moves gets the color of the opposite face
+        let checkerboard = Algorithm::parse_from_string(Arc::clone(&group), "R2 L2 U2 D2 F2 B2")
+            .unwrap()
+            .permutation()
+            .clone();
+
+        // Each session only ever sees one of the states, so each sticker is only calibrated with one color
+        let mut merged = Inference::new(assignment.clone().into(), &puzzle);
+        let mut other = Inference::new(assignment.clone().into(), &puzzle);
+
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"Two sessions, two different labs");
+
+        let mut img = [(0., 0., 0.); (48 + 6) * 20];
+
+        for _ in 0..15 {
+            simulate_picture(&solved, &group, 0.2, 0.1, &mut rng, &mut img);
+            merged.calibrate(&img, &solved);
+            simulate_picture(&checkerboard, &group, 0.2, 0.1, &mut rng, &mut img);
+            other.calibrate(&img, &checkerboard);
+        }
 
-            let v = slice[pos];
+        merged.merge(&other).unwrap();
 
-            slice.sort_by(|a, b| b.0.total_cmp(&a.0));
+        let matcher = Matcher::new(Arc::clone(&puzzle));
 
-            assert_eq!(slice[pos].0, v.0);
+        for _ in 0..5 {
+            for perm in [&solved, &checkerboard] {
+                simulate_picture(perm, &group, 0.2, 0.1, &mut rng, &mut img);
+                assert_eq!(matcher.most_likely(&merged.infer(&img)).unwrap().0, *perm);
+            }
         }
 
-        let mut rng = rand::rng();
+        // A calibration with a pixel left out can't be merged
+        let mut shifted = assignment.clone();
+        shifted[0] = crate::Pixel::Unassigned;
+        assert_eq!(
+            merged.merge(&Inference::new(shifted.into(), &puzzle)),
+            Err(IncompatibleCalibration::Assignment)
+        );
+
+        let assignment_2x2 = (0..cube2x2.permutation_group().facelet_count())
+            .map(crate::Pixel::Sticker)
+            .collect();
+        assert_eq!(
+            merged.merge(&Inference::new(assignment_2x2, &cube2x2)),
+            Err(IncompatibleCalibration::Puzzle)
+        );
+
+        let mut linear = Inference::new(assignment.clone().into(), &puzzle);
+        linear.set_params(InferenceParams {
+            linearize_srgb: true,
+            ..InferenceParams::default()
+        });
+        assert_eq!(
+            merged.merge(&linear),
+            Err(IncompatibleCalibration::Linearization)
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn saved_calibration() {
+        let assignment = standard_assignment();
 
-        verify(&mut rng, 2, &[5., 4., 3., 2., 1.]);
-        verify(&mut rng, 2, &[1., 2., 3., 4., 5.]);
-        verify(&mut rng, 3, &[1., 2., 1., 4., 3.]);
+        let puzzle = puzzle("3x3");
+        let group = puzzle.permutation_group();
+        let stabchain = StabilizerChain::new(&group);
+        let mut inference = Inference::new(assignment.clone().into(), &puzzle);
+        inference.set_seed(Some(3));
 
-        for i in 0..100 {
-            let pos = rng.random_range(0..i + 1);
-            let data = (0..i + 1).map(|_| rng.random()).collect::<Vec<_>>();
-            verify(&mut rng, pos, &data);
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"Write it down before we forget!!");
+        let mut img = [(0., 0., 0.); (48 + 6) * 20];
+        for _ in 0..10 {
+            let perm = stabchain.random(&mut rng);
+            simulate_picture(&perm, &group, 0.2, 0.1, &mut rng, &mut img);
+            inference.calibrate(&img, &perm);
         }
+        let text = inference.calibration_to_text();
+
+        let mut loaded = Inference::new(assignment.clone().into(), &puzzle);
+        loaded.set_seed(Some(3));
+        loaded.load_calibration(&text).unwrap();
+        assert_eq!(sorted_points(&loaded), sorted_points(&inference));
+        let perm = stabchain.random(&mut rng);
+        simulate_picture(&perm, &group, 0.2, 0.1, &mut rng, &mut img);
+        assert_eq!(loaded.infer(&img), inference.infer(&img));
+
+        // Only where the samples mean the same thing
+        let mut linear = Inference::new(assignment.clone().into(), &puzzle);
+        linear.set_params(InferenceParams {
+            linearize_srgb: true,
+            ..InferenceParams::default()
+        });
+        assert_eq!(
+            linear.load_calibration(&text),
+            Err(IncompatibleCalibration::Linearization.to_string())
+        );
+
+        let mut moved = assignment.clone();
+        moved.swap(0, 48 * 20);
+        let mut moved = Inference::new(moved.into(), &puzzle);
+        assert_eq!(
+            moved.load_calibration(&text),
+            Err(IncompatibleCalibration::Assignment.to_string())
+        );
+        assert!(!moved.is_calibrated());
+
+        let mut grouped = Inference::with_super_pixels(
+            assignment.into(),
+            crate::SuperPixels::from_labels((0..(48 + 6) * 20).collect()),
+            &puzzle,
+        );
+        assert_eq!(
+            grouped.load_calibration(&text),
+            Err(IncompatibleCalibration::Assignment.to_string())
+        );
+
+        assert!(loaded.load_calibration("qvis calibration 1\n").is_err());
     }
 }
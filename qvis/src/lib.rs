@@ -1,21 +1,93 @@
-use std::sync::Arc;
+#[cfg(feature = "image")]
+use std::path::Path;
+use std::{
+    borrow::Cow,
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    fmt,
+    sync::Arc,
+    time::Instant,
+};
 
 use internment::ArcIntern;
-use puzzle_theory::{permutations::Permutation, puzzle_geometry::PuzzleGeometry};
+use puzzle_theory::{
+    permutations::{Algorithm, Permutation},
+    puzzle_geometry::PuzzleGeometry,
+};
 
-use crate::{inference::Inference, puzzle_matching::Matcher};
+use crate::{
+    facelets::colors_in,
+    puzzle_matching::{Matcher, RecognitionOutcome, RecognitionThresholds, Rotation, StateMatcher},
+    smoothing::Smoother,
+};
 
+mod assignment_plan;
+mod confidence;
+pub mod evaluation;
+mod facelets;
 mod inference;
+mod moves;
+mod profiles;
 pub mod puzzle_matching;
+pub mod select;
+mod smoothing;
+mod solve_log;
+mod super_pixels;
+
+pub use assignment_plan::{
+    InvalidAssignment, StickerToAssign, assignment_plan, check_assignment, validate_assignment,
+};
+pub use confidence::ConfidenceCalibration;
+pub use facelets::{facelet_color, facelet_string, state_colors};
+pub use inference::{
+    Channels, IncompatibleCalibration, Inference, InferenceParams, InferenceScratch,
+    InvalidFaceletLabel, InvalidWeights, Scoring, UnderSampled,
+};
+pub use moves::moves_between;
+pub use profiles::{ProfileError, Profiles};
+pub use solve_log::{SolveLogEntry, SolveLogger};
+pub use super_pixels::SuperPixels;
 
 /// Processes images for computer vision
 pub struct CVProcessor {
+    puzzle: Arc<PuzzleGeometry>,
     image_size: usize,
     matcher: Matcher,
+    // What finds the most likely state in place of `matcher`, if something does
+    state_matcher: Option<Box<dyn StateMatcher>>,
     inference: Inference,
+    smoother: Option<Smoother>,
+    recognition_thresholds: RecognitionThresholds,
+    auto_calibration: Option<AutoCalibration>,
+    // How many frames `process_frame` has calibrated with so far
+    auto_calibrated: usize,
+    confidence_calibration: Option<ConfidenceCalibration>,
+    // The probability of being right that recognized states are held to, which is zero to accept every state
+    min_probability: f64,
+    // The reflection that undoes the mirror, if the images are mirror images of the puzzle like a front-facing camera takes
+    mirror: Option<Box<[usize]>>,
+    // The state that `process_frame` matched in the last frame, which the next frame is matched starting from
+    last_frame: Option<Permutation>,
+}
+
+/// How `CVProcessor::process_frame` keeps calibrating itself with the frames that it recognizes, so that it adapts as the lighting drifts
+#[derive(Debug, Clone, PartialEq)]
+pub struct AutoCalibration {
+    /// Only frames that `Matcher::recognize` is confident in with these thresholds are calibrated with. A frame that is misrecognized and calibrated with anyway teaches the processor the wrong colors, which makes the next misrecognition more likely, so these should be strict.
+    pub thresholds: RecognitionThresholds,
+    /// The most frames that are calibrated with, which caps how far the processor can drift from its manual calibration
+    pub max_frames: usize,
 }
 
+/// An image as `CVProcessor` takes and renders them: the `(r, g, b)` color of each pixel in order, with each channel ranging from zero to one
+pub type Image = Box<[(f64, f64, f64)]>;
+
+/// How much each sticker of the puzzle looks like each color, as log-likelihoods indexed by sticker. A sticker with no estimates at all is unobserved: it was covered, out of view, or too little of it was seen to tell. The matcher places no constraints on unobserved stickers and lets the rest of the puzzle decide what they are, which works as long as every piece has some sticker that was observed.
+pub type Observation = Box<[BTreeMap<ArcIntern<str>, f64>]>;
+
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Pixel {
     /// The pixel is not assigned to anything
     Unassigned,
@@ -32,32 +104,2546 @@ impl CVProcessor {
     ///
     /// The assignment is the same size as the image.
     ///
-    /// Each pixel is configured with a number determining which index sticker of the puzzle it belongs to. This method panics if any indices are out of range. The boolean parameter determines whether the pixel should be treated as white balance for the given face: `false` means that it is not white balance and `true` means that it is white balance.
+    /// Each pixel is either `Pixel::Sticker` with the index of the sticker of the puzzle that it belongs to, or `Pixel::WhiteBalance` with the color of the face that it is white balance for.
     ///
     /// White balance points should be selected such that the face is parallel with the face that it is acting as white balance for.
     ///
-    /// Pixels marked `None` will not be considered in the CV algorithm.
+    /// Pixels marked `Pixel::Unassigned` will not be considered in the CV algorithm.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the assignment doesn't have `image_size` entries, or assigns a pixel to a sticker or face that the puzzle doesn't have.
     pub fn new(
         puzzle: Arc<PuzzleGeometry>,
         image_size: usize,
         assignment: Box<[Pixel]>,
+    ) -> Result<CVProcessor, QvisError> {
+        assignment_plan::check_assignment(&assignment, image_size, &puzzle)?;
+
+        let inference = Inference::new(assignment, &puzzle);
+        Ok(CVProcessor::with_inference(puzzle, image_size, inference))
+    }
+
+    /// Like `new`, but the assignment has one entry per super-pixel of the image rather than per pixel, for images with so many pixels that an assignment of every one of them takes too much memory. Images still have every pixel; the pixels of each super-pixel are averaged before they are classified, which also smooths out noise.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the assignment doesn't have one entry per super-pixel, or assigns a super-pixel to a sticker or face that the puzzle doesn't have.
+    pub fn with_super_pixels(
+        puzzle: Arc<PuzzleGeometry>,
+        super_pixels: SuperPixels,
+        assignment: Box<[Pixel]>,
+    ) -> Result<CVProcessor, QvisError> {
+        assignment_plan::check_assignment(&assignment, super_pixels.len(), &puzzle)?;
+
+        let image_size = super_pixels.image_size();
+        let inference = Inference::with_super_pixels(assignment, super_pixels, &puzzle);
+        Ok(CVProcessor::with_inference(puzzle, image_size, inference))
+    }
+
+    /// A processor of images of `image_size` pixels that scores them with `inference`, with every setting at its default
+    fn with_inference(
+        puzzle: Arc<PuzzleGeometry>,
+        image_size: usize,
+        inference: Inference,
     ) -> CVProcessor {
         CVProcessor {
             image_size,
-            inference: Inference::new(assignment, &puzzle),
-            matcher: Matcher::new(puzzle),
+            inference,
+            matcher: Matcher::new(Arc::clone(&puzzle)),
+            state_matcher: None,
+            puzzle,
+            smoother: None,
+            recognition_thresholds: RecognitionThresholds::default(),
+            auto_calibration: None,
+            auto_calibrated: 0,
+            confidence_calibration: None,
+            min_probability: 0.,
+            mirror: None,
+            last_frame: None,
+        }
+    }
+
+    /// Find the most likely state with another matching algorithm, like `puzzle_matching::GreedyMatcher`, for comparing it with the default. This is what `process_image`, `process_image_with_scratch`, `process_masked_image`, and `process_observations` report, as well as `process_frame` for the first frame. `None`, the default, uses `Matcher`, which the methods that need more than the single most likely state, like `recognize`, always use.
+    pub fn set_state_matcher(&mut self, state_matcher: Option<Box<dyn StateMatcher>>) {
+        self.state_matcher = state_matcher;
+    }
+
+    /// Configure whether the images are mirror images of the puzzle, like a front-facing camera or a mirror shows, while the assignment and calibration are of the puzzle as it is. A mirror swaps the faces on the left and right of the picture, so `Some(color)` takes the color of either of them, in the orientation that the states are written in, and `None`, the default, turns mirroring off. The mirror image of a state is generally not a state that the puzzle can be in, so the reflection is undone before anything is matched, and `process_frame` doesn't calibrate itself with mirrored frames.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the puzzle has no reflection that swaps the face of `color` with another face and leaves the rest where they are. Mirroring is left as it was in that case.
+    pub fn set_mirrored(&mut self, swapped: Option<&ArcIntern<str>>) -> Result<(), QvisError> {
+        self.mirror = match swapped {
+            Some(color) => Some(
+                self.matcher
+                    .reflection_swapping(color)
+                    .ok_or_else(|| QvisError::NoReflection(ArcIntern::clone(color)))?
+                    .into(),
+            ),
+            None => None,
+        };
+
+        Ok(())
+    }
+
+    /// Configure what `recognize` considers a clear view of the puzzle
+    pub fn set_recognition_thresholds(&mut self, thresholds: RecognitionThresholds) {
+        self.recognition_thresholds = thresholds;
+    }
+
+    /// Configure whether `process_frame` calibrates with the frames that it is confident in. `None`, the default, disables this. Enabling it again starts the count of frames towards `max_frames` over.
+    pub fn set_auto_calibration(&mut self, auto_calibration: Option<AutoCalibration>) {
+        self.auto_calibration = auto_calibration;
+        self.auto_calibrated = 0;
+    }
+
+    /// Configure how many recent frames `process_frame` votes over to decide which state to report. `None` disables smoothing so that every frame is reported as-is.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the window is zero. The smoothing is left as it was in that case.
+    pub fn set_smoothing_window(&mut self, window: Option<usize>) -> Result<(), QvisError> {
+        if window == Some(0) {
+            return Err(QvisError::InvalidParams(InvalidParams::SmoothingWindow));
+        }
+
+        self.smoother = window.map(Smoother::new);
+
+        Ok(())
+    }
+
+    /// Configure how pixels are scored against the calibration samples. `InferenceParams::default()` is used until this is called.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `confidence_percentile` isn't less than one. The parameters are left as they were in that case.
+    pub fn set_inference_params(&mut self, params: InferenceParams) -> Result<(), QvisError> {
+        if !(..1.).contains(&params.confidence_percentile) {
+            return Err(QvisError::InvalidParams(
+                InvalidParams::ConfidencePercentile(params.confidence_percentile),
+            ));
+        }
+
+        self.inference.set_params(params);
+
+        Ok(())
+    }
+
+    /// How pixels are scored against the calibration samples, as set with `set_inference_params`
+    pub fn inference_params(&self) -> &InferenceParams {
+        self.inference.params()
+    }
+
+    /// Whether the colors are far enough apart in the calibration so far for `Scoring::Centroid` to be used. When they aren't, pixels are scored with `Scoring::Density` instead, which is slower but can tell closer colors apart.
+    pub fn centroids_separate(&self) -> bool {
+        self.inference.centroids_separate()
+    }
+
+    /// Seed the randomness of the inference so that the same calibration and image always give the same confidences, for reproducing a recognition while debugging. `None`, the default, seeds it from entropy.
+    pub fn set_seed(&mut self, seed: Option<u64>) {
+        self.inference.set_seed(seed);
+    }
+
+    /// Set how much each pixel counts towards the likelihood of its sticker's color, relative to the other pixels of the sticker. Every pixel starts out with a weight of one. Pixels near the edge of a sticker are the first to pick up the colors of its neighbours under motion blur or a slightly misaligned camera, so weighting the pixels near its center more makes the recognition more robust.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there isn't one weight per pixel, or per super-pixel if the processor was created with `with_super_pixels`, if a weight is negative or NaN, or if every pixel of a sticker has a weight of zero. The weights are left as they were in that case.
+    pub fn set_pixel_weights(&mut self, weights: &[f64]) -> Result<(), QvisError> {
+        let assigned = self
+            .inference
+            .super_pixels()
+            .map_or(self.image_size, SuperPixels::len);
+        if weights.len() != assigned {
+            return Err(QvisError::InvalidWeights(InvalidWeights::Size {
+                expected: assigned,
+                found: weights.len(),
+            }));
         }
+
+        Ok(self.inference.set_weights(weights)?)
     }
 
     /// Calibrate the CV processor with an image of the puzzle in the given state.
-    pub fn calibrate(&mut self, image: &[(f64, f64, f64)], state: Permutation) {
-        assert_eq!(self.image_size, image.len());
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the image doesn't have `image_size` pixels.
+    pub fn calibrate(
+        &mut self,
+        image: &[(f64, f64, f64)],
+        state: Permutation,
+    ) -> Result<(), QvisError> {
+        self.check_size(image.len())?;
 
         self.inference.calibrate(image, &state);
+
+        Ok(())
+    }
+
+    /// Calibrate the CV processor with an image of the solved puzzle. This is the usual first calibration step.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the image doesn't have `image_size` pixels.
+    pub fn calibrate_solved(&mut self, image: &[(f64, f64, f64)]) -> Result<(), QvisError> {
+        self.calibrate(image, Permutation::from_cycles(Vec::new()))
+    }
+
+    /// Render what the classifier sees in an image like `render_inferred_colors`, then calibrate the CV processor with it like `calibrate`. The image is rendered before its samples are added, since every pixel would otherwise find its own sample and the preview would always be right. Showing this for every calibration image closes the loop during calibration: stickers whose color comes out wrong in the preview need more samples, and once the previews stop being wrong the calibration covers the puzzle.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the image doesn't have `image_size` pixels.
+    pub fn calibrate_with_preview(
+        &mut self,
+        image: &[(f64, f64, f64)],
+        state: Permutation,
+    ) -> Result<Image, QvisError> {
+        let preview = self.render_inferred_colors(image)?;
+        self.calibrate(image, state)?;
+
+        Ok(preview)
+    }
+
+    /// Calibrate the CV processor with only some facelets of an image, each labeled with the color that it shows, for when the state of the rest of the puzzle isn't known. This is handy for correcting a single color that calibrated badly.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the image doesn't have `image_size` pixels, a facelet index is out of range, or a color isn't one of the puzzle's. Nothing is calibrated in that case.
+    pub fn calibrate_facelets(
+        &mut self,
+        image: &[(f64, f64, f64)],
+        labels: &[(usize, ArcIntern<str>)],
+    ) -> Result<(), QvisError> {
+        self.check_size(image.len())?;
+
+        Ok(self.inference.calibrate_facelets(image, labels)?)
+    }
+
+    /// Give `sticker` the pixels that `assignment` assigns to it and start its calibration over, keeping the calibration of every other sticker. This fixes a sticker that is consistently misclassified because of where its pixels were picked, like on a scratch or a reflection, without assigning and calibrating every sticker again: reselect its pixels, pass the assignment with only that sticker changed, and calibrate just that sticker with `calibrate_facelets`. The new pixels have a weight of one.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the assignment isn't the size of the one that the processor was created with or assigns pixels to stickers or faces that the puzzle doesn't have, or if the puzzle doesn't have the sticker. Nothing is reassigned in that case.
+    pub fn reassign_sticker(
+        &mut self,
+        sticker: usize,
+        assignment: &[Pixel],
+    ) -> Result<(), QvisError> {
+        let assigned = self
+            .inference
+            .super_pixels()
+            .map_or(self.image_size, SuperPixels::len);
+        assignment_plan::check_assignment(assignment, assigned, &self.puzzle)?;
+
+        Ok(self.inference.reassign_sticker(sticker, assignment)?)
+    }
+
+    /// Calibrate the CV processor with many images of the puzzle, each in the given state. This is equivalent to calling `calibrate` with each image in order but faster, which makes it the natural way to calibrate from a folder of photos.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if any of the images doesn't have `image_size` pixels. Nothing is calibrated in that case.
+    pub fn calibrate_batch(&mut self, images: &[(Image, Permutation)]) -> Result<(), QvisError> {
+        let images = images
+            .iter()
+            .map(|(image, state)| {
+                self.check_size(image.len())?;
+                Ok((&**image, state))
+            })
+            .collect::<Result<Vec<_>, QvisError>>()?;
+
+        self.inference.calibrate_batch(&images);
+
+        Ok(())
+    }
+
+    /// Calibrate the CV processor with an image of the puzzle after applying the given algorithm to a solved puzzle.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the image doesn't have `image_size` pixels.
+    pub fn calibrate_with_algorithm(
+        &mut self,
+        image: &[(f64, f64, f64)],
+        algorithm: &Algorithm,
+    ) -> Result<(), QvisError> {
+        self.calibrate(image, algorithm.permutation().clone())
+    }
+
+    /// Calibrate the CV processor with an image of the puzzle after applying the moves written in `moves` (for example `"R U R' U'"`) to a solved puzzle.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the image doesn't have `image_size` pixels, or if `moves` doesn't parse or contains moves that the puzzle doesn't have; nothing is calibrated in that case.
+    pub fn calibrate_with_moves(
+        &mut self,
+        image: &[(f64, f64, f64)],
+        moves: &str,
+    ) -> Result<(), QvisError> {
+        let algorithm = Algorithm::parse_from_string(self.puzzle.permutation_group(), moves)
+            .ok_or_else(|| InvalidAlgorithm(moves.to_owned()))?;
+
+        self.calibrate_with_algorithm(image, &algorithm)
+    }
+
+    /// Add the calibration of another CV processor to this one, for example to combine calibrations made by different people or under different lighting. This is as if this processor had also been calibrated with every image that `other` was; the settings of this processor are kept.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `other` recognizes a different puzzle or assigns the pixels differently. Nothing is merged in that case.
+    pub fn merge(&mut self, other: &CVProcessor) -> Result<(), QvisError> {
+        if self.image_size != other.image_size {
+            return Err(IncompatibleCalibration::Assignment.into());
+        }
+
+        Ok(self.inference.merge(&other.inference)?)
+    }
+
+    /// Save the calibration as the profile named `name`, replacing the profile if it already exists, so that it can be loaded again when the processor is back in the same lighting
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `name` can't be the name of a profile or the profile couldn't be written.
+    #[cfg(feature = "serde")]
+    pub fn save_profile(&self, profiles: &Profiles, name: &str) -> Result<(), QvisError> {
+        Ok(profiles.write(name, &self.calibration_text())?)
+    }
+
+    /// Replace the calibration with the profile named `name`. The settings of this processor are kept, including the confidence calibration, which should be fit again if it was fit under different lighting.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the profile couldn't be read or isn't a calibration of this puzzle with this assignment. The calibration is left as it was in that case.
+    #[cfg(feature = "serde")]
+    pub fn load_profile(&mut self, profiles: &Profiles, name: &str) -> Result<(), QvisError> {
+        let text = profiles.read(name)?;
+
+        self.load_calibration_text(&text)
+    }
+
+    /// The calibration as JSON, which is what `save_profile` writes. See `Inference::calibration_to_text`. Profiles need a file system, so this is how a calibration made elsewhere gets to a processor running in the browser.
+    #[cfg(feature = "serde")]
+    pub fn calibration_text(&self) -> String {
+        self.inference.calibration_to_text()
+    }
+
+    /// Replace the calibration with one written by `calibration_text`, like `load_profile` does. The settings of this processor are kept.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the text isn't a calibration of this puzzle with this assignment and these parameters. The calibration is left as it was in that case.
+    #[cfg(feature = "serde")]
+    pub fn load_calibration_text(&mut self, text: &str) -> Result<(), QvisError> {
+        Ok(self
+            .inference
+            .load_calibration(text)
+            .map_err(ProfileError::Invalid)?)
     }
 
     /// Process an image and return the most likely state that the puzzle appears to be in, along with the confidence in the prediction. This is guaranteed to be a valid member of the group.
-    pub fn process_image(&self, image: Box<[(f64, f64, f64)]>) -> (Permutation, f64) {
-        self.matcher.most_likely(&self.inference.infer(&image))
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the image doesn't have `image_size` pixels, nothing has been calibrated with yet, or the most likely state isn't likely enough to be right for `set_min_probability`.
+    pub fn process_image(&self, image: Image) -> Result<(Permutation, f64), QvisError> {
+        self.check_ready(image.len())?;
+
+        self.best(&timed("inference", || self.inference.infer(&image)))
+    }
+
+    /// Like `process_image`, but reuses the buffers in `scratch` for the per-pixel work. A real-time caller can create one `InferenceScratch` up front and pass it in for every frame to avoid allocating on each one.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the image doesn't have `image_size` pixels, nothing has been calibrated with yet, or the most likely state isn't likely enough to be right for `set_min_probability`.
+    pub fn process_image_with_scratch(
+        &self,
+        image: &[(f64, f64, f64)],
+        scratch: &mut InferenceScratch,
+    ) -> Result<(Permutation, f64), QvisError> {
+        self.check_ready(image.len())?;
+
+        self.best(timed("inference", || {
+            self.inference.infer_into(image, scratch)
+        }))
+    }
+
+    /// Like `process_image`, but only considers the given states, for when the context already narrows down what the puzzle can be. See `Matcher::most_likely_among`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the image doesn't have `image_size` pixels, nothing has been calibrated with yet, or there are no states to pick from.
+    pub fn process_image_among(
+        &self,
+        image: &[(f64, f64, f64)],
+        candidates: &[Permutation],
+    ) -> Result<(Permutation, f64), QvisError> {
+        self.check_ready(image.len())?;
+
+        self.matcher
+            .most_likely_among(&self.puzzle_confidences(image), candidates)
+            .ok_or(QvisError::NoValidState)
+    }
+
+    /// Like `process_image`, but only the observed pixels of the image are used. Stickers none of whose pixels were observed count as unseen, like stickers that no pixel is assigned to.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the image or its mask doesn't have `image_size` pixels, nothing has been calibrated with yet, or the most likely state isn't likely enough to be right for `set_min_probability`.
+    pub fn process_masked_image(
+        &self,
+        image: &MaskedImage,
+    ) -> Result<(Permutation, f64), QvisError> {
+        self.check_size(image.observed.len())?;
+        self.check_ready(image.pixels.len())?;
+
+        self.best(&timed("inference", || {
+            self.inference.infer_masked(&image.pixels, &image.observed)
+        }))
+    }
+
+    /// Like `process_image`, but for a short burst of frames of the puzzle held still. Each pixel is averaged across the frames before the average is recognized once, which cancels out much of the noise of the camera that a single frame has.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if there are no frames, if any of them doesn't have `image_size` pixels, or for any of the reasons that `process_image` does.
+    pub fn process_burst(&self, frames: &[Image]) -> Result<(Permutation, f64), QvisError> {
+        if frames.is_empty() {
+            return Err(QvisError::EmptyBurst);
+        }
+        for frame in frames {
+            self.check_size(frame.len())?;
+        }
+
+        let mut average = vec![(0., 0., 0.); self.image_size];
+        for frame in frames {
+            for ((sum_r, sum_g, sum_b), (r, g, b)) in average.iter_mut().zip(frame) {
+                *sum_r += r;
+                *sum_g += g;
+                *sum_b += b;
+            }
+        }
+
+        let count = frames.len() as f64;
+        for (r, g, b) in &mut average {
+            *r /= count;
+            *g /= count;
+            *b /= count;
+        }
+
+        self.process_image(average.into())
+    }
+
+    /// Estimate how much each sticker looks like each color in an image, as log-likelihoods. Stickers that no pixel is assigned to aren't visible and have no estimates. This is the first half of `process_image`; it is exposed so that photos from several angles can be combined with `process_observations`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the image doesn't have `image_size` pixels or nothing has been calibrated with yet.
+    pub fn observe(&self, image: &[(f64, f64, f64)]) -> Result<Observation, QvisError> {
+        self.check_ready(image.len())?;
+
+        Ok(self.inference.infer(image))
+    }
+
+    /// Like `observe`, but only the observed pixels of the image are used, like in `process_masked_image`. Stickers that are hidden by the mask, or that have less than `InferenceParams::min_observed_fraction` of their pixels observed, are unobserved.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the image or its mask doesn't have `image_size` pixels or nothing has been calibrated with yet.
+    pub fn observe_masked(&self, image: &MaskedImage) -> Result<Observation, QvisError> {
+        self.check_size(image.observed.len())?;
+        self.check_ready(image.pixels.len())?;
+
+        Ok(self.inference.infer_masked(&image.pixels, &image.observed))
+    }
+
+    /// Recognize the puzzle from photos taken from several angles, which is the only way to see every face of a cube. Each photo needs its own `CVProcessor`, with an assignment that marks what the photo shows and leaves the stickers that it can't see unassigned, and is turned into an observation with `observe`. The observations are combined with `puzzle_matching::fuse_observations` before matching, so any of the processors can do the matching.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the observations don't have one entry per sticker of the puzzle, or if the most likely state isn't likely enough to be right for `set_min_probability`.
+    pub fn process_observations(
+        &self,
+        observations: &[Observation],
+    ) -> Result<(Permutation, f64), QvisError> {
+        let stickers = self.puzzle.permutation_group().facelet_count();
+        if let Some(observation) = observations.iter().find(|v| v.len() != stickers) {
+            return Err(QvisError::ObservationSize {
+                expected: stickers,
+                found: observation.len(),
+            });
+        }
+
+        self.best(&puzzle_matching::fuse_observations(observations))
+    }
+
+    /// Like `process_image`, but the puzzle doesn't have to be held in the reference orientation: every way of holding it is tried, and the one that fits best is returned along with the state of the puzzle as it is held. See `Matcher::most_likely_any_orientation`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the image doesn't have `image_size` pixels, nothing has been calibrated with yet, or no state could be matched to the image in any orientation.
+    pub fn process_image_any_orientation(
+        &self,
+        image: &[(f64, f64, f64)],
+    ) -> Result<(Permutation, f64, Rotation), QvisError> {
+        self.check_ready(image.len())?;
+
+        self.matcher
+            .most_likely_any_orientation(&self.puzzle_confidences(image))
+            .ok_or(QvisError::NoValidState)
+    }
+
+    /// Like `process_image`, but returns `None` if the image can't tell the most likely state apart from the next most likely one. That happens when the puzzle has pieces that look identical, like the centers of a 4x4 or the pieces of a puzzle where faces share a color: `process_image` picks one of the look-alikes arbitrarily and reports it as confidently as if it could see the difference.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the image doesn't have `image_size` pixels or nothing has been calibrated with yet.
+    pub fn process_image_unambiguous(
+        &self,
+        image: Image,
+    ) -> Result<Option<(Permutation, f64)>, QvisError> {
+        self.check_ready(image.len())?;
+
+        let confidences = self.puzzle_confidences(&image);
+        let mut candidates = self.matcher.candidates(&confidences);
+        let best = candidates.next().ok_or(QvisError::NoValidState)?;
+
+        Ok(match candidates.next() {
+            Some((runner_up, _)) if self.matcher.looks_identical(&best.0, &runner_up) => None,
+            _ => Some(best),
+        })
+    }
+
+    /// Like `process_image`, but decides whether the image shows the puzzle clearly enough to report a state at all, according to the thresholds set with `set_recognition_thresholds`. Frames of a video captured while a face is being turned match no state well, and this lets the caller wait for a clean one instead. Images in which too few pixels look like any calibrated color aren't matched at all and come out as `RecognitionOutcome::NoPuzzle`, so that the caller can tell pointing the camera away from the puzzle apart from holding the puzzle badly.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the image doesn't have `image_size` pixels or nothing has been calibrated with yet.
+    pub fn recognize(&self, image: &[(f64, f64, f64)]) -> Result<RecognitionOutcome, QvisError> {
+        self.check_ready(image.len())?;
+
+        if self.inference.plausible_fraction(image)
+            < self.recognition_thresholds.min_plausible_fraction
+        {
+            return Ok(RecognitionOutcome::NoPuzzle);
+        }
+
+        Ok(self.matcher.recognize(
+            &self.puzzle_confidences(image),
+            &self.recognition_thresholds,
+        ))
+    }
+
+    /// The fraction of the pixels assigned to stickers that plausibly show one of the calibrated colors. See `Inference::plausible_fraction`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the image doesn't have `image_size` pixels or nothing has been calibrated with yet.
+    pub fn plausible_fraction(&self, image: &[(f64, f64, f64)]) -> Result<f64, QvisError> {
+        self.check_ready(image.len())?;
+
+        Ok(self.inference.plausible_fraction(image))
+    }
+
+    /// Whether the image shows the puzzle at rest in a state that `recognize` is confident in
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the image doesn't have `image_size` pixels or nothing has been calibrated with yet.
+    pub fn is_stable(&self, image: &[(f64, f64, f64)]) -> Result<bool, QvisError> {
+        Ok(matches!(
+            self.recognize(image)?,
+            RecognitionOutcome::Confident(..)
+        ))
+    }
+
+    /// Load the image at the given path and process it like `process_image`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the image couldn't be read or decoded, or for any of the reasons that `process_image` does.
+    #[cfg(feature = "image")]
+    pub fn process_image_file(
+        &self,
+        path: impl AsRef<Path>,
+    ) -> Result<(Permutation, f64), QvisError> {
+        self.process_image(load_image(path)?)
+    }
+
+    /// Render what the classifier sees in an image for visual verification: every pixel assigned to a sticker is painted with the average calibrated appearance of the color that the sticker most likely has, and every other pixel is black.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the image doesn't have `image_size` pixels.
+    pub fn render_inferred_colors(&self, image: &[(f64, f64, f64)]) -> Result<Image, QvisError> {
+        self.check_size(image.len())?;
+
+        Ok(self.inference.render(image))
+    }
+
+    /// How confidently each pixel of an image picks the color that its sticker most likely has, from zero to one, for showing as a heatmap over the image to see which pixels the calibration struggles with. The map is the same size as the image and pixels that aren't assigned to a sticker are zero. See `Inference::confidence_map`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the image doesn't have `image_size` pixels.
+    pub fn confidence_map(&self, image: &[(f64, f64, f64)]) -> Result<Box<[f64]>, QvisError> {
+        self.check_size(image.len())?;
+
+        Ok(self.inference.confidence_map(image))
+    }
+
+    /// Every calibration sample of each color, white balanced, for plotting the colors to see which of them the classifier confuses
+    pub fn color_points(&self) -> HashMap<ArcIntern<str>, Vec<[f64; 3]>> {
+        self.inference.color_points()
+    }
+
+    /// How many calibration samples each sticker has of each color, to tell how far along the calibration is. See `Inference::calibration_coverage`.
+    pub fn calibration_coverage(&self) -> HashMap<(usize, ArcIntern<str>), usize> {
+        self.inference.calibration_coverage()
+    }
+
+    /// The colors and stickers that need more calibration pictures than the others. See `Inference::under_sampled`.
+    pub fn under_sampled(&self) -> UnderSampled {
+        self.inference.under_sampled()
+    }
+
+    /// Fit how the log-likelihoods that `process_image` reports map to the probability that the state is right, using images of the puzzle labeled with the state that it is really in. The images should be different from the ones calibrated with, or the recognition will look more reliable than it is, and this should be done after calibrating, since calibrating changes the log-likelihoods.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error for any of the reasons that `process_image` does, other than the state being uncertain. The previous fit is kept in that case.
+    pub fn calibrate_confidence(
+        &mut self,
+        samples: &[(Image, Permutation)],
+    ) -> Result<(), QvisError> {
+        let group = self.puzzle.permutation_group();
+        let examples = samples
+            .iter()
+            .map(|(image, truth)| {
+                // Not `process_image`, which would leave out the unclear images that the fit needs the most
+                self.check_ready(image.len())?;
+                let (found, log_likelihood) = self
+                    .matcher
+                    .candidates(&self.puzzle_confidences(image))
+                    .next()
+                    .ok_or(QvisError::NoValidState)?;
+                let right = colors_in(&group, &found) == colors_in(&group, truth);
+                Ok((log_likelihood, right))
+            })
+            .collect::<Result<Vec<_>, QvisError>>()?;
+
+        self.confidence_calibration = Some(ConfidenceCalibration::fit(&examples));
+
+        Ok(())
+    }
+
+    /// The mapping that `probability` uses, so that it can be saved along with the rest of the calibration
+    pub fn confidence_calibration(&self) -> Option<ConfidenceCalibration> {
+        self.confidence_calibration
+    }
+
+    /// Replace the mapping that `probability` uses, for example with one saved from `confidence_calibration`. `None` removes it.
+    pub fn set_confidence_calibration(&mut self, calibration: Option<ConfidenceCalibration>) {
+        self.confidence_calibration = calibration;
+    }
+
+    /// Make `process_image` return `QvisError::Uncertain` instead of a state whose `probability` of being right is below `min_probability`, so that a video can skip frames that are too unclear to act on. The same goes for `process_image_with_scratch`, `process_masked_image`, `process_observations`, and `process_frame`. Zero, the default, accepts every state, and so does any threshold until the confidence has been calibrated, since there is no probability to hold the states to before then.
+    pub fn set_min_probability(&mut self, min_probability: f64) {
+        self.min_probability = min_probability;
+    }
+
+    /// The probability that a state recognized with the given log-likelihood is right, between zero and one. Unlike the log-likelihood, this means the same thing for every puzzle and image size, which makes it what thresholds shown to users should be in. Returns `None` until `calibrate_confidence` or `set_confidence_calibration` has been called.
+    pub fn probability(&self, log_likelihood: f64) -> Option<f64> {
+        self.confidence_calibration
+            .map(|calibration| calibration.probability(log_likelihood))
+    }
+
+    /// Write the given state of the puzzle in Kociemba's facelet notation (URFDLB), which is what most cube solvers accept. Returns `None` if the puzzle isn't a 2x2 or 3x3 cube with the standard color scheme.
+    pub fn facelet_string(&self, state: &Permutation) -> Option<String> {
+        facelets::facelet_string(&self.puzzle, state)
+    }
+
+    /// The shortest sequence of at most `max_moves` moves that takes the puzzle from recognized state `a` to recognized state `b`, or `None` if there is none. See `moves_between`.
+    pub fn moves_between(
+        &self,
+        a: &Permutation,
+        b: &Permutation,
+        max_moves: usize,
+    ) -> Option<Algorithm> {
+        moves::moves_between(&self.puzzle, a, b, max_moves)
+    }
+
+    /// Process a frame of a live video. This is the same as `process_image` except that if smoothing is enabled, the state reported is the one that the most recent frames agree on, which keeps a single bad frame from making the output flicker. If auto-calibration is enabled, the frame is also calibrated with afterwards if it is recognized confidently enough. The matching starts from the state of the previous frame, which is usually the same or one turn away, so most frames skip the search; see `Matcher::most_likely_near`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the image doesn't have `image_size` pixels, nothing has been calibrated with yet, or the most likely state isn't likely enough to be right for `set_min_probability`. The frame isn't counted towards the smoothing in that case.
+    pub fn process_frame(&mut self, image: Image) -> Result<(Permutation, f64), QvisError> {
+        self.check_ready(image.len())?;
+
+        let confidences = timed("inference", || self.inference.infer(&image));
+        let confidences = self.unmirrored(&confidences);
+        let result = match &self.last_frame {
+            Some(hint) => timed("matching", || {
+                self.matcher.most_likely_near(&confidences, hint)
+            })
+            .ok_or(QvisError::NoValidState)
+            .and_then(|result| self.check_confidence(result))?,
+            None => self.best_unmirrored(&confidences)?,
+        };
+        self.last_frame = Some(result.0.clone());
+
+        // The calibration is of the puzzle as it is, which a mirrored frame doesn't show
+        if self.mirror.is_none()
+            && let Some(auto_calibration) = &self.auto_calibration
+            && self.auto_calibrated < auto_calibration.max_frames
+            && let RecognitionOutcome::Confident(state, _) = self
+                .matcher
+                .recognize(&confidences, &auto_calibration.thresholds)
+        {
+            self.inference.calibrate(&image, &state);
+            self.auto_calibrated += 1;
+        }
+
+        Ok(match &mut self.smoother {
+            Some(smoother) => smoother.push(result),
+            None => result,
+        })
+    }
+
+    fn check_size(&self, found: usize) -> Result<(), QvisError> {
+        if found == self.image_size {
+            Ok(())
+        } else {
+            Err(QvisError::ImageSize {
+                expected: self.image_size,
+                found,
+            })
+        }
+    }
+
+    /// Check that an image of `found` pixels can be recognized
+    fn check_ready(&self, found: usize) -> Result<(), QvisError> {
+        self.check_size(found)?;
+
+        if self.inference.is_calibrated() {
+            Ok(())
+        } else {
+            Err(QvisError::Uncalibrated)
+        }
+    }
+
+    fn best(
+        &self,
+        confidences: &[BTreeMap<ArcIntern<str>, f64>],
+    ) -> Result<(Permutation, f64), QvisError> {
+        self.best_unmirrored(&self.unmirrored(confidences))
+    }
+
+    /// Like `best`, for confidences that `unmirrored` has already been applied to
+    fn best_unmirrored(
+        &self,
+        confidences: &[BTreeMap<ArcIntern<str>, f64>],
+    ) -> Result<(Permutation, f64), QvisError> {
+        timed("matching", || self.most_likely(confidences))
+            .ok_or(QvisError::NoValidState)
+            .and_then(|result| self.check_confidence(result))
+    }
+
+    /// How much each sticker of the puzzle itself looks like each color in an image, with the mirror undone if the images are mirrored
+    fn puzzle_confidences(&self, image: &[(f64, f64, f64)]) -> Observation {
+        let confidences = self.inference.infer(image);
+        match &self.mirror {
+            Some(reflection) => self.matcher.unmirrored(&confidences, reflection),
+            None => confidences,
+        }
+    }
+
+    /// The confidences of the puzzle itself, with the mirror undone if the images are mirrored
+    fn unmirrored<'a>(
+        &self,
+        confidences: &'a [BTreeMap<ArcIntern<str>, f64>],
+    ) -> Cow<'a, [BTreeMap<ArcIntern<str>, f64>]> {
+        match &self.mirror {
+            Some(reflection) => {
+                Cow::Owned(self.matcher.unmirrored(confidences, reflection).into_vec())
+            }
+            None => Cow::Borrowed(confidences),
+        }
+    }
+
+    fn most_likely(
+        &self,
+        confidences: &[BTreeMap<ArcIntern<str>, f64>],
+    ) -> Option<(Permutation, f64)> {
+        match &self.state_matcher {
+            Some(state_matcher) => state_matcher.most_likely(confidences),
+            None => StateMatcher::most_likely(&self.matcher, confidences),
+        }
+    }
+
+    /// Check that a recognized state is at least as likely to be right as `set_min_probability` requires
+    fn check_confidence(
+        &self,
+        (state, log_likelihood): (Permutation, f64),
+    ) -> Result<(Permutation, f64), QvisError> {
+        match self.probability(log_likelihood) {
+            Some(probability) if probability < self.min_probability => {
+                Err(QvisError::Uncertain { probability })
+            }
+            _ => Ok((state, log_likelihood)),
+        }
+    }
+}
+
+/// Compare two log-likelihoods, or anything else where greater is better, such that NaN is worse than everything, even negative infinity, and the same as any other NaN. `f64::total_cmp` puts positive NaNs above positive infinity instead, so a NaN that slipped through would beat every real state; with this, it never wins a comparison that anything else is in. Infinities order as usual, and zero and negative zero are equal.
+pub fn cmp_ll(a: f64, b: f64) -> Ordering {
+    match (a.is_nan(), b.is_nan()) {
+        (true, true) => Ordering::Equal,
+        (true, false) => Ordering::Less,
+        (false, true) => Ordering::Greater,
+        (false, false) => a.partial_cmp(&b).unwrap(),
+    }
+}
+
+/// Run `f`, logging how long it took at the debug level as the given stage of the recognition, to tell whether slow frames are slow to infer or to match. The clock isn't read unless debug logging is enabled, so this costs nothing otherwise.
+fn timed<T>(stage: &str, f: impl FnOnce() -> T) -> T {
+    // `Instant` panics in the browser
+    if cfg!(target_arch = "wasm32") || !log::log_enabled!(log::Level::Debug) {
+        return f();
+    }
+
+    let start = Instant::now();
+    let result = f();
+    log::debug!(
+        "{stage} took {:.2} ms",
+        start.elapsed().as_secs_f64() * 1000.
+    );
+
+    result
+}
+
+/// An image in which only some pixels were observed, for example a captured region with transparent parts or a frame with an overlay drawn over it. Pixels that weren't observed are left out of the recognition entirely rather than being read as whatever color they happen to hold.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MaskedImage {
+    /// One `(r, g, b)` tuple per pixel, like `CVProcessor::process_image` takes
+    pub pixels: Image,
+    /// Whether each pixel was observed
+    pub observed: Box<[bool]>,
+}
+
+impl MaskedImage {
+    /// Convert RGBA data with eight bits per channel, as it comes out of a canvas, treating fully transparent pixels as unobserved. This method panics if the length of the data isn't a multiple of four.
+    pub fn from_rgba8(data: &[u8]) -> MaskedImage {
+        let (rgba, []) = data.as_chunks::<4>() else {
+            panic!("The length of RGBA data must be a multiple of four");
+        };
+
+        let (pixels, observed) = rgba
+            .iter()
+            .map(|&[r, g, b, a]| {
+                (
+                    (
+                        f64::from(r) / 255.,
+                        f64::from(g) / 255.,
+                        f64::from(b) / 255.,
+                    ),
+                    a != 0,
+                )
+            })
+            .unzip::<_, _, Vec<_>, Vec<_>>();
+
+        MaskedImage {
+            pixels: pixels.into(),
+            observed: observed.into(),
+        }
+    }
+
+    /// Mark the pixels whose every channel is at least `threshold` as unobserved. These are glare, or are overexposed, and show the light rather than the color of the sticker under them.
+    pub fn mask_glare(&mut self, threshold: f64) {
+        for (pixel, observed) in self.pixels.iter().zip(self.observed.iter_mut()) {
+            if pixel.0 >= threshold && pixel.1 >= threshold && pixel.2 >= threshold {
+                *observed = false;
+            }
+        }
+    }
+}
+
+/// A move sequence that couldn't be parsed as an algorithm on the puzzle
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidAlgorithm(pub String);
+
+impl fmt::Display for InvalidAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "`{}` is not a valid algorithm for the puzzle", self.0)
+    }
+}
+
+impl Error for InvalidAlgorithm {}
+
+/// A setting of `CVProcessor` that can't be used
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InvalidParams {
+    /// `InferenceParams::confidence_percentile` isn't less than one
+    ConfidencePercentile(f64),
+    /// The smoothing window is zero, so there would be no frames to vote over
+    SmoothingWindow,
+}
+
+impl fmt::Display for InvalidParams {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvalidParams::ConfidencePercentile(percentile) => write!(
+                f,
+                "The confidence percentile is {percentile} but must be less than one"
+            ),
+            InvalidParams::SmoothingWindow => {
+                write!(f, "The smoothing window must contain at least one frame")
+            }
+        }
+    }
+}
+
+impl Error for InvalidParams {}
+
+/// Why `CVProcessor` couldn't do what was asked of it
+#[derive(Debug)]
+pub enum QvisError {
+    /// The image doesn't have as many pixels as the processor was created for
+    ImageSize {
+        /// How many pixels the processor was created for
+        expected: usize,
+        /// How many pixels the image has
+        found: usize,
+    },
+    /// An observation doesn't have one entry per sticker of the puzzle
+    ObservationSize {
+        /// How many stickers the puzzle has
+        expected: usize,
+        /// How many entries the observation has
+        found: usize,
+    },
+    /// The processor hasn't been calibrated with anything, so it has nothing to recognize colors by
+    Uncalibrated,
+    /// `CVProcessor::process_burst` was given no frames to average
+    EmptyBurst,
+    /// No state of the puzzle could be matched to the image
+    NoValidState,
+    /// The most likely state is less likely to be right than `CVProcessor::set_min_probability` requires
+    Uncertain {
+        /// How likely the most likely state is to be right
+        probability: f64,
+    },
+    /// The assignment doesn't fit the image or the puzzle that the processor is for
+    InvalidAssignment(InvalidAssignment),
+    /// A calibration can't be combined with this one, or loaded in place of it
+    IncompatibleCalibration(IncompatibleCalibration),
+    /// An algorithm couldn't be read as moves of the puzzle
+    InvalidAlgorithm(InvalidAlgorithm),
+    /// A facelet label given to calibrate with doesn't fit the puzzle
+    InvalidFaceletLabel(InvalidFaceletLabel),
+    /// A profile couldn't be saved or loaded
+    Profile(ProfileError),
+    /// A setting can't be used
+    InvalidParams(InvalidParams),
+    /// Pixel weights given to `CVProcessor::set_pixel_weights` can't be used
+    InvalidWeights(InvalidWeights),
+    /// `CVProcessor::set_mirrored` was given a color whose face no reflection of the puzzle swaps with another face while leaving the rest where they are
+    NoReflection(ArcIntern<str>),
+    /// The image couldn't be read or decoded
+    #[cfg(feature = "image")]
+    Image(image::ImageError),
+}
+
+impl fmt::Display for QvisError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            QvisError::ImageSize { expected, found } => {
+                write!(f, "the image has {found} pixels instead of {expected}")
+            }
+            QvisError::ObservationSize { expected, found } => {
+                write!(
+                    f,
+                    "the observation has {found} stickers instead of {expected}"
+                )
+            }
+            QvisError::Uncalibrated => write!(f, "nothing has been calibrated with yet"),
+            QvisError::EmptyBurst => write!(f, "the burst has no frames"),
+            QvisError::NoValidState => write!(f, "no state of the puzzle matches the image"),
+            QvisError::Uncertain { probability } => write!(
+                f,
+                "the most likely state is only right with a probability of {probability:.2}"
+            ),
+            QvisError::InvalidAssignment(err) => write!(f, "{err}"),
+            QvisError::IncompatibleCalibration(err) => write!(f, "{err}"),
+            QvisError::InvalidAlgorithm(err) => write!(f, "{err}"),
+            QvisError::InvalidFaceletLabel(err) => write!(f, "{err}"),
+            QvisError::Profile(err) => write!(f, "{err}"),
+            QvisError::InvalidParams(err) => write!(f, "{err}"),
+            QvisError::InvalidWeights(err) => write!(f, "{err}"),
+            QvisError::NoReflection(color) => write!(
+                f,
+                "no reflection of the puzzle swaps the {color} face with another face"
+            ),
+            #[cfg(feature = "image")]
+            QvisError::Image(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl Error for QvisError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            QvisError::InvalidAssignment(err) => Some(err),
+            QvisError::IncompatibleCalibration(err) => Some(err),
+            QvisError::InvalidAlgorithm(err) => Some(err),
+            QvisError::InvalidFaceletLabel(err) => Some(err),
+            QvisError::Profile(err) => Some(err),
+            QvisError::InvalidParams(err) => Some(err),
+            QvisError::InvalidWeights(err) => Some(err),
+            #[cfg(feature = "image")]
+            QvisError::Image(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<InvalidAssignment> for QvisError {
+    fn from(err: InvalidAssignment) -> Self {
+        QvisError::InvalidAssignment(err)
+    }
+}
+
+impl From<IncompatibleCalibration> for QvisError {
+    fn from(err: IncompatibleCalibration) -> Self {
+        QvisError::IncompatibleCalibration(err)
+    }
+}
+
+impl From<InvalidAlgorithm> for QvisError {
+    fn from(err: InvalidAlgorithm) -> Self {
+        QvisError::InvalidAlgorithm(err)
+    }
+}
+
+impl From<InvalidFaceletLabel> for QvisError {
+    fn from(err: InvalidFaceletLabel) -> Self {
+        QvisError::InvalidFaceletLabel(err)
+    }
+}
+
+impl From<ProfileError> for QvisError {
+    fn from(err: ProfileError) -> Self {
+        QvisError::Profile(err)
+    }
+}
+
+impl From<InvalidParams> for QvisError {
+    fn from(err: InvalidParams) -> Self {
+        QvisError::InvalidParams(err)
+    }
+}
+
+impl From<InvalidWeights> for QvisError {
+    fn from(err: InvalidWeights) -> Self {
+        QvisError::InvalidWeights(err)
+    }
+}
+
+#[cfg(feature = "image")]
+impl From<image::ImageError> for QvisError {
+    fn from(err: image::ImageError) -> Self {
+        QvisError::Image(err)
+    }
+}
+
+/// Load the image at the given path into the format that `CVProcessor` expects: one `(r, g, b)` tuple per pixel in row-major order with each channel ranging from zero to one.
+///
+/// # Errors
+///
+/// Returns an error if the image couldn't be read or decoded.
+#[cfg(feature = "image")]
+pub fn load_image(path: impl AsRef<Path>) -> Result<Image, image::ImageError> {
+    Ok(image::open(path)?
+        .to_rgb8()
+        .pixels()
+        .map(|pixel| {
+            let [r, g, b] = pixel.0;
+            (
+                f64::from(r) / 255.,
+                f64::from(g) / 255.,
+                f64::from(b) / 255.,
+            )
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::Cell,
+        sync::{
+            Arc,
+            atomic::{self, AtomicUsize},
+        },
+    };
+
+    use internment::ArcIntern;
+    use itertools::Itertools;
+    use log::LevelFilter;
+    use puzzle_theory::{
+        permutations::{Algorithm, Permutation, PermutationGroup, schreier_sims::StabilizerChain},
+        puzzle_geometry::{PuzzleGeometry, parsing::puzzle},
+    };
+    use rand::{Rng, SeedableRng};
+
+    use crate::{
+        AutoCalibration, CVProcessor, ConfidenceCalibration, Image, InferenceParams,
+        InvalidAlgorithm, InvalidAssignment, InvalidFaceletLabel, InvalidParams, InvalidWeights,
+        MaskedImage, Pixel, QvisError, Scoring, SuperPixels, cmp_ll,
+        puzzle_matching::{Matcher, RecognitionOutcome, RecognitionThresholds, fuse_observations},
+    };
+
+    pub const PIXELS_PER_STICKER: usize = 5;
+
+    const PALETTE: [(f64, f64, f64); 6] = [
+        (1., 0.2, 0.2),
+        (1., 0.6, 0.2),
+        (1., 1., 1.),
+        (0.8, 0.8, 0.2),
+        (0.2, 0.5, 1.),
+        (0.3, 1., 0.5),
+    ];
+
+    /// Assign a block of `PIXELS_PER_STICKER` consecutive pixels to each sticker of the puzzle
+    pub fn assignment(group: &PermutationGroup) -> Box<[Pixel]> {
+        (0..group.facelet_count())
+            .flat_map(|sticker| [sticker; PIXELS_PER_STICKER])
+            .map(Pixel::Sticker)
+            .collect()
+    }
+
+    /// Render a picture of the puzzle in the given state for an assignment made by `assignment`, coloring each sticker by the index of its color in a fixed palette and adding a bit of noise
+    pub fn picture<R: Rng + ?Sized>(
+        group: &PermutationGroup,
+        state: &Permutation,
+        rng: &mut R,
+    ) -> Image {
+        let colors = group.facelet_colors().iter().unique().collect_vec();
+
+        (0..group.facelet_count())
+            .flat_map(|sticker| {
+                let color = &group.facelet_colors()[state.state().get(sticker)];
+                let idx = colors.iter().position(|v| *v == color).unwrap();
+                [PALETTE[idx]; PIXELS_PER_STICKER]
+            })
+            .map(|(r, g, b)| {
+                let mut noise = || rng.random_range(0.95..1.05);
+                (r * noise(), g * noise(), b * noise())
+            })
+            .collect()
+    }
+
+    /// Return the color that appears at each sticker when the puzzle is in the given state
+    pub fn colors(group: &PermutationGroup, state: &Permutation) -> Vec<String> {
+        (0..group.facelet_count())
+            .map(|sticker| group.facelet_colors()[state.state().get(sticker)].to_string())
+            .collect()
+    }
+
+    /// The 3x3 that most of these tests recognize, with what they need to make pictures of it for an assignment made by `assignment`
+    pub struct Cube {
+        pub geometry: Arc<PuzzleGeometry>,
+        pub group: Arc<PermutationGroup>,
+        pub stabchain: StabilizerChain,
+        pub image_size: usize,
+    }
+
+    pub fn cube() -> Cube {
+        let geometry = puzzle("3x3").into_inner();
+        let group = geometry.permutation_group();
+        let stabchain = StabilizerChain::new(&group);
+        let image_size = group.facelet_count() * PIXELS_PER_STICKER;
+
+        Cube {
+            geometry,
+            group,
+            stabchain,
+            image_size,
+        }
+    }
+
+    /// A processor for the puzzle with an assignment made by `assignment` and nothing calibrated yet
+    pub fn processor(geometry: &Arc<PuzzleGeometry>) -> CVProcessor {
+        let group = geometry.permutation_group();
+        CVProcessor::new(
+            Arc::clone(geometry),
+            group.facelet_count() * PIXELS_PER_STICKER,
+            assignment(&group),
+        )
+        .unwrap()
+    }
+
+    /// A processor for the 3x3 calibrated with pictures of ten random states, along with the cube
+    pub fn calibrated<R: Rng + ?Sized>(rng: &mut R) -> (CVProcessor, Cube) {
+        let cube = cube();
+        let mut cv = processor(&cube.geometry);
+
+        for _ in 0..10 {
+            let state = cube.stabchain.random(rng);
+            cv.calibrate(&picture(&cube.group, &state, rng), state)
+                .unwrap();
+        }
+
+        (cv, cube)
+    }
+
+    #[test]
+    fn pipeline() {
+        // Each sticker is a square of `SIDE` by `SIDE` pixels. Every face of the puzzle gets a row of the image with its eight stickers followed by a white card lit the same way, which is its white balance.
+        const SIDE: usize = 4;
+        const WIDTH: usize = 9 * SIDE;
+
+        let Cube {
+            geometry,
+            group,
+            stabchain,
+            ..
+        } = cube();
+        let faces = group.facelet_colors().iter().unique().collect_vec();
+
+        // Which cell of the image each sticker goes in
+        let cells = (0..group.facelet_count())
+            .map(|sticker| {
+                let face = faces
+                    .iter()
+                    .position(|v| **v == group.facelet_colors()[sticker])
+                    .unwrap();
+                let column = (0..sticker)
+                    .filter(|v| group.facelet_colors()[*v] == group.facelet_colors()[sticker])
+                    .count();
+                (face, column)
+            })
+            .collect_vec();
+
+        let height = faces.len() * SIDE;
+        let cell_of = |idx: usize| (idx / WIDTH / SIDE, idx % WIDTH / SIDE);
+
+        let assignment = (0..WIDTH * height)
+            .map(|idx| {
+                let (face, column) = cell_of(idx);
+                if column == 8 {
+                    Pixel::WhiteBalance(ArcIntern::clone(faces[face]))
+                } else {
+                    Pixel::Sticker(cells.iter().position(|v| *v == (face, column)).unwrap())
+                }
+            })
+            .collect();
+
+        // Each face is lit differently in every picture, and the camera adds a bit of noise
+        let render = |state: &Permutation, rng: &mut rand::rngs::SmallRng| {
+            let lighting = faces
+                .iter()
+                .map(|_| {
+                    (
+                        rng.random_range(0.5..1.),
+                        rng.random_range(0.5..1.),
+                        rng.random_range(0.5..1.),
+                    )
+                })
+                .collect_vec();
+
+            (0..WIDTH * height)
+                .map(|idx| {
+                    let (face, column) = cell_of(idx);
+                    let (r, g, b) = if column == 8 {
+                        (1., 1., 1.)
+                    } else {
+                        let sticker = cells.iter().position(|v| *v == (face, column)).unwrap();
+                        let color = &group.facelet_colors()[state.state().get(sticker)];
+                        PALETTE[faces.iter().position(|v| *v == color).unwrap()]
+                    };
+                    let (light_r, light_g, light_b) = lighting[face];
+                    let mut noise = || rng.random_range(0.97..1.03);
+
+                    (
+                        r * light_r * noise(),
+                        g * light_g * noise(),
+                        b * light_b * noise(),
+                    )
+                })
+                .collect::<Box<[_]>>()
+        };
+
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"From the pixels to the state, ok");
+        let mut cv = CVProcessor::new(Arc::clone(&geometry), WIDTH * height, assignment).unwrap();
+
+        cv.calibrate_solved(&render(&Permutation::from_cycles(Vec::new()), &mut rng))
+            .unwrap();
+        for _ in 0..10 {
+            let state = stabchain.random(&mut rng);
+            cv.calibrate(&render(&state, &mut rng), state).unwrap();
+        }
+
+        for _ in 0..10 {
+            let state = stabchain.random(&mut rng);
+            let (found, _) = cv.process_image(render(&state, &mut rng)).unwrap();
+            assert_eq!(found, state);
+        }
+    }
+
+    #[test]
+    fn super_pixels() {
+        // Each sticker is a square of `SIDE` by `SIDE` pixels, all in one row, and each super-pixel is a quarter of a sticker
+        const SIDE: usize = 4;
+        const BLOCK: usize = 2;
+
+        let Cube {
+            geometry,
+            group,
+            stabchain,
+            ..
+        } = cube();
+        let faces = group.facelet_colors().iter().unique().collect_vec();
+        let (width, height) = (group.facelet_count() * SIDE, SIDE);
+
+        let per_pixel = (0..width * height)
+            .map(|idx| Pixel::Sticker(idx % width / SIDE))
+            .collect::<Box<[_]>>();
+        let super_pixels = SuperPixels::blocks(width, height, BLOCK);
+        let per_super_pixel = (0..super_pixels.len())
+            .map(|label| Pixel::Sticker(label % (width / BLOCK) * BLOCK / SIDE))
+            .collect::<Box<[_]>>();
+        assert_eq!(per_super_pixel.len() * BLOCK * BLOCK, per_pixel.len());
+
+        // A noisy camera
+        let render = |state: &Permutation, rng: &mut rand::rngs::SmallRng| {
+            (0..width * height)
+                .map(|idx| {
+                    let color = &group.facelet_colors()[state.state().get(idx % width / SIDE)];
+                    let (r, g, b) = PALETTE[faces.iter().position(|v| *v == color).unwrap()];
+                    let mut noise = || rng.random_range(0.85..1.15);
+                    (r * noise(), g * noise(), b * noise())
+                })
+                .collect::<Box<[_]>>()
+        };
+
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"Four pixels become one superpix!");
+        let mut pixels_cv =
+            CVProcessor::new(Arc::clone(&geometry), width * height, per_pixel).unwrap();
+        let mut super_cv =
+            CVProcessor::with_super_pixels(Arc::clone(&geometry), super_pixels, per_super_pixel)
+                .unwrap();
+
+        for _ in 0..10 {
+            let state = stabchain.random(&mut rng);
+            let image = render(&state, &mut rng);
+            pixels_cv.calibrate(&image, state.clone()).unwrap();
+            super_cv.calibrate(&image, state).unwrap();
+        }
+
+        for _ in 0..10 {
+            let state = stabchain.random(&mut rng);
+            let image = render(&state, &mut rng);
+
+            let (by_pixel, _) = pixels_cv.process_image(image.clone()).unwrap();
+            let (by_super_pixel, _) = super_cv.process_image(image.clone()).unwrap();
+            assert_eq!(by_pixel, state);
+            assert_eq!(by_super_pixel, state);
+
+            // Every pixel of a super-pixel is painted the same
+            let rendered = super_cv.render_inferred_colors(&image).unwrap();
+            assert_eq!(rendered.len(), width * height);
+            assert_eq!(rendered[0], rendered[width + 1]);
+        }
+
+        // Images still have to have every pixel
+        assert!(matches!(
+            super_cv.process_image(vec![(0., 0., 0.); width * height / 4].into()),
+            Err(QvisError::ImageSize { .. })
+        ));
+    }
+
+    #[test]
+    fn other_puzzles() {
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"Not every puzzle is a 3x3 cube!!");
+
+        for name in ["2x2", "4x4"] {
+            let geometry = puzzle(name).into_inner();
+            let group = geometry.permutation_group();
+            let solved = Permutation::from_cycles(Vec::new());
+
+            let mut cv = processor(&geometry);
+
+            for _ in 0..10 {
+                cv.calibrate(&picture(&group, &solved, &mut rng), solved.clone())
+                    .unwrap();
+            }
+
+            // Pieces with identical colors (like the centers of a 4x4) can't be told apart, so compare what the puzzle looks like rather than the permutation itself
+            let (found, _) = cv
+                .process_image(picture(&group, &solved, &mut rng))
+                .unwrap();
+            assert_eq!(colors(&group, &found), colors(&group, &solved), "{name}");
+        }
+    }
+
+    #[test]
+    fn calibrate_solved() {
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"Start from a solved puzzle, then");
+
+        for name in ["2x2", "3x3", "4x4"] {
+            let geometry = puzzle(name).into_inner();
+            let group = geometry.permutation_group();
+            let solved = Permutation::from_cycles(Vec::new());
+
+            let mut cv = processor(&geometry);
+            cv.calibrate_solved(&picture(&group, &solved, &mut rng))
+                .unwrap();
+
+            let image = picture(&group, &solved, &mut rng);
+            let (found, confidence) = cv.process_image(image.clone()).unwrap();
+            assert_eq!(colors(&group, &found), colors(&group, &solved), "{name}");
+
+            // Every sticker gets the color that it looks the most like, which no state can beat
+            let best = cv
+                .observe(&image)
+                .unwrap()
+                .iter()
+                .map(|v| v.values().copied().fold(f64::NEG_INFINITY, f64::max))
+                .sum::<f64>();
+            assert!((confidence - best).abs() <= 1e-9 * best.abs(), "{name}");
+        }
+    }
+
+    #[test]
+    fn auto_calibration() {
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"The sun is setting, keep up now!");
+
+        let Cube {
+            geometry,
+            group,
+            stabchain,
+            ..
+        } = cube();
+        let solved = Permutation::from_cycles(Vec::new());
+
+        let new = || processor(&geometry);
+        let mut fixed = new();
+        let mut learning = new();
+        let mut ignoring = new();
+
+        for _ in 0..5 {
+            let image = picture(&group, &solved, &mut rng);
+            for cv in [&mut fixed, &mut learning, &mut ignoring] {
+                cv.calibrate_solved(&image).unwrap();
+            }
+        }
+
+        learning.set_auto_calibration(Some(AutoCalibration {
+            thresholds: RecognitionThresholds::default(),
+            max_frames: 8,
+        }));
+        // Nothing explains a frame well enough
+        ignoring.set_auto_calibration(Some(AutoCalibration {
+            thresholds: RecognitionThresholds {
+                min_margin: 0.,
+                min_mean_log_likelihood: f64::INFINITY,
+                ..RecognitionThresholds::default()
+            },
+            max_frames: 8,
+        }));
+
+        // The light dims a little with every frame
+        let dimmed = |image: Image, brightness: f64| {
+            image
+                .iter()
+                .map(|(r, g, b)| (r * brightness, g * brightness, b * brightness))
+                .collect::<Box<[_]>>()
+        };
+
+        for frame in 0..10 {
+            let state = stabchain.random(&mut rng);
+            let image = dimmed(picture(&group, &state, &mut rng), 0.97_f64.powi(frame + 1));
+
+            for cv in [&mut fixed, &mut learning, &mut ignoring] {
+                cv.process_frame(image.clone()).unwrap();
+            }
+        }
+
+        assert_eq!(learning.auto_calibrated, 8);
+        assert_eq!(ignoring.auto_calibrated, 0);
+
+        let state = stabchain.random(&mut rng);
+        let image = dimmed(picture(&group, &state, &mut rng), 0.97_f64.powi(10));
+
+        // Frames that weren't confident enough taught nothing
+        assert_eq!(
+            ignoring.observe(&image).unwrap(),
+            fixed.observe(&image).unwrap()
+        );
+
+        // The frames that were learned from make the dim picture look more like the true state
+        let truth = |cv: &CVProcessor| {
+            cv.process_image_among(&image, std::slice::from_ref(&state))
+                .unwrap()
+                .1
+        };
+        assert!(truth(&learning) > truth(&fixed));
+        assert_eq!(learning.process_image(image.clone()).unwrap().0, state);
+    }
+
+    #[test]
+    fn calibrate_batch() {
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"A whole folder of cube pictures!");
+
+        let Cube {
+            geometry,
+            group,
+            stabchain,
+            ..
+        } = cube();
+
+        let mut batch = processor(&geometry);
+        let mut sequential = processor(&geometry);
+
+        let images = (0..10)
+            .map(|_| {
+                let state = stabchain.random(&mut rng);
+                (picture(&group, &state, &mut rng), state)
+            })
+            .collect::<Vec<_>>();
+
+        batch.calibrate_batch(&images).unwrap();
+        for (image, state) in &images {
+            sequential.calibrate(image, state.clone()).unwrap();
+        }
+
+        for _ in 0..10 {
+            let state = stabchain.random(&mut rng);
+            let image = picture(&group, &state, &mut rng);
+            let (found_batch, _) = batch.process_image(image.clone()).unwrap();
+            let (found_sequential, _) = sequential.process_image(image).unwrap();
+            assert_eq!(found_batch, found_sequential);
+        }
+    }
+
+    #[test]
+    fn calibrate_confidence() {
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"Ninety percent sure, give or ta!");
+
+        let (
+            mut cv,
+            Cube {
+                geometry,
+                group,
+                stabchain,
+                ..
+            },
+        ) = calibrated(&mut rng);
+
+        let image = picture(&group, &stabchain.random(&mut rng), &mut rng);
+        let (_, log_likelihood) = cv.process_image(image.clone()).unwrap();
+        assert_eq!(cv.probability(log_likelihood), None);
+
+        let samples = (0..10)
+            .map(|_| {
+                let state = stabchain.random(&mut rng);
+                (picture(&group, &state, &mut rng), state)
+            })
+            .collect::<Vec<_>>();
+        cv.calibrate_confidence(&samples).unwrap();
+
+        // Every sample was recognized correctly, so the recognition is likely right but not certainly
+        let probability = cv.probability(log_likelihood).unwrap();
+        assert!(probability > 0.5 && probability < 1.);
+
+        let saved = cv.confidence_calibration();
+        let mut restored = processor(&geometry);
+        restored.set_confidence_calibration(saved);
+        assert_eq!(restored.probability(log_likelihood), Some(probability));
+    }
+
+    #[test]
+    fn min_probability() {
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"Is that sticker red or orange?!?");
+
+        let (mut cv, Cube { group, .. }) = calibrated(&mut rng);
+        let solved = Permutation::from_cycles(Vec::new());
+
+        let clear = picture(&group, &solved, &mut rng);
+        // Every red sticker is halfway to orange, so red and orange nearly tie
+        let tie = clear
+            .iter()
+            .map(|&(r, g, b)| if g < 0.3 { (r, g * 2., b) } else { (r, g, b) })
+            .collect::<Box<[_]>>();
+
+        let (_, clear_log_likelihood) = cv.process_image(clear.clone()).unwrap();
+        let (_, tie_log_likelihood) = cv.process_image(tie.clone()).unwrap();
+        assert!(tie_log_likelihood < clear_log_likelihood);
+
+        // A probability of one half halfway between the two
+        let scale = 10. / (clear_log_likelihood - tie_log_likelihood);
+        cv.set_confidence_calibration(Some(ConfidenceCalibration {
+            scale,
+            offset: -scale * (clear_log_likelihood + tie_log_likelihood) / 2.,
+        }));
+
+        // Everything is accepted until a threshold is set
+        assert!(cv.process_image(tie.clone()).is_ok());
+
+        cv.set_min_probability(0.9);
+        assert_eq!(cv.process_image(clear).unwrap().0, solved);
+        assert!(matches!(
+            cv.process_image(tie),
+            Err(QvisError::Uncertain { probability }) if probability < 0.1
+        ));
+    }
+
+    #[test]
+    fn process_burst() {
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"Hold still, taking eight shots..");
+
+        let (
+            cv,
+            Cube {
+                group,
+                stabchain,
+                image_size,
+                ..
+            },
+        ) = calibrated(&mut rng);
+
+        let state = stabchain.random(&mut rng);
+        let clean = picture(&group, &state, &mut rng);
+
+        // Every frame has its own noise, far more than the calibration has and than the difference between the closest colors of the palette, which averaging enough of them shrinks to less than that difference
+        let mut frames = (0..32)
+            .map(|_| {
+                clean
+                    .iter()
+                    .map(|&(r, g, b)| {
+                        let mut noise = || rng.random_range(-1.0..1.0);
+                        (r + noise(), g + noise(), b + noise())
+                    })
+                    .collect::<Image>()
+            })
+            .collect_vec();
+
+        // None of the frames can be recognized on its own
+        for frame in &frames[..4] {
+            assert!(!matches!(
+                cv.process_image(frame.clone()),
+                Ok((found, _)) if found == state
+            ));
+        }
+
+        let (found, _) = cv.process_burst(&frames).unwrap();
+        assert_eq!(found, state);
+
+        assert!(matches!(cv.process_burst(&[]), Err(QvisError::EmptyBurst)));
+
+        frames.push(clean[1..].into());
+        assert!(matches!(
+            cv.process_burst(&frames),
+            Err(QvisError::ImageSize { found, .. }) if found == image_size - 1
+        ));
+    }
+
+    #[test]
+    fn mirrored() {
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"Selfie cameras flip every frame!");
+
+        let (
+            mut cv,
+            Cube {
+                geometry,
+                group,
+                stabchain,
+                image_size,
+            },
+        ) = calibrated(&mut rng);
+        let solved = Permutation::from_cycles(Vec::new());
+
+        // The red and orange faces are on the left and right of the picture
+        let red = ArcIntern::from("red");
+        let matcher = Matcher::new(Arc::clone(&geometry));
+        let reflection = matcher.reflection_swapping(&red).unwrap();
+        cv.set_mirrored(Some(&red)).unwrap();
+        assert!(matches!(
+            cv.set_mirrored(Some(&ArcIntern::from("pink"))),
+            Err(QvisError::NoReflection(_))
+        ));
+
+        for state in [solved.clone(), stabchain.random(&mut rng)] {
+            // Each spot of the mirror image shows the sticker that the reflection takes it to
+            let upright = picture(&group, &state, &mut rng);
+            let mirrored = (0..image_size)
+                .map(|idx| {
+                    let sticker = reflection[idx / PIXELS_PER_STICKER];
+                    upright[sticker * PIXELS_PER_STICKER + idx % PIXELS_PER_STICKER]
+                })
+                .collect::<Box<[_]>>();
+
+            assert_eq!(cv.process_image(mirrored.clone()).unwrap().0, state);
+            assert!(matches!(
+                cv.recognize(&mirrored).unwrap(),
+                RecognitionOutcome::Confident(found, _) if found == state
+            ));
+            assert!(cv.is_stable(&mirrored).unwrap());
+            assert_eq!(
+                cv.process_image_among(&mirrored, &[stabchain.random(&mut rng), state.clone()])
+                    .unwrap()
+                    .0,
+                state
+            );
+            assert_eq!(cv.process_frame(mirrored).unwrap().0, state);
+        }
+    }
+
+    #[test]
+    fn calibrate_with_moves() {
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"R U R' U' is the sexy move, yes!");
+
+        let Cube {
+            geometry, group, ..
+        } = cube();
+        let scramble = "R U R' U' F2 D";
+        let state = Algorithm::parse_from_string(Arc::clone(&group), scramble)
+            .unwrap()
+            .permutation()
+            .clone();
+
+        let mut from_moves = processor(&geometry);
+        let mut from_permutation = processor(&geometry);
+
+        for _ in 0..10 {
+            let image = picture(&group, &state, &mut rng);
+            from_moves.calibrate_with_moves(&image, scramble).unwrap();
+            from_permutation.calibrate(&image, state.clone()).unwrap();
+        }
+
+        for _ in 0..10 {
+            let image = picture(&group, &state, &mut rng);
+            assert_eq!(
+                from_moves.process_image(image.clone()).unwrap(),
+                from_permutation.process_image(image).unwrap()
+            );
+        }
+
+        let image = picture(&group, &state, &mut rng);
+        for moves in ["R U Q", "R (U"] {
+            assert!(matches!(
+                from_moves.calibrate_with_moves(&image, moves),
+                Err(QvisError::InvalidAlgorithm(InvalidAlgorithm(invalid))) if invalid == moves
+            ));
+        }
+    }
+
+    #[test]
+    fn look_alikes() {
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"Which of these centers is which?");
+
+        // None of the named puzzles have faces that share a color, but the centers of a 4x4 are indistinguishable in the same way
+        for (name, distinguishable) in [("3x3", true), ("4x4", false)] {
+            let geometry = puzzle(name).into_inner();
+            let group = geometry.permutation_group();
+            let solved = Permutation::from_cycles(Vec::new());
+
+            let mut cv = processor(&geometry);
+
+            for _ in 0..10 {
+                cv.calibrate(&picture(&group, &solved, &mut rng), solved.clone())
+                    .unwrap();
+            }
+
+            let found = cv
+                .process_image_unambiguous(picture(&group, &solved, &mut rng))
+                .unwrap();
+            assert_eq!(found.is_some(), distinguishable, "{name}");
+            if let Some((found, _)) = found {
+                assert_eq!(found, solved);
+            }
+        }
+    }
+
+    #[cfg(feature = "image")]
+    #[test]
+    fn image_file() {
+        use image::{Rgb, RgbImage};
+
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"A picture is worth 1000 stickers");
+
+        let (
+            cv,
+            Cube {
+                group, image_size, ..
+            },
+        ) = calibrated(&mut rng);
+        let solved = Permutation::from_cycles(Vec::new());
+
+        // Each row of the image is one sticker
+        let pixels = picture(&group, &solved, &mut rng);
+        // Each run of the tests gets its own file, so that tests run at the same time don't share it
+        let path =
+            std::env::temp_dir().join(format!("qvis_image_file_test_{}.png", std::process::id()));
+        RgbImage::from_fn(
+            PIXELS_PER_STICKER as u32,
+            group.facelet_count() as u32,
+            |x, y| {
+                let (r, g, b) = pixels[y as usize * PIXELS_PER_STICKER + x as usize];
+                Rgb([r, g, b].map(|v| (v.min(1.) * 255.).round() as u8))
+            },
+        )
+        .save(&path)
+        .unwrap();
+
+        let loaded = crate::load_image(&path).unwrap();
+        assert_eq!(loaded.len(), image_size);
+        for (a, b) in loaded.iter().zip(&pixels) {
+            assert!((a.0 - b.0.min(1.)).abs() < 0.01);
+        }
+
+        let (found, _) = cv.process_image_file(&path).unwrap();
+        assert_eq!(colors(&group, &found), colors(&group, &solved));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn render_inferred_colors() {
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"Paint by numbers, or by stickers");
+
+        let (
+            cv,
+            Cube {
+                group, image_size, ..
+            },
+        ) = calibrated(&mut rng);
+        let solved = Permutation::from_cycles(Vec::new());
+
+        let image = picture(&group, &solved, &mut rng);
+        let rendered = cv.render_inferred_colors(&image).unwrap();
+
+        assert_eq!(rendered.len(), image_size);
+
+        for (a, b) in rendered.iter().zip(&image) {
+            assert!((a.0 - b.0).abs() < 0.1);
+            assert!((a.1 - b.1).abs() < 0.1);
+            assert!((a.2 - b.2).abs() < 0.1);
+        }
+    }
+
+    #[test]
+    fn calibration_preview() {
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"Is it white? Is it yellow? Look!");
+
+        let Cube {
+            geometry,
+            group,
+            image_size,
+            ..
+        } = cube();
+        let solved = Permutation::from_cycles(Vec::new());
+        // Every sticker that moves gets the color of the opposite face, so the white and yellow faces swap colors
+        let checkerboard = Algorithm::parse_from_string(Arc::clone(&group), "R2 L2 U2 D2 F2 B2")
+            .unwrap()
+            .permutation()
+            .clone();
+
+        // Only the white and yellow faces are in the picture
+        let shown =
+            |sticker: usize| ["white", "yellow"].contains(&&*group.facelet_colors()[sticker]);
+        let assignment = assignment(&group)
+            .into_iter()
+            .map(|pixel| match pixel {
+                Pixel::Sticker(sticker) if !shown(sticker) => Pixel::Unassigned,
+                pixel => pixel,
+            })
+            .collect::<Box<[_]>>();
+        let is_shown = assignment
+            .iter()
+            .map(|pixel| matches!(pixel, Pixel::Sticker(_)))
+            .collect_vec();
+
+        let mut cv = CVProcessor::new(Arc::clone(&geometry), image_size, assignment).unwrap();
+
+        let matches = |preview: &[(f64, f64, f64)], image: &[(f64, f64, f64)]| {
+            preview
+                .iter()
+                .zip(image)
+                .zip(&is_shown)
+                .all(|((a, b), shown)| {
+                    !shown
+                        || ((a.0 - b.0).abs() < 0.1
+                            && (a.1 - b.1).abs() < 0.1
+                            && (a.2 - b.2).abs() < 0.1)
+                })
+        };
+
+        let image = picture(&group, &solved, &mut rng);
+        cv.calibrate_with_preview(&image, solved.clone()).unwrap();
+
+        // Having only seen the solved puzzle, every sticker can only look like its own face, so the preview shows the white and yellow faces the wrong way around
+        let image = picture(&group, &checkerboard, &mut rng);
+        let preview = cv
+            .calibrate_with_preview(&image, checkerboard.clone())
+            .unwrap();
+        assert!(!matches(&preview, &image));
+
+        // Now every sticker has seen both colors
+        for i in 0..10 {
+            let state = if i % 2 == 0 { &checkerboard } else { &solved };
+            let image = picture(&group, state, &mut rng);
+            let preview = cv.calibrate_with_preview(&image, state.clone()).unwrap();
+            assert!(matches(&preview, &image));
+        }
+    }
+
+    #[test]
+    fn multiple_views() {
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"Turn it around to see the back!!");
+
+        let Cube {
+            geometry,
+            group,
+            stabchain,
+            image_size,
+        } = cube();
+
+        // Each photo shows three of the six faces, and the pixels of the others are unassigned
+        let front_faces = |sticker: usize| sticker < group.facelet_count() / 2;
+        let view = |shows: &dyn Fn(usize) -> bool| {
+            let assignment = assignment(&group)
+                .into_iter()
+                .map(|pixel| match pixel {
+                    Pixel::Sticker(sticker) if !shows(sticker) => Pixel::Unassigned,
+                    pixel => pixel,
+                })
+                .collect();
+            CVProcessor::new(Arc::clone(&geometry), image_size, assignment).unwrap()
+        };
+        let mut front = view(&front_faces);
+        let mut back = view(&|sticker| !front_faces(sticker));
+
+        for _ in 0..10 {
+            let state = stabchain.random(&mut rng);
+            front
+                .calibrate(&picture(&group, &state, &mut rng), state.clone())
+                .unwrap();
+            back.calibrate(&picture(&group, &state, &mut rng), state)
+                .unwrap();
+        }
+
+        for _ in 0..10 {
+            let state = stabchain.random(&mut rng);
+            let front_observation = front.observe(&picture(&group, &state, &mut rng)).unwrap();
+            let back_observation = back.observe(&picture(&group, &state, &mut rng)).unwrap();
+
+            for sticker in 0..group.facelet_count() {
+                assert_eq!(front_observation[sticker].is_empty(), !front_faces(sticker));
+                assert_eq!(back_observation[sticker].is_empty(), front_faces(sticker));
+            }
+
+            let observations = [front_observation, back_observation];
+            let fused = fuse_observations(&observations);
+            for (sticker, log_likelihoods) in fused.iter().enumerate() {
+                let seen_by = if front_faces(sticker) { 0 } else { 1 };
+                assert_eq!(*log_likelihoods, observations[seen_by][sticker]);
+            }
+
+            assert_eq!(front.process_observations(&observations).unwrap().0, state);
+        }
+    }
+
+    #[test]
+    fn occluded() {
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"Get your thumb out of the photo!");
+
+        let (
+            mut cv,
+            Cube {
+                group,
+                stabchain,
+                image_size,
+                ..
+            },
+        ) = calibrated(&mut rng);
+        // Only how much of a sticker has to be seen changes, which the calibration doesn't depend on
+        cv.set_inference_params(InferenceParams {
+            min_observed_fraction: 0.5,
+            ..InferenceParams::default()
+        })
+        .unwrap();
+
+        // A whole face can't be seen: most of it is covered, one sticker mostly covered, and one mostly glare. Every piece has stickers on other faces, so the state is still determined.
+        let hidden = (0..group.facelet_count())
+            .filter(|sticker| group.facelet_colors()[*sticker] == group.facelet_colors()[0])
+            .collect_vec();
+        let (covered, mostly_covered, glare) = (&hidden[..6], hidden[6], hidden[7]);
+        // A sticker that is only partly covered is still seen
+        let partly_covered = (0..group.facelet_count())
+            .find(|sticker| !hidden.contains(sticker))
+            .unwrap();
+        let pixels =
+            |sticker: usize| sticker * PIXELS_PER_STICKER..(sticker + 1) * PIXELS_PER_STICKER;
+
+        for _ in 0..10 {
+            let state = stabchain.random(&mut rng);
+            let mut image = MaskedImage {
+                pixels: picture(&group, &state, &mut rng),
+                observed: vec![true; image_size].into(),
+            };
+            for sticker in covered {
+                image.observed[pixels(*sticker)].fill(false);
+            }
+            image.observed[pixels(mostly_covered)][..3].fill(false);
+            image.observed[pixels(partly_covered)][..2].fill(false);
+            image.pixels[pixels(glare)][..3].fill((1.5, 1.5, 1.5));
+            image.mask_glare(1.1);
+
+            let observation = cv.observe_masked(&image).unwrap();
+            for sticker in 0..group.facelet_count() {
+                assert_eq!(observation[sticker].is_empty(), hidden.contains(&sticker));
+            }
+
+            assert_eq!(cv.process_masked_image(&image).unwrap().0, state);
+            assert_eq!(cv.process_observations(&[observation]).unwrap().0, state);
+        }
+    }
+
+    #[test]
+    fn no_puzzle() {
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"Is the lens cap still on, or not");
+
+        let (
+            cv,
+            Cube {
+                group,
+                stabchain,
+                image_size,
+                ..
+            },
+        ) = calibrated(&mut rng);
+
+        // Black looks like none of the colors, so the camera isn't pointed at the puzzle
+        let black = vec![(0., 0., 0.); image_size];
+        assert_eq!(cv.plausible_fraction(&black).unwrap(), 0.);
+        assert_eq!(cv.recognize(&black).unwrap(), RecognitionOutcome::NoPuzzle);
+
+        let state = stabchain.random(&mut rng);
+        let image = picture(&group, &state, &mut rng);
+        assert!(cv.plausible_fraction(&image).unwrap() > 0.9);
+        match cv.recognize(&image).unwrap() {
+            RecognitionOutcome::Confident(found, _) => assert_eq!(found, state),
+            outcome => panic!("{outcome:?}"),
+        }
+    }
+
+    #[test]
+    fn drifted_brightness() {
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"Somebody drew the curtains a bit");
+
+        let Cube {
+            geometry,
+            group,
+            image_size,
+            ..
+        } = cube();
+
+        let mut cv = processor(&geometry);
+        let solved = Permutation::from_cycles(Vec::new());
+        cv.calibrate(&picture(&group, &solved, &mut rng), solved.clone())
+            .unwrap();
+
+        // Each pixel has seen a single sample, but the puzzle still looks like itself in dimmer light
+        let dimmed = picture(&group, &solved, &mut rng)
+            .iter()
+            .map(|(r, g, b)| (r * 0.9, g * 0.9, b * 0.9))
+            .collect_vec();
+        assert!(cv.plausible_fraction(&dimmed).unwrap() > 0.9);
+        assert_ne!(cv.recognize(&dimmed).unwrap(), RecognitionOutcome::NoPuzzle);
+
+        let black = vec![(0., 0., 0.); image_size];
+        assert_eq!(cv.recognize(&black).unwrap(), RecognitionOutcome::NoPuzzle);
+
+        // Unless the thresholds ask for more than any picture can give
+        cv.set_recognition_thresholds(RecognitionThresholds {
+            min_plausible_fraction: 1.01,
+            ..RecognitionThresholds::default()
+        });
+        assert_eq!(cv.recognize(&dimmed).unwrap(), RecognitionOutcome::NoPuzzle);
+    }
+
+    #[test]
+    fn moves_between() {
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"Which way did they turn it then?");
+
+        let (
+            cv,
+            Cube {
+                geometry, group, ..
+            },
+        ) = calibrated(&mut rng);
+        let parse = |moves: &str| {
+            Algorithm::parse_from_string(Arc::clone(&group), moves)
+                .unwrap()
+                .permutation()
+                .clone()
+        };
+
+        // Recognize the puzzle before and after a single R turn
+        let scramble = "F2 U' L D B2";
+        let before = parse(scramble);
+        let after = parse(&format!("{scramble} R"));
+        let recognized_before = cv
+            .process_image(picture(&group, &before, &mut rng))
+            .unwrap()
+            .0;
+        let recognized_after = cv
+            .process_image(picture(&group, &after, &mut rng))
+            .unwrap()
+            .0;
+        assert_eq!(recognized_before, before);
+        assert_eq!(recognized_after, after);
+
+        let turned = cv
+            .moves_between(&recognized_before, &recognized_after, 3)
+            .unwrap();
+        assert_eq!(*turned.permutation(), parse("R"));
+        let back = cv
+            .moves_between(&recognized_after, &recognized_before, 3)
+            .unwrap();
+        assert_eq!(*back.permutation(), parse("R'"));
+
+        // Two moves are found as two moves, but not when only one is allowed
+        let further = parse(&format!("{scramble} R U2"));
+        let turned = cv.moves_between(&before, &further, 3).unwrap();
+        assert_eq!(*turned.permutation(), parse("R U2"));
+        assert!(cv.moves_between(&before, &further, 1).is_none());
+
+        // Swapping two stickers of a piece can't be done by turning the puzzle
+        let twisted_sticker = {
+            let mut state = (0..group.facelet_count())
+                .map(|facelet| before.state().get(facelet))
+                .collect_vec();
+            let piece = &geometry.pieces_data().orbits()[0].pieces()[0];
+            state.swap(piece.stickers()[0], piece.stickers()[1]);
+            Permutation::from_state(state)
+        };
+        assert!(cv.moves_between(&before, &twisted_sticker, 3).is_none());
+    }
+
+    #[test]
+    fn log_likelihood_order() {
+        use std::cmp::Ordering;
+
+        let (inf, nan) = (f64::INFINITY, f64::NAN);
+
+        // NaN of either sign is worse than everything, even negative infinity
+        for nan in [nan, -nan] {
+            for v in [-inf, -1e300, 0., 5., inf] {
+                assert_eq!(cmp_ll(nan, v), Ordering::Less);
+                assert_eq!(cmp_ll(v, nan), Ordering::Greater);
+            }
+        }
+        assert_eq!(cmp_ll(nan, -nan), Ordering::Equal);
+
+        assert_eq!(cmp_ll(-inf, -1e300), Ordering::Less);
+        assert_eq!(cmp_ll(inf, 1e300), Ordering::Greater);
+        assert_eq!(cmp_ll(inf, inf), Ordering::Equal);
+        assert_eq!(cmp_ll(-inf, -inf), Ordering::Equal);
+        assert_eq!(cmp_ll(-0., 0.), Ordering::Equal);
+        assert_eq!(cmp_ll(-3., -2.), Ordering::Less);
+
+        // The best of some log-likelihoods is never the NaN
+        let lls = [-5., nan, -inf, -1., -nan];
+        assert_eq!(lls.into_iter().max_by(|a, b| cmp_ll(*a, *b)), Some(-1.));
+        assert!(
+            lls.into_iter()
+                .min_by(|a, b| cmp_ll(*a, *b))
+                .unwrap()
+                .is_nan()
+        );
+    }
+
+    #[test]
+    fn errors() {
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"Everything that can go wrong, ok");
+
+        let Cube {
+            geometry,
+            group,
+            image_size,
+            ..
+        } = cube();
+        let solved = Permutation::from_cycles(Vec::new());
+
+        let mut cv = processor(&geometry);
+        let image = picture(&group, &solved, &mut rng);
+
+        // Nothing to recognize the colors by yet
+        assert!(matches!(
+            cv.process_image(image.clone()),
+            Err(QvisError::Uncalibrated)
+        ));
+        assert!(matches!(cv.observe(&image), Err(QvisError::Uncalibrated)));
+
+        cv.calibrate_solved(&image).unwrap();
+
+        let cropped = &image[1..];
+        assert!(matches!(
+            cv.calibrate_solved(cropped),
+            Err(QvisError::ImageSize { expected, found }) if expected == image_size && found == image_size - 1
+        ));
+        assert!(matches!(
+            cv.process_image(cropped.into()),
+            Err(QvisError::ImageSize { .. })
+        ));
+        assert!(matches!(
+            cv.process_observations(&[cv.observe(&image).unwrap()[1..].into()]),
+            Err(QvisError::ObservationSize { .. })
+        ));
+
+        assert!(matches!(
+            cv.process_image_among(&image, &[]),
+            Err(QvisError::NoValidState)
+        ));
+
+        assert!(matches!(
+            cv.calibrate_facelets(&image, &[(group.facelet_count(), ArcIntern::from("white"))]),
+            Err(QvisError::InvalidFaceletLabel(_))
+        ));
+        assert!(matches!(
+            cv.calibrate_with_moves(&image, "R U Q"),
+            Err(QvisError::InvalidAlgorithm(_))
+        ));
+
+        let other = CVProcessor::new(
+            Arc::clone(&geometry),
+            image_size - 1,
+            assignment(&group)[1..].into(),
+        )
+        .unwrap();
+        assert!(matches!(
+            cv.merge(&other),
+            Err(QvisError::IncompatibleCalibration(_))
+        ));
+
+        // Assignments that don't fit the image or the puzzle
+        assert!(matches!(
+            CVProcessor::new(Arc::clone(&geometry), image_size + 1, assignment(&group)),
+            Err(QvisError::InvalidAssignment(InvalidAssignment::Size { expected, found }))
+                if expected == image_size + 1 && found == image_size
+        ));
+        let mut beyond = assignment(&group);
+        beyond[0] = Pixel::Sticker(group.facelet_count());
+        assert!(matches!(
+            CVProcessor::new(Arc::clone(&geometry), image_size, beyond),
+            Err(QvisError::InvalidAssignment(InvalidAssignment::Sticker {
+                pixel: 0,
+                ..
+            }))
+        ));
+        let mut pink = assignment(&group);
+        pink[1] = Pixel::WhiteBalance(ArcIntern::from("pink"));
+        assert!(matches!(
+            cv.reassign_sticker(0, &pink),
+            Err(QvisError::InvalidAssignment(InvalidAssignment::Face { .. }))
+        ));
+        assert!(matches!(
+            cv.reassign_sticker(0, &assignment(&group)[1..]),
+            Err(QvisError::InvalidAssignment(InvalidAssignment::Size { .. }))
+        ));
+        assert!(matches!(
+            cv.reassign_sticker(group.facelet_count(), &assignment(&group)),
+            Err(QvisError::InvalidFaceletLabel(InvalidFaceletLabel::Index(
+                _
+            )))
+        ));
+        assert!(matches!(
+            CVProcessor::new(Arc::clone(&geometry), image_size, pink),
+            Err(QvisError::InvalidAssignment(InvalidAssignment::Face {
+                pixel: 1,
+                ..
+            }))
+        ));
+        assert!(matches!(
+            CVProcessor::with_super_pixels(
+                Arc::clone(&geometry),
+                SuperPixels::blocks(image_size, 1, 2),
+                assignment(&group),
+            ),
+            Err(QvisError::InvalidAssignment(InvalidAssignment::Size { .. }))
+        ));
+
+        // Settings that can't be used
+        assert!(matches!(
+            cv.set_smoothing_window(Some(0)),
+            Err(QvisError::InvalidParams(InvalidParams::SmoothingWindow))
+        ));
+        assert!(matches!(
+            cv.set_inference_params(InferenceParams {
+                confidence_percentile: 1.,
+                ..InferenceParams::default()
+            }),
+            Err(QvisError::InvalidParams(
+                InvalidParams::ConfidencePercentile(_)
+            ))
+        ));
+        let mut weights = vec![1.; image_size];
+        assert!(matches!(
+            cv.set_pixel_weights(&weights[1..]),
+            Err(QvisError::InvalidWeights(InvalidWeights::Size { expected, found }))
+                if expected == image_size && found == image_size - 1
+        ));
+        weights[PIXELS_PER_STICKER] = -1.;
+        assert!(matches!(
+            cv.set_pixel_weights(&weights),
+            Err(QvisError::InvalidWeights(InvalidWeights::Negative(pixel))) if pixel == PIXELS_PER_STICKER
+        ));
+        weights[..PIXELS_PER_STICKER].fill(0.);
+        weights[PIXELS_PER_STICKER] = 1.;
+        assert!(matches!(
+            cv.set_pixel_weights(&weights),
+            Err(QvisError::InvalidWeights(InvalidWeights::ZeroSticker(0)))
+        ));
+
+        // Nothing failed halfway
+        assert_eq!(cv.process_image(image).unwrap().0, solved);
+    }
+
+    #[test]
+    fn seeded() {
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"Same seed, same answer, every go");
+
+        let Cube {
+            geometry,
+            group,
+            stabchain,
+            ..
+        } = cube();
+        let colors = group.facelet_colors().iter().unique().collect_vec();
+
+        // A camera without any noise that can't tell the first two colors apart, so that the nearest samples of those colors at each pixel are all exactly as near and the randomness picks which of them get to vote
+        let flat = |state: &Permutation| -> Image {
+            (0..group.facelet_count())
+                .flat_map(|sticker| {
+                    let color = &group.facelet_colors()[state.state().get(sticker)];
+                    let idx = colors.iter().position(|v| *v == color).unwrap();
+                    [PALETTE[idx.max(1)]; PIXELS_PER_STICKER]
+                })
+                .collect()
+        };
+
+        let calibration = (0..40)
+            .map(|_| {
+                let state = stabchain.random(&mut rng);
+                (flat(&state), state)
+            })
+            .collect_vec();
+        let image = flat(&stabchain.random(&mut rng));
+
+        let observe = |scoring, seed| {
+            let mut cv = processor(&geometry);
+            cv.set_seed(Some(seed));
+            cv.set_inference_params(InferenceParams {
+                scoring,
+                ..InferenceParams::default()
+            })
+            .unwrap();
+            for (image, state) in &calibration {
+                cv.calibrate(image, state.clone()).unwrap();
+            }
+
+            cv.observe(&image).unwrap()
+        };
+
+        for scoring in [Scoring::Density, Scoring::Vote] {
+            assert_eq!(observe(scoring, 42), observe(scoring, 42));
+        }
+
+        // The ties are broken differently with other seeds
+        let seeded = observe(Scoring::Vote, 42);
+        assert!((0..8).any(|seed| observe(Scoring::Vote, seed) != seeded));
+    }
+
+    #[test]
+    #[cfg(feature = "serde")]
+    fn profiles() {
+        use crate::{ProfileError, Profiles};
+
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"Office lights, living room lamps");
+
+        let Cube {
+            geometry,
+            group,
+            stabchain,
+            image_size,
+        } = cube();
+
+        // Each run of the tests gets its own directory, so that tests run at the same time don't share the profiles
+        let dir = std::env::temp_dir().join(format!("qvis_profiles_test_{}", std::process::id()));
+        // Left over from a run that failed
+        let _ = std::fs::remove_dir_all(&dir);
+        let profiles = Profiles::new(&dir);
+        assert!(profiles.list().unwrap().is_empty());
+
+        // The living room is much dimmer than the office
+        let dim = |image: Image| -> Image {
+            image
+                .iter()
+                .map(|(r, g, b)| (r * 0.4, g * 0.4, b * 0.4))
+                .collect()
+        };
+        let calibrated = |lighting: &dyn Fn(Image) -> Image, rng: &mut rand::rngs::SmallRng| {
+            let mut cv = processor(&geometry);
+            cv.set_seed(Some(7));
+            for _ in 0..5 {
+                let state = stabchain.random(rng);
+                cv.calibrate(&lighting(picture(&group, &state, rng)), state)
+                    .unwrap();
+            }
+            cv
+        };
+
+        let office = calibrated(&|image| image, &mut rng);
+        let living_room = calibrated(&dim, &mut rng);
+        office.save_profile(&profiles, "office").unwrap();
+        living_room.save_profile(&profiles, "living room").unwrap();
+        assert_eq!(profiles.list().unwrap(), ["living room", "office"]);
+
+        let image = picture(&group, &stabchain.random(&mut rng), &mut rng);
+        let dim_image = dim(image.clone());
+
+        let mut cv = processor(&geometry);
+        cv.set_seed(Some(7));
+        assert!(matches!(cv.observe(&image), Err(QvisError::Uncalibrated)));
+
+        cv.load_profile(&profiles, "office").unwrap();
+        assert_eq!(cv.observe(&image).unwrap(), office.observe(&image).unwrap());
+
+        cv.load_profile(&profiles, "living room").unwrap();
+        assert_eq!(
+            cv.observe(&dim_image).unwrap(),
+            living_room.observe(&dim_image).unwrap()
+        );
+
+        // Switching back doesn't keep anything from the living room
+        cv.load_profile(&profiles, "office").unwrap();
+        assert_eq!(cv.observe(&image).unwrap(), office.observe(&image).unwrap());
+
+        assert!(matches!(
+            cv.save_profile(&profiles, "../office"),
+            Err(QvisError::Profile(ProfileError::InvalidName(_)))
+        ));
+        assert!(matches!(
+            cv.load_profile(&profiles, "kitchen"),
+            Err(QvisError::Profile(ProfileError::Io(_)))
+        ));
+
+        // A profile of a different assignment fails to load and leaves the calibration be
+        let mut shifted = assignment(&group);
+        shifted.rotate_left(1);
+        let mut other = CVProcessor::new(Arc::clone(&geometry), image_size, shifted).unwrap();
+        assert!(matches!(
+            other.load_profile(&profiles, "office"),
+            Err(QvisError::Profile(ProfileError::Invalid(_)))
+        ));
+        assert!(matches!(
+            other.observe(&image),
+            Err(QvisError::Uncalibrated)
+        ));
+
+        // So does one whose samples were decoded differently
+        let mut linear = processor(&geometry);
+        linear
+            .set_inference_params(InferenceParams {
+                linearize_srgb: true,
+                ..InferenceParams::default()
+            })
+            .unwrap();
+        assert!(matches!(
+            linear.load_profile(&profiles, "office"),
+            Err(QvisError::Profile(ProfileError::Invalid(_)))
+        ));
+
+        // Without a file system, like in the browser
+        let mut browser = processor(&geometry);
+        browser.set_seed(Some(7));
+        browser
+            .load_calibration_text(&office.calibration_text())
+            .unwrap();
+        assert_eq!(
+            browser.observe(&image).unwrap(),
+            office.observe(&image).unwrap()
+        );
+        assert!(browser.load_calibration_text("office").is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    thread_local! {
+        // Whether the timings logged on this thread are counted, so that the logger only sees the test that turned it on and not every other test running at the same time
+        static COUNTING: Cell<bool> = const { Cell::new(false) };
+    }
+
+    /// Counts the recognition timings that are logged on threads that turned counting on
+    struct TimingCounter(AtomicUsize);
+
+    impl log::Log for TimingCounter {
+        fn enabled(&self, metadata: &log::Metadata) -> bool {
+            metadata.level() <= log::Level::Debug && COUNTING.with(Cell::get)
+        }
+
+        fn log(&self, record: &log::Record) {
+            if self.enabled(record.metadata()) && record.args().to_string().contains(" took ") {
+                self.0.fetch_add(1, atomic::Ordering::Relaxed);
+            }
+        }
+
+        fn flush(&self) {}
+    }
+
+    #[test]
+    fn timing_logs() {
+        static COUNTER: TimingCounter = TimingCounter(AtomicUsize::new(0));
+        // Another test may have set it first
+        let _ = log::set_logger(&COUNTER);
+        // Only raised, and the logger ignores the other threads, so the tests running alongside this one are unaffected
+        if log::max_level() < LevelFilter::Debug {
+            log::set_max_level(LevelFilter::Debug);
+        }
+
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"How long does it take? Log it!!!");
+        let Cube {
+            geometry,
+            group,
+            stabchain,
+            ..
+        } = cube();
+
+        let mut cv = processor(&geometry);
+        cv.set_seed(Some(3));
+        for _ in 0..5 {
+            let state = stabchain.random(&mut rng);
+            cv.calibrate(&picture(&group, &state, &mut rng), state)
+                .unwrap();
+        }
+        let image = picture(&group, &stabchain.random(&mut rng), &mut rng);
+
+        let quiet = cv.process_image(image.clone()).unwrap();
+        assert_eq!(COUNTER.0.load(atomic::Ordering::Relaxed), 0);
+
+        COUNTING.with(|counting| counting.set(true));
+        let timed = cv.process_image(image).unwrap();
+        COUNTING.with(|counting| counting.set(false));
+
+        assert_eq!(quiet, timed);
+        // Both the inference and the matching were timed
+        assert!(COUNTER.0.load(atomic::Ordering::Relaxed) >= 2);
     }
 }
@@ -0,0 +1,120 @@
+use itertools::Itertools;
+use puzzle_theory::{
+    permutations::{Algorithm, Permutation, PermutationGroup},
+    puzzle_geometry::PuzzleGeometry,
+};
+
+/// A turn of one of the puzzle's generators by some number of steps, as the state that it brings the solved puzzle to
+pub(crate) struct Turn {
+    generator: usize,
+    name: String,
+    pub(crate) state: Vec<usize>,
+}
+
+/// Find the shortest sequence of at most `max_moves` moves that turns the puzzle from state `a` into state `b`, for following along with someone turning the puzzle between two recognitions. Turning a face by any amount counts as one move, and two equal states give the empty sequence.
+///
+/// Returns `None` if no sequence that short gets from one to the other, which includes the states not being reachable from each other by legal moves at all, as when one of them was misrecognized as a state that needs a piece to be taken apart. The search tries every sequence up to the length, so `max_moves` should be small; three is instant on a 3x3.
+pub fn moves_between(
+    puzzle: &PuzzleGeometry,
+    a: &Permutation,
+    b: &Permutation,
+    max_moves: usize,
+) -> Option<Algorithm> {
+    let moves = move_names_between(&puzzle.permutation_group(), a, b, max_moves)?;
+    Algorithm::parse_from_string(puzzle.permutation_group(), &moves.join(" "))
+}
+
+/// `moves_between`, as the name of each move, like `R` or `U2`
+pub(crate) fn move_names_between(
+    group: &PermutationGroup,
+    a: &Permutation,
+    b: &Permutation,
+    max_moves: usize,
+) -> Option<Vec<String>> {
+    let turns = turns(group);
+
+    let (a, b) = (state_of(group, a), state_of(group, b));
+    let mut path = Vec::new();
+
+    // Deepening one move at a time finds the shortest sequence first
+    let found = (0..=max_moves).any(|depth| search(&turns, &a, &b, depth, &mut path));
+    if !found {
+        return None;
+    }
+
+    Some(
+        path.into_iter()
+            .map(|turn| turns[turn].name.clone())
+            .collect(),
+    )
+}
+
+/// Every turn of every generator of the group by any amount, which are the states one move away from solved
+pub(crate) fn turns(group: &PermutationGroup) -> Vec<Turn> {
+    group
+        .generators()
+        .enumerate()
+        .flat_map(|(generator, (name, permutation))| {
+            let step = state_of(group, permutation);
+            let identity = (0..step.len()).collect_vec();
+
+            // Every power of the generator up to the one that brings the puzzle back
+            let mut powers = vec![step.clone()];
+            while let Some(last) = powers.last().filter(|last| **last != identity) {
+                powers.push(then(last, &step));
+            }
+            powers.pop();
+
+            let order = powers.len() + 1;
+            powers.into_iter().enumerate().map(move |(i, state)| Turn {
+                generator,
+                name: match i + 1 {
+                    1 => name.to_string(),
+                    power if power == order - 1 => format!("{name}'"),
+                    power => format!("{name}{power}"),
+                },
+                state,
+            })
+        })
+        .collect()
+}
+
+/// Where each facelet of `permutation` comes from, written out for every facelet of the group
+pub(crate) fn state_of(group: &PermutationGroup, permutation: &Permutation) -> Vec<usize> {
+    (0..group.facelet_count())
+        .map(|facelet| permutation.state().get(facelet))
+        .collect()
+}
+
+/// The state that turning a puzzle in state `a` by `turn` leaves it in. Whatever `turn` brings into a position comes from where `turn` takes it from, which in `a` holds `a`'s facelet for that position.
+pub(crate) fn then(a: &[usize], turn: &[usize]) -> Vec<usize> {
+    turn.iter().map(|from| a[*from]).collect()
+}
+
+/// Look for exactly `depth` more turns that bring `current` to `target`, pushing them onto `path`. Turning the same generator twice in a row is never shorter than turning it once, so it isn't tried.
+fn search(
+    turns: &[Turn],
+    current: &[usize],
+    target: &[usize],
+    depth: usize,
+    path: &mut Vec<usize>,
+) -> bool {
+    if depth == 0 {
+        return current == target;
+    }
+
+    let last = path.last().map(|turn| turns[*turn].generator);
+    for (i, turn) in turns.iter().enumerate() {
+        if Some(turn.generator) == last {
+            continue;
+        }
+
+        path.push(i);
+        if search(turns, &then(current, &turn.state), target, depth - 1, path) {
+            return true;
+        }
+        path.pop();
+    }
+
+    false
+}
@@ -0,0 +1,134 @@
+//! Named calibrations kept side by side, so that moving between places with different lighting means loading the calibration that was made there instead of calibrating again.
+//!
+//! The profiles are a directory with one file per profile, named after the profile:
+//!
+//! ```text
+//! profiles/
+//!     office.calibration
+//!     living room.calibration
+//! ```
+//!
+//! Each file holds a calibration as written by `CVProcessor::save_profile`, which needs the `serde` feature.
+
+use std::{error::Error, fmt, io, path::PathBuf};
+
+const EXTENSION: &str = "calibration";
+
+/// Why a profile couldn't be saved or loaded
+#[derive(Debug)]
+pub enum ProfileError {
+    /// The name can't be used as a file name. Names are made of letters, digits, spaces, `-`, and `_`.
+    InvalidName(String),
+    /// The profile couldn't be read or written
+    Io(io::Error),
+    /// The profile isn't a calibration of this processor's puzzle with its assignment
+    Invalid(String),
+}
+
+impl fmt::Display for ProfileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProfileError::InvalidName(name) => {
+                write!(f, "`{name}` can't be the name of a profile")
+            }
+            ProfileError::Io(err) => write!(f, "{err}"),
+            ProfileError::Invalid(reason) => write!(f, "The profile is invalid: {reason}"),
+        }
+    }
+}
+
+impl Error for ProfileError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ProfileError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ProfileError {
+    fn from(err: io::Error) -> Self {
+        ProfileError::Io(err)
+    }
+}
+
+/// The directory that profiles are saved in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Profiles {
+    dir: PathBuf,
+}
+
+impl Profiles {
+    /// Keep profiles in `dir`. The directory is created when the first profile is saved.
+    pub fn new(dir: impl Into<PathBuf>) -> Profiles {
+        Profiles { dir: dir.into() }
+    }
+
+    /// The names of every saved profile in alphabetical order. There are none if the directory doesn't exist yet.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the directory couldn't be read.
+    pub fn list(&self) -> io::Result<Vec<String>> {
+        let entries = match std::fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+
+        let mut names = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path
+                .extension()
+                .is_some_and(|extension| extension == EXTENSION)
+                && let Some(name) = path.file_stem().and_then(|name| name.to_str())
+                && is_valid_name(name)
+            {
+                names.push(name.to_owned());
+            }
+        }
+        names.sort();
+
+        Ok(names)
+    }
+
+    /// Where the profile named `name` is saved
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `name` can't be used as a file name.
+    pub fn path(&self, name: &str) -> Result<PathBuf, ProfileError> {
+        if !is_valid_name(name) {
+            return Err(ProfileError::InvalidName(name.to_owned()));
+        }
+
+        Ok(self.dir.join(format!("{name}.{EXTENSION}")))
+    }
+
+    /// Save `text` as the profile named `name`, replacing the profile if it already exists
+    #[cfg(feature = "serde")]
+    pub(crate) fn write(&self, name: &str, text: &str) -> Result<(), ProfileError> {
+        let path = self.path(name)?;
+        std::fs::create_dir_all(&self.dir)?;
+        std::fs::write(path, text)?;
+
+        Ok(())
+    }
+
+    /// Read the profile named `name`
+    #[cfg(feature = "serde")]
+    pub(crate) fn read(&self, name: &str) -> Result<String, ProfileError> {
+        Ok(std::fs::read_to_string(self.path(name)?)?)
+    }
+}
+
+/// Whether `name` is safe to put in a file name, which rules out anything that could point outside of the directory
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with(' ')
+        && !name.ends_with(' ')
+        && name
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, ' ' | '-' | '_'))
+}
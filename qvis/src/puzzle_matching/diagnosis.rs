@@ -0,0 +1,122 @@
+use std::collections::BTreeMap;
+
+use internment::ArcIntern;
+use itertools::Itertools;
+use puzzle_theory::permutations::Permutation;
+
+use crate::{
+    cmp_ll,
+    puzzle_matching::{IMPLAUSIBLE_LOG_LIKELIHOOD, Matcher, finite_log_likelihood},
+};
+
+/// The smallest change that would make a state that isn't a member of the group into one that is. Orbits are numbered in the order of `PuzzleGeometry::pieces_data` and spots in the order of the orbit's pieces.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Inconsistency {
+    /// Twisting the piece in `spot` of `orbit` another `by` orientations would make the state possible, so it was probably misread as twisted
+    Twisted {
+        orbit: usize,
+        spot: usize,
+        by: usize,
+    },
+    /// Swapping the pieces in two spots of `orbit` would make the state possible, so they were probably misread as each other
+    Swapped { orbit: usize, spots: (usize, usize) },
+}
+
+impl Matcher {
+    /// Explain why `state` isn't a member of the group by finding the single twist or swap of pieces that makes it one while losing the least log-likelihood under `confidences`. This turns a recognition that gave a strange answer into which sticker was probably misread.
+    ///
+    /// Returns `None` if `state` is already a member of the group or if no single twist or swap fixes it.
+    pub fn diagnose(
+        &self,
+        confidences: &[BTreeMap<ArcIntern<str>, f64>],
+        state: &Permutation,
+    ) -> Option<Inconsistency> {
+        if self.stab_chain.is_member(state.clone()) {
+            return None;
+        }
+
+        let group = self.orbits.first()?.puzzle.permutation_group();
+        let comes_from = (0..group.facelet_count())
+            .map(|sticker| state.state().get(sticker))
+            .collect_vec();
+
+        let log_likelihood = |comes_from: &[usize]| -> f64 {
+            comes_from
+                .iter()
+                .zip(confidences)
+                // A sticker that wasn't seen says nothing either way
+                .filter(|(_, confidences)| !confidences.is_empty())
+                .map(|(is, confidences)| {
+                    confidences
+                        .get(&group.facelet_colors()[*is])
+                        .map_or(IMPLAUSIBLE_LOG_LIKELIHOOD, |v| finite_log_likelihood(*v))
+                })
+                .sum()
+        };
+
+        let mut fixes = Vec::new();
+
+        for (orbit_idx, orbit_matcher) in self.orbits.iter().enumerate() {
+            let orbit = &orbit_matcher.orbit;
+            let pieces_data = orbit_matcher.puzzle.pieces_data();
+            let ori_nums = pieces_data.orientation_numbers();
+
+            for (spot, piece) in orbit.pieces().iter().enumerate() {
+                for by in 1..orbit.orientation_count() {
+                    let mut fixed = comes_from.clone();
+
+                    for sticker in piece.stickers() {
+                        let twisted = (0..by)
+                            .fold(*sticker, |sticker, _| piece.twist().mapping().get(sticker));
+                        fixed[*sticker] = comes_from[twisted];
+                    }
+
+                    fixes.push((
+                        Inconsistency::Twisted {
+                            orbit: orbit_idx,
+                            spot,
+                            by,
+                        },
+                        fixed,
+                    ));
+                }
+            }
+
+            for (a, b) in (0..orbit.pieces().len()).tuple_combinations() {
+                let mut fixed = comes_from.clone();
+
+                for sticker_a in orbit.pieces()[a].stickers() {
+                    let sticker_b = orbit.pieces()[b]
+                        .stickers()
+                        .iter()
+                        .find(|v| ori_nums[**v] == ori_nums[*sticker_a])
+                        .unwrap();
+
+                    fixed[*sticker_a] = comes_from[*sticker_b];
+                    fixed[*sticker_b] = comes_from[*sticker_a];
+                }
+
+                fixes.push((
+                    Inconsistency::Swapped {
+                        orbit: orbit_idx,
+                        spots: (a, b),
+                    },
+                    fixed,
+                ));
+            }
+        }
+
+        fixes
+            .into_iter()
+            .map(|(inconsistency, fixed)| {
+                let ll = log_likelihood(&fixed);
+                (inconsistency, fixed, ll)
+            })
+            .sorted_by(|(_, _, a), (_, _, b)| cmp_ll(*b, *a))
+            .find(|(_, fixed, _)| {
+                self.stab_chain
+                    .is_member(Permutation::from_state(fixed.clone()))
+            })
+            .map(|(inconsistency, _, _)| inconsistency)
+    }
+}
@@ -0,0 +1,82 @@
+//! A matching algorithm that places the pieces of each orbit one at a time, most likely first, as a simple baseline for the exact search of `Matcher` to be compared against.
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use internment::ArcIntern;
+use itertools::Itertools;
+use puzzle_theory::{
+    permutations::{Permutation, schreier_sims::StabilizerChain},
+    puzzle_geometry::PuzzleGeometry,
+};
+
+use crate::{
+    cmp_ll,
+    puzzle_matching::{OrbitMatcher, StateMatcher},
+};
+
+/// Matches each orbit greedily: the piece and orientation that is most likely in any spot is placed there first, then the most likely one of what is left, and so on until every spot has a piece. This skips the search of `Matcher` entirely, but a confident mistake early on can't be taken back, and the state that the orbits add up to isn't always one that the puzzle can be in.
+pub struct GreedyMatcher {
+    orbits: Box<[OrbitMatcher]>,
+    stab_chain: StabilizerChain,
+    facelet_count: usize,
+}
+
+impl GreedyMatcher {
+    pub fn new(puzzle: Arc<PuzzleGeometry>) -> GreedyMatcher {
+        let data = puzzle.pieces_data();
+        let group = puzzle.permutation_group();
+
+        GreedyMatcher {
+            orbits: data
+                .orbits()
+                .iter()
+                .map(|orbit| OrbitMatcher::new(Arc::clone(&puzzle), orbit))
+                .collect(),
+            stab_chain: StabilizerChain::new(&group),
+            facelet_count: group.facelet_count(),
+        }
+    }
+}
+
+impl StateMatcher for GreedyMatcher {
+    // Returns `None` if the greedy placements don't add up to a state that the puzzle can be in
+    fn most_likely(
+        &self,
+        log_likelihoods: &[BTreeMap<ArcIntern<str>, f64>],
+    ) -> Option<(Permutation, f64)> {
+        let mut cycles = Vec::new();
+        let mut log_likelihood = 0.;
+
+        for orbit in &self.orbits {
+            let costs = orbit.cost_matrix(log_likelihoods);
+            let (spots, pieces, oris) = costs.dim();
+
+            // Every way of placing a piece in a spot, most likely first. The sort is stable, so ties go to the lowest indices.
+            let placements = (0..spots)
+                .cartesian_product(0..pieces)
+                .cartesian_product(0..oris)
+                .map(|((spot, piece), ori)| [spot, piece, ori])
+                .sorted_by(|a, b| cmp_ll(costs[*b], costs[*a]));
+
+            let mut matching = vec![None; spots];
+            let mut placed = vec![false; pieces];
+            for [spot, piece, ori] in placements {
+                if matching[spot].is_none() && !placed[piece] {
+                    matching[spot] = Some((piece, ori));
+                    placed[piece] = true;
+                    log_likelihood += costs[[spot, piece, ori]];
+                }
+            }
+
+            // An orbit has as many pieces as spots, so every spot got one
+            let matching = matching.into_iter().map(Option::unwrap).collect_vec();
+            let state = orbit.matching_state(&matching, self.facelet_count);
+            cycles.extend(state.cycles().iter().cloned());
+        }
+
+        let state = Permutation::from_cycles(cycles);
+        self.stab_chain
+            .is_member(state.clone())
+            .then_some((state, log_likelihood))
+    }
+}
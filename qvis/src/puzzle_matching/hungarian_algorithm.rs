@@ -1,13 +1,17 @@
-use std::mem;
+use std::{cmp::Ordering, mem};
 
 use ndarray::{Array2, ArrayRef2};
 
+use crate::cmp_ll;
+
 #[derive(Default, Clone, Copy, Debug)]
 struct Node {
     potential: f64,
     matches_with: Option<usize>,
     bfs_comes_from: Option<usize>,
     visited: bool,
+    // For nodes on the right, the least reduced cost of an edge to them from a visited node on the left, along with that node. This is kept up to date as the BFS visits nodes so that `relax_potentials` doesn't have to scan every edge for it.
+    slack: Option<(f64, usize)>,
 }
 
 /// Allows storing the left and right nodes of the bipartite graph in the same list
@@ -17,52 +21,89 @@ struct Element {
     right: Node,
 }
 
-/// Return a maximum cost matching where the number at index `i` is the index that `i` matches with. The `costs[i][j]` represents the cost of matching `i` with `j`. If the cost is `None`, then we consider matching those two elements to be disallowed. In this case, the function will return `None`.
+/// The state of the solver once it has found a maximum matching. After making a single entry of the costs less likely, the new maximum matching can be found from here with `rematch` instead of starting over.
+#[derive(Clone, Debug)]
+pub struct Solution {
+    data: Box<[Element]>,
+    is_tight: Array2<bool>,
+}
+
+impl Solution {
+    /// The index that each index on the left matches with
+    pub fn matching(&self) -> Vec<usize> {
+        self.data
+            .iter()
+            .map(|elt| elt.left.matches_with.unwrap())
+            .collect()
+    }
+}
+
+/// Find a maximum cost matching, which `Solution::matching` gives as the index that each `i` matches with. The `costs[i][j]` represents the cost of matching `i` with `j`. If the cost is `None`, then we consider matching those two elements to be disallowed. If no matching avoids the disallowed pairs, the function will return `None`.
 ///
 /// <https://timroughgarden.org/w16/l/l5.pdf>
-pub fn maximum_matching(costs: &ArrayRef2<Option<f64>>) -> Option<Vec<usize>> {
+pub fn solve(costs: &ArrayRef2<Option<f64>>) -> Option<Solution> {
     assert!(costs.is_square());
 
-    if costs.is_empty() {
-        return Some(Vec::new());
-    }
-
-    let mut is_tight = Array2::from_shape_fn(costs.raw_dim(), |_| false);
+    let is_tight = Array2::from_shape_fn(costs.raw_dim(), |_| false);
 
     // Each value is a tuple of `(left potential, right potential, left matches to, right matches to, bfs depth)`
     let mut data: Box<[_]> = Box::from(vec![Element::default(); costs.shape()[0]]);
 
+    if costs.is_empty() {
+        return Some(Solution { data, is_tight });
+    }
+
     // We need the reduced cost to be <=0 and we can make that happen in the case of negative costs by setting all of the potentials on the left to the min cost.
+    // If nothing is allowed, there's no matching at all
     let min_cost = costs
         .iter()
         .filter_map(|v| *v)
-        .max_by(|a, b| a.total_cmp(b))
-        .unwrap();
+        .max_by(|a, b| cmp_ll(*a, *b))?;
 
     for elt in &mut data {
         elt.left.potential = min_cost;
     }
 
+    complete(Solution { data, is_tight }, costs)
+}
+
+/// Find the maximum matching after `costs[[i, j]]` was lowered or disallowed, where `previous` is the solution for the costs before the change. Lowering a cost keeps the potentials feasible and every other matched pair tight, so only `i` has to be matched again, which takes a single augmenting path rather than a full solve. This is the usual speedup for Murty's algorithm.
+pub fn rematch(
+    previous: &Solution,
+    costs: &ArrayRef2<Option<f64>>,
+    (i, j): (usize, usize),
+) -> Option<Solution> {
+    let mut solution = previous.clone();
+
+    solution.is_tight[[i, j]] = false;
+    if solution.data[i].left.matches_with == Some(j) {
+        solution.data[i].left.matches_with = None;
+        solution.data[j].right.matches_with = None;
+    }
+
+    complete(solution, costs)
+}
+
+/// Augment the matching until everything on the left is matched
+fn complete(mut solution: Solution, costs: &ArrayRef2<Option<f64>>) -> Option<Solution> {
+    let Solution { data, is_tight } = &mut solution;
+
     while let Some((i, _)) = data
         .iter()
         .enumerate()
         .find(|(_, elt)| elt.left.matches_with.is_none())
     {
-        match find_augmenting_path(i, &mut data, &is_tight, costs) {
-            Some(endpoint) => toggle_augmenting_path(endpoint, &mut data),
+        match find_augmenting_path(i, data, is_tight, costs) {
+            Some(endpoint) => toggle_augmenting_path(endpoint, data),
             None => {
-                if !relax_potentials(&mut data, &mut is_tight, costs) {
+                if !relax_potentials(data, is_tight) {
                     return None;
                 }
             }
         }
     }
 
-    Some(
-        data.into_iter()
-            .map(|elt| elt.left.matches_with.unwrap())
-            .collect(),
-    )
+    Some(solution)
 }
 
 /// Attempt to find an augmenting (good) path that we can use to increase the number of matched nodes by one. If there exists one, then this will return the right index and the information to recover the path is stored in the `bfs_comes_from` fields. Otherwise, the BFS data will still be stored and can be used to relax node prices along the path.
@@ -78,11 +119,12 @@ fn find_augmenting_path(
         elt.left.visited = false;
         elt.right.bfs_comes_from = None;
         elt.right.visited = false;
+        elt.right.slack = None;
     }
 
     // These are always items on the left side of the bipartite graph
     let mut current_level = vec![start_from];
-    data[start_from].left.visited = true;
+    visit_left(start_from, data, costs);
     let mut next_level = vec![];
 
     while !current_level.is_empty() {
@@ -101,7 +143,7 @@ fn find_augmenting_path(
                             // If this is matched with something on the left, then we must search that node in the next layer if it is unvisited
                             if !data[new_left_idx].left.visited {
                                 data[new_left_idx].left.bfs_comes_from = Some(right_idx);
-                                data[new_left_idx].left.visited = true;
+                                visit_left(new_left_idx, data, costs);
                                 next_level.push(new_left_idx);
                             }
                         }
@@ -120,6 +162,26 @@ fn find_augmenting_path(
     None
 }
 
+/// Mark the node on the left as visited and lower the slack of the nodes on the right to the reduced costs of their edges from it. Ties go to the node with the lowest index, so that the edge that `relax_potentials` makes tight doesn't depend on the order that the nodes were visited in.
+fn visit_left(left_idx: usize, data: &mut [Element], costs: &ArrayRef2<Option<f64>>) {
+    data[left_idx].left.visited = true;
+    let potential = data[left_idx].left.potential;
+
+    for right_idx in 0..costs.shape()[1] {
+        let Some(c) = costs[[left_idx, right_idx]] else {
+            continue;
+        };
+
+        let right = &mut data[right_idx].right;
+        let reduced = potential + right.potential - c;
+        if right.slack.is_none_or(|(slack, i)| {
+            reduced.total_cmp(&slack).then(left_idx.cmp(&i)) == Ordering::Less
+        }) {
+            right.slack = Some((reduced, left_idx));
+        }
+    }
+}
+
 /// Set the matching to the xor of the current matching with the augmenting path
 fn toggle_augmenting_path(mut endpoint: usize, data: &mut [Element]) {
     loop {
@@ -135,26 +197,33 @@ fn toggle_augmenting_path(mut endpoint: usize, data: &mut [Element]) {
     }
 }
 
-/// Relax the potentials along the path to make at least one more edge tight
+/// Relax the potentials along the path to make at least one more edge tight. The least slack is found from the slack of each node on the right, which `find_augmenting_path` leaves behind.
 ///
 /// Returns whether anything was able to be relaxed
-fn relax_potentials(
-    data: &mut [Element],
-    is_tight: &mut ArrayRef2<bool>,
-    costs: &ArrayRef2<Option<f64>>,
-) -> bool {
-    let Some(((i, j), δ)) = costs
-        .indexed_iter()
-        .filter_map(|(idxs, v)| v.map(|v| (idxs, v)))
-        .filter(|((i, j), _)| data[*i].left.visited && !data[*j].right.visited)
-        .map(|((i, j), c)| ((i, j), data[i].left.potential + data[j].right.potential - c))
-        .min_by(|(_, a), (_, b)| a.total_cmp(b))
+fn relax_potentials(data: &mut [Element], is_tight: &mut ArrayRef2<bool>) -> bool {
+    let Some(((i, j), δ)) = data
+        .iter()
+        .enumerate()
+        .filter(|(_, elt)| !elt.right.visited)
+        .filter_map(|(j, elt)| elt.right.slack.map(|(δ, i)| ((i, j), δ)))
+        .min_by(|((a_i, a_j), a), ((b_i, b_j), b)| {
+            a.total_cmp(b).then((a_i, a_j).cmp(&(b_i, b_j)))
+        })
     else {
         return false;
     };
 
     is_tight[[i, j]] = true;
 
+    // Raising the potential of the visited nodes on the right loosens their edges to the unvisited nodes on the left
+    if δ != 0. {
+        for ((i, j), tight) in is_tight.indexed_iter_mut() {
+            if !data[i].left.visited && data[j].right.visited {
+                *tight = false;
+            }
+        }
+    }
+
     for elt in data {
         if elt.left.visited {
             elt.left.potential -= δ;
@@ -170,9 +239,15 @@ fn relax_potentials(
 
 #[cfg(test)]
 mod tests {
-    use ndarray::array;
+    use itertools::Itertools;
+    use ndarray::{Array2, ArrayRef2, array};
+    use rand::{Rng, SeedableRng};
 
-    use super::maximum_matching;
+    use super::{rematch, solve};
+
+    fn maximum_matching(costs: &ArrayRef2<Option<f64>>) -> Option<Vec<usize>> {
+        solve(costs).map(|solution| solution.matching())
+    }
 
     #[test]
     fn example() {
@@ -213,6 +288,30 @@ mod tests {
         );
     }
 
+    #[test]
+    fn nothing_allowed() {
+        assert_eq!(maximum_matching(&Array2::from_elem((3, 3), None)), None);
+        assert_eq!(maximum_matching(&Array2::from_elem((1, 1), None)), None);
+        assert_eq!(
+            maximum_matching(&Array2::<Option<f64>>::from_elem((0, 0), None)),
+            Some(vec![])
+        );
+
+        // One piece that can't go anywhere rules out the whole matching
+        assert_eq!(
+            maximum_matching(&array![
+                [Some(-1.), Some(-2.), Some(-3.)],
+                [None, None, None],
+                [Some(-3.), Some(-2.), Some(-1.)],
+            ]),
+            None
+        );
+
+        // Disallowing the last allowed entry
+        let solution = solve(&array![[Some(-1.)]]).unwrap();
+        assert!(rematch(&solution, &array![[None]], (0, 0)).is_none());
+    }
+
     #[test]
     fn tightness_not_through_epsilon() {
         // This matching leads to the relaxing of potentials not working properly due to floating point rounding error because the precise value of the tightness is never close enough to zero to be considered zero under ε=1e-9. The solution is to keep track of tightness in a separate array.
@@ -302,4 +401,105 @@ mod tests {
             Some(vec![4, 1, 0, 2, 5, 6, 3, 7])
         );
     }
+
+    #[test]
+    fn rematch_matches_full_solve() {
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"Murty says one path is plenty!!!");
+
+        let cost = |costs: &Array2<Option<f64>>, matching: &[usize]| -> f64 {
+            matching
+                .iter()
+                .enumerate()
+                .map(|(i, j)| costs[[i, *j]].unwrap())
+                .sum()
+        };
+
+        for _ in 0..200 {
+            let n = rng.random_range(1..8);
+            // Small integers make ties common and keep the sums exact
+            let mut costs =
+                Array2::from_shape_fn((n, n), |_| Some(f64::from(rng.random_range(-20_i8..=0))));
+            let mut solution = solve(&costs).unwrap();
+
+            for _ in 0..n {
+                let i = rng.random_range(0..n);
+                let j = solution.matching()[i];
+                costs[[i, j]] = if rng.random_bool(0.3) {
+                    None
+                } else {
+                    costs[[i, j]].map(|v| v - f64::from(rng.random_range(1_i8..10)))
+                };
+
+                let full = maximum_matching(&costs);
+                let incremental = rematch(&solution, &costs, (i, j));
+                assert_eq!(full.is_some(), incremental.is_some());
+
+                let (Some(full), Some(incremental)) = (full, incremental) else {
+                    break;
+                };
+                assert_eq!(cost(&costs, &incremental.matching()), cost(&costs, &full));
+                solution = incremental;
+            }
+        }
+    }
+
+    #[test]
+    fn matches_brute_force() {
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"Try every matching there is, ok?");
+
+        for _ in 0..1000 {
+            let n = rng.random_range(1..6);
+            let costs = Array2::from_shape_fn((n, n), |_| {
+                (!rng.random_bool(0.2)).then(|| f64::from(rng.random_range(-20_i8..=0)))
+            });
+
+            let best = (0..n)
+                .permutations(n)
+                .filter_map(|matching| {
+                    matching
+                        .iter()
+                        .enumerate()
+                        .map(|(i, j)| costs[[i, *j]])
+                        .sum::<Option<f64>>()
+                })
+                .max_by(|a, b| a.total_cmp(b));
+            let found = maximum_matching(&costs).map(|matching| {
+                matching
+                    .iter()
+                    .enumerate()
+                    .map(|(i, j)| costs[[i, *j]].unwrap())
+                    .sum::<f64>()
+            });
+
+            assert_eq!(found, best, "{costs:?}");
+        }
+    }
+
+    #[test]
+    fn matches_brute_force_large() {
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"Eight by eight, every one of em!");
+        let n = 8;
+
+        for _ in 0..10 {
+            // Arbitrary fractions rather than small integers, so that the slack of every node ends up different
+            let costs = Array2::from_shape_fn((n, n), |_| {
+                (!rng.random_bool(0.1)).then(|| rng.random_range(-100.0..0.))
+            });
+            let cost = |matching: &[usize]| {
+                matching
+                    .iter()
+                    .enumerate()
+                    .map(|(i, j)| costs[[i, *j]])
+                    .sum::<Option<f64>>()
+            };
+
+            let best = (0..n)
+                .permutations(n)
+                .filter_map(|matching| cost(&matching))
+                .max_by(|a, b| a.total_cmp(b));
+            let found = maximum_matching(&costs).and_then(|matching| cost(&matching));
+
+            assert_eq!(found, best, "{costs:?}");
+        }
+    }
 }
@@ -1,6 +1,6 @@
 use std::{
     cmp::Ordering,
-    collections::{BinaryHeap, HashMap},
+    collections::{BTreeMap, BinaryHeap, HashMap},
     sync::Arc,
 };
 
@@ -12,13 +12,96 @@ use puzzle_theory::{
     puzzle_geometry::{OrbitData, OriNum, PuzzleGeometry},
 };
 
-use crate::puzzle_matching::hungarian_algorithm::maximum_matching;
+use crate::{
+    Observation, cmp_ll, moves,
+    puzzle_matching::hungarian_algorithm::{Solution, rematch, solve},
+};
 
+mod diagnosis;
+mod greedy;
 mod hungarian_algorithm;
+mod recognition;
+mod rotations;
+mod warm_start;
+
+pub use diagnosis::Inconsistency;
+pub use greedy::GreedyMatcher;
+pub use recognition::{RecognitionOutcome, RecognitionThresholds};
+pub use rotations::Rotation;
+
+/// What a sticker's log-likelihood for a color counts as if it is `-∞` or NaN. Keeping it finite means that a color that the classifier has never seen for a sticker only makes the states that need it very unlikely rather than breaking the matching for its whole orbit.
+const IMPLAUSIBLE_LOG_LIKELIHOOD: f64 = -1e9;
+/// What a sticker's log-likelihood for a color counts as if it is `∞`, which happens when a pixel exactly matches a calibration sample
+const CERTAIN_LOG_LIKELIHOOD: f64 = 1e9;
+
+/// Clamp a log-likelihood from the classifier to a finite value so that infinities and NaNs can't poison the sums and comparisons of the matching
+fn finite_log_likelihood(log_likelihood: f64) -> f64 {
+    if log_likelihood.is_nan() {
+        IMPLAUSIBLE_LOG_LIKELIHOOD
+    } else {
+        log_likelihood.clamp(IMPLAUSIBLE_LOG_LIKELIHOOD, CERTAIN_LOG_LIKELIHOOD)
+    }
+}
+
+/// Scale an orbit's log-likelihood by its weight. An orbit with a weight of zero counts for nothing even if its log-likelihood is `-∞`, which multiplying would turn into NaN.
+fn weighted_log_likelihood(weight: f64, log_likelihood: f64) -> f64 {
+    if weight == 0. {
+        0.
+    } else {
+        weight * log_likelihood
+    }
+}
+
+/// Combine the log-likelihoods from several photos of the same puzzle, taken from different angles, into one observation that the matcher can use. The photos are independent evidence, so the log-likelihoods of a sticker that more than one photo shows add up. A photo that doesn't show a sticker leaves it unobserved, and a sticker that none of the photos show stays that way, which the matcher takes as saying nothing about it. This method panics if the photos aren't of the same number of stickers.
+pub fn fuse_observations(observations: &[Observation]) -> Observation {
+    let Some(stickers) = observations.first().map(|v| v.len()) else {
+        return Box::new([]);
+    };
+
+    let mut fused = vec![BTreeMap::new(); stickers];
+
+    for observation in observations {
+        assert_eq!(observation.len(), stickers);
+
+        for (fused, log_likelihoods) in fused.iter_mut().zip(observation) {
+            for (color, log_likelihood) in log_likelihoods {
+                *fused.entry(ArcIntern::clone(color)).or_insert(0.) += log_likelihood;
+            }
+        }
+    }
+
+    fused.into()
+}
+
+/// A way of finding the most likely state of the puzzle from how much each sticker looks like each color, which `CVProcessor::set_state_matcher` swaps in to try other matching algorithms without changing the rest of the recognition. `Matcher` is the default and is exact; `GreedyMatcher` is a much simpler one to compare it against.
+pub trait StateMatcher: Send + Sync {
+    /// The most likely state of the puzzle given the log-likelihood of each color at each sticker, along with its log-likelihood, or `None` if no state could be found. The state must be a valid member of the group.
+    fn most_likely(
+        &self,
+        log_likelihoods: &[BTreeMap<ArcIntern<str>, f64>],
+    ) -> Option<(Permutation, f64)>;
+}
+
+/// How `Matcher` reports its way through the candidates, for showing that a search that has to go through many of them to find a state that the puzzle can be in, like for a bad frame or colors that are hard to tell apart, is still going
+#[derive(Clone)]
+pub struct Progress {
+    /// How many candidates are examined between reports
+    pub every: usize,
+    /// Called with how many candidates have been examined so far and the log-likelihood of the last one. The candidates come in order of decreasing log-likelihood, so none of the ones still to come is more likely.
+    pub callback: Arc<dyn Fn(usize, f64) + Send + Sync>,
+}
 
 pub struct Matcher {
     orbits: Box<[OrbitMatcher]>,
     stab_chain: StabilizerChain,
+    disallow_below: Option<f64>,
+    progress: Option<Progress>,
+    orbit_weights: Box<[f64]>,
+    rotations: Box<[Rotation]>,
+    reflections: Box<[Box<[usize]>]>,
+    facelet_colors: Box<[ArcIntern<str>]>,
+    // The states one turn away from solved, for `most_likely_near`
+    turns: Box<[Vec<usize>]>,
 }
 
 impl Matcher {
@@ -29,74 +112,226 @@ impl Matcher {
             .orbits()
             .iter()
             .map(|orbit| OrbitMatcher::new(Arc::clone(&puzzle), orbit))
-            .collect();
+            .collect::<Box<[_]>>();
 
         Matcher {
+            orbit_weights: vec![1.; orbits.len()].into(),
             orbits,
             stab_chain: StabilizerChain::new(&puzzle.permutation_group()),
+            disallow_below: None,
+            progress: None,
+            rotations: rotations::rotations(&puzzle).into(),
+            reflections: rotations::reflections(&puzzle).into(),
+            facelet_colors: puzzle.permutation_group().facelet_colors().into(),
+            turns: moves::turns(&puzzle.permutation_group())
+                .into_iter()
+                .map(|turn| turn.state)
+                .collect(),
         }
     }
 
-    pub fn most_likely(&self, confidences: &[HashMap<ArcIntern<str>, f64>]) -> (Permutation, f64) {
+    /// Rule out placing a piece somewhere outright if the log-likelihood of it being there is below `threshold`, rather than only finding it unlikely. `None`, the default, rules nothing out. If an orbit has no way of placing its pieces within the threshold, no states are found at all.
+    pub fn set_disallow_below(&mut self, threshold: Option<f64>) {
+        self.disallow_below = threshold;
+    }
+
+    /// Report progress through the candidates as they are enumerated, counting every candidate that is examined, including the ones that turn out not to be members of the group. `None`, the default, reports nothing. This method panics if `every` is zero.
+    pub fn set_progress(&mut self, progress: Option<Progress>) {
+        assert!(progress.as_ref().is_none_or(|progress| progress.every > 0));
+        self.progress = progress;
+    }
+
+    /// Scale each orbit's log-likelihood by its weight before adding them up to rank states, one weight per orbit in the order of `PuzzleGeometry::pieces_data`. Every weight is one by default; raising an orbit's weight makes the matcher trust that orbit more when the orbits disagree about the state, like when the colors of one kind of piece are harder to tell apart, and a weight of zero ignores the orbit entirely. The log-likelihoods that the candidates come with are weighted too.
+    ///
+    /// This method panics if there isn't exactly one weight per orbit or if any weight is negative or NaN, since a negative weight would turn an orbit's best matchings into its worst.
+    pub fn set_orbit_weights(&mut self, weights: Box<[f64]>) {
+        assert_eq!(weights.len(), self.orbits.len());
+        assert!(weights.iter().all(|weight| *weight >= 0.));
+        self.orbit_weights = weights;
+    }
+
+    /// Lazily yield every state of the puzzle that is a valid member of the group, in order of decreasing log-likelihood, along with that log-likelihood.
+    pub fn candidates<'a>(
+        &'a self,
+        confidences: &'a [BTreeMap<ArcIntern<str>, f64>],
+    ) -> impl Iterator<Item = (Permutation, f64)> + 'a {
+        self.matchings(confidences, true)
+    }
+
+    /// Like `candidates`, but without checking that the states are members of the group. This is for debugging: if the true state shows up near the top here but not in `candidates`, the color classification is fine and the group constraint is the problem.
+    pub fn unfiltered_candidates<'a>(
+        &'a self,
+        confidences: &'a [BTreeMap<ArcIntern<str>, f64>],
+    ) -> impl Iterator<Item = (Permutation, f64)> + 'a {
+        self.matchings(confidences, false)
+    }
+
+    /// Like `candidates`, but along with the piece that the matcher placed in each spot of each orbit and how it is twisted there, for showing how the pieces are oriented without working it out from the states again
+    pub fn matched_candidates<'a>(
+        &'a self,
+        confidences: &'a [BTreeMap<ArcIntern<str>, f64>],
+    ) -> impl Iterator<Item = MatchedState> + 'a {
+        self.matched_states(confidences, true)
+    }
+
+    fn matchings<'a>(
+        &'a self,
+        confidences: &'a [BTreeMap<ArcIntern<str>, f64>],
+        only_members: bool,
+    ) -> impl Iterator<Item = (Permutation, f64)> + 'a {
+        self.matched_states(confidences, only_members)
+            .map(|matched| (matched.state, matched.log_likelihood))
+    }
+
+    fn matched_states<'a>(
+        &'a self,
+        confidences: &'a [BTreeMap<ArcIntern<str>, f64>],
+        only_members: bool,
+    ) -> impl Iterator<Item = MatchedState> + 'a {
         let iters = self
             .orbits
             .iter()
             .map(|v| SavedIter {
-                iter: v.most_likely_matchings(confidences),
+                iter: v.most_likely_matchings(confidences, only_members, self.disallow_below),
                 saved: Vec::new(),
             })
             .collect();
 
-        let mut states = PuzzleIter::new(iters);
+        PuzzleIter::new(iters, self.orbit_weights.clone())
+            .with_progress(self.progress.clone())
+            .filter(move |matched| {
+                !only_members || self.stab_chain.is_member(matched.state.clone())
+            })
+    }
 
-        states
-            .find(|(v, _)| self.stab_chain.is_member(v.clone()))
-            .unwrap()
+    /// Return the first of the `candidates`, or `None` if there are none, which can only happen if pieces were ruled out with `set_disallow_below`
+    pub fn most_likely(
+        &self,
+        confidences: &[BTreeMap<ArcIntern<str>, f64>],
+    ) -> Option<(Permutation, f64)> {
+        self.candidates(confidences).next()
     }
+
+    /// Build the cost matrices that the matcher searches for the given observation, one per orbit in the order of `PuzzleGeometry::pieces_data`, without enumerating any states. This is the place to start when figuring out why a state was misrecognized.
+    pub fn cost_matrices(&self, confidences: &[BTreeMap<ArcIntern<str>, f64>]) -> Vec<OrbitCosts> {
+        self.orbits
+            .iter()
+            .map(|orbit| {
+                let costs = orbit.cost_matrix(confidences);
+                let best_orientations = costs.map_axis(Axis(2), |v| {
+                    v.iter()
+                        .enumerate()
+                        .max_by(|(_, a), (_, b)| cmp_ll(**a, **b))
+                        .map_or(0, |(ori, _)| ori)
+                });
+
+                OrbitCosts {
+                    costs,
+                    best_orientations,
+                }
+            })
+            .collect()
+    }
+}
+
+impl StateMatcher for Matcher {
+    fn most_likely(
+        &self,
+        log_likelihoods: &[BTreeMap<ArcIntern<str>, f64>],
+    ) -> Option<(Permutation, f64)> {
+        self.candidates(log_likelihoods).next()
+    }
+}
+
+/// The costs of placing each piece of an orbit in each spot of the orbit
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrbitCosts {
+    /// The log-likelihood of the piece in spot `i` being piece `j` twisted by orientation `k`, indexed by `[i, j, k]`
+    pub costs: Array3<f64>,
+    /// The most likely orientation of the piece in spot `i` if it is piece `j`, indexed by `[i, j]`
+    pub best_orientations: Array2<usize>,
+}
+
+/// A state of the puzzle found by the matcher, along with how it placed the pieces of each orbit
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchedState {
+    pub state: Permutation,
+    pub log_likelihood: f64,
+    /// The piece in each spot of each orbit, indexed by `[orbit][spot]`. Orbits are in the order of `PuzzleGeometry::pieces_data` and pieces and spots are both indices into the orbit's `OrbitData::pieces`.
+    pub pieces: Box<[Box<[usize]>]>,
+    /// How many times the piece in each spot of each orbit is twisted from its solved orientation, indexed by `[orbit][spot]` like `pieces`. Orientations are numbered like the ones of `OrbitCosts`.
+    pub orientations: Box<[Box<[usize]>]>,
+}
+
+/// A matching of the pieces of a single orbit
+#[derive(Debug, Clone, PartialEq)]
+struct OrbitMatching {
+    state: Permutation,
+    log_likelihood: f64,
+    // The piece in each spot of the orbit along with its orientation
+    matching: Box<[(usize, usize)]>,
 }
 
-struct SavedIter<I: Iterator<Item = (Permutation, f64)>> {
-    saved: Vec<(Permutation, f64)>,
+struct SavedIter<I: Iterator<Item = OrbitMatching>> {
+    saved: Vec<OrbitMatching>,
     iter: I,
 }
 
-impl<I: Iterator<Item = (Permutation, f64)>> SavedIter<I> {
-    fn get(&mut self, i: usize) -> (&Permutation, f64) {
+impl<I: Iterator<Item = OrbitMatching>> SavedIter<I> {
+    /// Get the `i`th item of the iterator, or `None` if it has fewer items
+    fn get(&mut self, i: usize) -> Option<&OrbitMatching> {
         while self.saved.len() <= i {
-            self.saved.push(self.iter.next().unwrap());
+            self.saved.push(self.iter.next()?);
         }
 
-        let (perm, ll) = self.saved.get(i).unwrap();
-        (perm, *ll)
+        self.saved.get(i)
     }
 }
 
-struct PuzzleIter<I: Iterator<Item = (Permutation, f64)>> {
+struct PuzzleIter<I: Iterator<Item = OrbitMatching>> {
     heap: BinaryHeap<PuzzleHeapElt>,
     iters: Box<[SavedIter<I>]>,
+    weights: Box<[f64]>,
     cache: Option<PuzzleHeapElt>,
+    progress: Option<Progress>,
+    // How many states have been yielded so far
+    examined: usize,
 }
 
-impl<I: Iterator<Item = (Permutation, f64)>> PuzzleIter<I> {
-    fn new(mut iters: Box<[SavedIter<I>]>) -> PuzzleIter<I> {
+impl<I: Iterator<Item = OrbitMatching>> PuzzleIter<I> {
+    /// Combine the matchings of each orbit, scaling the log-likelihoods of each by its weight in `weights`
+    fn new(mut iters: Box<[SavedIter<I>]>, weights: Box<[f64]>) -> PuzzleIter<I> {
         let mut heap = BinaryHeap::new();
 
-        heap.push(PuzzleHeapElt::new(vec![0; iters.len()].into(), &mut iters));
+        heap.extend(PuzzleHeapElt::new(
+            vec![0; iters.len()].into(),
+            &mut iters,
+            &weights,
+        ));
 
         PuzzleIter {
             heap,
             iters,
+            weights,
             cache: None,
+            progress: None,
+            examined: 0,
         }
     }
+
+    /// Report every `Progress::every` states that are yielded
+    fn with_progress(mut self, progress: Option<Progress>) -> PuzzleIter<I> {
+        self.progress = progress;
+        self
+    }
 }
 
-impl<I: Iterator<Item = (Permutation, f64)>> Iterator for PuzzleIter<I> {
-    type Item = (Permutation, f64);
+impl<I: Iterator<Item = OrbitMatching>> Iterator for PuzzleIter<I> {
+    type Item = MatchedState;
 
     fn next(&mut self) -> Option<Self::Item> {
         if let Some(prev) = self.cache.take() {
-            let splitted = prev.split(&mut self.iters);
+            let splitted = prev.split(&mut self.iters, &self.weights);
 
             self.heap.extend(splitted);
         }
@@ -108,21 +343,33 @@ impl<I: Iterator<Item = (Permutation, f64)>> Iterator for PuzzleIter<I> {
         }
 
         let mut ll = 0.;
-        let cycles = item
-            .idxs
-            .iter()
-            .zip(&mut self.iters)
-            .flat_map(|(v, iter)| {
-                let (perm, orbit_ll) = iter.get(*v);
-                ll += orbit_ll;
-                perm.cycles().iter()
-            })
-            .cloned()
-            .collect();
+        let mut cycles = Vec::new();
+        let mut pieces = Vec::with_capacity(item.idxs.len());
+        let mut orientations = Vec::with_capacity(item.idxs.len());
+        for ((v, iter), weight) in item.idxs.iter().zip(&mut self.iters).zip(&self.weights) {
+            // Every element in the heap was checked to be in range
+            let orbit = iter.get(*v).unwrap();
+            ll += weighted_log_likelihood(*weight, orbit.log_likelihood);
+            cycles.extend(orbit.state.cycles().iter().cloned());
+            pieces.push(orbit.matching.iter().map(|(is, _)| *is).collect());
+            orientations.push(orbit.matching.iter().map(|(_, ori)| *ori).collect());
+        }
 
         self.cache = Some(item);
 
-        Some((Permutation::from_cycles(cycles), ll))
+        self.examined += 1;
+        if let Some(progress) = &self.progress
+            && self.examined.is_multiple_of(progress.every)
+        {
+            (progress.callback)(self.examined, ll);
+        }
+
+        Some(MatchedState {
+            state: Permutation::from_cycles(cycles),
+            log_likelihood: ll,
+            pieces: pieces.into(),
+            orientations: orientations.into(),
+        })
     }
 }
 
@@ -132,31 +379,39 @@ struct PuzzleHeapElt {
 }
 
 impl PuzzleHeapElt {
-    fn new<I: Iterator<Item = (Permutation, f64)>>(
+    /// Returns `None` if any of the orbits doesn't have as many matchings as the index asks for
+    fn new<I: Iterator<Item = OrbitMatching>>(
         idxs: Box<[usize]>,
         iters: &mut [SavedIter<I>],
-    ) -> PuzzleHeapElt {
+        weights: &[f64],
+    ) -> Option<PuzzleHeapElt> {
+        // Each orbit's matchings come in order of decreasing log-likelihood, and scaling them by a nonnegative weight keeps them in that order, so splitting still finds the states in order
         let ll = idxs
             .iter()
             .zip(iters.iter_mut())
-            .map(|(idx, iter)| iter.get(*idx).1)
-            .sum::<f64>();
+            .zip(weights)
+            .map(|((idx, iter), weight)| {
+                iter.get(*idx)
+                    .map(|orbit| weighted_log_likelihood(*weight, orbit.log_likelihood))
+            })
+            .sum::<Option<f64>>()?;
 
-        PuzzleHeapElt {
+        Some(PuzzleHeapElt {
             idxs,
             log_likelihood: ll,
-        }
+        })
     }
 
-    fn split<I: Iterator<Item = (Permutation, f64)>>(
+    fn split<I: Iterator<Item = OrbitMatching>>(
         &self,
         iters: &mut [SavedIter<I>],
+        weights: &[f64],
     ) -> Vec<PuzzleHeapElt> {
         (0..self.idxs.len())
-            .map(|i| {
+            .filter_map(|i| {
                 let mut idxs = self.idxs.clone();
                 idxs[i] += 1;
-                PuzzleHeapElt::new(idxs, iters)
+                PuzzleHeapElt::new(idxs, iters, weights)
             })
             .collect_vec()
     }
@@ -178,7 +433,9 @@ impl PartialOrd for PuzzleHeapElt {
 
 impl Ord for PuzzleHeapElt {
     fn cmp(&self, other: &Self) -> Ordering {
-        self.log_likelihood.total_cmp(&other.log_likelihood)
+        cmp_ll(self.log_likelihood, other.log_likelihood)
+            // Break ties in favor of the smallest indices, like `OrbitHeapElt`, so that equally likely states always come out in the same order
+            .then_with(|| other.idxs.cmp(&self.idxs))
     }
 }
 
@@ -251,10 +508,7 @@ impl OrbitMatcher {
         }
     }
 
-    fn most_likely_matchings(
-        &self,
-        log_likelihoods: &[HashMap<ArcIntern<str>, f64>],
-    ) -> impl Iterator<Item = (Permutation, f64)> {
+    fn cost_matrix(&self, log_likelihoods: &[BTreeMap<ArcIntern<str>, f64>]) -> Array3<f64> {
         // Data for matching piece i to piece j where piece j gives the cost for each possible orientation
         let mut cost_matrix = Array3::zeros([
             self.orbit.pieces().len(),
@@ -276,7 +530,7 @@ impl OrbitMatcher {
 
                 for (color, log_likelihood) in log_likelihoods[*sticker]
                     .iter()
-                    .map(|(a, b)| (ArcIntern::clone(a), *b))
+                    .map(|(a, b)| (ArcIntern::clone(a), finite_log_likelihood(*b)))
                 {
                     for (piece, ori) in self.sticker_color_piece.get(&(ori_num, color)).unwrap() {
                         cost_row[[*piece, *ori]] += log_likelihood;
@@ -285,8 +539,41 @@ impl OrbitMatcher {
             }
         }
 
+        cost_matrix
+    }
+
+    /// The state that puts the piece `matching[spot].0` in each spot of the orbit, twisted by `matching[spot].1`, and leaves the other orbits solved
+    fn matching_state(&self, matching: &[(usize, usize)], facelet_count: usize) -> Permutation {
+        let data = self.puzzle.pieces_data();
+        let ori_nums = data.orientation_numbers();
+
+        let mut mapping_comes_from = (0..facelet_count).collect_vec();
+        for (spot, (is, ori)) in matching.iter().enumerate() {
+            for sticker_spot in self.orbit.pieces()[spot].stickers() {
+                // This is minus because to make this piece twisted by `ori` amount, we have to have it so that the piece accrues `+ori` orientation when coming here, so if for example we were at sticker `+2` and `ori=1`, then sticker `1` would come to this location by adding one orientation. Therefore, we needd to subtract `ori`.
+                let target_ori = ori_nums[*sticker_spot] - *ori;
+                mapping_comes_from[*sticker_spot] = *self.orbit.pieces()[*is]
+                    .stickers()
+                    .iter()
+                    .find(|v| ori_nums[**v] == target_ori)
+                    .unwrap();
+            }
+        }
+
+        Permutation::from_state(mapping_comes_from)
+    }
+
+    /// Yield the matchings of the orbit in order of decreasing log-likelihood. If `only_members` is set, matchings that aren't members of the orbit's group are skipped.
+    fn most_likely_matchings(
+        &self,
+        log_likelihoods: &[BTreeMap<ArcIntern<str>, f64>],
+        only_members: bool,
+        disallow_below: Option<f64>,
+    ) -> impl Iterator<Item = OrbitMatching> {
+        let cost_matrix = self.cost_matrix(log_likelihoods);
+
         let mut heap = BinaryHeap::new();
-        heap.push(OrbitHeapElt::new(&cost_matrix));
+        heap.extend(OrbitHeapElt::new(&cost_matrix, disallow_below));
 
         MatchIter {
             orbit_matcher: self,
@@ -295,8 +582,8 @@ impl OrbitMatcher {
             cache: None,
             facelet_count: self.puzzle.permutation_group().facelet_count(),
         }
-        .dedup_by(|a, b| a.0 == b.0)
-        .filter(|(perm, _)| self.stab_chain.is_member(perm.clone()))
+        .dedup_by(|a, b| a.state == b.state)
+        .filter(move |orbit| !only_members || self.stab_chain.is_member(orbit.state.clone()))
     }
 }
 
@@ -310,7 +597,7 @@ struct MatchIter<'a> {
 }
 
 impl<'a> Iterator for MatchIter<'a> {
-    type Item = (Permutation, f64);
+    type Item = OrbitMatching;
 
     fn next(&mut self) -> Option<Self::Item> {
         if let Some(item) = self.cache.take() {
@@ -323,68 +610,112 @@ impl<'a> Iterator for MatchIter<'a> {
             self.heap.pop();
         }
 
-        let data = self.orbit_matcher.puzzle.pieces_data();
-        let ori_nums = data.orientation_numbers();
-
-        let mut mapping_comes_from = (0..self.facelet_count).collect_vec();
-        for (spot, (is, ori)) in item.matching.iter().enumerate() {
-            for sticker_spot in self.orbit_matcher.orbit.pieces()[spot].stickers() {
-                // This is minus because to make this piece twisted by `ori` amount, we have to have it so that the piece accrues `+ori` orientation when coming here, so if for example we were at sticker `+2` and `ori=1`, then sticker `1` would come to this location by adding one orientation. Therefore, we needd to subtract `ori`.
-                let target_ori = ori_nums[*sticker_spot] - *ori;
-                mapping_comes_from[*sticker_spot] = *self.orbit_matcher.orbit.pieces()[*is]
-                    .stickers()
-                    .iter()
-                    .find(|v| ori_nums[**v] == target_ori)
-                    .unwrap();
-            }
-        }
-
-        let ll = item.log_likelihood;
+        let orbit = OrbitMatching {
+            state: self
+                .orbit_matcher
+                .matching_state(&item.matching, self.facelet_count),
+            log_likelihood: item.log_likelihood,
+            matching: item.matching.clone().into(),
+        };
 
         self.cache = Some(item);
 
-        Some((Permutation::from_state(mapping_comes_from), ll))
+        Some(orbit)
     }
 }
 
-#[derive(Debug)]
+/// A matching of an orbit as the solution of the assignment problem, the `(piece, orientation)` that each slot is matched with, and its log likelihood
+type Matching = (Solution, Vec<(usize, usize)>, f64);
+
+#[derive(Debug, Clone)]
 struct OrbitHeapElt {
     allowed: Array3<bool>,
     cost_matrix_2d: Array2<Option<f64>>,
     oris_chosen: Array2<Option<usize>>,
     log_likelihood: f64,
     matching: Vec<(usize, usize)>,
+    // Kept so that the matchings of the splits can be found incrementally
+    solution: Solution,
 }
 
 impl OrbitHeapElt {
-    fn new(cost_matrix_3d: &ArrayRef3<f64>) -> OrbitHeapElt {
-        let maxima = cost_matrix_3d.map_axis(Axis(2), |v| {
-            v.into_iter()
-                .copied()
-                .enumerate()
-                .max_by(|(_, a), (_, b)| a.total_cmp(b))
-                .unwrap()
+    /// Find the best matching, where entries of the cost matrix below `disallow_below` aren't allowed. Returns `None` if there is no matching using only allowed entries.
+    fn new(cost_matrix_3d: &ArrayRef3<f64>, disallow_below: Option<f64>) -> Option<OrbitHeapElt> {
+        debug_assert!(
+            cost_matrix_3d.iter().all(|v| v.is_finite()),
+            "The cost matrix must be finite: {cost_matrix_3d}"
+        );
+
+        let allowed =
+            cost_matrix_3d.map(|v| disallow_below.is_none_or(|threshold| *v >= threshold));
+
+        let shape = (cost_matrix_3d.shape()[0], cost_matrix_3d.shape()[1]);
+        let maxima = Array2::from_shape_fn(shape, |(i, j)| {
+            Self::best_allowed_ori(cost_matrix_3d, &allowed, i, j)
         });
 
-        let cost_matrix_2d = maxima.map(|v| Some(v.1));
-        let oris_chosen = maxima.map(|v| Some(v.0));
+        let cost_matrix_2d = maxima.map(|v| v.map(|(_, v)| v));
+        let oris_chosen = maxima.map(|v| v.map(|(v, _)| v));
 
-        let (matching, log_likelihood) = Self::mk_matching(&cost_matrix_2d, &oris_chosen).unwrap();
+        let (solution, matching, log_likelihood) =
+            Self::mk_matching(&cost_matrix_2d, &oris_chosen)?;
 
-        OrbitHeapElt {
-            allowed: Array3::from_elem(cost_matrix_3d.raw_dim(), true),
+        Some(OrbitHeapElt {
+            allowed,
             cost_matrix_2d,
             oris_chosen,
             log_likelihood,
             matching,
-        }
+            solution,
+        })
     }
 
+    /// The most likely orientation of the piece in spot `i` if it is piece `j`, out of the allowed ones, along with its cost
+    fn best_allowed_ori(
+        cost_matrix_3d: &ArrayRef3<f64>,
+        allowed: &ArrayRef3<bool>,
+        i: usize,
+        j: usize,
+    ) -> Option<(usize, f64)> {
+        cost_matrix_3d
+            .slice(s![i, j, ..])
+            .iter()
+            .zip(allowed.slice(s![i, j, ..]))
+            .enumerate()
+            .filter(|(_, (_, v))| **v)
+            .max_by(|(_, (a, _)), (_, (b, _))| cmp_ll(**a, **b))
+            .map(|(a, (b, _))| (a, *b))
+    }
+
+    /// Find the best matching from scratch
     fn mk_matching(
         cost_matrix_2d: &ArrayRef2<Option<f64>>,
         oris_chosen: &ArrayRef2<Option<usize>>,
-    ) -> Option<(Vec<(usize, usize)>, f64)> {
-        let matching = maximum_matching(cost_matrix_2d)?
+    ) -> Option<Matching> {
+        let solution = solve(cost_matrix_2d)?;
+
+        Some(Self::read_solution(solution, cost_matrix_2d, oris_chosen))
+    }
+
+    /// Find the best matching after the cost of matching `i` with `j` went down, starting from this element's matching
+    fn remk_matching(
+        &self,
+        cost_matrix_2d: &ArrayRef2<Option<f64>>,
+        oris_chosen: &ArrayRef2<Option<usize>>,
+        (i, j): (usize, usize),
+    ) -> Option<Matching> {
+        let solution = rematch(&self.solution, cost_matrix_2d, (i, j))?;
+
+        Some(Self::read_solution(solution, cost_matrix_2d, oris_chosen))
+    }
+
+    fn read_solution(
+        solution: Solution,
+        cost_matrix_2d: &ArrayRef2<Option<f64>>,
+        oris_chosen: &ArrayRef2<Option<usize>>,
+    ) -> Matching {
+        let matching = solution
+            .matching()
             .into_iter()
             .enumerate()
             .map(|(i, j)| (j, oris_chosen[[i, j]].unwrap()))
@@ -396,7 +727,7 @@ impl OrbitHeapElt {
             .map(|(i, (j, _))| cost_matrix_2d[[i, *j]].unwrap())
             .sum();
 
-        Some((matching, log_likelihood))
+        (solution, matching, log_likelihood)
     }
 
     fn split(&self, cost_matrix_3d: &ArrayRef3<f64>) -> impl Iterator<Item = OrbitHeapElt> {
@@ -411,19 +742,13 @@ impl OrbitHeapElt {
 
                 allowed[[i, j, ori]] = false;
 
-                let maybe_ori = cost_matrix_3d
-                    .slice(s![i, j, ..])
-                    .iter()
-                    .zip(allowed.slice(s![i, j, ..]))
-                    .enumerate()
-                    .filter(|(_, (_, v))| **v)
-                    .max_by(|(_, (a, _)), (_, (b, _))| a.total_cmp(b))
-                    .map(|(a, (b, _))| (a, *b));
+                let maybe_ori = Self::best_allowed_ori(cost_matrix_3d, &allowed, i, j);
 
                 cost_matrix_2d[[i, j]] = maybe_ori.map(|(_, v)| v);
                 oris_chosen[[i, j]] = maybe_ori.map(|(v, _)| v);
 
-                let (matching, log_likelihood) = Self::mk_matching(&cost_matrix_2d, &oris_chosen)?;
+                let (solution, matching, log_likelihood) =
+                    self.remk_matching(&cost_matrix_2d, &oris_chosen, (i, j))?;
 
                 Some(OrbitHeapElt {
                     allowed,
@@ -431,6 +756,7 @@ impl OrbitHeapElt {
                     oris_chosen,
                     log_likelihood,
                     matching,
+                    solution,
                 })
             })
     }
@@ -456,9 +782,9 @@ impl Ord for OrbitHeapElt {
             return Ordering::Equal;
         }
 
-        match self.log_likelihood.total_cmp(&other.log_likelihood) {
-            // Break ties
-            Ordering::Equal => self.matching.cmp(&other.matching),
+        match cmp_ll(self.log_likelihood, other.log_likelihood) {
+            // Break ties in favor of the lexicographically smallest matching, so that equally likely matchings come out in the same order no matter what order they were found in
+            Ordering::Equal => other.matching.cmp(&self.matching),
             v => v,
         }
     }
@@ -467,20 +793,23 @@ impl Ord for OrbitHeapElt {
 #[cfg(test)]
 mod tests {
     use std::{
-        collections::HashMap,
-        sync::{Arc, LazyLock},
+        collections::{BTreeMap, BinaryHeap, HashMap},
+        sync::{Arc, LazyLock, Mutex},
     };
 
     use internment::ArcIntern;
     use itertools::Itertools;
-    use ndarray::array;
+    use ndarray::{Array3, array};
     use puzzle_theory::{
         permutations::{Algorithm, Permutation, schreier_sims::StabilizerChain},
         puzzle_geometry::{PuzzleGeometry, parsing::puzzle},
     };
     use rand::{Rng, SeedableRng};
 
-    use crate::puzzle_matching::{Matcher, OrbitHeapElt, PuzzleIter, SavedIter};
+    use crate::puzzle_matching::{
+        GreedyMatcher, Inconsistency, Matcher, OrbitHeapElt, OrbitMatching, Progress, PuzzleIter,
+        RecognitionOutcome, RecognitionThresholds, SavedIter, StateMatcher,
+    };
 
     #[test]
     fn heap_elt() {
@@ -490,7 +819,7 @@ mod tests {
             [[-9., -10.], [-4., -10.], [-8., -10.],]
         ];
 
-        let elt = OrbitHeapElt::new(&cost_matrix_3d);
+        let elt = OrbitHeapElt::new(&cost_matrix_3d, None).unwrap();
 
         assert_eq!(elt.log_likelihood, -15.);
         assert_eq!(
@@ -706,14 +1035,27 @@ mod tests {
         assert_eq!(splits3[2].log_likelihood, -18.);
     }
 
+    /// Matchings of a single orbit with the given states and log-likelihoods, for testing how the orbits are combined
+    fn orbit_matchings<const N: usize>(
+        matchings: [(Permutation, f64); N],
+    ) -> impl Iterator<Item = OrbitMatching> {
+        matchings
+            .into_iter()
+            .map(|(state, log_likelihood)| OrbitMatching {
+                state,
+                log_likelihood,
+                matching: Box::default(),
+            })
+    }
+
     #[test]
     fn saved_iter() {
-        let mut iter = [
+        let iter = [
             (Permutation::from_cycles(vec![vec![1, 2, 3]]), 1.),
             (Permutation::from_cycles(vec![vec![2, 3]]), 2.),
             (Permutation::from_cycles(vec![vec![1, 2]]), 3.),
-        ]
-        .into_iter();
+        ];
+        let mut iter = orbit_matchings(iter);
 
         let mut saved_iter = SavedIter {
             saved: Vec::new(),
@@ -721,29 +1063,80 @@ mod tests {
         };
 
         assert_eq!(
-            saved_iter.get(0),
-            (&Permutation::from_cycles(vec![vec![1, 2, 3]]), 1.)
+            saved_iter
+                .get(0)
+                .map(|orbit| (&orbit.state, orbit.log_likelihood)),
+            Some((&Permutation::from_cycles(vec![vec![1, 2, 3]]), 1.))
         );
         assert_eq!(
-            saved_iter.get(1),
-            (&Permutation::from_cycles(vec![vec![2, 3]]), 2.)
+            saved_iter
+                .get(1)
+                .map(|orbit| (&orbit.state, orbit.log_likelihood)),
+            Some((&Permutation::from_cycles(vec![vec![2, 3]]), 2.))
         );
         assert_eq!(
-            saved_iter.get(0),
-            (&Permutation::from_cycles(vec![vec![1, 2, 3]]), 1.)
+            saved_iter
+                .get(0)
+                .map(|orbit| (&orbit.state, orbit.log_likelihood)),
+            Some((&Permutation::from_cycles(vec![vec![1, 2, 3]]), 1.))
         );
         assert_eq!(
-            saved_iter.get(1),
-            (&Permutation::from_cycles(vec![vec![2, 3]]), 2.)
+            saved_iter
+                .get(1)
+                .map(|orbit| (&orbit.state, orbit.log_likelihood)),
+            Some((&Permutation::from_cycles(vec![vec![2, 3]]), 2.))
         );
 
         assert_eq!(
-            iter.next(),
+            iter.next().map(|orbit| (orbit.state, orbit.log_likelihood)),
             Some((Permutation::from_cycles(vec![vec![1, 2]]), 3.))
         );
         assert_eq!(iter.next(), None);
     }
 
+    #[test]
+    fn progress() {
+        let a = [
+            (Permutation::from_cycles(vec![vec![0, 1]]), -1.),
+            (Permutation::from_cycles(vec![vec![1, 2]]), -3.),
+            (Permutation::from_cycles(vec![vec![0, 2]]), -100.),
+        ];
+        let b = [
+            (Permutation::from_cycles(vec![vec![10, 11]]), -2.),
+            (Permutation::from_cycles(vec![vec![11, 12]]), -5.),
+            (Permutation::from_cycles(vec![vec![10, 12]]), -100.),
+        ];
+
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let puzzle_iter = PuzzleIter::new(
+            Box::from([
+                SavedIter {
+                    saved: Vec::new(),
+                    iter: orbit_matchings(a),
+                },
+                SavedIter {
+                    saved: Vec::new(),
+                    iter: orbit_matchings(b),
+                },
+            ]),
+            Box::from([1., 1.]),
+        )
+        .with_progress(Some(Progress {
+            every: 2,
+            callback: Arc::new({
+                let reports = Arc::clone(&reports);
+                move |examined, ll| reports.lock().unwrap().push((examined, ll))
+            }),
+        }));
+
+        // All nine combinations, reported after every second one
+        assert_eq!(puzzle_iter.count(), 9);
+        assert_eq!(
+            *reports.lock().unwrap(),
+            [(2, -5.), (4, -8.), (6, -102.), (8, -105.)]
+        );
+    }
+
     #[test]
     fn puzzle_iter() {
         let a = [
@@ -757,40 +1150,51 @@ mod tests {
             (Permutation::from_cycles(vec![vec![10, 12]]), -100.),
         ];
 
-        let mut puzzle_iter = PuzzleIter::new(Box::from([
-            SavedIter {
-                saved: Vec::new(),
-                iter: a.into_iter(),
-            },
-            SavedIter {
-                saved: Vec::new(),
-                iter: b.into_iter(),
-            },
-        ]));
+        let mut puzzle_iter = PuzzleIter::new(
+            Box::from([
+                SavedIter {
+                    saved: Vec::new(),
+                    iter: orbit_matchings(a),
+                },
+                SavedIter {
+                    saved: Vec::new(),
+                    iter: orbit_matchings(b),
+                },
+            ]),
+            Box::from([1., 1.]),
+        );
 
         assert_eq!(
-            puzzle_iter.next(),
+            puzzle_iter
+                .next()
+                .map(|matched| (matched.state, matched.log_likelihood)),
             Some((
                 Permutation::from_cycles(vec![vec![0, 1], vec![10, 11]]),
                 -3.
             ))
         );
         assert_eq!(
-            puzzle_iter.next(),
+            puzzle_iter
+                .next()
+                .map(|matched| (matched.state, matched.log_likelihood)),
             Some((
                 Permutation::from_cycles(vec![vec![1, 2], vec![10, 11]]),
                 -5.
             ))
         );
         assert_eq!(
-            puzzle_iter.next(),
+            puzzle_iter
+                .next()
+                .map(|matched| (matched.state, matched.log_likelihood)),
             Some((
                 Permutation::from_cycles(vec![vec![0, 1], vec![11, 12]]),
                 -6.
             ))
         );
         assert_eq!(
-            puzzle_iter.next(),
+            puzzle_iter
+                .next()
+                .map(|matched| (matched.state, matched.log_likelihood)),
             Some((
                 Permutation::from_cycles(vec![vec![1, 2], vec![11, 12]]),
                 -8.
@@ -798,6 +1202,642 @@ mod tests {
         );
     }
 
+    #[test]
+    fn zero_weight() {
+        let a = [
+            (Permutation::from_cycles(vec![vec![0, 1]]), -1.),
+            (Permutation::from_cycles(vec![vec![1, 2]]), -3.),
+        ];
+        // An orbit that can't be matched at all
+        let b = [
+            (
+                Permutation::from_cycles(vec![vec![10, 11]]),
+                f64::NEG_INFINITY,
+            ),
+            (
+                Permutation::from_cycles(vec![vec![11, 12]]),
+                f64::NEG_INFINITY,
+            ),
+        ];
+
+        let mut puzzle_iter = PuzzleIter::new(
+            Box::from([
+                SavedIter {
+                    saved: Vec::new(),
+                    iter: orbit_matchings(a),
+                },
+                SavedIter {
+                    saved: Vec::new(),
+                    iter: orbit_matchings(b),
+                },
+            ]),
+            Box::from([1., 0.]),
+        );
+
+        // The orbit with no weight counts for nothing rather than making every state NaN
+        assert_eq!(
+            puzzle_iter
+                .next()
+                .map(|matched| (matched.state, matched.log_likelihood)),
+            Some((
+                Permutation::from_cycles(vec![vec![0, 1], vec![10, 11]]),
+                -1.
+            ))
+        );
+        assert_eq!(
+            puzzle_iter.next().map(|matched| matched.log_likelihood),
+            Some(-1.)
+        );
+        assert_eq!(
+            puzzle_iter.nth(1).map(|matched| matched.log_likelihood),
+            Some(-3.)
+        );
+    }
+
+    #[test]
+    fn ties() {
+        // Every matching is equally likely
+        let cost_matrix_3d = Array3::from_elem((3, 3, 1), -1.);
+        let elt = OrbitHeapElt::new(&cost_matrix_3d, None).unwrap();
+        let mut elts = elt.split(&cost_matrix_3d).collect_vec();
+        elts.push(elt);
+        assert!(elts.iter().all(|elt| elt.log_likelihood == -3.));
+
+        let popped = |elts: Vec<OrbitHeapElt>| {
+            BinaryHeap::from(elts)
+                .into_sorted_vec()
+                .into_iter()
+                .rev()
+                .map(|elt| elt.matching)
+                .collect_vec()
+        };
+        let reversed = elts.iter().rev().cloned().collect_vec();
+        let forwards = popped(elts);
+        assert!(forwards.is_sorted());
+        assert_eq!(popped(reversed), forwards);
+
+        let a = [
+            (Permutation::from_cycles(vec![vec![0, 1]]), -1.),
+            (Permutation::from_cycles(vec![vec![1, 2]]), -1.),
+        ];
+        let b = [
+            (Permutation::from_cycles(vec![vec![10, 11]]), -2.),
+            (Permutation::from_cycles(vec![vec![11, 12]]), -2.),
+        ];
+
+        let puzzle_iter = PuzzleIter::new(
+            Box::from([
+                SavedIter {
+                    saved: Vec::new(),
+                    iter: orbit_matchings(a),
+                },
+                SavedIter {
+                    saved: Vec::new(),
+                    iter: orbit_matchings(b),
+                },
+            ]),
+            Box::from([1., 1.]),
+        );
+
+        assert_eq!(
+            puzzle_iter
+                .map(|matched| (matched.state, matched.log_likelihood))
+                .collect_vec(),
+            [
+                (vec![0, 1], vec![10, 11]),
+                (vec![0, 1], vec![11, 12]),
+                (vec![1, 2], vec![10, 11]),
+                (vec![1, 2], vec![11, 12]),
+            ]
+            .map(|(a, b)| (Permutation::from_cycles(vec![a, b]), -3.))
+        );
+    }
+
+    #[test]
+    fn incremental_split() {
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"Split it up and solve it again!!");
+
+        for _ in 0..50 {
+            let n = rng.random_range(1..6);
+            let cost_matrix_3d =
+                Array3::from_shape_fn((n, n, 3), |_| f64::from(rng.random_range(-20_i8..=0)));
+
+            let mut elts = vec![OrbitHeapElt::new(&cost_matrix_3d, None).unwrap()];
+            for _ in 0..3 {
+                elts = elts
+                    .iter()
+                    .flat_map(|elt| elt.split(&cost_matrix_3d))
+                    .collect_vec();
+
+                for elt in &elts {
+                    let (_, _, full) =
+                        OrbitHeapElt::mk_matching(&elt.cost_matrix_2d, &elt.oris_chosen).unwrap();
+                    assert_eq!(elt.log_likelihood, full);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn non_finite() {
+        let geometry = puzzle("3x3").into_inner();
+        let group = geometry.permutation_group();
+        let matcher = Matcher::new(Arc::clone(&geometry));
+        let solved = Permutation::from_cycles(Vec::new());
+
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"Divide by zero, what could break");
+        let (mut observation, _) = observe(&solved, &geometry, &mut rng, 0);
+
+        // A pixel right on top of a calibration sample has an infinite density
+        *observation[0].get_mut(&group.facelet_colors()[0]).unwrap() = f64::INFINITY;
+        for log_likelihood in observation[1].values_mut() {
+            *log_likelihood = f64::NAN;
+        }
+
+        let (found, ll) = matcher.most_likely(&observation).unwrap();
+        assert_eq!(found, solved);
+        assert!(ll.is_finite());
+    }
+
+    #[test]
+    fn implausible_sticker() {
+        let geometry = puzzle("3x3").into_inner();
+        let mut matcher = Matcher::new(Arc::clone(&geometry));
+        let solved = Permutation::from_cycles(Vec::new());
+
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"This sticker looks like nothing!");
+        let (mut observation, _) = observe(&solved, &geometry, &mut rng, 0);
+
+        // The classifier has never seen anything like this sticker
+        for log_likelihood in observation[0].values_mut() {
+            *log_likelihood = f64::NEG_INFINITY;
+        }
+
+        // The rest of its piece gives it away
+        assert_eq!(matcher.most_likely(&observation).unwrap().0, solved);
+
+        matcher.set_disallow_below(Some(-1e6));
+        assert!(matcher.candidates(&observation).next().is_none());
+        assert_eq!(matcher.most_likely(&observation), None);
+        assert!(matcher.most_likely_any_orientation(&observation).is_none());
+    }
+
+    #[test]
+    fn diagnose() {
+        let geometry = puzzle("3x3").into_inner();
+        let group = geometry.permutation_group();
+        let matcher = Matcher::new(Arc::clone(&geometry));
+        let solved = Permutation::from_cycles(Vec::new());
+
+        let (edges, orbit) = geometry
+            .pieces_data()
+            .orbits()
+            .iter()
+            .find_position(|orbit| orbit.orientation_count() == 2)
+            .unwrap();
+        let edge = &orbit.pieces()[0];
+
+        // Flip the first edge in place, which is impossible on a real cube
+        let mut comes_from = (0..group.facelet_count()).collect_vec();
+        for sticker in edge.stickers() {
+            comes_from[*sticker] = edge.twist().mapping().get(*sticker);
+        }
+        let flipped = Permutation::from_state(comes_from);
+
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"Did somebody peel off a sticker?");
+        let (mut observation, _) = observe(&flipped, &geometry, &mut rng, 0);
+
+        // The edge only looks a little more flipped than not
+        for sticker in edge.stickers() {
+            *observation[*sticker]
+                .get_mut(&group.facelet_colors()[*sticker])
+                .unwrap() = -10.;
+        }
+
+        let (found, _) = matcher.unfiltered_candidates(&observation).next().unwrap();
+        assert_eq!(found, flipped);
+        assert_eq!(
+            matcher.diagnose(&observation, &found),
+            Some(Inconsistency::Twisted {
+                orbit: edges,
+                spot: 0,
+                by: 1
+            })
+        );
+
+        let (found, _) = matcher.most_likely(&observation).unwrap();
+        assert_eq!(found, solved);
+        assert_eq!(matcher.diagnose(&observation, &found), None);
+    }
+
+    #[test]
+    fn matched_orientations() {
+        let geometry = puzzle("3x3").into_inner();
+        let matcher = Matcher::new(Arc::clone(&geometry));
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"Twist one corner, then another!!");
+        let orbits = geometry.pieces_data().orbits().to_owned();
+        let corners = orbits
+            .iter()
+            .position(|orbit| orbit.orientation_count() == 3)
+            .unwrap();
+
+        // Twists the corner at UFR one way, then the one at UBR back the other way
+        let twist = "R' D' R D R' D' R D U R' D' R D R' D' R D R' D' R D R' D' R D U'";
+        let mut matched = |moves: &str| {
+            let state = Algorithm::parse_from_string(geometry.permutation_group(), moves)
+                .unwrap()
+                .permutation()
+                .clone();
+            let (observation, _) = observe(&state, &geometry, &mut rng, 0);
+            let matched = matcher.matched_candidates(&observation).next().unwrap();
+            assert_eq!(matched.state, state);
+            matched
+        };
+
+        let solved = matched("");
+        assert!(solved.orientations.iter().flatten().all(|ori| *ori == 0));
+        for (orbit, pieces) in orbits.iter().zip(&solved.pieces) {
+            assert_eq!(**pieces, (0..orbit.pieces().len()).collect_vec());
+        }
+
+        let twisted = matched(twist);
+        // Twisting corners doesn't move any pieces
+        assert_eq!(twisted.pieces, solved.pieces);
+        for (orbit, orientations) in twisted.orientations.iter().enumerate() {
+            if orbit != corners {
+                assert!(orientations.iter().all(|ori| *ori == 0));
+            }
+        }
+
+        // The spot of the corner whose stickers have the given colors, and its sticker of each color
+        let colors = geometry.permutation_group().facelet_colors().to_owned();
+        let corner = |faces: [&str; 3]| {
+            orbits[corners]
+                .pieces()
+                .iter()
+                .position(|piece| {
+                    piece
+                        .stickers()
+                        .iter()
+                        .map(|sticker| &*colors[*sticker])
+                        .sorted()
+                        .eq(faces.into_iter().sorted())
+                })
+                .unwrap()
+        };
+        let sticker = |spot: usize, color: &str| {
+            *orbits[corners].pieces()[spot]
+                .stickers()
+                .iter()
+                .find(|sticker| &*colors[**sticker] == color)
+                .unwrap()
+        };
+        let ufr = corner(["white", "green", "red"]);
+        let ubr = corner(["white", "blue", "red"]);
+
+        let corner_orientations = &twisted.orientations[corners];
+        let twisted_spots = (0..corner_orientations.len())
+            .filter(|spot| corner_orientations[*spot] != 0)
+            .collect_vec();
+        assert_eq!(twisted_spots, [ufr, ubr].into_iter().sorted().collect_vec());
+
+        // The red sticker of the UFR corner is now on top, and its orientation is exactly how many twists of the piece bring it there from the right
+        let (white, red) = (sticker(ufr, "white"), sticker(ufr, "red"));
+        assert_eq!(twisted.state.state().get(white), red);
+        let twists = orbits[corners].pieces()[ufr].twist();
+        let orientation = corner_orientations[ufr];
+        assert_eq!(
+            (0..orientation).fold(red, |sticker, _| twists.mapping().get(sticker)),
+            white
+        );
+        // So is the UBR corner's, which is twisted the other way
+        let (white, red) = (sticker(ubr, "white"), sticker(ubr, "red"));
+        assert_eq!(twisted.state.state().get(white), red);
+        assert_eq!(corner_orientations[ubr], 3 - orientation);
+
+        // Twisting the same corners again adds to their orientations
+        let twice = matched(&format!("{twist} {twist}"));
+        for spot in [ufr, ubr] {
+            assert_eq!(
+                twice.orientations[corners][spot],
+                corner_orientations[spot] * 2 % 3
+            );
+        }
+    }
+
+    #[test]
+    fn orbit_weights() {
+        let geometry = puzzle("3x3").into_inner();
+        let mut matcher = Matcher::new(Arc::clone(&geometry));
+        let solved = Permutation::from_cycles(Vec::new());
+        // Swaps two corners and two edges
+        let t_perm = Algorithm::parse_from_string(
+            geometry.permutation_group(),
+            "R U R' U' R' F R2 U' R' U' R U R' F'",
+        )
+        .unwrap()
+        .permutation()
+        .clone();
+
+        let orbit_stickers = |orientation_count| {
+            geometry
+                .pieces_data()
+                .orbits()
+                .iter()
+                .find(|orbit| orbit.orientation_count() == orientation_count)
+                .unwrap()
+                .pieces()
+                .iter()
+                .flat_map(|piece| piece.stickers().iter().copied())
+                .collect_vec()
+        };
+        let corners = orbit_stickers(3);
+        let edges = orbit_stickers(2);
+
+        // The corners look swapped but the edges don't, so no valid state agrees with both orbits
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"Which pieces are you sure about?");
+        let (mut observation, _) = observe(&t_perm, &geometry, &mut rng, 0);
+        let (edges_solved, _) = observe(&solved, &geometry, &mut rng, 0);
+        for sticker in &edges {
+            observation[*sticker].clone_from(&edges_solved[*sticker]);
+        }
+
+        let agrees = |found: &Permutation, expected: &Permutation, stickers: &[usize]| {
+            stickers
+                .iter()
+                .all(|sticker| found.state().get(*sticker) == expected.state().get(*sticker))
+        };
+        let orbit_weight = |orientation_count, weight| {
+            geometry
+                .pieces_data()
+                .orbits()
+                .iter()
+                .map(|orbit| {
+                    if orbit.orientation_count() == orientation_count {
+                        weight
+                    } else {
+                        1.
+                    }
+                })
+                .collect()
+        };
+
+        matcher.set_orbit_weights(orbit_weight(2, 100.));
+        let (found, _) = matcher.most_likely(&observation).unwrap();
+        assert!(agrees(&found, &solved, &edges));
+        assert!(!agrees(&found, &t_perm, &corners));
+
+        matcher.set_orbit_weights(orbit_weight(3, 100.));
+        let (found, _) = matcher.most_likely(&observation).unwrap();
+        assert!(agrees(&found, &t_perm, &corners));
+        assert!(!agrees(&found, &solved, &edges));
+    }
+
+    #[test]
+    fn recognize() {
+        let geometry = puzzle("3x3").into_inner();
+        let matcher = Matcher::new(Arc::clone(&geometry));
+        let solved = Permutation::from_cycles(Vec::new());
+        let turned = Algorithm::parse_from_string(geometry.permutation_group(), "R")
+            .unwrap()
+            .permutation()
+            .clone();
+
+        let thresholds = RecognitionThresholds {
+            min_margin: 1.,
+            min_mean_log_likelihood: -50.,
+            ..RecognitionThresholds::default()
+        };
+
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"Caught it right in the middle!!!");
+        let (before, _) = observe(&solved, &geometry, &mut rng, 0);
+        let (after, _) = observe(&turned, &geometry, &mut rng, 0);
+
+        assert_eq!(
+            matcher.recognize(&before, &thresholds),
+            RecognitionOutcome::Confident(solved, 0.)
+        );
+
+        // A frame captured mid-turn looks halfway between the two states, so they are equally likely, and only somewhat unlikely since half of each moved sticker still matches
+        let blurred = before
+            .iter()
+            .zip(&after)
+            .map(|(before, after)| {
+                before
+                    .iter()
+                    .map(|(color, ll)| (ArcIntern::clone(color), (ll + after[color]) / 2.))
+                    .collect()
+            })
+            .collect_vec();
+        assert_eq!(
+            matcher.recognize(&blurred, &thresholds),
+            RecognitionOutcome::Ambiguous
+        );
+
+        // Every color is equally unlikely everywhere, so nothing explains the observation
+        let blank = before
+            .iter()
+            .map(|v| {
+                v.keys()
+                    .map(|color| (ArcIntern::clone(color), -100.))
+                    .collect()
+            })
+            .collect_vec();
+        assert_eq!(
+            matcher.recognize(&blank, &thresholds),
+            RecognitionOutcome::NoValidState
+        );
+    }
+
+    #[test]
+    fn any_orientation() {
+        let geometry = puzzle("3x3").into_inner();
+        let matcher = Matcher::new(Arc::clone(&geometry));
+        let solved = Permutation::from_cycles(Vec::new());
+
+        let rotations = matcher.rotations();
+        assert_eq!(rotations.len(), 24);
+        assert!(rotations[0].is_identity());
+        assert_eq!(rotations.iter().filter(|v| v.is_identity()).count(), 1);
+
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"Holding it upside down and back!");
+        let (upright, _) = observe(&solved, &geometry, &mut rng, 0);
+
+        for rotation in [&rotations[0], &rotations[7], &rotations[23]] {
+            // Each sticker shows the color that its face shows when held this way
+            let held = upright
+                .iter()
+                .map(|v| {
+                    v.iter()
+                        .map(|(color, ll)| (ArcIntern::clone(rotation.shown(color)), *ll))
+                        .collect()
+                })
+                .collect_vec();
+
+            let (state, ll, found) = matcher.most_likely_any_orientation(&held).unwrap();
+            assert_eq!(state, solved);
+            assert_eq!(ll, 0.);
+            assert_eq!(&found, rotation);
+        }
+    }
+
+    #[test]
+    fn reflections() {
+        let geometry = puzzle("3x3").into_inner();
+        let group = geometry.permutation_group();
+        let matcher = Matcher::new(Arc::clone(&geometry));
+
+        let reflections = matcher.reflections();
+        assert_eq!(reflections.len(), 24);
+
+        for reflection in reflections {
+            assert!(
+                reflection
+                    .iter()
+                    .copied()
+                    .sorted()
+                    .eq(0..group.facelet_count())
+            );
+            assert!(!reflection.iter().copied().eq(0..group.facelet_count()));
+
+            // The stickers of a face go to the stickers of a single face, and those of a piece to those of a single piece
+            let colors = group.facelet_colors();
+            for (a, b) in (0..group.facelet_count()).tuple_combinations() {
+                assert_eq!(
+                    colors[a] == colors[b],
+                    colors[reflection[a]] == colors[reflection[b]]
+                );
+            }
+            for orbit in geometry.pieces_data().orbits() {
+                for piece in orbit.pieces() {
+                    let image = reflection[piece.stickers()[0]];
+                    assert!(orbit.pieces().iter().any(|other| {
+                        other.stickers().contains(&image)
+                            && piece
+                                .stickers()
+                                .iter()
+                                .all(|sticker| other.stickers().contains(&reflection[*sticker]))
+                    }));
+                }
+            }
+        }
+
+        // A mirror swaps the faces on its left and right and leaves the others be
+        let colors = group.facelet_colors();
+        let (red, orange) = (ArcIntern::from("red"), ArcIntern::from("orange"));
+        let reflection = matcher.reflection_swapping(&red).unwrap();
+        assert_eq!(matcher.reflection_swapping(&orange), Some(reflection));
+        for (sticker, color) in colors.iter().enumerate() {
+            let shown = &colors[reflection[sticker]];
+            if *color == red {
+                assert_eq!(*shown, orange);
+            } else if *color != orange {
+                assert_eq!(shown, color);
+            }
+        }
+
+        // Undoing the reflection that a mirror image was taken with gives back the state
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"Mirror, mirror, on the wall, eh?");
+        let state = StabilizerChain::new(&group).random(&mut rng);
+        let (observation, expected_ll) = observe(&state, &geometry, &mut rng, 0);
+        let mirrored = (0..group.facelet_count())
+            .map(|spot| observation[reflection[spot]].clone())
+            .collect_vec();
+
+        let unmirrored = matcher.unmirrored(&mirrored, reflection);
+        assert_eq!(*unmirrored, *observation);
+        assert_eq!(
+            StateMatcher::most_likely(&matcher, &unmirrored),
+            Some((state, expected_ll))
+        );
+    }
+
+    #[test]
+    fn most_likely_among() {
+        let geometry = puzzle("3x3").into_inner();
+        let matcher = Matcher::new(Arc::clone(&geometry));
+
+        let mut candidates = vec![Permutation::from_cycles(Vec::new())];
+        candidates.extend(["R", "R2", "U", "R U"].map(|moves| {
+            Algorithm::parse_from_string(geometry.permutation_group(), moves)
+                .unwrap()
+                .permutation()
+                .clone()
+        }));
+
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"It has to be one of these, right");
+        for expected in &candidates {
+            let (observation, expected_ll) = observe(expected, &geometry, &mut rng, 0);
+            assert_eq!(
+                matcher.most_likely_among(&observation, &candidates),
+                Some((expected.clone(), expected_ll))
+            );
+            assert_eq!(
+                matcher
+                    .most_likely_among(&observation, &candidates)
+                    .unwrap()
+                    .1,
+                matcher.most_likely(&observation).unwrap().1
+            );
+        }
+
+        let (observation, _) = observe(&candidates[1], &geometry, &mut rng, 0);
+        assert_eq!(matcher.most_likely_among(&observation, &[]), None);
+    }
+
+    #[test]
+    fn most_likely_near() {
+        let geometry = puzzle("3x3").into_inner();
+        let matcher = Matcher::new(Arc::clone(&geometry));
+        let state = |moves: &str| {
+            Algorithm::parse_from_string(geometry.permutation_group(), moves)
+                .unwrap()
+                .permutation()
+                .clone()
+        };
+
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"Same as last frame, give or take");
+        let expected = state("R U");
+        let (observation, expected_ll) = observe(&expected, &geometry, &mut rng, 0);
+        assert_eq!(
+            matcher.most_likely(&observation),
+            Some((expected.clone(), expected_ll))
+        );
+
+        // The state of the last frame, or one turn before it, is found without enumerating anything
+        for hint in [state("R U"), state("R"), state("R U R")] {
+            assert_eq!(
+                matcher.best_near(&observation, &hint),
+                Some((expected.clone(), expected_ll))
+            );
+            assert_eq!(
+                matcher.most_likely_near(&observation, &hint),
+                Some((expected.clone(), expected_ll))
+            );
+        }
+
+        // A hint that is more than a turn off falls back to enumerating the candidates
+        let hint = state("F2 B2 L D'");
+        assert_eq!(matcher.best_near(&observation, &hint), None);
+        assert_eq!(
+            matcher.most_likely_near(&observation, &hint),
+            Some((expected.clone(), expected_ll))
+        );
+
+        // Even the hint itself loses to a better state
+        let (observation, solved_ll) = observe(
+            &Permutation::from_cycles(Vec::new()),
+            &geometry,
+            &mut rng,
+            0,
+        );
+        assert_eq!(matcher.best_near(&observation, &expected), None);
+        assert_eq!(
+            matcher.most_likely_near(&observation, &expected),
+            Some((Permutation::from_cycles(Vec::new()), solved_ll))
+        );
+    }
+
     static CONFOUNDING_COLORS: LazyLock<HashMap<ArcIntern<str>, ArcIntern<str>>> =
         LazyLock::new(|| {
             let mut map = HashMap::new();
@@ -809,17 +1849,16 @@ mod tests {
             map
         });
 
-    /// Test whether the matcher identifies the permutation correctly and returns whether it does so.
-    fn test_perm<R: Rng + ?Sized>(
+    /// Simulate the observation of the given permutation, returning it along with the log-likelihood of the true state.
+    fn observe<R: Rng + ?Sized>(
         perm: &Permutation,
-        matcher: &Matcher,
         geometry: &PuzzleGeometry,
         rng: &mut R,
         noise: i64,
-    ) -> bool {
+    ) -> (Vec<BTreeMap<ArcIntern<str>, f64>>, f64) {
         let group = geometry.permutation_group();
 
-        let mut baseline = HashMap::new();
+        let mut baseline = BTreeMap::new();
 
         for color in geometry.permutation_group().facelet_colors() {
             baseline.insert(ArcIntern::clone(color), -100.);
@@ -848,7 +1887,98 @@ mod tests {
             }
         }
 
-        let (found, ll) = matcher.most_likely(&observation);
+        (observation, expected_ll)
+    }
+
+    #[test]
+    fn greedy() {
+        let geometry = puzzle("3x3").into_inner();
+        let stabchain = StabilizerChain::new(&geometry.permutation_group());
+        let matcher = Matcher::new(Arc::clone(&geometry));
+        let greedy = GreedyMatcher::new(Arc::clone(&geometry));
+
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"Greedy grabs the best piece now!");
+
+        // Without noise, the most likely piece in every spot is the right one, so placing them greedily finds the same state as the search
+        for _ in 0..20 {
+            let state = stabchain.random(&mut rng);
+            let (observation, expected_ll) = observe(&state, &geometry, &mut rng, 0);
+
+            let found = StateMatcher::most_likely(&matcher, &observation);
+            assert_eq!(found, Some((state, expected_ll)));
+            assert_eq!(greedy.most_likely(&observation), found);
+        }
+    }
+
+    #[test]
+    fn cost_matrices() {
+        let geometry = puzzle("3x3").into_inner();
+        let matcher = Matcher::new(Arc::clone(&geometry));
+        let solved = Permutation::from_cycles(Vec::new());
+
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"Why did you pick that piece, eh?");
+        let (observation, _) = observe(&solved, &geometry, &mut rng, 0);
+
+        let orbits = matcher.cost_matrices(&observation);
+        assert_eq!(orbits.len(), geometry.pieces_data().orbits().len());
+
+        for orbit in orbits {
+            let pieces = orbit.costs.shape()[0];
+
+            for spot in 0..pieces {
+                let diagonal = orbit.costs[[spot, spot, 0]];
+                assert_eq!(orbit.best_orientations[[spot, spot]], 0);
+
+                for (piece, ori) in (0..pieces).cartesian_product(0..orbit.costs.shape()[2]) {
+                    if (piece, ori) != (spot, 0) {
+                        assert!(orbit.costs[[spot, piece, ori]] < diagonal);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn unfiltered() {
+        let geometry = puzzle("3x3").into_inner();
+        let stabchain = StabilizerChain::new(&geometry.permutation_group());
+        let matcher = Matcher::new(Arc::clone(&geometry));
+        let solved = Permutation::from_cycles(Vec::new());
+
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"Twist a corner, see what sticks!");
+        let (observation, _) = observe(&solved, &geometry, &mut rng, 0);
+
+        let unfiltered = matcher
+            .unfiltered_candidates(&observation)
+            .take(10)
+            .collect_vec();
+        assert_eq!(unfiltered[0].0, solved);
+        // The runners up change a single piece, like twisting one corner, which can't be done on a real cube
+        assert!(
+            unfiltered
+                .iter()
+                .any(|(perm, _)| !stabchain.is_member(perm.clone()))
+        );
+
+        assert!(
+            matcher
+                .candidates(&observation)
+                .take(10)
+                .all(|(perm, _)| stabchain.is_member(perm))
+        );
+    }
+
+    /// Test whether the matcher identifies the permutation correctly and returns whether it does so.
+    fn test_perm<R: Rng + ?Sized>(
+        perm: &Permutation,
+        matcher: &Matcher,
+        geometry: &PuzzleGeometry,
+        rng: &mut R,
+        noise: i64,
+    ) -> bool {
+        let (observation, expected_ll) = observe(perm, geometry, rng, noise);
+
+        let (found, ll) = matcher.most_likely(&observation).unwrap();
 
         if found == *perm {
             assert_eq!(ll, expected_ll);
@@ -901,4 +2031,36 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn candidates() {
+        let geometry = puzzle("3x3").into_inner();
+        let stabchain = StabilizerChain::new(&geometry.permutation_group());
+
+        let matcher = Matcher::new(Arc::clone(&geometry));
+
+        let mut rng = rand::rngs::SmallRng::from_seed(*b"Candidates arrive in order, best");
+
+        for _ in 0..10 {
+            let perm = stabchain.random(&mut rng);
+            let (observation, _) = observe(&perm, &geometry, &mut rng, 15);
+
+            let candidates = matcher.candidates(&observation).take(5).collect_vec();
+
+            assert_eq!(candidates.len(), 5);
+            assert_eq!(
+                Some(&candidates[0]),
+                matcher.most_likely(&observation).as_ref()
+            );
+            assert_eq!(candidates[0].0, perm);
+
+            for ((_, a), (_, b)) in candidates.iter().tuple_windows() {
+                assert!(a >= b);
+            }
+
+            for (candidate, _) in &candidates {
+                assert!(stabchain.is_member(candidate.clone()));
+            }
+        }
+    }
 }
@@ -0,0 +1,117 @@
+use std::collections::BTreeMap;
+
+use internment::ArcIntern;
+use puzzle_theory::permutations::Permutation;
+
+use crate::{
+    cmp_ll,
+    facelets::colors_in,
+    puzzle_matching::{Matcher, finite_log_likelihood},
+};
+
+/// How many candidates that are nearly as likely as the best one `Matcher::recognize` looks at for one that looks different. Puzzles with pieces that look identical, like the centers of a 4x4, have millions of arrangements that look the same, so the search has to stop somewhere.
+const AMBIGUITY_SEARCH_LIMIT: usize = 64;
+
+/// Whether an observation shows the puzzle clearly enough to report a state, as decided by `Matcher::recognize`
+#[derive(Debug, Clone, PartialEq)]
+pub enum RecognitionOutcome {
+    /// The most likely state along with its log-likelihood, which is clearly more likely than any state that looks different
+    Confident(Permutation, f64),
+    /// A state that looks different is about as likely as the most likely one. In a video this usually means that the frame was captured mid-turn or is blurred.
+    Ambiguous,
+    /// Even the most likely valid state explains the observation badly, or the puzzle has no valid state at all
+    NoValidState,
+    /// Too few pixels look like any of the calibrated colors for the picture to show the puzzle at all, so the camera probably isn't pointed at it. Only `CVProcessor::recognize` tells this apart from `NoValidState`, since the matcher never sees the pixels.
+    NoPuzzle,
+}
+
+/// The thresholds that `Matcher::recognize` holds the most likely state to, and that `CVProcessor::recognize` holds the picture to before matching it at all. The defaults only reject exact ties, states that were ruled out entirely and pictures that mostly look like none of the colors; what counts as clear depends on the calibration, so these should be tuned on real pictures, for example with the margins reported by `evaluation::evaluate`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecognitionThresholds {
+    /// The most likely state is ambiguous unless it has more than this much more log-likelihood than every state that looks different
+    pub min_margin: f64,
+    /// The most likely state explains the observation badly if its log-likelihood averaged over the stickers that were seen is below this
+    pub min_mean_log_likelihood: f64,
+    /// `CVProcessor::recognize` finds no puzzle in a picture unless at least this fraction of the pixels assigned to stickers look like a calibrated color. See `Inference::plausible_fraction`.
+    pub min_plausible_fraction: f64,
+}
+
+impl Default for RecognitionThresholds {
+    fn default() -> Self {
+        RecognitionThresholds {
+            min_margin: 0.,
+            min_mean_log_likelihood: f64::NEG_INFINITY,
+            min_plausible_fraction: 0.5,
+        }
+    }
+}
+
+impl Matcher {
+    /// Decide whether the observation shows the puzzle clearly enough to report its most likely state, rather than always reporting one like `most_likely` does. This lets a live video wait for a clean frame.
+    pub fn recognize(
+        &self,
+        confidences: &[BTreeMap<ArcIntern<str>, f64>],
+        thresholds: &RecognitionThresholds,
+    ) -> RecognitionOutcome {
+        let mut candidates = self.candidates(confidences);
+
+        let Some((best, best_ll)) = candidates.next() else {
+            return RecognitionOutcome::NoValidState;
+        };
+
+        let seen = confidences.iter().filter(|v| !v.is_empty()).count();
+        if seen > 0 && best_ll / (seen as f64) < thresholds.min_mean_log_likelihood {
+            return RecognitionOutcome::NoValidState;
+        }
+
+        let is_ambiguous = candidates
+            .take(AMBIGUITY_SEARCH_LIMIT)
+            .take_while(|(_, ll)| best_ll - ll <= thresholds.min_margin)
+            .any(|(candidate, _)| !self.looks_identical(&best, &candidate));
+
+        if is_ambiguous {
+            RecognitionOutcome::Ambiguous
+        } else {
+            RecognitionOutcome::Confident(best, best_ll)
+        }
+    }
+
+    /// Like `most_likely`, but only considers the given states, for when the context already narrows down what the puzzle can be, like the next step of a tutorial. Each state is scored directly from the log-likelihoods of the colors that it puts on each sticker, so nothing is enumerated. The states don't have to be valid members of the group. Ties go to the state that comes first, and `None` is returned if there are no states to pick from.
+    pub fn most_likely_among(
+        &self,
+        confidences: &[BTreeMap<ArcIntern<str>, f64>],
+        candidates: &[Permutation],
+    ) -> Option<(Permutation, f64)> {
+        let group = self.orbits.first()?.puzzle.permutation_group();
+
+        let mut best: Option<(&Permutation, f64)> = None;
+        for candidate in candidates {
+            // A color missing from a sticker's log-likelihoods counts for nothing, just like in the cost matrices
+            let ll = confidences
+                .iter()
+                .zip(&colors_in(&group, candidate))
+                .map(|(confidences, color)| {
+                    confidences
+                        .get(color)
+                        .map_or(0., |v| finite_log_likelihood(*v))
+                })
+                .sum::<f64>();
+
+            if best.is_none_or(|(_, best_ll)| cmp_ll(ll, best_ll).is_gt()) {
+                best = Some((candidate, ll));
+            }
+        }
+
+        best.map(|(state, ll)| (state.clone(), ll))
+    }
+
+    /// Whether every sticker has the same color in both states
+    pub(crate) fn looks_identical(&self, a: &Permutation, b: &Permutation) -> bool {
+        let Some(orbit) = self.orbits.first() else {
+            return true;
+        };
+        let group = orbit.puzzle.permutation_group();
+
+        colors_in(&group, a) == colors_in(&group, b)
+    }
+}
@@ -0,0 +1,306 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use internment::ArcIntern;
+use itertools::Itertools;
+use puzzle_theory::{permutations::Permutation, puzzle_geometry::PuzzleGeometry};
+
+use crate::{Observation, cmp_ll, puzzle_matching::Matcher};
+
+/// A way of holding the puzzle, described by the color that each face shows compared to the reference orientation that the puzzle's states are written in
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rotation {
+    shown: HashMap<ArcIntern<str>, ArcIntern<str>>,
+}
+
+impl Rotation {
+    /// The color that the face which has `color` in the reference orientation shows when the puzzle is held this way
+    pub fn shown<'a>(&'a self, color: &'a ArcIntern<str>) -> &'a ArcIntern<str> {
+        self.shown.get(color).unwrap_or(color)
+    }
+
+    /// Whether this is the reference orientation
+    pub fn is_identity(&self) -> bool {
+        self.shown.iter().all(|(a, b)| a == b)
+    }
+}
+
+/// Find every way of holding the puzzle by finding the recolorings of its faces that turn every piece into another piece of the puzzle, with its colors going around it in the same direction. Requiring the direction to be kept rules out mirror images, which can't be reached by turning the puzzle in your hand.
+pub(super) fn rotations(puzzle: &PuzzleGeometry) -> Vec<Rotation> {
+    let colors = puzzle
+        .permutation_group()
+        .facelet_colors()
+        .iter()
+        .unique()
+        .cloned()
+        .collect_vec();
+
+    recolorings(puzzle, &colors, false)
+        .into_iter()
+        .map(|mapping| Rotation {
+            shown: mapping
+                .into_iter()
+                .enumerate()
+                .map(|(from, to)| {
+                    (
+                        ArcIntern::clone(&colors[from]),
+                        ArcIntern::clone(&colors[to]),
+                    )
+                })
+                .collect(),
+        })
+        .collect()
+}
+
+/// Find every reflection of the puzzle, as the sticker that it takes each sticker to, from the recolorings of its faces that turn every piece into another piece of the puzzle with its colors going around it in the opposite direction. A reflection takes each piece to the piece with its recolored colors, and each sticker to the sticker of that piece with its recolored color. Pieces that look exactly alike are taken to each other in order, since there is no telling them apart anyway.
+pub(super) fn reflections(puzzle: &PuzzleGeometry) -> Vec<Box<[usize]>> {
+    let group = puzzle.permutation_group();
+    let data = puzzle.pieces_data();
+    let colors = group
+        .facelet_colors()
+        .iter()
+        .unique()
+        .cloned()
+        .collect_vec();
+    let color_of = |sticker: usize| {
+        colors
+            .iter()
+            .position(|v| *v == group.facelet_colors()[sticker])
+            .unwrap()
+    };
+
+    recolorings(puzzle, &colors, true)
+        .into_iter()
+        .map(|mapping| {
+            let mut reflection = (0..group.facelet_count()).collect_vec();
+
+            for orbit in data.orbits() {
+                let mut taken = vec![false; orbit.pieces().len()];
+
+                for piece in orbit.pieces() {
+                    let recolored = piece
+                        .stickers()
+                        .iter()
+                        .map(|sticker| mapping[color_of(*sticker)])
+                        .collect_vec();
+
+                    let (idx, image) = orbit
+                        .pieces()
+                        .iter()
+                        .enumerate()
+                        .find(|(idx, other)| {
+                            !taken[*idx]
+                                && other
+                                    .stickers()
+                                    .iter()
+                                    .map(|sticker| color_of(*sticker))
+                                    .sorted()
+                                    .eq(recolored.iter().copied().sorted())
+                        })
+                        .unwrap();
+                    taken[idx] = true;
+
+                    let mut unused = image.stickers().to_vec();
+                    for (sticker, color) in piece.stickers().iter().zip(&recolored) {
+                        let position = unused
+                            .iter()
+                            .position(|other| color_of(*other) == *color)
+                            .unwrap();
+                        reflection[*sticker] = unused.remove(position);
+                    }
+                }
+            }
+
+            reflection.into()
+        })
+        .collect()
+}
+
+/// Find the recolorings of the faces of the puzzle, as the index in `colors` of the color that each color becomes, that turn every piece into another piece of the puzzle with its colors going around it in the same direction, or in the opposite direction if `mirrored` is set. The identity is found first if it is one of them.
+fn recolorings(
+    puzzle: &PuzzleGeometry,
+    colors: &[ArcIntern<str>],
+    mirrored: bool,
+) -> Vec<Vec<usize>> {
+    let group = puzzle.permutation_group();
+    let color_idx = |color: &ArcIntern<str>| colors.iter().position(|v| v == color).unwrap();
+
+    // The colors of each piece in the order that twisting it cycles through them
+    let pieces = puzzle
+        .pieces_data()
+        .orbits()
+        .iter()
+        .flat_map(|orbit| {
+            orbit.pieces().iter().map(|piece| {
+                let mut sticker = piece.stickers()[0];
+                (0..orbit.orientation_count())
+                    .map(|_| {
+                        let color = color_idx(&group.facelet_colors()[sticker]);
+                        sticker = piece.twist().mapping().get(sticker);
+                        color
+                    })
+                    .collect_vec()
+            })
+        })
+        .collect_vec();
+
+    let existing = pieces.iter().map(|v| canonical(v)).collect::<HashSet<_>>();
+
+    // Check each piece as soon as all of its colors are mapped
+    let mut completed_by = vec![Vec::new(); colors.len()];
+    for piece in &pieces {
+        if let Some(last) = piece.iter().max() {
+            completed_by[*last].push(piece);
+        }
+    }
+
+    let mut found = Vec::new();
+    let mut mapping = Vec::new();
+    let mut used = vec![false; colors.len()];
+    search(
+        &completed_by,
+        &existing,
+        mirrored,
+        &mut mapping,
+        &mut used,
+        &mut found,
+    );
+
+    found
+}
+
+/// Extend `mapping`, which maps the first colors, in every way that keeps the pieces that it completes pieces of the puzzle, with their colors reversed if `mirrored` is set. The identity mapping is found first.
+fn search(
+    completed_by: &[Vec<&Vec<usize>>],
+    existing: &HashSet<Vec<usize>>,
+    mirrored: bool,
+    mapping: &mut Vec<usize>,
+    used: &mut [bool],
+    found: &mut Vec<Vec<usize>>,
+) {
+    let color = mapping.len();
+    if color == completed_by.len() {
+        found.push(mapping.clone());
+        return;
+    }
+
+    for to in 0..used.len() {
+        if used[to] {
+            continue;
+        }
+
+        mapping.push(to);
+        let keeps_pieces = completed_by[color].iter().all(|piece| {
+            let mut mapped = piece.iter().map(|v| mapping[*v]).collect_vec();
+            if mirrored {
+                mapped.reverse();
+            }
+            existing.contains(&canonical(&mapped))
+        });
+
+        if keeps_pieces {
+            used[to] = true;
+            search(completed_by, existing, mirrored, mapping, used, found);
+            used[to] = false;
+        }
+        mapping.pop();
+    }
+}
+
+/// The colors of a piece starting from the smallest, so that the same piece written starting from a different sticker compares equal
+fn canonical(colors: &[usize]) -> Vec<usize> {
+    let start = (0..colors.len()).min_by_key(|i| colors[*i]).unwrap_or(0);
+
+    colors[start..]
+        .iter()
+        .chain(&colors[..start])
+        .copied()
+        .collect()
+}
+
+impl Matcher {
+    /// Every way of holding the puzzle that `most_likely_any_orientation` tries, starting with the reference orientation
+    pub fn rotations(&self) -> &[Rotation] {
+        &self.rotations
+    }
+
+    /// Every reflection of the puzzle, as the sticker that it takes each sticker to
+    pub fn reflections(&self) -> &[Box<[usize]>] {
+        &self.reflections
+    }
+
+    /// The reflection that swaps the face of the given color with another face and leaves every other face where it is, or `None` if the puzzle has no such reflection. This is what a mirror, or a front-facing camera, does to the faces on the left and right of the picture: on a cube held with a face towards the camera, either of the side faces picks the reflection that swaps the two.
+    pub fn reflection_swapping(&self, color: &ArcIntern<str>) -> Option<&[usize]> {
+        self.reflections
+            .iter()
+            .find(|reflection| {
+                // A reflection takes the stickers of a face to the stickers of a single face
+                let recolored = |from: &ArcIntern<str>| {
+                    self.facelet_colors
+                        .iter()
+                        .position(|v| v == from)
+                        .map(|sticker| &self.facelet_colors[reflection[sticker]])
+                };
+
+                recolored(color).is_some_and(|other| {
+                    other != color
+                        && recolored(other) == Some(color)
+                        && self
+                            .facelet_colors
+                            .iter()
+                            .all(|v| v == color || v == other || recolored(v) == Some(v))
+                })
+            })
+            .map(|reflection| &**reflection)
+    }
+
+    /// Turn an observation of a mirror image of the puzzle, like a front-facing camera shows, back into an observation of the puzzle itself, given the reflection that the mirror applies. In a mirror image, each spot shows the sticker that the reflection takes the spot to.
+    pub fn unmirrored(
+        &self,
+        confidences: &[BTreeMap<ArcIntern<str>, f64>],
+        reflection: &[usize],
+    ) -> Observation {
+        let mut unmirrored = vec![BTreeMap::new(); confidences.len()];
+        for (spot, confidences) in confidences.iter().enumerate() {
+            unmirrored[reflection[spot]] = confidences.clone();
+        }
+        unmirrored.into()
+    }
+
+    /// Like `most_likely`, but also tries every other way of holding the puzzle in case it isn't being held in the reference orientation, and returns the one that explains the observation best. The state is the puzzle as it is held, with each face named by the color that it shows, so that holding a solved puzzle in any orientation gives the solved state.
+    ///
+    /// On puzzles where turning the whole puzzle is the same as turning its faces, like the 2x2, every orientation explains the observation equally well and the reference orientation is reported. Returns `None` if no orientation has any candidates, which can only happen if pieces were ruled out with `set_disallow_below`.
+    pub fn most_likely_any_orientation(
+        &self,
+        confidences: &[BTreeMap<ArcIntern<str>, f64>],
+    ) -> Option<(Permutation, f64, Rotation)> {
+        let mut best: Option<(Permutation, f64, Rotation)> = None;
+
+        for rotation in self.rotations.iter() {
+            // How much each sticker looks like each color of the reference orientation, if the puzzle is held this way
+            let recolored = confidences
+                .iter()
+                .map(|confidences| {
+                    confidences
+                        .keys()
+                        .filter_map(|color| {
+                            let ll = confidences.get(rotation.shown(color))?;
+                            Some((ArcIntern::clone(color), *ll))
+                        })
+                        .collect::<BTreeMap<_, _>>()
+                })
+                .collect_vec();
+
+            let Some((state, ll)) = self.most_likely(&recolored) else {
+                continue;
+            };
+            if best
+                .as_ref()
+                .is_none_or(|(_, best_ll, _)| cmp_ll(ll, *best_ll).is_gt())
+            {
+                best = Some((state, ll, rotation.clone()));
+            }
+        }
+
+        best
+    }
+}
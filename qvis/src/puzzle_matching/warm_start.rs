@@ -0,0 +1,99 @@
+use std::collections::BTreeMap;
+
+use internment::ArcIntern;
+use puzzle_theory::permutations::Permutation;
+
+use crate::{
+    cmp_ll,
+    moves::{state_of, then},
+    puzzle_matching::{Matcher, finite_log_likelihood, weighted_log_likelihood},
+};
+
+impl Matcher {
+    /// Like `most_likely`, but starting from a guess at the state, like the one recognized in the previous frame of a video. The guess and every state one turn away from it are scored first, and if the best of them is as likely as the most likely matching of every orbit on its own, nothing can beat it and it is returned without enumerating any candidates. Consecutive frames usually show the same state or one turn apart, so this skips most of the matching in a live video.
+    ///
+    /// The guess only decides what is looked at first: if it isn't good enough, the candidates are enumerated like `most_likely` does, so a wrong guess costs the time that scoring its neighbors took and never changes the answer. Ties go to the guess. Returns `None` if there are no candidates, which can only happen if pieces were ruled out with `set_disallow_below`.
+    pub fn most_likely_near(
+        &self,
+        confidences: &[BTreeMap<ArcIntern<str>, f64>],
+        hint: &Permutation,
+    ) -> Option<(Permutation, f64)> {
+        self.best_near(confidences, hint)
+            .or_else(|| self.candidates(confidences).next())
+    }
+
+    /// The most likely of `hint` and the states one turn away from it, if no candidate can be more likely
+    pub(super) fn best_near(
+        &self,
+        confidences: &[BTreeMap<ArcIntern<str>, f64>],
+        hint: &Permutation,
+    ) -> Option<(Permutation, f64)> {
+        // A turn of a state that can't be reached is another state that can't be reached
+        if !self.stab_chain.is_member(hint.clone()) {
+            return None;
+        }
+
+        let group = self.orbits.first()?.puzzle.permutation_group();
+        let hint = state_of(&group, hint);
+
+        let mut best: Option<(Vec<usize>, f64)> = None;
+        for state in
+            std::iter::once(hint.clone()).chain(self.turns.iter().map(|turn| then(&hint, turn)))
+        {
+            let Some(ll) = self.log_likelihood_of(confidences, &state) else {
+                continue;
+            };
+
+            if best
+                .as_ref()
+                .is_none_or(|(_, best_ll)| cmp_ll(ll, *best_ll).is_gt())
+            {
+                best = Some((state, ll));
+            }
+        }
+        let (state, ll) = best?;
+
+        // No state is more likely than the most likely matching of each orbit, whether or not the matchings together make a valid state
+        let (_, bound) = self.matchings(confidences, false).next()?;
+
+        (ll >= bound).then(|| (Permutation::from_state(state), ll))
+    }
+
+    /// The log-likelihood that the matcher gives the state where each facelet comes from `state`, or `None` if it places a piece somewhere that `set_disallow_below` rules out. The log-likelihoods are added up in the same order as the cost matrices and the matchings add them up, so that a state scored here comes out exactly as likely as when the matcher finds it.
+    fn log_likelihood_of(
+        &self,
+        confidences: &[BTreeMap<ArcIntern<str>, f64>],
+        state: &[usize],
+    ) -> Option<f64> {
+        let group = self.orbits.first()?.puzzle.permutation_group();
+
+        let mut ll = 0.;
+        for (orbit, weight) in self.orbits.iter().zip(&self.orbit_weights) {
+            let mut orbit_ll = 0.;
+
+            for spot in orbit.orbit.pieces() {
+                let mut spot_ll = 0.;
+                for sticker in spot.stickers() {
+                    let color = &group.facelet_colors()[state[*sticker]];
+                    // A color missing from a sticker's log-likelihoods counts for nothing, just like in the cost matrices
+                    spot_ll += confidences[*sticker]
+                        .get(color)
+                        .map_or(0., |v| finite_log_likelihood(*v));
+                }
+
+                if self
+                    .disallow_below
+                    .is_some_and(|threshold| spot_ll < threshold)
+                {
+                    return None;
+                }
+
+                orbit_ll += spot_ll;
+            }
+
+            ll += weighted_log_likelihood(*weight, orbit_ll);
+        }
+
+        Some(ll)
+    }
+}
@@ -0,0 +1,199 @@
+//! Selecting the elements at given ranks of a slice without fully sorting it
+
+use std::cmp::Ordering;
+
+use rand::Rng;
+
+// This quickselect code is copied from <https://gitlab.com/hrovnyak/nmr-schedule>
+
+fn partition<T, R: Rng + ?Sized>(
+    rng: &mut R,
+    slice: &mut [T],
+    by: &impl Fn(&T, &T) -> Ordering,
+) -> usize {
+    slice.swap(0, rng.random_range(0..slice.len()));
+
+    let mut i = 1;
+    let mut j = slice.len() - 1;
+
+    loop {
+        while i < slice.len() && !matches!(by(&slice[i], &slice[0]), Ordering::Less) {
+            i += 1;
+        }
+
+        while matches!(by(&slice[j], &slice[0]), Ordering::Less) {
+            j -= 1;
+        }
+
+        // If the indices crossed, return
+        if i > j {
+            slice.swap(0, j);
+            return j;
+        }
+
+        // Swap the elements at the left and right indices
+        slice.swap(i, j);
+        i += 1;
+    }
+}
+
+/// Standard quickselect algorithm: https://en.wikipedia.org/wiki/Quickselect
+/// Sorts in descending order
+///
+/// After calling this function, the value at index `find_spot` is guaranteed to be at the correctly sorted position and all values at indices less than `find_spot` are guaranteed to be greater than the value at `find_spot` and vice versa for indices greater. If `find_spot` is out of range, the slice is left partially sorted without any guarantees.
+fn quickselect<T, R: Rng + ?Sized>(
+    rng: &mut R,
+    mut slice: &mut [T],
+    by: impl Fn(&T, &T) -> Ordering,
+    mut find_spot: usize,
+) {
+    loop {
+        let len = slice.len();
+
+        if len < 2 {
+            return;
+        }
+
+        let spot_found = partition(rng, slice, &by);
+
+        match find_spot.cmp(&spot_found) {
+            Ordering::Less => slice = &mut slice[0..spot_found],
+            Ordering::Equal => return,
+            Ordering::Greater => {
+                slice = &mut slice[spot_found + 1..len];
+                find_spot = find_spot - spot_found - 1;
+            }
+        }
+    }
+}
+
+/// Find the element that would be at index `n` if `slice` were sorted in descending order by `by`, in expected linear time. The slice is reordered so that the elements before index `n` are all greater than or equal to it and the elements after are all less than or equal to it.
+///
+/// Returns `None` if `n` is out of range.
+pub fn select_nth<'a, T, R: Rng + ?Sized>(
+    rng: &mut R,
+    slice: &'a mut [T],
+    by: impl Fn(&T, &T) -> Ordering,
+    n: usize,
+) -> Option<&'a mut T> {
+    if n >= slice.len() {
+        return None;
+    }
+
+    quickselect(rng, slice, by, n);
+    Some(&mut slice[n])
+}
+
+/// Reorder `slice` so that its `k` greatest elements according to `by` come first and return them, in no particular order. If `k` is greater than the length of the slice, the whole slice is returned.
+pub fn top_k<'a, T, R: Rng + ?Sized>(
+    rng: &mut R,
+    slice: &'a mut [T],
+    by: impl Fn(&T, &T) -> Ordering,
+    k: usize,
+) -> &'a mut [T] {
+    let k = k.min(slice.len());
+
+    if k > 0 && k < slice.len() {
+        quickselect(rng, slice, by, k - 1);
+    }
+
+    &mut slice[..k]
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+
+    use super::{quickselect, select_nth, top_k};
+
+    #[test]
+    fn test_quickselect() {
+        fn verify<R: Rng + ?Sized>(rng: &mut R, pos: usize, slice: &[f64]) {
+            let mut slice = slice
+                .iter()
+                .enumerate()
+                .map(|(a, b)| (*b, a))
+                .collect::<Vec<_>>();
+
+            quickselect(rng, &mut slice, |a, b| a.0.total_cmp(&b.0), pos);
+
+            for i in 0..pos {
+                assert!(
+                    slice[i].0 >= slice[pos].0,
+                    "Pos: {pos}, Index: {i} - {slice:?}"
+                );
+            }
+
+            for i in pos + 1..slice.len() {
+                assert!(
+                    slice[i].0 <= slice[pos].0,
+                    "Pos: {pos}, Index: {i} - {slice:?}"
+                );
+            }
+
+            let v = slice[pos];
+
+            slice.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+            assert_eq!(slice[pos].0, v.0);
+        }
+
+        let mut rng = rand::rng();
+
+        verify(&mut rng, 2, &[5., 4., 3., 2., 1.]);
+        verify(&mut rng, 2, &[1., 2., 3., 4., 5.]);
+        verify(&mut rng, 3, &[1., 2., 1., 4., 3.]);
+
+        for i in 0..100 {
+            let pos = rng.random_range(0..i + 1);
+            let data = (0..i + 1).map(|_| rng.random()).collect::<Vec<_>>();
+            verify(&mut rng, pos, &data);
+        }
+    }
+
+    #[test]
+    fn out_of_range() {
+        let mut rng = rand::rng();
+
+        let mut empty: [f64; 0] = [];
+        assert_eq!(select_nth(&mut rng, &mut empty, f64::total_cmp, 0), None);
+        assert!(top_k(&mut rng, &mut empty, f64::total_cmp, 3).is_empty());
+
+        let mut slice = [3., 1., 2.];
+        assert_eq!(select_nth(&mut rng, &mut slice, f64::total_cmp, 3), None);
+        assert_eq!(select_nth(&mut rng, &mut slice, f64::total_cmp, 100), None);
+        assert_eq!(
+            select_nth(&mut rng, &mut slice, f64::total_cmp, 2).copied(),
+            Some(1.)
+        );
+        assert_eq!(
+            select_nth(&mut rng, &mut slice, f64::total_cmp, 0).copied(),
+            Some(3.)
+        );
+
+        let mut all = top_k(&mut rng, &mut slice, f64::total_cmp, 5).to_vec();
+        all.sort_by(f64::total_cmp);
+        assert_eq!(all, [1., 2., 3.]);
+    }
+
+    #[test]
+    fn top() {
+        let mut rng = rand::rng();
+
+        for _ in 0..100 {
+            let len = rng.random_range(0..30);
+            let k = rng.random_range(0..len + 2);
+            let data = (0..len).map(|_| rng.random()).collect::<Vec<f64>>();
+
+            let mut slice = data.clone();
+            let mut top = top_k(&mut rng, &mut slice, f64::total_cmp, k).to_vec();
+            top.sort_by(|a, b| b.total_cmp(a));
+
+            let mut expected = data;
+            expected.sort_by(|a, b| b.total_cmp(a));
+            expected.truncate(k);
+
+            assert_eq!(top, expected);
+        }
+    }
+}
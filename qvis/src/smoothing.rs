@@ -0,0 +1,74 @@
+use std::collections::VecDeque;
+
+use puzzle_theory::permutations::Permutation;
+
+/// Keeps a short history of recognition results so that a single bad frame of a video doesn't make the reported state flicker
+pub(crate) struct Smoother {
+    window: usize,
+    history: VecDeque<(Permutation, f64)>,
+}
+
+impl Smoother {
+    /// Create a smoother that votes over the last `window` frames. This method panics if `window` is zero.
+    pub fn new(window: usize) -> Smoother {
+        assert!(
+            window > 0,
+            "The smoothing window must contain at least one frame"
+        );
+
+        Smoother {
+            window,
+            history: VecDeque::with_capacity(window),
+        }
+    }
+
+    /// Record the result for a new frame and return the state that the most frames in the window agree on. Ties are broken in favor of the most recently seen state, and the confidence returned is the one from the most recent frame that recognized the winning state.
+    pub fn push(&mut self, result: (Permutation, f64)) -> (Permutation, f64) {
+        if self.history.len() == self.window {
+            self.history.pop_front();
+        }
+
+        self.history.push_back(result);
+
+        self.history
+            .iter()
+            .max_by_key(|(perm, _)| self.history.iter().filter(|(v, _)| v == perm).count())
+            .cloned()
+            .unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use puzzle_theory::permutations::Permutation;
+
+    use super::Smoother;
+
+    #[test]
+    fn outlier() {
+        let a = Permutation::from_cycles(vec![vec![0, 1]]);
+        let b = Permutation::from_cycles(vec![vec![1, 2]]);
+
+        let mut smoother = Smoother::new(3);
+
+        assert_eq!(smoother.push((a.clone(), -1.)), (a.clone(), -1.));
+        assert_eq!(smoother.push((a.clone(), -2.)), (a.clone(), -2.));
+        // The outlier frame is outvoted
+        assert_eq!(smoother.push((b.clone(), -3.)), (a.clone(), -2.));
+        assert_eq!(smoother.push((a.clone(), -4.)), (a.clone(), -4.));
+        // Once the new state is in the majority, it gets reported
+        assert_eq!(smoother.push((b.clone(), -5.)), (b.clone(), -5.));
+        assert_eq!(smoother.push((b.clone(), -6.)), (b, -6.));
+    }
+
+    #[test]
+    fn window_of_one() {
+        let a = Permutation::from_cycles(vec![vec![0, 1]]);
+        let b = Permutation::from_cycles(vec![vec![1, 2]]);
+
+        let mut smoother = Smoother::new(1);
+
+        assert_eq!(smoother.push((a.clone(), -1.)), (a, -1.));
+        assert_eq!(smoother.push((b.clone(), -2.)), (b, -2.));
+    }
+}
@@ -0,0 +1,199 @@
+//! Logging the states that a puzzle goes through while it is being solved, for analyzing the solve afterwards. The recognitions of a video are fed in as they come, and only the ones where the state changed are kept, along with the moves that got it there.
+
+use std::{sync::Arc, time::Duration};
+
+use puzzle_theory::{permutations::Permutation, puzzle_geometry::PuzzleGeometry};
+
+use crate::{QvisError, facelets, moves};
+
+/// A change of state in a `SolveLogger`'s log
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SolveLogEntry {
+    /// When the new state was first recognized, in seconds, measured from whatever the times given to `SolveLogger::record` are measured from
+    pub time: f64,
+    /// The new state in Kociemba's facelet notation, or `None` if the puzzle isn't a 2x2 or 3x3 cube with the standard color scheme. See `facelet_string`.
+    pub facelets: Option<String>,
+    /// The shortest sequence of moves from the previous state to this one, like `["R", "U2"]`, or `None` for the first state and for states that no sequence of at most the logger's `max_moves` moves reaches. The latter happens when moves are made faster than the frames come in or a frame is misrecognized.
+    pub moves: Option<Vec<String>>,
+}
+
+/// Records the states of a puzzle recognized over the course of a solve as a timestamped log of the changes between them
+pub struct SolveLogger {
+    puzzle: Arc<PuzzleGeometry>,
+    max_moves: usize,
+    last: Option<Permutation>,
+    entries: Vec<SolveLogEntry>,
+}
+
+impl SolveLogger {
+    /// Create an empty log of a solve of the given puzzle. The moves between two logged states are looked for up to `max_moves` moves, which should be small, since looking for them tries every sequence that long; see `moves_between`.
+    pub fn new(puzzle: Arc<PuzzleGeometry>, max_moves: usize) -> SolveLogger {
+        SolveLogger {
+            puzzle,
+            max_moves,
+            last: None,
+            entries: Vec::new(),
+        }
+    }
+
+    /// Record what `CVProcessor::process_image` or `CVProcessor::process_frame` returned for a picture taken at `time`. A state that differs from the last one logged is added to the log and returned. Errors, like a frame that was too unclear to recognize, and states that haven't changed are skipped and give `None`.
+    pub fn record(
+        &mut self,
+        time: Duration,
+        recognized: Result<(Permutation, f64), QvisError>,
+    ) -> Option<&SolveLogEntry> {
+        let (state, _) = recognized.ok()?;
+        if self.last.as_ref() == Some(&state) {
+            return None;
+        }
+
+        let moves = self.last.as_ref().and_then(|last| {
+            moves::move_names_between(
+                &self.puzzle.permutation_group(),
+                last,
+                &state,
+                self.max_moves,
+            )
+        });
+        self.entries.push(SolveLogEntry {
+            time: time.as_secs_f64(),
+            facelets: facelets::facelet_string(&self.puzzle, &state),
+            moves,
+        });
+        self.last = Some(state);
+
+        self.entries.last()
+    }
+
+    /// Every change of state logged so far, in the order they happened
+    pub fn entries(&self) -> &[SolveLogEntry] {
+        &self.entries
+    }
+
+    /// Write the log as JSON, with an object for each entry, whose fields are those of `SolveLogEntry`:
+    ///
+    /// ```json
+    /// {"entries":[
+    ///   {"time":0.0,"facelets":"UUUUUUUUURRRRRRRRRFFFFFFFFFDDDDDDDDDLLLLLLLLLBBBBBBBBB","moves":null},
+    ///   {"time":1.5,"facelets":"UUFUUFUUFRRRRRRRRRFFDFFDFFDDDBDDBDDBLLLLLLLLLUBBUBBUBB","moves":["R"]}
+    /// ]}
+    /// ```
+    ///
+    /// Fields that are `None` are `null`, and the whole log is on one line.
+    ///
+    /// # Panics
+    ///
+    /// This method doesn't actually panic, since everything in the log can be written as JSON.
+    #[cfg(feature = "serde")]
+    pub fn to_json(&self) -> String {
+        #[derive(serde::Serialize)]
+        struct Log<'a> {
+            entries: &'a [SolveLogEntry],
+        }
+
+        serde_json::to_string(&Log {
+            entries: &self.entries,
+        })
+        .unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use puzzle_theory::{
+        permutations::{Algorithm, Permutation},
+        puzzle_geometry::parsing::puzzle,
+    };
+
+    use super::SolveLogger;
+    use crate::QvisError;
+
+    #[test]
+    fn solve_log() {
+        let geometry = puzzle("3x3").into_inner();
+        let parse = |moves: &str| {
+            Algorithm::parse_from_string(geometry.permutation_group(), moves)
+                .unwrap()
+                .permutation()
+                .clone()
+        };
+
+        let mut logger = SolveLogger::new(Arc::clone(&geometry), 2);
+        let seconds = Duration::from_secs_f64;
+
+        let frames = [
+            (0., Ok(Permutation::from_cycles(Vec::new()))),
+            // Nothing changed
+            (0.5, Ok(Permutation::from_cycles(Vec::new()))),
+            (1.5, Ok(parse("R"))),
+            // A hand covered the puzzle
+            (2., Err(QvisError::NoValidState)),
+            (2.5, Ok(parse("R U2"))),
+            // Turned too fast for the frames to keep up
+            (3., Ok(parse("R U2 F L' D"))),
+        ];
+        for (time, state) in frames {
+            logger.record(seconds(time), state.map(|state| (state, 0.)));
+        }
+
+        let entries = logger.entries();
+        assert_eq!(
+            entries.iter().map(|entry| entry.time).collect::<Vec<_>>(),
+            [0., 1.5, 2.5, 3.]
+        );
+        assert_eq!(
+            entries
+                .iter()
+                .map(|entry| entry.moves.clone())
+                .collect::<Vec<_>>(),
+            [
+                None,
+                Some(vec!["R".to_owned()]),
+                Some(vec!["U2".to_owned()]),
+                None
+            ]
+        );
+        assert_eq!(
+            entries[0].facelets.as_deref(),
+            Some("UUUUUUUUURRRRRRRRRFFFFFFFFFDDDDDDDDDLLLLLLLLLBBBBBBBBB")
+        );
+        assert!(entries.iter().all(|entry| entry.facelets.is_some()));
+
+        // Turning back is logged as the move back
+        let back = logger.record(seconds(4.), Ok((parse("R"), 0.)));
+        assert_eq!(
+            back.and_then(|entry| entry.moves.clone()),
+            Some(vec!["U2".to_owned()])
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json() {
+        use super::SolveLogEntry;
+
+        let geometry = puzzle("3x3").into_inner();
+        let mut logger = SolveLogger::new(Arc::clone(&geometry), 1);
+        assert_eq!(logger.to_json(), r#"{"entries":[]}"#);
+
+        logger.entries = vec![
+            SolveLogEntry {
+                time: 0.,
+                facelets: None,
+                moves: None,
+            },
+            SolveLogEntry {
+                time: 1.25,
+                facelets: Some("UUU\"".to_owned()),
+                moves: Some(vec!["R".to_owned(), "U'".to_owned()]),
+            },
+        ];
+        assert_eq!(
+            logger.to_json(),
+            r#"{"entries":[{"time":0.0,"facelets":null,"moves":null},{"time":1.25,"facelets":"UUU\"","moves":["R","U'"]}]}"#
+        );
+    }
+}
@@ -0,0 +1,161 @@
+//! Grouping the pixels of an image into super-pixels that are assigned to stickers and classified as a whole. Averaging the pixels of each super-pixel smooths out the noise of the camera and small misalignments, and an assignment of super-pixels is much smaller than one of every pixel of a high resolution image.
+
+use crate::Channels;
+
+/// Which super-pixel each pixel of an image belongs to. Super-pixels are numbered from zero, and every super-pixel has at least one pixel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SuperPixels {
+    labels: Box<[usize]>,
+    count: usize,
+}
+
+impl SuperPixels {
+    /// Square blocks of `block_size` pixels on a side over an image of `width` by `height` pixels in row-major order. The blocks are numbered in row-major order too, and the ones at the right and bottom edges are cut short if the image isn't a whole number of blocks across. This method panics if `block_size` is zero or the image is empty.
+    pub fn blocks(width: usize, height: usize, block_size: usize) -> SuperPixels {
+        assert!(block_size > 0);
+        assert!(width > 0 && height > 0);
+
+        let blocks_across = width.div_ceil(block_size);
+        let labels = (0..height)
+            .flat_map(|y| {
+                (0..width).map(move |x| (y / block_size) * blocks_across + x / block_size)
+            })
+            .collect();
+
+        SuperPixels {
+            labels,
+            count: blocks_across * height.div_ceil(block_size),
+        }
+    }
+
+    /// Super-pixels given by the label of each pixel of the image, like the regions that SLIC segments an image into. This method panics if the image is empty or if some label below the greatest one has no pixels, since that super-pixel would have no color.
+    pub fn from_labels(labels: Box<[usize]>) -> SuperPixels {
+        let count = labels.iter().max().expect("The image is empty") + 1;
+
+        let mut seen = vec![false; count];
+        for label in &labels {
+            seen[*label] = true;
+        }
+        assert!(seen.iter().all(|seen| *seen), "A super-pixel has no pixels");
+
+        SuperPixels { labels, count }
+    }
+
+    /// How many super-pixels there are, which is how long an assignment of them is
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    /// Whether there are no super-pixels, which never happens
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /// How many pixels the image has
+    pub fn image_size(&self) -> usize {
+        self.labels.len()
+    }
+
+    /// The super-pixel that the pixel at `idx` belongs to
+    pub fn label(&self, idx: usize) -> usize {
+        self.labels[idx]
+    }
+
+    /// Average every channel of the pixels of each super-pixel. If only the pixels marked in `observed` were observed, only those are averaged, and super-pixels with none of them are unobserved and black.
+    pub fn average<const K: usize, P: Channels<K>>(
+        &self,
+        image: &[P],
+        observed: Option<&[bool]>,
+    ) -> (Box<[P]>, Option<Box<[bool]>>) {
+        let mut sums = vec![([0.; K], 0_usize); self.count];
+
+        for (idx, (value, label)) in image.iter().zip(&self.labels).enumerate() {
+            if observed.is_some_and(|observed| !observed[idx]) {
+                continue;
+            }
+
+            let (sum, count) = &mut sums[*label];
+            for (sum, v) in sum.iter_mut().zip(value.channels()) {
+                *sum += v;
+            }
+            *count += 1;
+        }
+
+        let averages = sums
+            .iter()
+            .map(|&(sum, count)| {
+                if count == 0 {
+                    return P::from_channels([0.; K]);
+                }
+
+                let count = count as f64;
+                P::from_channels(sum.map(|v| v / count))
+            })
+            .collect();
+        let observed = observed.map(|_| sums.iter().map(|(_, count)| *count > 0).collect());
+
+        (averages, observed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SuperPixels;
+
+    #[test]
+    fn blocks() {
+        // Five by three pixels in blocks of two leaves a column and a row of cut short blocks
+        let super_pixels = SuperPixels::blocks(5, 3, 2);
+        assert_eq!(super_pixels.len(), 6);
+        assert_eq!(super_pixels.image_size(), 15);
+        assert_eq!(
+            (0..15)
+                .map(|idx| super_pixels.label(idx))
+                .collect::<Vec<_>>(),
+            [0, 0, 1, 1, 2, 0, 0, 1, 1, 2, 3, 3, 4, 4, 5]
+        );
+
+        assert_eq!(SuperPixels::blocks(4, 4, 1).len(), 16);
+        assert_eq!(SuperPixels::blocks(4, 4, 10).len(), 1);
+    }
+
+    #[test]
+    fn from_labels() {
+        let super_pixels = SuperPixels::from_labels(Box::from([1, 0, 2, 1]));
+        assert_eq!(super_pixels.len(), 3);
+        assert_eq!(super_pixels.label(3), 1);
+    }
+
+    #[test]
+    #[should_panic = "A super-pixel has no pixels"]
+    fn missing_label() {
+        SuperPixels::from_labels(Box::from([0, 2]));
+    }
+
+    #[test]
+    fn average() {
+        let super_pixels = SuperPixels::from_labels(Box::from([0, 1, 0, 1, 2]));
+        let image = [
+            (0.25, 0.5, 0.75),
+            (1., 1., 1.),
+            (0.75, 1., 0.25),
+            (0., 0., 0.),
+            (0.5, 0.5, 0.5),
+        ];
+
+        let (averages, observed) = super_pixels.average(&image, None);
+        assert_eq!(
+            *averages,
+            [(0.5, 0.75, 0.5), (0.5, 0.5, 0.5), (0.5, 0.5, 0.5)]
+        );
+        assert_eq!(observed, None);
+
+        let (averages, observed) =
+            super_pixels.average(&image, Some(&[true, false, true, true, false]));
+        // Only the black pixel of the second super-pixel was observed
+        assert_eq!(averages[1], (0., 0., 0.));
+        assert_eq!(averages[2], (0., 0., 0.));
+        assert_eq!(observed.as_deref(), Some(&[true, true, false][..]));
+    }
+}
@@ -1,11 +1,19 @@
 use crate::{
+    browser_assignment::{BrowserAssignment, CapturedFrame},
+    connection::ConnectionStatus,
+    cube_net::CubeNet,
+    message_log::MessageLog,
     messages_logger::MessagesLogger,
-    server_fns::{TAKE_PICTURE_CHANNEL, TakePictureMessage},
+    server_fns::{
+        CalibrationStatus, TAKE_PICTURE_CHANNEL, TakePictureMessage, calibration_status,
+        puzzle_name, surface, upload_calibration,
+    },
     video::Video,
 };
-use leptos::prelude::*;
+use leptos::{prelude::*, reactive::owner::Owner};
 use leptos_ws::ChannelSignal;
-use log::{LevelFilter, info};
+use log::{LevelFilter, info, warn};
+use qvis::Pixel;
 
 pub fn shell(options: LeptosOptions) -> impl IntoView {
     view! {
@@ -27,9 +35,41 @@ pub fn shell(options: LeptosOptions) -> impl IntoView {
     }
 }
 
+/// Check whether the server is reachable, then keep checking forever at the interval given by the resulting status. If the server comes back after being unreachable, the websocket is reopened since it won't have survived.
+fn watch_connection(
+    status: ReadSignal<ConnectionStatus>,
+    set_status: WriteSignal<ConnectionStatus>,
+    owner: Owner,
+) {
+    leptos::task::spawn_local(async move {
+        let previous = status.get_untracked();
+        let next = match puzzle_name().await {
+            Ok(_) => previous.on_success(),
+            Err(e) => {
+                if previous == ConnectionStatus::Connected {
+                    warn!("Lost the connection to the server: {e}");
+                }
+                previous.on_failure()
+            }
+        };
+
+        if previous.is_reconnect(next) {
+            info!("Reconnected to the server");
+            owner.with(leptos_ws::provide_websocket);
+        }
+        set_status.set(next);
+
+        set_timeout(
+            move || watch_connection(status, set_status, owner),
+            next.next_check_in(),
+        );
+    });
+}
+
 #[component]
 pub fn App() -> impl IntoView {
-    let (messages, set_messages) = signal(Vec::<(u32, String)>::new());
+    let (messages, set_messages) = signal(MessageLog::default());
+    let (shown_level, set_shown_level) = signal(LevelFilter::Debug);
     let logger = Box::leak(Box::new(MessagesLogger::new(set_messages)));
     if log::set_logger(logger).is_ok() {
         log::set_max_level(LevelFilter::Debug);
@@ -37,6 +77,15 @@ pub fn App() -> impl IntoView {
 
     leptos_ws::provide_websocket();
 
+    let (connection_status, set_connection_status) = signal(ConnectionStatus::Connecting);
+    let owner = Owner::current().expect("The app is rendered within a reactive owner");
+    // Effects only run in the browser
+    Effect::new(move |_| watch_connection(connection_status, set_connection_status, owner.clone()));
+
+    let puzzle = Resource::new(|| (), |()| puzzle_name());
+    let calibration = Resource::new(|| (), |()| calibration_status());
+    let puzzle_surface = Resource::new(|| (), |()| surface());
+
     let take_picture_channel = ChannelSignal::new(TAKE_PICTURE_CHANNEL).unwrap();
     let take_picture_channel2 = take_picture_channel.clone();
 
@@ -44,22 +93,42 @@ pub fn App() -> impl IntoView {
     let (overflowing, set_overflowing) = signal(true);
     let (take_picture_command, set_take_picture) = signal(());
 
+    let (recognized_facelets, set_recognized_facelets) = signal(None::<String>);
+    // The frame that the stickers are being assigned on in the browser, if they are
+    let (assignment_frame, set_assignment_frame) = signal(None::<CapturedFrame>);
+    // The last sticker assignment made in the browser, which lets the browser recognize the puzzle by itself
+    let (browser_assignment, set_browser_assignment) = signal(None::<Box<[Pixel]>>);
+
     let take_picture_resp = Callback::new(move |resp| {
+        if let TakePictureMessage::PermutationResult(state) = &resp {
+            let facelets = puzzle
+                .get_untracked()
+                .and_then(Result::ok)
+                .and_then(|name| {
+                    let geometry =
+                        puzzle_theory::puzzle_geometry::parsing::puzzle(&name).into_inner();
+                    qvis::facelet_string(&geometry, state)
+                });
+            set_recognized_facelets.set(facelets);
+        }
         take_picture_channel2.send_message(resp).unwrap();
     });
 
     take_picture_channel
         .on_client(move |msg: &TakePictureMessage| {
             info!("Recieved message {msg:#?}");
-            let TakePictureMessage::TakePicture = msg else {
-                return;
-            };
-            set_take_picture.set(());
+            match msg {
+                TakePictureMessage::TakePicture => set_take_picture.set(()),
+                TakePictureMessage::Failed(reason) => warn!("Taking a picture failed: {reason}"),
+                TakePictureMessage::PermutationResult(_)
+                | TakePictureMessage::NeedsStickerAssignment => (),
+            }
         })
         .unwrap();
 
     Effect::new(move |_| {
-        messages.get();
+        messages.track();
+        shown_level.track();
         let Some(container) = messages_container.get() else {
             return;
         };
@@ -70,12 +139,98 @@ pub fn App() -> impl IntoView {
     });
 
     view! {
-      <header class="mb-5 font-sans text-4xl font-bold tracking-wider text-center bg-[rgb(47,48,80)] leading-20">
+      <header class="relative mb-5 font-sans text-4xl font-bold tracking-wider text-center bg-[rgb(47,48,80)] leading-20">
         "QVIS"
+        <span class="flex absolute top-0 right-4 gap-2 items-center h-full text-sm font-normal tracking-normal">
+          <span
+            class="w-3 h-3 rounded-full"
+            class=("bg-green-500", move || connection_status.get() == ConnectionStatus::Connected)
+            class=("bg-amber-400", move || connection_status.get() != ConnectionStatus::Connected)
+          />
+          {move || connection_status.get().to_string()}
+        </span>
       </header>
       <main class="flex flex-col gap-4 justify-center mr-4 ml-4 text-center">
-        <Video take_picture_resp take_picture_command />
-        "Messages:"
+        <Suspense>
+          {move || {
+            puzzle
+              .get()
+              .map(|puzzle| match puzzle {
+                Ok(name) => format!("Puzzle: {name}"),
+                Err(e) => format!("Failed to fetch the puzzle: {e}"),
+              })
+          }}
+        </Suspense>
+        <Suspense>
+          {move || {
+            calibration
+              .get()
+              .map(|status| match status {
+                Ok(CalibrationStatus::Uncalibrated) => {
+                  "Take a picture to assign the stickers".to_owned()
+                }
+                Ok(CalibrationStatus::AssignedButUncalibrated) => {
+                  "Take a picture of the solved puzzle to calibrate".to_owned()
+                }
+                Ok(CalibrationStatus::Ready) => "Ready to recognize the puzzle".to_owned(),
+                Err(e) => format!("Failed to fetch the calibration status: {e}"),
+              })
+          }}
+        </Suspense>
+        <Video
+          take_picture_resp
+          take_picture_command
+          puzzle_name=Signal::derive(move || puzzle.get().and_then(Result::ok))
+          surface=Signal::derive(move || puzzle_surface.get().and_then(Result::ok))
+          pixel_assignment=browser_assignment
+          on_capture=Callback::new(move |frame| set_assignment_frame.set(Some(frame)))
+          on_calibrated=Callback::new(move |text: String| {
+            leptos::task::spawn_local(async move {
+              match upload_calibration(text).await {
+                Ok(()) => calibration.refetch(),
+                Err(e) => warn!("Failed to save the calibration: {e}"),
+              }
+            });
+          })
+        />
+        {move || {
+          assignment_frame
+            .get()
+            .map(|frame| {
+              let on_done = Callback::new(move |assignment: Option<Box<[Pixel]>>| {
+                if assignment.is_some() {
+                  set_browser_assignment.set(assignment);
+                }
+                set_assignment_frame.set(None);
+                calibration.refetch();
+              });
+              view! { <BrowserAssignment frame on_done /> }
+            })
+        }}
+        <CubeNet facelets=recognized_facelets.into() />
+        <div class="flex gap-4 justify-center items-center">
+          "Messages:"
+          <select
+            class="px-2 bg-black border-2 border-gray-300"
+            prop:value=move || shown_level.get().to_string()
+            on:change=move |ev| {
+              if let Ok(level) = event_target_value(&ev).parse() {
+                set_shown_level.set(level);
+              }
+            }
+          >
+            <option value="ERROR">"Errors"</option>
+            <option value="WARN">"Warnings"</option>
+            <option value="INFO">"Info"</option>
+            <option value="DEBUG">"Debug"</option>
+          </select>
+          <button
+            class="px-2 border-2 border-gray-300"
+            on:click=move |_| set_messages.update(MessageLog::clear)
+          >
+            "Clear"
+          </button>
+        </div>
         <div class="relative h-72 font-mono text-left border-2 border-gray-300">
           <div
             class:hidden=move || !overflowing.get()
@@ -86,8 +241,14 @@ pub fn App() -> impl IntoView {
             class="overflow-y-auto h-full [&::-webkit-scrollbar]:w-3 [&::-webkit-scrollbar-thumb]:bg-white"
           >
             <ul class="pl-4 list-disc list-inside">
-              <For each=move || messages.get() key=|msg| msg.0 let((_, msg))>
-                <li>{msg}</li>
+              <For
+                each=move || {
+                  messages.with(|log| log.visible(shown_level.get()).cloned().collect::<Vec<_>>())
+                }
+                key=|msg| msg.id
+                let(msg)
+              >
+                <li>{msg.text}</li>
               </For>
             </ul>
           </div>
@@ -0,0 +1,163 @@
+//! An overlay for the sticker assignment UI that tints every region that has already been assigned, so that gaps and overlaps between stickers are easy to spot.
+
+#[cfg(feature = "ssr")]
+use opencv::{core::Vec3b, prelude::*};
+use qvis::Pixel;
+use std::collections::BTreeMap;
+
+/// The fractional part of the golden ratio. Stepping around the color wheel by this much keeps consecutive colors far apart no matter how many are needed.
+const GOLDEN_RATIO_CONJUGATE: f64 = 0.618_033_988_749_895;
+
+/// The tint for the sticker with the given index, as a fully saturated BGR color
+pub fn overlay_color(sticker: usize) -> [u8; 3] {
+    #[allow(clippy::cast_precision_loss)]
+    let hue = (sticker as f64 * GOLDEN_RATIO_CONJUGATE).fract();
+
+    // HSV to RGB with full saturation and value, in the order of the blue, green, and red channels
+    [1.0, 3.0, 5.0].map(|n: f64| {
+        let k = (n + hue * 6.0) % 6.0;
+        to_channel(1.0 - k.min(4.0 - k).clamp(0.0, 1.0))
+    })
+}
+
+/// Convert a value in `[0, 1]` to an 8 bit color channel
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn to_channel(value: f64) -> u8 {
+    (value * 255.0).round() as u8
+}
+
+/// The tint for every sticker that has at least one pixel assigned to it
+pub fn overlay_colors(pixel_assignment: &[Pixel]) -> BTreeMap<usize, [u8; 3]> {
+    pixel_assignment
+        .iter()
+        .filter_map(|pixel| match pixel {
+            Pixel::Sticker(sticker) => Some((*sticker, overlay_color(*sticker))),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The pixels out of `selected` that are already assigned to something other than `sticker`, which assigning the selection to `sticker` would steal
+pub fn overlapping_pixels(
+    selected: &[usize],
+    pixel_assignment: &[Pixel],
+    sticker: usize,
+) -> Vec<usize> {
+    selected
+        .iter()
+        .copied()
+        .filter(|&idx| match &pixel_assignment[idx] {
+            Pixel::Unassigned => false,
+            Pixel::Sticker(other) => *other != sticker,
+            Pixel::WhiteBalance(_) => true,
+        })
+        .collect()
+}
+
+/// Unassign every pixel of `sticker`, so that it can be selected again from scratch, and return how many pixels it had
+pub fn clear_sticker(pixel_assignment: &mut [Pixel], sticker: usize) -> usize {
+    let mut cleared = 0;
+    for pixel in pixel_assignment {
+        if matches!(pixel, Pixel::Sticker(v) if *v == sticker) {
+            *pixel = Pixel::Unassigned;
+            cleared += 1;
+        }
+    }
+
+    cleared
+}
+
+/// Blend the tint of each assigned sticker into `img`, which must have one pixel for every entry of `pixel_assignment`
+///
+/// # Errors
+///
+/// This function will return an `OpenCV` error.
+#[cfg(feature = "ssr")]
+pub fn draw_overlay(img: &mut Mat, pixel_assignment: &[Pixel]) -> opencv::Result<()> {
+    let colors = overlay_colors(pixel_assignment);
+    let pixels: &mut [Vec3b] = img.data_typed_mut()?;
+    assert_eq!(pixels.len(), pixel_assignment.len());
+
+    for (pixel, assignment) in pixels.iter_mut().zip(pixel_assignment) {
+        if let Pixel::Sticker(sticker) = assignment {
+            let color = colors[sticker];
+            for (channel, tint) in pixel.iter_mut().zip(color) {
+                *channel = u8::midpoint(*channel, tint);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{clear_sticker, overlapping_pixels, overlay_colors};
+    use qvis::Pixel;
+    use std::collections::HashSet;
+
+    #[test]
+    fn distinct_colors() {
+        let pixel_assignment = (0..54)
+            .flat_map(|sticker| [Pixel::Sticker(sticker), Pixel::Unassigned])
+            .chain([Pixel::WhiteBalance("white".into()), Pixel::Sticker(0)])
+            .collect::<Vec<_>>();
+
+        let colors = overlay_colors(&pixel_assignment);
+        assert_eq!(colors.len(), 54);
+        assert!(colors.keys().copied().eq(0..54));
+        assert_eq!(colors.values().collect::<HashSet<_>>().len(), 54);
+    }
+
+    #[test]
+    fn only_assigned_stickers() {
+        let colors = overlay_colors(&[Pixel::Unassigned, Pixel::Sticker(7), Pixel::Sticker(3)]);
+        assert!(colors.keys().copied().eq([3, 7]));
+        assert_ne!(colors[&3], colors[&7]);
+
+        assert!(overlay_colors(&[Pixel::Unassigned]).is_empty());
+    }
+
+    #[test]
+    fn overlap() {
+        let pixel_assignment = [
+            Pixel::Unassigned,
+            Pixel::Sticker(0),
+            Pixel::Sticker(1),
+            Pixel::WhiteBalance("white".into()),
+            Pixel::Sticker(1),
+            Pixel::Unassigned,
+        ];
+
+        assert_eq!(
+            overlapping_pixels(&[0, 1, 2, 3, 5], &pixel_assignment, 1),
+            [1, 3]
+        );
+        assert_eq!(
+            overlapping_pixels(&[0, 1, 2, 3, 4, 5], &pixel_assignment, 0),
+            [2, 3, 4]
+        );
+        assert!(overlapping_pixels(&[0, 5], &pixel_assignment, 0).is_empty());
+        assert!(overlapping_pixels(&[], &pixel_assignment, 0).is_empty());
+    }
+
+    #[test]
+    fn clear() {
+        let mut pixel_assignment = [
+            Pixel::Sticker(3),
+            Pixel::Unassigned,
+            Pixel::Sticker(4),
+            Pixel::WhiteBalance("white".into()),
+            Pixel::Sticker(3),
+        ];
+
+        assert_eq!(clear_sticker(&mut pixel_assignment, 3), 2);
+        assert!(matches!(pixel_assignment[0], Pixel::Unassigned));
+        assert!(matches!(pixel_assignment[4], Pixel::Unassigned));
+        // Every other sticker and the white balance are left alone
+        assert!(matches!(pixel_assignment[2], Pixel::Sticker(4)));
+        assert!(matches!(pixel_assignment[3], Pixel::WhiteBalance(_)));
+
+        assert_eq!(clear_sticker(&mut pixel_assignment, 3), 0);
+    }
+}
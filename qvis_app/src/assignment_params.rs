@@ -0,0 +1,235 @@
+//! Saving where the trackbars of the sticker assignment UI were left for each puzzle, so that reopening the UI puts them back instead of having them tuned again every session.
+//!
+//! The settings are saved as JSON:
+//!
+//! ```text
+//! {"format":"qvis assignment params 2","erosion_size":4,"upper_diff":2,"preview_samples":20}
+//! ```
+//!
+//! Until anything is saved, the settings start from the defaults for what the faces of the puzzle are made of, which is its `Surface`.
+
+use qvis::InferenceParams;
+use serde::{Deserialize, Serialize};
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+/// Written into the saved settings so that files that aren't the settings, or are the settings of an older format, are rejected
+const FORMAT: &str = "qvis assignment params 2";
+/// How much neighbouring pixels of a sticker may differ in each channel to be proposed as part of the same sticker
+const STICKER_PROPOSAL_TOLERANCE: u8 = 6;
+/// Like `STICKER_PROPOSAL_TOLERANCE`, but for the faces of stickerless puzzles, whose shade varies more across them
+const STICKERLESS_PROPOSAL_TOLERANCE: u8 = 10;
+
+/// The settings of the sticker assignment UI that the user tunes with its trackbars
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AssignmentParams {
+    /// The size of the kernel that the selection is eroded with before it is assigned
+    pub erosion_size: i32,
+    /// How much brighter than the point dragged from a pixel can be and still be selected
+    pub upper_diff: i32,
+    /// How many pixels from the interior of the selection are highlighted
+    pub preview_samples: i32,
+}
+
+/// The settings as they are saved, along with their format
+#[derive(Serialize, Deserialize)]
+struct SavedParams {
+    format: String,
+    #[serde(flatten)]
+    params: AssignmentParams,
+}
+
+/// What the faces of the puzzle are made of, which changes how much a single face's color varies across it. Stickerless puzzles have their colors molded into glossy plastic, which catches sharp highlights and shades unevenly where flat stickers look nearly uniform, so they need looser tolerances to select a whole face and more erosion to leave the highlights at its edges out. The server picks stickerless puzzles when the `QVIS_STICKERLESS` environment variable is set, and tells the browser with `server_fns::surface` so that it recognizes them with the right `inference_params`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Surface {
+    /// Flat stickers on a black or colored body
+    #[default]
+    Stickers,
+    /// Colored plastic with no stickers
+    Stickerless,
+}
+
+impl Surface {
+    /// The settings that the sticker assignment UI starts from when none are saved
+    pub fn default_params(self) -> AssignmentParams {
+        match self {
+            Surface::Stickers => AssignmentParams {
+                erosion_size: 4,
+                upper_diff: 2,
+                preview_samples: 20,
+            },
+            Surface::Stickerless => AssignmentParams {
+                erosion_size: 6,
+                upper_diff: 4,
+                preview_samples: 20,
+            },
+        }
+    }
+
+    /// How much neighbouring pixels may differ in each channel to be proposed as part of the same sticker
+    pub fn proposal_tolerance(self) -> u8 {
+        match self {
+            Surface::Stickers => STICKER_PROPOSAL_TOLERANCE,
+            Surface::Stickerless => STICKERLESS_PROPOSAL_TOLERANCE,
+        }
+    }
+
+    /// How the recognition should score pixels against the calibration samples for puzzles made of this. See `InferenceParams::stickerless`.
+    pub fn inference_params(self) -> InferenceParams {
+        match self {
+            Surface::Stickers => InferenceParams::default(),
+            Surface::Stickerless => InferenceParams::stickerless(),
+        }
+    }
+}
+
+impl AssignmentParams {
+    /// Where the settings for the puzzle named `puzzle_name` are saved. Characters that can't safely be part of a file name are replaced.
+    pub fn path(puzzle_name: &str) -> PathBuf {
+        let name = puzzle_name
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect::<String>();
+
+        PathBuf::from(format!("assignment_params_{name}.json"))
+    }
+
+    /// Write the settings as JSON
+    ///
+    /// # Panics
+    ///
+    /// This method doesn't actually panic, since the settings are only numbers.
+    pub fn to_text(&self) -> String {
+        let saved = SavedParams {
+            format: FORMAT.to_owned(),
+            params: *self,
+        };
+
+        serde_json::to_string(&saved).unwrap()
+    }
+
+    /// Read settings written by `to_text`
+    ///
+    /// # Errors
+    ///
+    /// Returns a description of what is wrong with the text if it isn't the settings.
+    pub fn from_text(text: &str) -> Result<AssignmentParams, String> {
+        let SavedParams { format, params } = serde_json::from_str::<SavedParams>(text)
+            .map_err(|e| format!("Not the assignment settings: {e}"))?;
+
+        if format != FORMAT {
+            return Err(format!("Unknown assignment settings format `{format}`"));
+        }
+
+        Ok(params)
+    }
+
+    /// Write the settings to `path`
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file couldn't be written.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        std::fs::write(path, self.to_text())
+    }
+
+    /// Read the settings saved at `path`, or `None` if there aren't any
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file couldn't be read or isn't the settings.
+    pub fn load(path: &Path) -> io::Result<Option<AssignmentParams>> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        AssignmentParams::from_text(&text)
+            .map(Some)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AssignmentParams, Surface};
+    use qvis::InferenceParams;
+    use std::path::PathBuf;
+
+    #[test]
+    fn round_trip() {
+        let params = AssignmentParams {
+            erosion_size: 7,
+            upper_diff: 3,
+            preview_samples: 0,
+        };
+
+        let text = params.to_text();
+        assert_eq!(AssignmentParams::from_text(&text), Ok(params));
+
+        assert!(AssignmentParams::from_text("").is_err());
+        assert!(AssignmentParams::from_text(&text.replace(":3", r#":"three""#)).is_err());
+        assert!(AssignmentParams::from_text(&text.replace(r#","preview_samples":0"#, "")).is_err());
+        assert!(AssignmentParams::from_text(&text.replace(" 2", " 1")).is_err());
+
+        // Each run of the tests gets its own directory, so that tests run at the same time don't share the file
+        let dir = std::env::temp_dir().join(format!(
+            "qvis_assignment_params_test_{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("params.json");
+        assert_eq!(AssignmentParams::load(&path).unwrap(), None);
+        params.save(&path).unwrap();
+        assert_eq!(AssignmentParams::load(&path).unwrap(), Some(params));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn surfaces() {
+        let stickers = Surface::default();
+        assert_eq!(stickers, Surface::Stickers);
+        assert_eq!(stickers.proposal_tolerance(), 6);
+        assert_eq!(stickers.inference_params(), InferenceParams::default());
+
+        // Stickerless faces are selected more loosely and eroded more
+        let stickerless = Surface::Stickerless;
+        assert_eq!(
+            stickerless.default_params(),
+            AssignmentParams {
+                erosion_size: 6,
+                upper_diff: 4,
+                preview_samples: 20,
+            }
+        );
+        assert!(stickerless.default_params().upper_diff > stickers.default_params().upper_diff);
+        assert!(stickerless.default_params().erosion_size > stickers.default_params().erosion_size);
+        assert_eq!(stickerless.proposal_tolerance(), 10);
+        assert_eq!(
+            stickerless.inference_params(),
+            InferenceParams::stickerless()
+        );
+    }
+
+    #[test]
+    fn path() {
+        assert_eq!(
+            AssignmentParams::path("3x3"),
+            PathBuf::from("assignment_params_3x3.json")
+        );
+        // Not somewhere else entirely
+        assert_eq!(
+            AssignmentParams::path("../megaminx"),
+            PathBuf::from("assignment_params____megaminx.json")
+        );
+    }
+}
@@ -0,0 +1,201 @@
+//! The protocol between the server and the sticker assignment UI. `OpenCV`'s highgui only works on the main thread, so the server sends requests to it over a channel and the main thread serves them one at a time.
+
+use qvis::Pixel;
+use std::{
+    panic::{self, AssertUnwindSafe},
+    sync::mpsc::Receiver,
+};
+use tokio::sync::oneshot::{self, error::TryRecvError};
+
+/// A request for the user to assign stickers, as received by the main thread
+#[derive(Debug)]
+pub struct AssignmentRequest {
+    done_tx: oneshot::Sender<Result<Box<[Pixel]>, String>>,
+    cancel_rx: oneshot::Receiver<()>,
+}
+
+/// The server's side of an `AssignmentRequest`. Dropping it cancels the request.
+#[derive(Debug)]
+pub struct PendingAssignment {
+    done_rx: oneshot::Receiver<Result<Box<[Pixel]>, String>>,
+    cancel_tx: oneshot::Sender<()>,
+}
+
+impl AssignmentRequest {
+    /// Create a request to send to the main thread, along with the handle for waiting on it
+    pub fn new() -> (AssignmentRequest, PendingAssignment) {
+        let (done_tx, done_rx) = oneshot::channel();
+        let (cancel_tx, cancel_rx) = oneshot::channel();
+
+        (
+            AssignmentRequest { done_tx, cancel_rx },
+            PendingAssignment { done_rx, cancel_tx },
+        )
+    }
+
+    /// Whether the server cancelled the request or stopped waiting for it
+    pub fn is_cancelled(&mut self) -> bool {
+        !matches!(self.cancel_rx.try_recv(), Err(TryRecvError::Empty))
+    }
+
+    fn respond(self, result: Result<Box<[Pixel]>, String>) {
+        // If the server stopped waiting, there's nobody to tell
+        let _ = self.done_tx.send(result);
+    }
+}
+
+impl PendingAssignment {
+    /// Ask the UI to stop working on the request
+    pub fn cancel(self) {
+        let _ = self.cancel_tx.send(());
+    }
+
+    /// Wait for the user to finish assigning stickers
+    ///
+    /// # Errors
+    ///
+    /// Returns a description of what went wrong if the UI failed or stopped serving requests.
+    pub async fn finished(self) -> Result<Box<[Pixel]>, String> {
+        self.done_rx
+            .await
+            .map_err(|_| "The sticker assignment UI stopped without finishing".to_owned())?
+    }
+}
+
+/// Serve assignment requests one at a time until every sender is gone. `run_ui` runs the UI for a single request and is given a function that tells whether the request was cancelled; it returns `Ok(None)` if it stopped because of that. Errors and panics in the UI are reported back to the server for that request only, so that later requests are still served.
+pub fn serve<E: std::fmt::Display>(
+    requests: &Receiver<AssignmentRequest>,
+    mut run_ui: impl FnMut(&mut dyn FnMut() -> bool) -> Result<Option<Box<[Pixel]>>, E>,
+) {
+    while let Ok(mut request) = requests.recv() {
+        if request.is_cancelled() {
+            continue;
+        }
+
+        let result =
+            panic::catch_unwind(AssertUnwindSafe(|| run_ui(&mut || request.is_cancelled())));
+
+        match result {
+            Ok(Ok(Some(assignment))) => request.respond(Ok(assignment)),
+            Ok(Ok(None)) => {}
+            Ok(Err(e)) => request.respond(Err(format!("The sticker assignment UI failed: {e}"))),
+            Err(_) => request.respond(Err("The sticker assignment UI panicked".to_owned())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AssignmentRequest, serve};
+    use qvis::Pixel;
+    use std::{sync::mpsc, thread};
+
+    fn assignment(sticker: usize) -> Box<[Pixel]> {
+        Box::new([Pixel::Sticker(sticker)])
+    }
+
+    fn block_on<T>(future: impl Future<Output = T>) -> T {
+        tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    fn sticker(result: Result<Box<[Pixel]>, String>) -> Result<usize, String> {
+        result.map(|assignment| match assignment[0] {
+            Pixel::Sticker(sticker) => sticker,
+            _ => panic!("Expected a sticker"),
+        })
+    }
+
+    #[test]
+    fn finished_failed_and_panicked() {
+        let (tx, rx) = mpsc::channel();
+        let pending = (0..4)
+            .map(|_| {
+                let (request, pending) = AssignmentRequest::new();
+                tx.send(request).unwrap();
+                pending
+            })
+            .collect::<Vec<_>>();
+        drop(tx);
+
+        let mut runs = 0;
+        serve(&rx, |_| {
+            runs += 1;
+            match runs {
+                1 => Ok(Some(assignment(1))),
+                2 => Err("no window"),
+                3 => panic!("oops"),
+                _ => Ok(Some(assignment(4))),
+            }
+        });
+        assert_eq!(runs, 4);
+
+        let results = pending
+            .into_iter()
+            .map(|pending| sticker(block_on(pending.finished())))
+            .collect::<Vec<_>>();
+        assert_eq!(results[0], Ok(1));
+        assert_eq!(
+            results[1],
+            Err("The sticker assignment UI failed: no window".to_owned())
+        );
+        assert_eq!(
+            results[2],
+            Err("The sticker assignment UI panicked".to_owned())
+        );
+        // The UI kept serving requests after the failures
+        assert_eq!(results[3], Ok(4));
+    }
+
+    #[test]
+    fn cancelled_before_starting() {
+        let (tx, rx) = mpsc::channel();
+
+        let (request, pending) = AssignmentRequest::new();
+        tx.send(request).unwrap();
+        pending.cancel();
+
+        // Dropping the handle cancels too
+        let (request, pending) = AssignmentRequest::new();
+        tx.send(request).unwrap();
+        drop(pending);
+
+        let (request, pending) = AssignmentRequest::new();
+        tx.send(request).unwrap();
+        drop(tx);
+
+        let mut runs = 0;
+        serve(&rx, |_| {
+            runs += 1;
+            Ok::<_, String>(Some(assignment(2)))
+        });
+
+        assert_eq!(runs, 1);
+        assert_eq!(sticker(block_on(pending.finished())), Ok(2));
+    }
+
+    #[test]
+    fn cancelled_while_running() {
+        let (tx, rx) = mpsc::channel();
+        let (request, pending) = AssignmentRequest::new();
+        tx.send(request).unwrap();
+        drop(tx);
+
+        let (started_tx, started_rx) = mpsc::channel();
+        let ui = thread::spawn(move || {
+            serve(&rx, |is_cancelled| {
+                started_tx.send(()).unwrap();
+                while !is_cancelled() {
+                    thread::yield_now();
+                }
+                Ok::<_, String>(None)
+            });
+        });
+
+        started_rx.recv().unwrap();
+        pending.cancel();
+        ui.join().unwrap();
+    }
+}
@@ -0,0 +1,192 @@
+//! Saving the progress of the sticker assignment UI to disk, so that an `OpenCV` error or a crash partway through assigning a big puzzle doesn't throw away minutes of work.
+//!
+//! A snapshot is JSON, with the sticker that was being assigned, the status of every sticker, and the assignment of every pixel in row-major order in the encoding that `qvis::Pixel` has with its `serde` feature.
+
+use crate::sticker_navigation::StickerStatus;
+use qvis::Pixel;
+use serde::{Deserialize, Serialize};
+use std::{
+    io,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+/// Written into every snapshot so that files that aren't snapshots, or are snapshots of an older format, are rejected
+const FORMAT: &str = "qvis assignment snapshot 2";
+
+/// How often the assignment UI saves its progress, and where
+#[derive(Debug, Clone)]
+pub struct Autosave {
+    pub path: PathBuf,
+    pub interval: Duration,
+}
+
+/// The progress of the sticker assignment UI
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AssignmentSnapshot {
+    /// The sticker that was being assigned
+    pub current_sticker: usize,
+    /// The status of every sticker
+    pub statuses: Vec<StickerStatus>,
+    pub pixel_assignment: Box<[Pixel]>,
+}
+
+/// A snapshot as it is saved, along with its format
+#[derive(Serialize, Deserialize)]
+struct SavedSnapshot<S> {
+    format: String,
+    #[serde(flatten)]
+    snapshot: S,
+}
+
+impl AssignmentSnapshot {
+    /// Write the snapshot as JSON
+    ///
+    /// # Panics
+    ///
+    /// This method doesn't actually panic, since everything in a snapshot can be written as JSON.
+    pub fn to_text(&self) -> String {
+        let saved = SavedSnapshot {
+            format: FORMAT.to_owned(),
+            snapshot: self,
+        };
+
+        serde_json::to_string(&saved).unwrap()
+    }
+
+    /// Read a snapshot written by `to_text`
+    ///
+    /// # Errors
+    ///
+    /// Returns a description of what is wrong with the text if it isn't a snapshot.
+    pub fn from_text(text: &str) -> Result<AssignmentSnapshot, String> {
+        let SavedSnapshot { format, snapshot } =
+            serde_json::from_str::<SavedSnapshot<AssignmentSnapshot>>(text)
+                .map_err(|e| format!("Not an assignment snapshot: {e}"))?;
+
+        if format != FORMAT {
+            return Err(format!("Unknown assignment snapshot format `{format}`"));
+        }
+        if snapshot.current_sticker >= snapshot.statuses.len() {
+            return Err(format!(
+                "The current sticker {} is out of range",
+                snapshot.current_sticker
+            ));
+        }
+
+        Ok(snapshot)
+    }
+
+    /// Write the snapshot to `path`. It is written to a temporary file next to it first, so that crashing while saving leaves the previous snapshot intact.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file couldn't be written.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, self.to_text())?;
+        std::fs::rename(tmp, path)
+    }
+
+    /// Read the snapshot saved at `path`, or `None` if there isn't one
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file couldn't be read or isn't a snapshot.
+    pub fn load(path: &Path) -> io::Result<Option<AssignmentSnapshot>> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        AssignmentSnapshot::from_text(&text)
+            .map(Some)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AssignmentSnapshot;
+    use crate::sticker_navigation::StickerStatus;
+    use internment::ArcIntern;
+    use qvis::Pixel;
+
+    /// `Pixel` isn't `PartialEq`
+    fn same_pixel(a: &Pixel, b: &Pixel) -> bool {
+        match (a, b) {
+            (Pixel::Unassigned, Pixel::Unassigned) => true,
+            (Pixel::Sticker(a), Pixel::Sticker(b)) => a == b,
+            (Pixel::WhiteBalance(a), Pixel::WhiteBalance(b)) => a == b,
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let white = ArcIntern::<str>::from("white");
+        let pixel_assignment = [
+            vec![Pixel::Unassigned; 7],
+            vec![Pixel::Sticker(0); 3],
+            vec![Pixel::Sticker(2); 4],
+            vec![Pixel::Unassigned; 2],
+            vec![Pixel::WhiteBalance(white); 5],
+            vec![Pixel::Sticker(0)],
+        ]
+        .concat();
+
+        // Partway through, with a sticker skipped
+        let snapshot = AssignmentSnapshot {
+            current_sticker: 3,
+            statuses: vec![
+                StickerStatus::Assigned,
+                StickerStatus::Skipped,
+                StickerStatus::Assigned,
+                StickerStatus::Pending,
+                StickerStatus::Pending,
+            ],
+            pixel_assignment: pixel_assignment.into_boxed_slice(),
+        };
+
+        let text = snapshot.to_text();
+        let loaded = AssignmentSnapshot::from_text(&text).unwrap();
+        assert_eq!(loaded.current_sticker, 3);
+        assert_eq!(loaded.statuses, snapshot.statuses);
+        assert_eq!(
+            loaded.pixel_assignment.len(),
+            snapshot.pixel_assignment.len()
+        );
+        assert!(
+            loaded
+                .pixel_assignment
+                .iter()
+                .zip(&snapshot.pixel_assignment)
+                .all(|(a, b)| same_pixel(a, b))
+        );
+
+        // Each run of the tests gets its own directory, so that tests run at the same time don't share the file
+        let dir = std::env::temp_dir().join(format!(
+            "qvis_assignment_snapshot_test_{}",
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("snapshot.json");
+        snapshot.save(&path).unwrap();
+        let loaded = AssignmentSnapshot::load(&path).unwrap().unwrap();
+        assert_eq!(loaded.to_text(), text);
+        std::fs::remove_file(&path).unwrap();
+        assert!(AssignmentSnapshot::load(&path).unwrap().is_none());
+        std::fs::remove_dir(&dir).unwrap();
+
+        for invalid in [
+            "",
+            r#"{"format":"qvis assignment snapshot 2","current_sticker":5,"statuses":["Assigned","Pending"],"pixel_assignment":[]}"#,
+            r#"{"format":"qvis assignment snapshot 2","current_sticker":0,"statuses":["Assigned","Lost"],"pixel_assignment":[]}"#,
+            r#"{"format":"qvis assignment snapshot 2","current_sticker":0,"statuses":["Assigned","Pending"],"pixel_assignment":[{"Sticker":"three"}]}"#,
+            r#"{"format":"qvis assignment snapshot 1","current_sticker":0,"statuses":["Assigned","Pending"],"pixel_assignment":[]}"#,
+        ] {
+            assert!(AssignmentSnapshot::from_text(invalid).is_err(), "{invalid}");
+        }
+    }
+}
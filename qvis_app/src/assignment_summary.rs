@@ -0,0 +1,76 @@
+//! Pixel counts for a finished sticker assignment, used to point out stickers that were barely covered.
+
+use qvis::Pixel;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AssignmentSummary {
+    /// How many pixels are assigned to each sticker
+    pub sticker_pixels: Vec<usize>,
+    pub white_balance_pixels: usize,
+    pub unassigned_pixels: usize,
+}
+
+impl AssignmentSummary {
+    /// Count the pixels assigned to each of `num_stickers` stickers
+    ///
+    /// # Panics
+    ///
+    /// This method panics if a pixel is assigned to a sticker that doesn't exist.
+    pub fn new(pixel_assignment: &[Pixel], num_stickers: usize) -> AssignmentSummary {
+        let mut summary = AssignmentSummary {
+            sticker_pixels: vec![0; num_stickers],
+            white_balance_pixels: 0,
+            unassigned_pixels: 0,
+        };
+
+        for pixel in pixel_assignment {
+            match pixel {
+                Pixel::Unassigned => summary.unassigned_pixels += 1,
+                Pixel::WhiteBalance(_) => summary.white_balance_pixels += 1,
+                Pixel::Sticker(sticker) => summary.sticker_pixels[*sticker] += 1,
+            }
+        }
+
+        summary
+    }
+
+    /// The stickers with fewer than `min_pixels` pixels assigned to them
+    pub fn sparse_stickers(&self, min_pixels: usize) -> Vec<usize> {
+        (0..self.sticker_pixels.len())
+            .filter(|&sticker| self.sticker_pixels[sticker] < min_pixels)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AssignmentSummary;
+    use qvis::Pixel;
+
+    #[test]
+    fn summary() {
+        let pixel_assignment = [
+            Pixel::Sticker(0),
+            Pixel::Unassigned,
+            Pixel::Sticker(2),
+            Pixel::Sticker(0),
+            Pixel::WhiteBalance("white".into()),
+            Pixel::Unassigned,
+            Pixel::Sticker(0),
+        ];
+
+        let summary = AssignmentSummary::new(&pixel_assignment, 4);
+        assert_eq!(
+            summary,
+            AssignmentSummary {
+                sticker_pixels: vec![3, 0, 1, 0],
+                white_balance_pixels: 1,
+                unassigned_pixels: 2,
+            }
+        );
+
+        assert_eq!(summary.sparse_stickers(1), [1, 3]);
+        assert_eq!(summary.sparse_stickers(2), [1, 2, 3]);
+        assert!(summary.sparse_stickers(0).is_empty());
+    }
+}
@@ -0,0 +1,104 @@
+//! Sending a sticker assignment made in the browser to the server, in the encoding that `qvis::Pixel` has with its `serde` feature.
+
+use puzzle_theory::puzzle_geometry::PuzzleGeometry;
+use qvis::Pixel;
+use serde::{Deserialize, Serialize};
+
+/// A sticker assignment as it is sent to the server
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AssignmentUpload {
+    /// The width of the image that was assigned, in pixels
+    pub width: usize,
+    /// The height of the image that was assigned, in pixels
+    pub height: usize,
+    /// The assignment of every pixel in row-major order
+    pub pixels: Box<[Pixel]>,
+}
+
+impl AssignmentUpload {
+    /// Encode the assignment of a `width` by `height` image
+    ///
+    /// # Panics
+    ///
+    /// This function panics if the assignment doesn't have a pixel for every pixel of the image.
+    pub fn new(width: usize, height: usize, pixel_assignment: &[Pixel]) -> AssignmentUpload {
+        assert_eq!(pixel_assignment.len(), width * height);
+
+        AssignmentUpload {
+            width,
+            height,
+            pixels: pixel_assignment.into(),
+        }
+    }
+
+    /// Decode the assignment, checking that it has an entry for every pixel of the `width` by `height` image and only assigns pixels to stickers and faces that `puzzle` has. The server never sees the image, so nothing checks that it is the size of the frames that are recognized until the browser makes a `qvis::CVProcessor` with the assignment, which rejects frames of any other size.
+    ///
+    /// # Errors
+    ///
+    /// Returns a description of what is wrong with the assignment if it isn't valid.
+    pub fn into_pixels(self, puzzle: &PuzzleGeometry) -> Result<Box<[Pixel]>, String> {
+        let pixel_count = self
+            .width
+            .checked_mul(self.height)
+            .ok_or("The image has too many pixels")?;
+        qvis::check_assignment(&self.pixels, pixel_count, puzzle).map_err(|e| {
+            format!(
+                "{e}, which doesn't fit the {}x{} image",
+                self.width, self.height
+            )
+        })?;
+
+        Ok(self.pixels)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AssignmentUpload;
+    use internment::ArcIntern;
+    use puzzle_theory::puzzle_geometry::parsing::puzzle;
+    use qvis::Pixel;
+
+    #[test]
+    fn serialization() {
+        let geometry = puzzle("3x3").into_inner();
+
+        let white = ArcIntern::<str>::from("white");
+        let pixel_assignment = [
+            vec![Pixel::Unassigned; 5],
+            vec![Pixel::Sticker(1); 4],
+            vec![Pixel::WhiteBalance(white); 3],
+            vec![Pixel::Sticker(0); 2],
+            vec![Pixel::Unassigned; 6],
+        ]
+        .concat();
+
+        let upload = AssignmentUpload::new(5, 4, &pixel_assignment);
+
+        // As the server function sends it
+        let json = serde_json::to_string(&upload).unwrap();
+        let received = serde_json::from_str::<AssignmentUpload>(&json).unwrap();
+
+        let decoded = received.into_pixels(&geometry).unwrap();
+        assert_eq!(decoded.len(), pixel_assignment.len());
+        for (a, b) in decoded.iter().zip(&pixel_assignment) {
+            assert_eq!(format!("{a:?}"), format!("{b:?}"));
+        }
+
+        // A sticker or face that the puzzle doesn't have
+        let mut beyond = upload.clone();
+        beyond.pixels[0] = Pixel::Sticker(48);
+        assert!(beyond.into_pixels(&geometry).is_err());
+        let mut pink = upload.clone();
+        pink.pixels[0] = Pixel::WhiteBalance(ArcIntern::from("pink"));
+        assert!(pink.into_pixels(&geometry).is_err());
+
+        // Pixels that don't cover the image
+        let mut taller = upload.clone();
+        taller.height += 1;
+        assert!(taller.into_pixels(&geometry).is_err());
+        let mut overflowing = upload;
+        overflowing.width = usize::MAX;
+        assert!(overflowing.into_pixels(&geometry).is_err());
+    }
+}
@@ -0,0 +1,320 @@
+//! Assigning the stickers in the browser on a frame captured from the camera, so that the puzzle can be set up from a phone without anyone at the server's screen. It works like the `OpenCV` UI: drag from a point on the sticker that is asked for to flood fill outwards from it, with longer drags filling more loosely, then assign the selection and move on to the next sticker.
+
+use crate::{
+    assignment_overlay::{overlapping_pixels, overlay_color},
+    assignment_upload::AssignmentUpload,
+    flood_fill_selector::{flood_fill_region, flood_fill_tolerances},
+    server_fns::{assignment_plan, upload_assignment},
+    sticker_navigation::{StickerNavigation, StickerStatus},
+};
+use leptos::{ev::PointerEvent, html, prelude::*};
+use log::{info, warn};
+use qvis::Pixel;
+use wasm_bindgen::{Clamped, JsCast};
+use web_sys::{CanvasRenderingContext2d, ImageData};
+
+/// A frame captured from the camera
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CapturedFrame {
+    pub width: u32,
+    pub height: u32,
+    /// The color of every pixel in row-major order
+    pub pixels: Vec<[u8; 3]>,
+}
+
+impl CapturedFrame {
+    /// Drop the alpha channel of image data read from a canvas
+    pub fn from_rgba8(width: u32, height: u32, rgba: &[u8]) -> CapturedFrame {
+        CapturedFrame {
+            width,
+            height,
+            pixels: rgba
+                .chunks_exact(4)
+                .map(|pixel| [pixel[0], pixel[1], pixel[2]])
+                .collect(),
+        }
+    }
+}
+
+/// Assign `selected` to `sticker`, replacing whatever was assigned to it before. Pixels that are already assigned to something else are left be, like in the `OpenCV` UI.
+fn assign_selection(pixel_assignment: &mut [Pixel], selected: &[usize], sticker: usize) {
+    for pixel in pixel_assignment.iter_mut() {
+        if matches!(pixel, Pixel::Sticker(other) if *other == sticker) {
+            *pixel = Pixel::Unassigned;
+        }
+    }
+
+    let overlapping = overlapping_pixels(selected, pixel_assignment, sticker);
+    if !overlapping.is_empty() {
+        warn!(
+            "{} pixels of sticker {sticker} were already assigned; left them be",
+            overlapping.len()
+        );
+    }
+
+    // Both lists are sorted
+    let mut overlapping = overlapping.iter().peekable();
+    for &idx in selected {
+        if overlapping.next_if_eq(&&idx).is_none() {
+            pixel_assignment[idx] = Pixel::Sticker(sticker);
+        }
+    }
+}
+
+/// The frame as RGBA image data, with every assigned sticker tinted with its color from the `OpenCV` UI's overlay and the selection lightened
+fn tinted_frame(frame: &CapturedFrame, pixel_assignment: &[Pixel], selection: &[usize]) -> Vec<u8> {
+    let blend = |a: [u8; 3], b: [u8; 3]| [0, 1, 2].map(|c| a[c] / 2 + b[c] / 2);
+
+    let mut rgba = frame
+        .pixels
+        .iter()
+        .zip(pixel_assignment)
+        .flat_map(|(color, assignment)| {
+            let [r, g, b] = match assignment {
+                Pixel::Sticker(sticker) => {
+                    let [b, g, r] = overlay_color(*sticker);
+                    blend(*color, [r, g, b])
+                }
+                Pixel::Unassigned | Pixel::WhiteBalance(_) => *color,
+            };
+            [r, g, b, u8::MAX]
+        })
+        .collect::<Vec<_>>();
+
+    for &idx in selection {
+        let [r, g, b] = blend(frame.pixels[idx], [u8::MAX; 3]);
+        rgba[idx * 4..idx * 4 + 3].copy_from_slice(&[r, g, b]);
+    }
+
+    rgba
+}
+
+/// The position on the canvas under the pointer, in pixels of the frame rather than of the page
+#[allow(clippy::cast_possible_truncation)]
+fn frame_position(canvas: &web_sys::HtmlCanvasElement, ev: &PointerEvent) -> Option<(i32, i32)> {
+    let client_width = canvas.client_width();
+    if client_width == 0 {
+        return None;
+    }
+
+    let scale = f64::from(canvas.width()) / f64::from(client_width);
+    Some((
+        (f64::from(ev.offset_x()) * scale) as i32,
+        (f64::from(ev.offset_y()) * scale) as i32,
+    ))
+}
+
+#[component]
+pub fn BrowserAssignment(
+    frame: CapturedFrame,
+    /// Called with the assignment once it was uploaded, or with `None` if the user gave up on it
+    on_done: Callback<Option<Box<[Pixel]>>>,
+) -> impl IntoView {
+    let canvas_ref = NodeRef::<html::Canvas>::new();
+    let plan = Resource::new(|| (), |()| assignment_plan());
+
+    let (width, height) = (frame.width, frame.height);
+    let frame = StoredValue::new(frame);
+    let (pixel_assignment, set_pixel_assignment) = signal(
+        vec![Pixel::Unassigned; frame.with_value(|frame| frame.pixels.len())].into_boxed_slice(),
+    );
+    let navigation = RwSignal::new(None::<StickerNavigation>);
+    let selection = RwSignal::new(Vec::<usize>::new());
+    let drag_origin = RwSignal::new(None::<(i32, i32)>);
+    let (uploading, set_uploading) = signal(false);
+
+    Effect::new(move |_| {
+        if let Some(Ok(plan)) = plan.get()
+            && !plan.is_empty()
+            && navigation.with_untracked(Option::is_none)
+        {
+            navigation.set(Some(StickerNavigation::new(plan.len())));
+        }
+    });
+
+    Effect::new(move |_| {
+        let Some(canvas) = canvas_ref.get() else {
+            return;
+        };
+        let rgba = pixel_assignment.with(|pixel_assignment| {
+            selection.with(|selection| {
+                frame.with_value(|frame| tinted_frame(frame, pixel_assignment, selection))
+            })
+        });
+
+        let drawn = canvas
+            .get_context("2d")
+            .ok()
+            .flatten()
+            .and_then(|ctx| ctx.dyn_into::<CanvasRenderingContext2d>().ok())
+            .zip(
+                ImageData::new_with_u8_clamped_array_and_sh(
+                    Clamped(rgba.as_slice()),
+                    width,
+                    height,
+                )
+                .ok(),
+            )
+            .is_some_and(|(ctx, image)| ctx.put_image_data(&image, 0.0, 0.0).is_ok());
+        if !drawn {
+            warn!("Failed to draw the frame to assign stickers on");
+        }
+    });
+
+    let select = move |origin: (i32, i32), drag: (i32, i32)| {
+        let (Ok(x), Ok(y)) = (usize::try_from(origin.0), usize::try_from(origin.1)) else {
+            return;
+        };
+        let tolerances = flood_fill_tolerances(origin, drag, 0, 1);
+        selection.set(frame.with_value(|frame| {
+            flood_fill_region(&frame.pixels, width as usize, (x, y), tolerances)
+        }));
+    };
+
+    let pointer_down = move |ev: PointerEvent| {
+        let Some(canvas) = canvas_ref.get_untracked() else {
+            return;
+        };
+        // Keep getting the drag when it leaves the canvas
+        let _ = canvas.set_pointer_capture(ev.pointer_id());
+        if let Some(origin) = frame_position(&canvas, &ev) {
+            drag_origin.set(Some(origin));
+            select(origin, origin);
+        }
+    };
+
+    let pointer_move = move |ev: PointerEvent| {
+        let (Some(origin), Some(canvas)) =
+            (drag_origin.get_untracked(), canvas_ref.get_untracked())
+        else {
+            return;
+        };
+        if let Some(drag) = frame_position(&canvas, &ev) {
+            select(origin, drag);
+        }
+    };
+
+    let assign = move |_| {
+        let Some(sticker) =
+            navigation.with_untracked(|n| n.as_ref().map(StickerNavigation::current))
+        else {
+            return;
+        };
+        let selected = selection.get_untracked();
+        if selected.is_empty() {
+            return;
+        }
+
+        set_pixel_assignment.update(|pixel_assignment| {
+            assign_selection(pixel_assignment, &selected, sticker);
+        });
+        info!("Assigned {} pixels to sticker {sticker}", selected.len());
+        navigation.update(|n| {
+            if let Some(n) = n {
+                n.assign();
+            }
+        });
+        selection.set(Vec::new());
+    };
+
+    let skip = move |_| {
+        navigation.update(|n| {
+            if let Some(n) = n {
+                n.skip();
+            }
+        });
+        selection.set(Vec::new());
+    };
+
+    let previous = move |_| {
+        navigation.update(|n| {
+            if let Some(n) = n {
+                n.previous();
+            }
+        });
+        selection.set(Vec::new());
+    };
+
+    let upload = move |_| {
+        let upload = pixel_assignment.with_untracked(|pixel_assignment| {
+            AssignmentUpload::new(width as usize, height as usize, pixel_assignment)
+        });
+        set_uploading.set(true);
+        leptos::task::spawn_local(async move {
+            match upload_assignment(upload).await {
+                Ok(()) => {
+                    info!("Uploaded the sticker assignment");
+                    on_done.run(Some(pixel_assignment.get_untracked()));
+                }
+                Err(e) => warn!("Failed to upload the sticker assignment: {e}"),
+            }
+            set_uploading.set(false);
+        });
+    };
+
+    let prompt = move || {
+        let plan = plan.get()?.ok()?;
+        navigation.with(|n| {
+            let n = n.as_ref()?;
+            let sticker = plan.get(n.current())?;
+            Some(if n.is_finished() {
+                "Every sticker is assigned or skipped; upload the assignment or keep redoing stickers"
+                    .to_owned()
+            } else {
+                format!(
+                    "Choose {} on {}{}",
+                    sticker.labels.concat(),
+                    sticker.color,
+                    match n.status(n.current()) {
+                        StickerStatus::Pending => "",
+                        StickerStatus::Assigned => " (redoing)",
+                        StickerStatus::Skipped => " (skipped)",
+                    }
+                )
+            })
+        })
+    };
+
+    view! {
+      <div class="flex flex-col gap-2 items-center">
+        <Suspense>{prompt}</Suspense>
+        <canvas
+          node_ref=canvas_ref
+          width=width.to_string()
+          height=height.to_string()
+          on:pointerdown=pointer_down
+          on:pointermove=pointer_move
+          on:pointerup=move |_| drag_origin.set(None)
+          on:pointercancel=move |_| drag_origin.set(None)
+          class="w-full border-2 border-amber-300 max-w-[800px] touch-none"
+        />
+        <div class="flex gap-4 justify-center">
+          <button class="px-2 border-2 border-gray-300" on:click=previous>
+            "Back"
+          </button>
+          <button
+            class="px-2 border-2 border-gray-300 disabled:opacity-50"
+            disabled=move || selection.with(Vec::is_empty)
+            on:click=assign
+          >
+            "Assign"
+          </button>
+          <button class="px-2 border-2 border-gray-300" on:click=skip>
+            "Skip"
+          </button>
+          <button
+            class="px-2 border-2 border-gray-300 disabled:opacity-50"
+            disabled=move || {
+              uploading.get() || !navigation.with(|n| n.as_ref().is_some_and(StickerNavigation::is_finished))
+            }
+            on:click=upload
+          >
+            "Upload"
+          </button>
+          <button class="px-2 border-2 border-gray-300" on:click=move |_| on_done.run(None)>
+            "Cancel"
+          </button>
+        </div>
+      </div>
+    }
+}
@@ -0,0 +1,125 @@
+//! Recognizing the puzzle in the browser, so that taking a picture doesn't send the frame to the server at all. The browser only has the sticker assignment that it made itself, so it calibrates with the first picture taken after assigning, which is of the solved puzzle like the calibration status asks for.
+
+use crate::assignment_params::Surface;
+use puzzle_theory::{permutations::Permutation, puzzle_geometry::parsing::puzzle};
+use qvis::{CVProcessor, MaskedImage, Pixel, QvisError};
+
+/// Recognize the puzzle named `puzzle_name` in RGBA image data read from a canvas. If there is no processor in `cv` yet, one is made from `pixel_assignment` with the inference parameters for the puzzle's `surface` and calibrated with the picture, which is taken to be of the solved puzzle. Returns `None` if there is neither a processor nor an assignment to make one from.
+pub fn recognize_rgba(
+    cv: &mut Option<CVProcessor>,
+    puzzle_name: &str,
+    surface: Surface,
+    pixel_assignment: Option<&[Pixel]>,
+    rgba: &[u8],
+) -> Option<Result<Permutation, QvisError>> {
+    let image = MaskedImage::from_rgba8(rgba);
+
+    if let Some(cv) = cv {
+        return Some(cv.process_masked_image(&image).map(|(state, _)| state));
+    }
+
+    let pixel_assignment = pixel_assignment?;
+    let mut calibrated = match CVProcessor::new(
+        puzzle(puzzle_name).into_inner(),
+        image.pixels.len(),
+        pixel_assignment.into(),
+    ) {
+        Ok(calibrated) => calibrated,
+        Err(e) => return Some(Err(e)),
+    };
+    if let Err(e) = calibrated
+        .set_inference_params(surface.inference_params())
+        .and_then(|()| calibrated.calibrate_solved(&image.pixels))
+    {
+        return Some(Err(e));
+    }
+    *cv = Some(calibrated);
+
+    Some(Ok(Permutation::from_cycles(Vec::new())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::recognize_rgba;
+    use crate::assignment_params::Surface;
+    use puzzle_theory::{permutations::Permutation, puzzle_geometry::parsing::puzzle};
+    use qvis::{InferenceParams, Pixel};
+
+    #[test]
+    fn recognize() {
+        let palette = [
+            [255, 50, 50],
+            [255, 150, 50],
+            [255, 255, 255],
+            [200, 200, 50],
+            [50, 125, 255],
+            [75, 255, 125],
+        ];
+
+        let geometry = puzzle("3x3").into_inner();
+        let group = geometry.permutation_group();
+        let colors = group.facelet_colors();
+        let faces = colors.iter().fold(Vec::new(), |mut faces, color| {
+            if !faces.contains(&color) {
+                faces.push(color);
+            }
+            faces
+        });
+        // Four pixels per sticker
+        let pixel_assignment = (0..group.facelet_count())
+            .flat_map(|sticker| std::iter::repeat_n(Pixel::Sticker(sticker), 4))
+            .collect::<Vec<_>>();
+        let solved = colors
+            .iter()
+            .flat_map(|color| {
+                let [r, g, b] = palette[faces.iter().position(|face| *face == color).unwrap()];
+                // A bit of variation between the pixels of a sticker, like a camera would have
+                (0..4).flat_map(move |k| [r - 2 * k, g - 2 * k, b - 2 * k, 255])
+            })
+            .collect::<Vec<_>>();
+
+        // Nothing to recognize the colors by
+        let mut cv = None;
+        assert!(recognize_rgba(&mut cv, "3x3", Surface::Stickers, None, &solved).is_none());
+
+        // The first picture calibrates
+        let identity = Permutation::from_cycles(Vec::new());
+        let state = recognize_rgba(
+            &mut cv,
+            "3x3",
+            Surface::Stickers,
+            Some(&pixel_assignment),
+            &solved,
+        );
+        assert_eq!(state.unwrap().unwrap(), identity);
+        assert_eq!(
+            cv.as_ref().unwrap().inference_params(),
+            &InferenceParams::default()
+        );
+
+        let state = recognize_rgba(&mut cv, "3x3", Surface::Stickers, None, &solved);
+        assert_eq!(state.unwrap().unwrap(), identity);
+
+        // A picture of a different size than the assignment
+        assert!(
+            recognize_rgba(&mut cv, "3x3", Surface::Stickers, None, &solved[4..])
+                .unwrap()
+                .is_err()
+        );
+
+        // Stickerless puzzles are recognized with their own parameters
+        let mut stickerless = None;
+        let state = recognize_rgba(
+            &mut stickerless,
+            "3x3",
+            Surface::Stickerless,
+            Some(&pixel_assignment),
+            &solved,
+        );
+        assert_eq!(state.unwrap().unwrap(), identity);
+        assert_eq!(
+            stickerless.unwrap().inference_params(),
+            &InferenceParams::stickerless()
+        );
+    }
+}
@@ -0,0 +1,76 @@
+//! Which camera the video asks the browser for. The back camera is best for pointing at a puzzle, but most laptops only have a front one, so when the browser can't give the camera that was asked for, the video falls back to a less particular one instead of showing nothing.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CameraChoice {
+    /// The camera facing away from the user, like the back camera of a phone
+    Environment,
+    /// The camera facing the user, like a laptop's webcam
+    User,
+    /// Whatever camera the browser picks
+    Any,
+}
+
+impl CameraChoice {
+    /// The camera to try next after this one failed to open with the `DOMException` named `error`, or `None` if there is nothing better to try. Only errors that say the camera doesn't exist or can't be used are worth another try; if the user denied access to the camera, a different one would be denied too.
+    pub fn fallback(self, error: &str) -> Option<CameraChoice> {
+        if !matches!(
+            error,
+            "NotFoundError" | "OverconstrainedError" | "NotReadableError"
+        ) {
+            return None;
+        }
+
+        match self {
+            CameraChoice::Environment => Some(CameraChoice::User),
+            CameraChoice::User => Some(CameraChoice::Any),
+            CameraChoice::Any => None,
+        }
+    }
+
+    /// Why the video isn't showing the back camera, for telling the user, or `None` if it is
+    pub fn notice(self) -> Option<&'static str> {
+        match self {
+            CameraChoice::Environment => None,
+            CameraChoice::User => Some("There is no back camera, so the front camera is used"),
+            CameraChoice::Any => {
+                Some("There is no back or front camera, so another camera is used")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CameraChoice;
+
+    #[test]
+    fn fallback() {
+        let mut choice = CameraChoice::Environment;
+        let mut tried = vec![choice];
+        while let Some(next) = choice.fallback("NotFoundError") {
+            choice = next;
+            tried.push(choice);
+        }
+        assert_eq!(
+            tried,
+            [
+                CameraChoice::Environment,
+                CameraChoice::User,
+                CameraChoice::Any
+            ]
+        );
+
+        assert_eq!(
+            CameraChoice::Environment.fallback("OverconstrainedError"),
+            Some(CameraChoice::User)
+        );
+        assert_eq!(
+            CameraChoice::User.fallback("NotReadableError"),
+            Some(CameraChoice::Any)
+        );
+
+        // Denying one camera denies them all
+        assert_eq!(CameraChoice::Environment.fallback("NotAllowedError"), None);
+        assert_eq!(CameraChoice::Environment.fallback(""), None);
+    }
+}
@@ -0,0 +1,115 @@
+//! Tracking whether the app can reach the server, backing off exponentially between checks while it can't.
+
+use std::{fmt, time::Duration};
+
+/// How often to check on the connection while it is up
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[must_use]
+pub enum ConnectionStatus {
+    /// The connection hasn't been checked yet
+    Connecting,
+    Connected,
+    /// The last `failures` checks failed in a row
+    Disconnected {
+        failures: u32,
+    },
+}
+
+impl ConnectionStatus {
+    /// The status after a check of the connection succeeded
+    pub fn on_success(self) -> ConnectionStatus {
+        ConnectionStatus::Connected
+    }
+
+    /// The status after a check of the connection failed
+    pub fn on_failure(self) -> ConnectionStatus {
+        match self {
+            ConnectionStatus::Disconnected { failures } => ConnectionStatus::Disconnected {
+                failures: failures.saturating_add(1),
+            },
+            ConnectionStatus::Connecting | ConnectionStatus::Connected => {
+                ConnectionStatus::Disconnected { failures: 1 }
+            }
+        }
+    }
+
+    /// Whether going from this status to `next` means that the connection came back after being lost, in which case the websocket has to be reopened
+    pub fn is_reconnect(self, next: ConnectionStatus) -> bool {
+        matches!(self, ConnectionStatus::Disconnected { .. }) && next == ConnectionStatus::Connected
+    }
+
+    /// How long to wait before checking the connection again. This doubles with every failure in a row, up to a limit, so that a server that is down isn't flooded with requests.
+    pub fn next_check_in(self) -> Duration {
+        match self {
+            ConnectionStatus::Connecting | ConnectionStatus::Connected => HEARTBEAT_INTERVAL,
+            ConnectionStatus::Disconnected { failures } => INITIAL_BACKOFF
+                .saturating_mul(2_u32.saturating_pow(failures.saturating_sub(1)))
+                .min(MAX_BACKOFF),
+        }
+    }
+}
+
+impl fmt::Display for ConnectionStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectionStatus::Connecting => write!(f, "Connecting"),
+            ConnectionStatus::Connected => write!(f, "Connected"),
+            ConnectionStatus::Disconnected { .. } => write!(f, "Reconnecting"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ConnectionStatus, HEARTBEAT_INTERVAL, MAX_BACKOFF};
+    use std::time::Duration;
+
+    #[test]
+    fn transitions() {
+        let status = ConnectionStatus::Connecting;
+        assert_eq!(status.next_check_in(), HEARTBEAT_INTERVAL);
+
+        let connected = status.on_success();
+        assert_eq!(connected, ConnectionStatus::Connected);
+        assert!(!status.is_reconnect(connected));
+
+        let lost = connected.on_failure();
+        assert_eq!(lost, ConnectionStatus::Disconnected { failures: 1 });
+        assert!(!connected.is_reconnect(lost));
+
+        let still_lost = lost.on_failure();
+        assert_eq!(still_lost, ConnectionStatus::Disconnected { failures: 2 });
+
+        let back = still_lost.on_success();
+        assert_eq!(back, ConnectionStatus::Connected);
+        assert!(still_lost.is_reconnect(back));
+        assert!(!back.is_reconnect(back.on_success()));
+
+        // Failing to connect in the first place
+        assert_eq!(
+            ConnectionStatus::Connecting.on_failure(),
+            ConnectionStatus::Disconnected { failures: 1 }
+        );
+    }
+
+    #[test]
+    fn backoff() {
+        let mut status = ConnectionStatus::Connected;
+        let mut delays = Vec::new();
+        for _ in 0..8 {
+            status = status.on_failure();
+            delays.push(status.next_check_in().as_secs());
+        }
+        assert_eq!(delays, [1, 2, 4, 8, 16, 30, 30, 30]);
+
+        let status = ConnectionStatus::Disconnected { failures: u32::MAX };
+        assert_eq!(status.next_check_in(), MAX_BACKOFF);
+        assert_eq!(status.on_failure(), status);
+
+        assert_eq!(status.on_success().next_check_in(), Duration::from_secs(5));
+    }
+}
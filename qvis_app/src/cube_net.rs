@@ -0,0 +1,33 @@
+use crate::net_layout::{net_cells, net_size};
+use leptos::prelude::*;
+
+/// The recognized state of a cube drawn as its unfolded net, given in Kociemba's facelet notation. Nothing is drawn if there is no state or the puzzle isn't a cube.
+#[component]
+pub fn CubeNet(facelets: Signal<Option<String>>) -> impl IntoView {
+    move || {
+        let (cells, side) = net_cells(&facelets.get()?)?;
+        let (columns, rows) = net_size(side);
+
+        Some(view! {
+          <div
+            class="grid gap-0.5 self-center w-full max-w-[400px]"
+            style:grid-template-columns=format!("repeat({columns}, minmax(0, 1fr))")
+            style:grid-template-rows=format!("repeat({rows}, auto)")
+          >
+            {cells
+              .into_iter()
+              .map(|cell| {
+                view! {
+                  <div
+                    class="border border-black aspect-square"
+                    style:grid-column=(cell.column + 1).to_string()
+                    style:grid-row=(cell.row + 1).to_string()
+                    style:background-color=cell.color
+                  />
+                }
+              })
+              .collect_view()}
+          </div>
+        })
+    }
+}
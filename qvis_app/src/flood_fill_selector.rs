@@ -0,0 +1,354 @@
+//! The drag-to-flood-fill interaction used to select a region of an image: the user drags from a seed point and the length and direction of the drag control how tolerant the flood fill is in each color channel.
+
+use crate::region_proposal::grow_region;
+#[cfg(feature = "ssr")]
+use opencv::{
+    core::{BORDER_CONSTANT, Point, Rect, Scalar},
+    imgproc::{self, FLOODFILL_FIXED_RANGE, FLOODFILL_MASK_ONLY},
+    prelude::*,
+};
+use rand::{SeedableRng, rngs::SmallRng, seq::SliceRandom};
+use std::f64::consts::PI;
+
+pub const MAX_PIXEL_VALUE: i32 = 255;
+/// How many pixels `imgproc::flood_fill_mask` requires its mask to be padded by on every side
+pub const MASK_PADDING: i32 = 1;
+
+/// Scale a drag distance down into a per-channel flood fill tolerance, with `n` nudging the result so that different channels end up with slightly different tolerances
+pub fn c(x: i32, n: i32) -> i32 {
+    (x + n) / 6
+}
+
+/// Decode a number into one of the 6! permutations of `0..6` in lexicographic order, wrapping around every 720
+pub fn perm6_from_number(mut n: u16) -> [i32; 6] {
+    const FACT: [u16; 7] = [1, 1, 2, 6, 24, 120, 720];
+    n %= FACT[6];
+
+    let mut elems = vec![0, 1, 2, 3, 4, 5];
+    let mut result = [0; 6];
+
+    for i in 0..6 {
+        let f = FACT[5 - i];
+        let idx = (n / f) as usize;
+        n %= f;
+
+        result[i] = elems.remove(idx);
+    }
+
+    result
+}
+
+/// How far the user dragged, growing faster than linearly so that small drags allow fine control
+#[allow(clippy::cast_possible_truncation)]
+pub fn drag_distance(origin: (i32, i32), drag: (i32, i32)) -> i32 {
+    (f64::from(drag.0 - origin.0)
+        .hypot(f64::from(drag.1 - origin.1))
+        .powf(1.5)
+        / 20.0) as i32
+}
+
+/// The direction that the user dragged in, used to pick which of the 6! tolerance orderings to use. Going once around the circle walks through every ordering in `perm6_from_number` twenty times, so that rotating the drag slightly is enough to try a different ordering.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+pub fn drag_angle(origin: (i32, i32), drag: (i32, i32)) -> u16 {
+    // angle is between [-pi, pi]; add pi and multiply by 360/pi to get a range
+    // of [0, 720] throughout the full circle which is 6!
+    //
+    // multiply it again by 20 to increase the periodicity
+    ((f64::from(drag.1 - origin.1).atan2(f64::from(drag.0 - origin.0)) + PI) * 360.0 / PI * 20.0)
+        as u16
+}
+
+/// The lower and upper per-channel flood fill tolerances for a drag. `upper_diff` out of `max_upper_diff` additionally loosens the upper tolerance so that lighter shades of the sticker are included.
+pub fn flood_fill_tolerances(
+    origin: (i32, i32),
+    drag: (i32, i32),
+    upper_diff: i32,
+    max_upper_diff: i32,
+) -> ([i32; 3], [i32; 3]) {
+    let distance = drag_distance(origin, drag);
+    let perm6 = perm6_from_number(drag_angle(origin, drag));
+    let extra = upper_diff * MAX_PIXEL_VALUE / max_upper_diff;
+
+    (
+        [
+            c(distance, perm6[0]),
+            c(distance, perm6[1]),
+            c(distance, perm6[2]),
+        ],
+        [
+            c(distance, perm6[3] + extra),
+            c(distance, perm6[4] + extra),
+            c(distance, perm6[5] + extra),
+        ],
+    )
+}
+
+/// Pick up to `count` of the pixels that are selected in `mask` at random and return their indices, for previewing which pixels are in the interior of a selection.
+///
+/// The random number generator is seeded by the drag origin along with `seed` rather than by entropy alone, so that redrawing while the user adjusts a drag keeps showing the same pixels instead of flickering, and starting a new selection picks new ones. The same `seed` always picks the same pixels for the same drag.
+pub fn sample_selected_pixels(
+    mask: &[u8],
+    origin: (i32, i32),
+    count: usize,
+    seed: u64,
+) -> Vec<usize> {
+    let mut bytes = [0; 32];
+    bytes[0..4].copy_from_slice(&origin.0.to_be_bytes());
+    bytes[4..8].copy_from_slice(&origin.1.to_be_bytes());
+    bytes[8..16].copy_from_slice(&seed.to_be_bytes());
+    let mut rng = SmallRng::from_seed(bytes);
+
+    let mut selected = mask
+        .iter()
+        .enumerate()
+        .filter_map(|(i, &value)| (i32::from(value) == MAX_PIXEL_VALUE).then_some(i))
+        .collect::<Vec<_>>();
+
+    selected.partial_shuffle(&mut rng, count).0.to_vec()
+}
+
+/// Flood fill `img` from `origin` with the tolerances given by `flood_fill_tolerances`, writing the selected region into `mask` as `MAX_PIXEL_VALUE`. The mask must be padded by one pixel on every side as `imgproc::flood_fill_mask` requires, and `mask_roi` is the unpadded region of it, which gets cleared before filling.
+///
+/// # Errors
+///
+/// This function will return an `OpenCV` error.
+#[cfg(feature = "ssr")]
+pub fn flood_fill_drag(
+    img: &mut Mat,
+    mask: &mut Mat,
+    mask_roi: Rect,
+    origin: (i32, i32),
+    (lower, upper): ([i32; 3], [i32; 3]),
+) -> opencv::Result<()> {
+    Mat::roi_mut(mask, mask_roi)?.set_to_def(&Scalar::all(0.0))?;
+    imgproc::flood_fill_mask(
+        img,
+        mask,
+        Point::new(origin.0, origin.1),
+        Scalar::default(), // ignored
+        &mut Rect::default(),
+        Scalar::from((lower[0], lower[1], lower[2])),
+        Scalar::from((upper[0], upper[1], upper[2])),
+        4 | FLOODFILL_FIXED_RANGE | FLOODFILL_MASK_ONLY | (MAX_PIXEL_VALUE << 8),
+    )?;
+
+    Ok(())
+}
+
+/// The same fill as `flood_fill_drag` without `OpenCV`, for the assignment UI in the browser: starting at `origin`, select the neighbouring pixels whose every channel is within `lower` below and `upper` above the color at `origin`. Pixels are given in row-major order, `width` to a row, and the indices of the selected pixels are returned sorted.
+pub fn flood_fill_region(
+    pixels: &[[u8; 3]],
+    width: usize,
+    origin: (usize, usize),
+    (lower, upper): ([i32; 3], [i32; 3]),
+) -> Vec<usize> {
+    let start = origin.1 * width + origin.0;
+    if origin.0 >= width || start >= pixels.len() {
+        return Vec::new();
+    }
+
+    let seed = pixels[start];
+    let in_range = |idx: usize| {
+        (0..3).all(|c| {
+            let diff = i32::from(pixels[idx][c]) - i32::from(seed[c]);
+            -lower[c] <= diff && diff <= upper[c]
+        })
+    };
+
+    let mut selected = vec![false; pixels.len()];
+    let mut region = grow_region(pixels.len(), width, start, &mut selected, |_, neighbour| {
+        in_range(neighbour)
+    });
+
+    region.sort_unstable();
+    region
+}
+
+/// Dilate a mask padded by `MASK_PADDING` into `dst`, which ends up the same size as `mask`.
+///
+/// The padding can't simply be cropped off first because `OpenCV` treats an ROI as a view into the full matrix, so morphology operations on an ROI read the pixels around it rather than applying the border mode. Instead, the whole padded mask is dilated after clearing a ring `MASK_PADDING + 1` pixels wide around its edge: the padding, which flood fill uses as scratch space and can leave nonzero, and the outermost ring of the image itself. This keeps anything in the padding from being dilated into the image. Note that this clears that ring of `mask` as well.
+///
+/// # Errors
+///
+/// This function will return an `OpenCV` error.
+#[cfg(feature = "ssr")]
+pub fn dilate_padded_mask(mask: &mut Mat, dst: &mut Mat, kernel: &Mat) -> opencv::Result<()> {
+    let ring = MASK_PADDING + 1;
+    let rows = mask.rows();
+    let cols = mask.cols();
+    for strip in [
+        Rect::new(0, 0, cols, ring),
+        Rect::new(0, rows - ring, cols, ring),
+        Rect::new(0, ring, ring, rows - 2 * ring),
+        Rect::new(cols - ring, ring, ring, rows - 2 * ring),
+    ] {
+        mask.roi_mut(strip)?.set_to_def(&Scalar::all(0.0))?;
+    }
+
+    imgproc::dilate(
+        mask,
+        dst,
+        kernel,
+        Point::new(-1, -1),
+        1,
+        BORDER_CONSTANT,
+        imgproc::morphology_default_border_value()?,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        MAX_PIXEL_VALUE, c, drag_angle, flood_fill_region, perm6_from_number,
+        sample_selected_pixels,
+    };
+
+    #[test]
+    fn perm6() {
+        assert_eq!(perm6_from_number(0), [0, 1, 2, 3, 4, 5]);
+        assert_eq!(perm6_from_number(1), [0, 1, 2, 3, 5, 4]);
+        assert_eq!(perm6_from_number(2), [0, 1, 2, 4, 3, 5]);
+        assert_eq!(perm6_from_number(120), [1, 0, 2, 3, 4, 5]);
+        assert_eq!(perm6_from_number(719), [5, 4, 3, 2, 1, 0]);
+        assert_eq!(perm6_from_number(720), perm6_from_number(0));
+        assert_eq!(
+            perm6_from_number(u16::MAX),
+            perm6_from_number(u16::MAX % 720)
+        );
+
+        for n in 0..720 {
+            let mut perm = perm6_from_number(n);
+            perm.sort_unstable();
+            assert_eq!(perm, [0, 1, 2, 3, 4, 5]);
+        }
+    }
+
+    #[test]
+    fn angle() {
+        let perm6 = |drag| perm6_from_number(drag_angle((100, 100), drag));
+
+        assert_eq!(drag_angle((100, 100), (90, 100)), 14400);
+        assert_eq!(drag_angle((100, 100), (100, 90)), 3600);
+        assert_eq!(drag_angle((100, 100), (110, 100)), 7200);
+        assert_eq!(drag_angle((100, 100), (100, 110)), 10800);
+
+        // Every axis-aligned drag lands on a multiple of 720 and therefore the identity ordering
+        assert_eq!(perm6((90, 100)), [0, 1, 2, 3, 4, 5]);
+        assert_eq!(perm6((100, 90)), [0, 1, 2, 3, 4, 5]);
+        assert_eq!(perm6((110, 100)), [0, 1, 2, 3, 4, 5]);
+        assert_eq!(perm6((100, 110)), [0, 1, 2, 3, 4, 5]);
+
+        // Diagonals are half of a period away
+        assert_eq!(perm6((110, 110)), [3, 0, 1, 2, 4, 5]);
+        assert_eq!(perm6((90, 90)), [3, 0, 1, 2, 4, 5]);
+
+        assert_eq!(perm6((103, 104)), [5, 3, 2, 0, 4, 1]);
+        assert_eq!(perm6((200, 101)), [0, 1, 5, 4, 2, 3]);
+    }
+
+    #[test]
+    fn sampling() {
+        let max = u8::try_from(MAX_PIXEL_VALUE).unwrap();
+        let mask = (0..200)
+            .map(|i| if i % 3 == 0 { max } else { 0 })
+            .collect::<Vec<_>>();
+
+        let samples = sample_selected_pixels(&mask, (12, 34), 20, 7);
+        assert_eq!(samples.len(), 20);
+        assert!(samples.iter().all(|&i| mask[i] == max));
+        assert_eq!(samples, sample_selected_pixels(&mask, (12, 34), 20, 7));
+        assert_ne!(samples, sample_selected_pixels(&mask, (34, 12), 20, 7));
+        // Another run of the UI with another seed picks other pixels for the same drag
+        assert_ne!(samples, sample_selected_pixels(&mask, (12, 34), 20, 8));
+
+        // Asking for more samples than there are selected pixels gives all of them
+        let mut all = sample_selected_pixels(&mask, (12, 34), 1000, 7);
+        all.sort_unstable();
+        assert!(all.into_iter().eq((0..200).step_by(3)));
+
+        assert!(sample_selected_pixels(&mask, (12, 34), 0, 7).is_empty());
+    }
+
+    #[test]
+    fn tolerance() {
+        assert_eq!(c(0, 0), 0);
+        assert_eq!(c(0, 5), 0);
+        assert_eq!(c(6, 0), 1);
+        assert_eq!(c(1, 5), 1);
+        assert_eq!(c(60, 5), 10);
+        assert_eq!(c(60, 255 + 5), 53);
+    }
+
+    #[test]
+    fn fill_without_opencv() {
+        // A 4x3 image with a bright 2x2 square in the top left, shaded by one in its bottom row, and a pixel of the same color in the bottom right that isn't connected to it
+        let (bright, shaded, dark) = ([200, 100, 50], [201, 101, 51], [10, 10, 10]);
+        #[rustfmt::skip]
+        let pixels = [
+            bright, bright, dark, dark,
+            shaded, shaded, dark, dark,
+            dark, dark, dark, bright,
+        ];
+
+        let exact = ([0; 3], [0; 3]);
+        assert_eq!(flood_fill_region(&pixels, 4, (0, 0), exact), vec![0, 1]);
+        let tolerant = ([0; 3], [1; 3]);
+        assert_eq!(
+            flood_fill_region(&pixels, 4, (1, 0), tolerant),
+            vec![0, 1, 4, 5]
+        );
+        // The tolerance is from the color at the origin, not from the neighbour
+        assert_eq!(flood_fill_region(&pixels, 4, (0, 1), tolerant), vec![4, 5]);
+        assert_eq!(
+            flood_fill_region(&pixels, 4, (0, 1), ([1; 3], [0; 3])),
+            vec![0, 1, 4, 5]
+        );
+        // One channel out of range is enough to stop the fill
+        assert_eq!(
+            flood_fill_region(&pixels, 4, (0, 0), ([0; 3], [1, 1, 0])),
+            vec![0, 1]
+        );
+
+        assert_eq!(flood_fill_region(&pixels, 4, (2, 1), exact).len(), 7);
+        assert!(flood_fill_region(&pixels, 4, (4, 0), exact).is_empty());
+        assert!(flood_fill_region(&pixels, 4, (0, 3), exact).is_empty());
+    }
+
+    #[cfg(feature = "ssr")]
+    #[test]
+    fn dilate_without_leaking() {
+        use super::{MAX_PIXEL_VALUE, dilate_padded_mask};
+        use opencv::{
+            core::{CV_8UC1, Size},
+            imgproc::{self, MORPH_RECT},
+            prelude::*,
+        };
+
+        let max = u8::try_from(MAX_PIXEL_VALUE).unwrap();
+
+        // A 7x7 image padded to 9x9, with garbage in the padding and a single selected pixel in the middle
+        let mut mask = Mat::zeros(9, 9, CV_8UC1).unwrap().to_mat().unwrap();
+        for i in 0..9 {
+            *mask.at_2d_mut::<u8>(0, i).unwrap() = max;
+            *mask.at_2d_mut::<u8>(i, 8).unwrap() = max;
+        }
+        *mask.at_2d_mut::<u8>(4, 4).unwrap() = max;
+
+        let kernel = imgproc::get_structuring_element_def(MORPH_RECT, Size::new(3, 3)).unwrap();
+        let mut dilated = Mat::default();
+        dilate_padded_mask(&mut mask, &mut dilated, &kernel).unwrap();
+
+        assert_eq!(dilated.rows(), 9);
+        assert_eq!(dilated.cols(), 9);
+        for y in 0..9 {
+            for x in 0..9 {
+                let expected = if (3..=5).contains(&y) && (3..=5).contains(&x) {
+                    max
+                } else {
+                    0
+                };
+                assert_eq!(*dilated.at_2d::<u8>(y, x).unwrap(), expected, "({x}, {y})");
+            }
+        }
+    }
+}
@@ -0,0 +1,55 @@
+//! Holding the frame on the canvas still, so that it can be compared with the recognized state without the next picture replacing it. The camera keeps running while the frame is frozen, so unfreezing picks the live video back up without asking for the camera again.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FrameFreeze {
+    /// Pictures are taken from the live video
+    #[default]
+    Live,
+    /// The frame on the canvas is kept, and pictures are taken from it instead of the video
+    Frozen,
+}
+
+impl FrameFreeze {
+    /// Freeze or unfreeze the frame. There is nothing to freeze until a frame has been drawn to the canvas, so freezing does nothing before then.
+    #[must_use]
+    pub fn toggle(self, has_frame: bool) -> FrameFreeze {
+        match self {
+            FrameFreeze::Live if has_frame => FrameFreeze::Frozen,
+            FrameFreeze::Live | FrameFreeze::Frozen => FrameFreeze::Live,
+        }
+    }
+
+    /// Whether pictures should draw a new frame from the video onto the canvas
+    pub fn is_live(self) -> bool {
+        self == FrameFreeze::Live
+    }
+
+    /// The label of the button that toggles the freeze
+    pub fn button_label(self) -> &'static str {
+        match self {
+            FrameFreeze::Live => "Freeze frame",
+            FrameFreeze::Frozen => "Resume video",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FrameFreeze;
+
+    #[test]
+    fn toggle() {
+        let freeze = FrameFreeze::default();
+        assert!(freeze.is_live());
+
+        // Nothing has been captured to hold still
+        assert_eq!(freeze.toggle(false), FrameFreeze::Live);
+
+        let frozen = freeze.toggle(true);
+        assert_eq!(frozen, FrameFreeze::Frozen);
+        assert!(!frozen.is_live());
+
+        assert_eq!(frozen.toggle(true), FrameFreeze::Live);
+        assert_eq!(frozen.toggle(false), FrameFreeze::Live);
+    }
+}
@@ -0,0 +1,234 @@
+//! Keyboard shortcuts for the sticker assignment UI, kept separate from the `OpenCV` event loop so that they can be remapped and tested without a window.
+
+/// What `highgui::wait_key` returns when no key was pressed before it timed out
+pub const NO_KEY: i32 = -1;
+
+/// Something the user can do from the keyboard
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// Assign the currently selected region to the current sticker
+    Assign,
+    /// Forget every assignment and start over from the first sticker
+    Restart,
+    /// Start or stop dragging out a flood fill selection
+    ToggleDragging,
+    /// Select the next region that looks like a sticker, as an alternative to dragging out a selection
+    Propose,
+    /// Leave the current sticker unassigned and move on
+    Skip,
+    /// Go back to the previous sticker to redo it
+    Previous,
+    /// Go forward to the next sticker to redo it
+    Next,
+    /// Show or hide the tint over stickers that have already been assigned
+    ToggleOverlay,
+    /// Magnify the image around the pointer to select pixels precisely
+    ZoomIn,
+    /// Undo a step of zooming in
+    ZoomOut,
+}
+
+impl Action {
+    /// A short description of the action for the on-screen legend
+    pub fn description(self) -> &'static str {
+        match self {
+            Action::Assign => "assign sticker",
+            Action::Restart => "restart",
+            Action::ToggleDragging => "start/stop selecting",
+            Action::Propose => "propose a sticker",
+            Action::Skip => "skip sticker",
+            Action::Previous => "previous sticker",
+            Action::Next => "next sticker",
+            Action::ToggleOverlay => "show/hide assigned",
+            Action::ZoomIn => "zoom in",
+            Action::ZoomOut => "zoom out",
+        }
+    }
+}
+
+/// Which key triggers each action
+#[derive(Debug, Clone)]
+pub struct KeyBindings {
+    pub assign: char,
+    pub restart: char,
+    pub toggle_dragging: char,
+    pub propose: char,
+    pub skip: char,
+    pub previous: char,
+    pub next: char,
+    pub toggle_overlay: char,
+    pub zoom_in: char,
+    pub zoom_out: char,
+}
+
+impl Default for KeyBindings {
+    fn default() -> Self {
+        KeyBindings {
+            assign: 'd',
+            restart: 'r',
+            toggle_dragging: 's',
+            propose: 'p',
+            skip: 'x',
+            previous: '[',
+            next: ']',
+            toggle_overlay: 'o',
+            zoom_in: '=',
+            zoom_out: '-',
+        }
+    }
+}
+
+impl KeyBindings {
+    fn bindings(&self) -> [(char, Action); 10] {
+        [
+            (self.toggle_dragging, Action::ToggleDragging),
+            (self.propose, Action::Propose),
+            (self.assign, Action::Assign),
+            (self.skip, Action::Skip),
+            (self.previous, Action::Previous),
+            (self.next, Action::Next),
+            (self.toggle_overlay, Action::ToggleOverlay),
+            (self.zoom_in, Action::ZoomIn),
+            (self.zoom_out, Action::ZoomOut),
+            (self.restart, Action::Restart),
+        ]
+    }
+
+    /// The action bound to a key code returned by `highgui::wait_key`, if any
+    pub fn action(&self, key: i32) -> Option<Action> {
+        self.bindings()
+            .into_iter()
+            .find(|(c, _)| key_code(*c) == Some(key))
+            .map(|(_, action)| action)
+    }
+
+    /// One line per binding describing what each key does
+    pub fn legend(&self) -> Vec<String> {
+        self.bindings()
+            .into_iter()
+            .map(|(c, action)| format!("{}: {}", c.to_uppercase(), action.description()))
+            .collect()
+    }
+}
+
+/// The key code that `highgui::wait_key` reports for a character
+fn key_code(c: char) -> Option<i32> {
+    i32::try_from(u32::from(c)).ok()
+}
+
+/// Turns the stream of keys reported by `highgui::wait_key` into actions, triggering each action once per key press even if the key repeats while it is held down. A poll that reports no key ends the press, so the next report of the key is a new press.
+#[derive(Debug)]
+pub struct KeyDispatcher {
+    bindings: KeyBindings,
+    /// The key that was reported by the last poll, if it reported one
+    last_key: Option<i32>,
+}
+
+impl KeyDispatcher {
+    pub fn new(bindings: KeyBindings) -> KeyDispatcher {
+        KeyDispatcher {
+            bindings,
+            last_key: None,
+        }
+    }
+
+    pub fn bindings(&self) -> &KeyBindings {
+        &self.bindings
+    }
+
+    /// Handle the result of one poll of `highgui::wait_key` and return the action to perform, if any
+    pub fn dispatch(&mut self, key: i32) -> Option<Action> {
+        if key == NO_KEY {
+            self.last_key = None;
+            return None;
+        }
+
+        let held = self.last_key == Some(key);
+        self.last_key = Some(key);
+
+        if held {
+            None
+        } else {
+            self.bindings.action(key)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Action, KeyBindings, KeyDispatcher, NO_KEY, key_code};
+
+    fn key(c: char) -> i32 {
+        key_code(c).unwrap()
+    }
+
+    #[test]
+    fn bindings() {
+        let (s, d) = (key('s'), key('d'));
+
+        let bindings = KeyBindings::default();
+        assert_eq!(bindings.action(d), Some(Action::Assign));
+        assert_eq!(bindings.action(key('r')), Some(Action::Restart));
+        assert_eq!(bindings.action(s), Some(Action::ToggleDragging));
+        assert_eq!(bindings.action(key('p')), Some(Action::Propose));
+        assert_eq!(bindings.action(key('x')), Some(Action::Skip));
+        assert_eq!(bindings.action(key('[')), Some(Action::Previous));
+        assert_eq!(bindings.action(key(']')), Some(Action::Next));
+        assert_eq!(bindings.action(key('o')), Some(Action::ToggleOverlay));
+        assert_eq!(bindings.action(key('=')), Some(Action::ZoomIn));
+        assert_eq!(bindings.action(key('-')), Some(Action::ZoomOut));
+        assert_eq!(bindings.action(key('q')), None);
+        assert_eq!(bindings.action(NO_KEY), None);
+
+        let bindings = KeyBindings {
+            assign: ' ',
+            ..KeyBindings::default()
+        };
+        assert_eq!(bindings.action(key(' ')), Some(Action::Assign));
+        assert_eq!(bindings.action(d), None);
+        assert_eq!(
+            bindings.legend(),
+            [
+                "S: start/stop selecting",
+                "P: propose a sticker",
+                " : assign sticker",
+                "X: skip sticker",
+                "[: previous sticker",
+                "]: next sticker",
+                "O: show/hide assigned",
+                "=: zoom in",
+                "-: zoom out",
+                "R: restart"
+            ]
+        );
+    }
+
+    #[test]
+    fn held_key_fires_once() {
+        let s = key('s');
+        let mut dispatcher = KeyDispatcher::new(KeyBindings::default());
+
+        assert_eq!(dispatcher.dispatch(s), Some(Action::ToggleDragging));
+        // Key repeat reports the key on every poll
+        for _ in 0..10 {
+            assert_eq!(dispatcher.dispatch(s), None);
+        }
+
+        // Released
+        assert_eq!(dispatcher.dispatch(NO_KEY), None);
+        assert_eq!(dispatcher.dispatch(s), Some(Action::ToggleDragging));
+        assert_eq!(dispatcher.dispatch(s), None);
+    }
+
+    #[test]
+    fn different_key_fires() {
+        let (s, d) = (key('s'), key('d'));
+        let mut dispatcher = KeyDispatcher::new(KeyBindings::default());
+
+        assert_eq!(dispatcher.dispatch(s), Some(Action::ToggleDragging));
+        assert_eq!(dispatcher.dispatch(d), Some(Action::Assign));
+        assert_eq!(dispatcher.dispatch(s), Some(Action::ToggleDragging));
+        assert_eq!(dispatcher.dispatch(key('q')), None);
+        assert_eq!(dispatcher.dispatch(s), Some(Action::ToggleDragging));
+    }
+}
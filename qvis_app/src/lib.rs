@@ -6,11 +6,33 @@
 )]
 
 pub mod app;
+pub mod assignment_overlay;
+pub mod assignment_params;
+#[cfg(feature = "ssr")]
+pub mod assignment_requests;
+pub mod assignment_snapshot;
+pub mod assignment_summary;
+pub mod assignment_upload;
+pub mod browser_assignment;
+pub mod browser_recognition;
+pub mod camera_choice;
+pub mod connection;
+pub mod cube_net;
+pub mod flood_fill_selector;
+pub mod frame_freeze;
+pub mod key_bindings;
+pub mod message_log;
 pub mod messages_logger;
+pub mod net_layout;
 #[cfg(feature = "ssr")]
 pub mod pixel_assignment_ui;
+pub mod region_proposal;
 pub mod server_fns;
+#[cfg(feature = "ssr")]
+pub mod server_settings;
+pub mod sticker_navigation;
 pub mod video;
+pub mod viewport;
 
 #[cfg(feature = "hydrate")]
 #[wasm_bindgen::prelude::wasm_bindgen]
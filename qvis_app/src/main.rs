@@ -12,21 +12,31 @@ use leptos_axum::{
     generate_route_list_with_exclusions_and_ssg_and_context, handle_server_fns_with_context,
 };
 use leptos_ws::WsSignals;
-use puzzle_theory::puzzle_geometry::parsing::puzzle;
-use qvis::Pixel;
 use qvis_app::{
     app::{App, shell},
+    assignment_params::{AssignmentParams, Surface},
+    assignment_requests,
+    assignment_snapshot::Autosave,
+    key_bindings::KeyBindings,
     pixel_assignment_ui,
+    server_fns::{ClientReplyTimeout, PixelAssignmentUiTx, PuzzleName},
+    server_settings::ServerSettings,
 };
 use std::thread;
 use tokio::net::TcpListener;
 
+/// Where the sticker assignment UI saves its progress, so that it can be resumed if the UI fails
+const AUTOSAVE_FILE: &str = "pixel_assignment_autosave.txt";
+
 #[derive(Clone, FromRef)]
 pub struct AppState {
     server_signals: WsSignals,
     routes: Option<Vec<AxumRouteListing>>,
     options: LeptosOptions,
-    pixel_assignment_ui_tx: std::sync::mpsc::Sender<tokio::sync::oneshot::Sender<Box<[Pixel]>>>,
+    pixel_assignment_ui_tx: PixelAssignmentUiTx,
+    puzzle_name: PuzzleName,
+    surface: Surface,
+    client_reply_timeout: ClientReplyTimeout,
 }
 
 async fn server_fn_handler(
@@ -41,6 +51,9 @@ async fn server_fn_handler(
             provide_context(state.options.clone());
             provide_context(state.server_signals.clone());
             provide_context(state.pixel_assignment_ui_tx.clone());
+            provide_context(state.puzzle_name.clone());
+            provide_context(state.surface);
+            provide_context(state.client_reply_timeout);
         },
         request,
     )
@@ -61,11 +74,21 @@ async fn leptos_routes_handler(state: State<AppState>, req: Request<AxumBody>) -
     handler(state, req).await.into_response()
 }
 
+/// Run the web server until it fails. Returning drops `pixel_assignment_ui_tx`, which lets the main thread know to exit.
 #[tokio::main]
 async fn server_main(
-    pixel_assignment_ui_tx: std::sync::mpsc::Sender<tokio::sync::oneshot::Sender<Box<[Pixel]>>>,
+    pixel_assignment_ui_tx: PixelAssignmentUiTx,
+    puzzle_name: PuzzleName,
+    surface: Surface,
+    client_reply_timeout: ClientReplyTimeout,
 ) {
-    let conf = get_configuration(None).unwrap();
+    let conf = match get_configuration(None) {
+        Ok(conf) => conf,
+        Err(e) => {
+            log!("Failed to load the Leptos configuration: {e}");
+            return;
+        }
+    };
     let leptos_options = conf.leptos_options;
     let addr = leptos_options.site_addr;
 
@@ -75,6 +98,9 @@ async fn server_main(
         routes: None,
         server_signals: server_signals.clone(),
         pixel_assignment_ui_tx,
+        puzzle_name,
+        surface,
+        client_reply_timeout,
     };
     let state1 = state.clone();
     let state2 = state.clone();
@@ -98,24 +124,78 @@ async fn server_main(
         ))
         .with_state(state);
 
-    log!("listening on https://{addr}");
+    let listener = match TcpListener::bind(&addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            log!("Failed to listen on {addr}: {e}");
+            return;
+        }
+    };
+    // TLS isn't terminated here, so anything that needs a secure context (such as camera access from a phone) has to go through a proxy that provides it
+    log!("listening on http://{addr}");
 
-    let listener = TcpListener::bind(&addr).await.unwrap();
-    axum::serve(listener, app.into_make_service())
-        .await
-        .unwrap();
+    if let Err(e) = axum::serve(listener, app.into_make_service()).await {
+        log!("The server stopped: {e}");
+    }
 }
 
 fn main() {
-    let (pixel_assignment_ui_tx, pixel_assignment_ui_rx) =
-        std::sync::mpsc::channel::<tokio::sync::oneshot::Sender<Box<[Pixel]>>>();
+    let (pixel_assignment_ui_tx, pixel_assignment_ui_rx) = std::sync::mpsc::channel();
+
+    let settings = ServerSettings::from_env();
+
+    let puzzle_name = PuzzleName(settings.puzzle);
+    let puzzle_geometry = match puzzle_name.geometry() {
+        Ok(puzzle_geometry) => puzzle_geometry,
+        Err(e) => {
+            log!("{e}, so QVIS_PUZZLE has to be changed");
+            std::process::exit(1);
+        }
+    };
+    log!("recognizing the {} puzzle", puzzle_name.0);
+
+    let assignment_image = settings.assignment_image;
+    log!("assigning stickers in {}", assignment_image.display());
 
-    thread::spawn(move || server_main(pixel_assignment_ui_tx));
+    let autosave = Autosave {
+        path: AUTOSAVE_FILE.into(),
+        interval: settings.autosave_interval,
+    };
+
+    let surface = settings.surface;
+    let seed = settings.seed;
+    log!("the faces of the puzzle are {surface:?}");
+
+    let default = ClientReplyTimeout::default();
+    let client_reply_timeout = ClientReplyTimeout {
+        timeout: settings.client_reply_timeout.unwrap_or(default.timeout),
+        ..default
+    };
+
+    // Where the trackbars of the sticker assignment UI were left is remembered separately for each puzzle
+    let assignment_params = AssignmentParams::path(&puzzle_name.0);
+
+    thread::spawn(move || {
+        server_main(
+            pixel_assignment_ui_tx,
+            puzzle_name,
+            surface,
+            client_reply_timeout,
+        );
+    });
 
     // For some reason highgui doesn't work unless it's on the main thread
-    let puzzle_geometry = puzzle("3x3").into_inner();
-    while let Ok(pixel_assignment_done_tx) = pixel_assignment_ui_rx.recv() {
-        let assignment = pixel_assignment_ui::pixel_assignment_ui(&puzzle_geometry).unwrap();
-        pixel_assignment_done_tx.send(assignment).unwrap();
-    }
+    assignment_requests::serve(&pixel_assignment_ui_rx, |is_cancelled| {
+        pixel_assignment_ui::pixel_assignment_ui(
+            &puzzle_geometry,
+            &assignment_image,
+            KeyBindings::default(),
+            Some(&autosave),
+            Some(&assignment_params),
+            surface,
+            None,
+            seed,
+            is_cancelled,
+        )
+    });
 }
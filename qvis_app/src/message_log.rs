@@ -0,0 +1,120 @@
+//! The messages shown in the app, kept separate from the logger and the component so that the bookkeeping can be tested without a browser.
+
+use log::{Level, LevelFilter};
+use std::collections::VecDeque;
+
+/// How many messages the app keeps before dropping the oldest
+pub const DEFAULT_MAX_MESSAGES: usize = 500;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    /// Unique among every message logged, so that it can key the list of messages
+    pub id: u32,
+    pub level: Level,
+    pub text: String,
+}
+
+/// The most recent messages, up to a maximum. Once it is full, every new message pushes out the oldest one so that a long session doesn't grow the list forever.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MessageLog {
+    messages: VecDeque<Message>,
+    max_messages: usize,
+}
+
+impl MessageLog {
+    /// Create an empty log that keeps at most `max_messages` messages
+    pub fn new(max_messages: usize) -> MessageLog {
+        MessageLog {
+            messages: VecDeque::new(),
+            max_messages,
+        }
+    }
+
+    pub fn push(&mut self, message: Message) {
+        self.messages.push_back(message);
+        self.truncate();
+    }
+
+    /// Change how many messages are kept, dropping the oldest ones if there are now too many
+    pub fn set_max_messages(&mut self, max_messages: usize) {
+        self.max_messages = max_messages;
+        self.truncate();
+    }
+
+    pub fn clear(&mut self) {
+        self.messages.clear();
+    }
+
+    /// The messages that pass the filter, oldest first
+    pub fn visible(&self, filter: LevelFilter) -> impl Iterator<Item = &Message> {
+        self.messages
+            .iter()
+            .filter(move |message| message.level <= filter)
+    }
+
+    fn truncate(&mut self) {
+        let excess = self.messages.len().saturating_sub(self.max_messages);
+        self.messages.drain(..excess);
+    }
+}
+
+impl Default for MessageLog {
+    fn default() -> Self {
+        MessageLog::new(DEFAULT_MAX_MESSAGES)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Message, MessageLog};
+    use log::{Level, LevelFilter};
+
+    fn message(id: u32, level: Level) -> Message {
+        Message {
+            id,
+            level,
+            text: format!("Message {id}"),
+        }
+    }
+
+    fn ids(log: &MessageLog, filter: LevelFilter) -> Vec<u32> {
+        log.visible(filter).map(|message| message.id).collect()
+    }
+
+    #[test]
+    fn drops_oldest() {
+        let mut log = MessageLog::new(3);
+        for id in 0..5 {
+            log.push(message(id, Level::Info));
+        }
+        assert_eq!(ids(&log, LevelFilter::Trace), [2, 3, 4]);
+
+        log.set_max_messages(1);
+        assert_eq!(ids(&log, LevelFilter::Trace), [4]);
+
+        log.set_max_messages(0);
+        log.push(message(5, Level::Info));
+        assert_eq!(ids(&log, LevelFilter::Trace), []);
+
+        log.set_max_messages(2);
+        log.push(message(6, Level::Info));
+        log.clear();
+        assert_eq!(ids(&log, LevelFilter::Trace), []);
+    }
+
+    #[test]
+    fn filters_by_level() {
+        let mut log = MessageLog::new(10);
+        for (id, level) in [Level::Debug, Level::Error, Level::Info, Level::Warn]
+            .into_iter()
+            .enumerate()
+        {
+            log.push(message(u32::try_from(id).unwrap(), level));
+        }
+
+        assert_eq!(ids(&log, LevelFilter::Debug), [0, 1, 2, 3]);
+        assert_eq!(ids(&log, LevelFilter::Info), [1, 2, 3]);
+        assert_eq!(ids(&log, LevelFilter::Warn), [1, 3]);
+        assert_eq!(ids(&log, LevelFilter::Off), []);
+    }
+}
@@ -1,14 +1,15 @@
+use crate::message_log::{Message, MessageLog};
 use leptos::prelude::*;
 use log::{Log, Metadata, Record};
 use std::sync::atomic::{AtomicU32, Ordering};
 
 pub struct MessagesLogger {
-    writer: WriteSignal<Vec<(u32, String)>>,
+    writer: WriteSignal<MessageLog>,
     id: AtomicU32,
 }
 
 impl MessagesLogger {
-    pub fn new(writer: WriteSignal<Vec<(u32, String)>>) -> Self {
+    pub fn new(writer: WriteSignal<MessageLog>) -> Self {
         Self {
             writer,
             id: AtomicU32::new(0),
@@ -23,10 +24,11 @@ impl Log for MessagesLogger {
 
     fn log(&self, record: &Record) {
         self.writer.update(|v| {
-            v.push((
-                self.id.fetch_add(1, Ordering::SeqCst),
-                format!("[{}] {}", record.level(), record.args()),
-            ));
+            v.push(Message {
+                id: self.id.fetch_add(1, Ordering::SeqCst),
+                level: record.level(),
+                text: format!("[{}] {}", record.level(), record.args()),
+            });
         });
     }
 
@@ -0,0 +1,112 @@
+//! Laying out the stickers of a cube as the classic cross-shaped net, from the state written in Kociemba's facelet notation. The notation has the same layout for every size of cube, so nothing here is specific to the 3x3.
+
+/// Where each face goes in the net, in units of faces, for the faces in the URFDLB order of the notation:
+///
+/// ```text
+///   U
+/// L F R B
+///   D
+/// ```
+const FACE_ORIGINS: [(usize, usize); 6] = [(1, 0), (2, 1), (1, 1), (1, 2), (0, 1), (3, 1)];
+
+/// A sticker of the net
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NetCell {
+    pub column: usize,
+    pub row: usize,
+    /// The CSS color of the sticker
+    pub color: &'static str,
+}
+
+/// The column and row in the net of the sticker at `index` of `face`, where faces are numbered in URFDLB order, stickers are numbered in reading order within their face, and each face is `side` stickers wide
+pub fn net_position(face: usize, index: usize, side: usize) -> (usize, usize) {
+    let (face_column, face_row) = FACE_ORIGINS[face];
+
+    (
+        face_column * side + index % side,
+        face_row * side + index / side,
+    )
+}
+
+/// The number of columns and rows of the net of a cube whose faces are `side` stickers wide
+pub fn net_size(side: usize) -> (usize, usize) {
+    (4 * side, 3 * side)
+}
+
+/// Lay out the state of a cube written in Kociemba's facelet notation, along with how many stickers wide each face is. Returns `None` if the string isn't the notation for a cube.
+pub fn net_cells(facelets: &str) -> Option<(Vec<NetCell>, usize)> {
+    let per_face = facelets.len() / 6;
+    let side = per_face.isqrt();
+    if per_face == 0 || side * side != per_face || per_face * 6 != facelets.len() {
+        return None;
+    }
+
+    let cells = facelets
+        .chars()
+        .enumerate()
+        .map(|(i, letter)| {
+            let (column, row) = net_position(i / per_face, i % per_face, side);
+
+            Some(NetCell {
+                column,
+                row,
+                color: qvis::facelet_color(letter)?,
+            })
+        })
+        .collect::<Option<Vec<_>>>()?;
+
+    Some((cells, side))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{NetCell, net_cells, net_position, net_size};
+
+    #[test]
+    fn positions() {
+        // 3x3
+        assert_eq!(net_position(0, 0, 3), (3, 0));
+        assert_eq!(net_position(0, 8, 3), (5, 2));
+        assert_eq!(net_position(2, 4, 3), (4, 4));
+        assert_eq!(net_position(3, 8, 3), (5, 8));
+        assert_eq!(net_position(4, 3, 3), (0, 4));
+        assert_eq!(net_position(5, 8, 3), (11, 5));
+        assert_eq!(net_size(3), (12, 9));
+
+        // 2x2
+        assert_eq!(net_position(1, 0, 2), (4, 2));
+        assert_eq!(net_position(4, 3, 2), (1, 3));
+        assert_eq!(net_position(3, 2, 2), (2, 5));
+        assert_eq!(net_size(2), (8, 6));
+    }
+
+    #[test]
+    fn cells() {
+        let (cells, side) = net_cells("UUUURRRRFFFFDDDDLLLLBBBB").unwrap();
+        assert_eq!(side, 2);
+        assert_eq!(cells.len(), 24);
+        assert_eq!(
+            cells[5],
+            NetCell {
+                column: 5,
+                row: 2,
+                color: "red"
+            }
+        );
+
+        // Every sticker gets its own spot
+        let (cells, _) =
+            net_cells("UUUUUUUUURRRRRRRRRFFFFFFFFFDDDDDDDDDLLLLLLLLLBBBBBBBBB").unwrap();
+        let mut positions = cells
+            .iter()
+            .map(|cell| (cell.column, cell.row))
+            .collect::<Vec<_>>();
+        positions.sort_unstable();
+        positions.dedup();
+        assert_eq!(positions.len(), 54);
+
+        assert_eq!(net_cells(""), None);
+        assert_eq!(net_cells("UUURRRFFFDDDLLLBBB"), None);
+        assert_eq!(net_cells("UUUUXRRRFFFFDDDDLLLLBBBB"), None);
+    }
+}
@@ -1,3 +1,17 @@
+use crate::{
+    assignment_overlay::{clear_sticker, draw_overlay, overlapping_pixels},
+    assignment_params::{AssignmentParams, Surface},
+    assignment_snapshot::{AssignmentSnapshot, Autosave},
+    assignment_summary::AssignmentSummary,
+    flood_fill_selector::{
+        MASK_PADDING, MAX_PIXEL_VALUE, dilate_padded_mask, flood_fill_drag, flood_fill_tolerances,
+        sample_selected_pixels,
+    },
+    key_bindings::{Action, KeyBindings, KeyDispatcher},
+    region_proposal::propose_regions,
+    sticker_navigation::{StickerNavigation, StickerStatus},
+    viewport::{Viewport, ZOOM_STEP},
+};
 use internment::ArcIntern;
 use opencv::{
     core::{BORDER_CONSTANT, CV_8UC1, CV_8UC3, Point, Rect, Scalar, Size, Vec3b},
@@ -7,26 +21,33 @@ use opencv::{
 };
 use puzzle_theory::puzzle_geometry::{Face, PuzzleGeometry};
 use qvis::Pixel;
-use rand::{SeedableRng, rngs::SmallRng, seq::SliceRandom};
 use std::{
-    f64::consts::PI,
+    path::Path,
     sync::{Arc, Mutex},
+    time::Instant,
 };
 
 const WINDOW_NAME: &str = "Qvis Sticker Assignment";
 const EROSION_SIZE_TRACKBAR_NAME: &str = "Erosion size";
-const EROSION_SIZE_TRACKBAR_MINDEFMAX: [i32; 3] = [2, 4, 20];
+const EROSION_SIZE_TRACKBAR_MINMAX: [i32; 2] = [2, 20];
 const UPPER_DIFF_TRACKBAR_NAME: &str = "Upper diff";
-const UPPER_DIFF_TRACKBAR_MINDEFMAX: [i32; 3] = [0, 2, 5];
+const UPPER_DIFF_TRACKBAR_MINMAX: [i32; 2] = [0, 5];
 const SUBMIT_BUTTON_NAME: &str = "Assign sticker";
 const EROSION_KERNEL_MORPH_SHAPE: i32 = MORPH_ELLIPSE;
 const DEF_ANCHOR: Point = Point::new(-1, -1);
 const XY_CIRCLE_RADIUS: i32 = 6;
-const MAX_PIXEL_VALUE: i32 = 255;
+/// The most pixels that are shown at once. Larger images are still assigned at their full resolution, since the assignment has to have a pixel for each pixel of the frames it is used to recognize, but are shown downscaled to this many pixels as an overview until they are zoomed in on.
 const MAX_PIXEL_COUNT: i32 = 500_000 * 100;
 const ERODE_UNTIL_PERCENT: (i32, i32) = (1, 3);
 const MIN_SAMPLES: i32 = 30;
-const NUM_QVIS_PIXELS: usize = 20;
+const PREVIEW_SAMPLES_TRACKBAR_NAME: &str = "Preview samples";
+const PREVIEW_SAMPLES_TRACKBAR_MINMAX: [i32; 2] = [0, 100];
+const LEGEND_LINE_HEIGHT: i32 = 28;
+const PROGRESS_BAR_WIDTH: i32 = 300;
+/// Whether selecting pixels that already belong to another sticker reassigns them rather than leaving them be
+const STEAL_OVERLAPPING_PIXELS: bool = false;
+/// The largest fraction of the image that a proposed sticker may cover, which keeps the background from being proposed
+const PROPOSAL_MAX_FRACTION: (i32, i32) = (1, 20);
 
 enum UIState {
     OpenCVError(opencv::Error),
@@ -43,96 +64,120 @@ struct State {
     erosion_kernel: Mat,
     erosion_kernel_times_two: Mat,
     displayed_img: Mat,
+    /// The part of `displayed_img` that the window shows, with the text drawn on top so that it stays readable at any zoom
+    shown_img: Mat,
+    /// Which part of the image is shown. Every position other than the window's own, like `maybe_xy` and the drag, is in pixels of the full image.
+    viewport: Viewport,
     mask_roi: Rect,
     pixel_assignment: Box<[Pixel]>,
     work: Vec<(Face, Vec<ArcIntern<str>>)>,
-    current_sticker_idx: usize,
+    navigation: StickerNavigation,
+    erosion_size: i32,
     upper_flood_fill_diff: i32,
+    /// How many pixels from the interior of the selection to highlight. This is only a preview; the whole selection is assigned to the sticker.
+    num_preview_samples: usize,
+    /// Which pixels are previewed for a drag, which is the same on every run of the UI that is given the same seed
+    preview_seed: u64,
     maybe_drag_origin: Option<(i32, i32)>,
     maybe_drag_xy: Option<(i32, i32)>,
     maybe_xy: Option<(i32, i32)>,
     dragging: bool,
+    legend: Vec<String>,
+    show_overlay: bool,
+    /// A warning about the last action, shown until the next sticker is assigned or skipped
+    warning: Option<String>,
+    /// What the faces of the puzzle are made of, which decides how loosely stickers are proposed
+    surface: Surface,
+    /// Regions of the image that look like stickers and haven't been assigned yet, as pixel indices
+    proposals: Vec<Vec<usize>>,
+    /// Which of the proposals is selected, if the selection came from one rather than from a drag
+    proposal: Option<usize>,
     ui: UIState,
 }
 
-fn c(x: i32, n: i32) -> i32 {
-    (x + n) / 6
+/// Draw white text with a black outline so that it is readable on top of any image
+fn put_outlined_text(img: &mut Mat, text: &str, org: Point, scale: f64) -> opencv::Result<()> {
+    #[allow(clippy::cast_possible_truncation)]
+    let thickness = (scale * 2.0).round() as i32;
+    imgproc::put_text(
+        img,
+        text,
+        org,
+        imgproc::FONT_HERSHEY_SIMPLEX,
+        scale,
+        Scalar::all(0.0),
+        thickness + 3,
+        imgproc::LINE_8,
+        false,
+    )?;
+    imgproc::put_text(
+        img,
+        text,
+        org,
+        imgproc::FONT_HERSHEY_SIMPLEX,
+        scale,
+        Scalar::all(f64::from(MAX_PIXEL_VALUE)),
+        thickness,
+        imgproc::LINE_8,
+        false,
+    )
 }
 
-fn perm6_from_number(mut n: u16) -> [i32; 6] {
-    const FACT: [u16; 7] = [1, 1, 2, 6, 24, 120, 720];
-    n %= FACT[6];
-
-    let mut elems = vec![0, 1, 2, 3, 4, 5];
-    let mut result = [0; 6];
+/// Draw which sticker is being assigned out of how many, along with a bar showing how many are done
+fn draw_progress(state: &mut State) -> opencv::Result<()> {
+    let num_stickers = state.work.len();
+    let num_assigned = num_stickers - state.navigation.unassigned().len();
 
-    for i in 0..6 {
-        let f = FACT[5 - i];
-        let idx = (n / f) as usize;
-        n %= f;
-
-        result[i] = elems.remove(idx);
+    let bar = Rect::new(10, 60, PROGRESS_BAR_WIDTH, 20);
+    let filled_width = PROGRESS_BAR_WIDTH * i32::try_from(num_assigned).unwrap()
+        / i32::try_from(num_stickers).unwrap();
+    if filled_width > 0 {
+        imgproc::rectangle(
+            &mut state.shown_img,
+            Rect::new(bar.x, bar.y, filled_width, bar.height),
+            Scalar::all(f64::from(MAX_PIXEL_VALUE)),
+            FILLED,
+            LINE_8,
+            0,
+        )?;
     }
+    imgproc::rectangle(&mut state.shown_img, bar, Scalar::all(0.0), 2, LINE_8, 0)?;
 
-    result
+    put_outlined_text(
+        &mut state.shown_img,
+        &format!(
+            "Sticker {} of {}, {} assigned",
+            state.navigation.current() + 1,
+            num_stickers,
+            num_assigned
+        ),
+        Point::new(bar.x + bar.width + 10, bar.y + bar.height - 2),
+        0.7,
+    )
 }
 
 fn update_display(state: &mut State) -> opencv::Result<()> {
     state.img.copy_to(&mut state.displayed_img)?;
+    if state.show_overlay {
+        draw_overlay(&mut state.displayed_img, &state.pixel_assignment)?;
+    }
     let ran;
-    let shuffled;
-    let mut nonzeroes: Vec<usize>;
+    let preview_samples;
     if let Some((drag_origin_x, drag_origin_y)) = state.maybe_drag_origin
         && let Some((drag_x, drag_y)) = state.maybe_drag_xy
     {
         ran = true;
-        #[allow(clippy::cast_possible_truncation)]
-        let distance = (f64::from(drag_x - drag_origin_x)
-            .hypot(f64::from(drag_y - drag_origin_y))
-            .powf(1.5)
-            / 20.0) as i32;
-        // angle is between [-pi, pi]; add pi and multiply by 360/pi to get a range
-        // of [0, 720] throughout the full circle which is 6!
-        //
-        // multiply it again by 20 to increase the periodicity
-        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
-        let angle = (f64::from(drag_y - drag_origin_y).atan2(f64::from(drag_x - drag_origin_x))
-            + PI * 360.0 / PI * 20.0) as u16;
-        let perm6 = perm6_from_number(angle);
-
-        Mat::roi_mut(&mut state.grayscale_mask, state.mask_roi)?.set_to_def(&Scalar::all(0.0))?;
-        imgproc::flood_fill_mask(
+        flood_fill_drag(
             &mut state.img,
             &mut state.grayscale_mask,
-            Point::new(drag_origin_x, drag_origin_y),
-            Scalar::default(), // ignored
-            &mut Rect::default(),
-            Scalar::from((
-                c(distance, perm6[0]),
-                c(distance, perm6[1]),
-                c(distance, perm6[2]),
-            )),
-            Scalar::from((
-                c(
-                    distance,
-                    perm6[3]
-                        + state.upper_flood_fill_diff * MAX_PIXEL_VALUE
-                            / UPPER_DIFF_TRACKBAR_MINDEFMAX[2],
-                ),
-                c(
-                    distance,
-                    perm6[4]
-                        + state.upper_flood_fill_diff * MAX_PIXEL_VALUE
-                            / UPPER_DIFF_TRACKBAR_MINDEFMAX[2],
-                ),
-                c(
-                    distance,
-                    perm6[5]
-                        + state.upper_flood_fill_diff * MAX_PIXEL_VALUE
-                            / UPPER_DIFF_TRACKBAR_MINDEFMAX[2],
-                ),
-            )),
-            4 | FLOODFILL_FIXED_RANGE | FLOODFILL_MASK_ONLY | (MAX_PIXEL_VALUE << 8),
+            state.mask_roi,
+            (drag_origin_x, drag_origin_y),
+            flood_fill_tolerances(
+                (drag_origin_x, drag_origin_y),
+                (drag_x, drag_y),
+                state.upper_flood_fill_diff,
+                UPPER_DIFF_TRACKBAR_MINMAX[1],
+            ),
         )?;
 
         imgproc::erode(
@@ -172,29 +217,10 @@ fn update_display(state: &mut State) -> opencv::Result<()> {
             &mut state.grayscale_mask
         };
 
-        let rows = to_dilate.rows();
-        let cols = to_dilate.cols();
-        to_dilate
-            .roi_mut(Rect::new(0, 0, cols, 2))?
-            .set_to_def(&Scalar::all(0.0))?;
-        to_dilate
-            .roi_mut(Rect::new(0, rows - 2, cols, 2))?
-            .set_to_def(&Scalar::all(0.0))?;
-        to_dilate
-            .roi_mut(Rect::new(0, 2, 2, rows - 4))?
-            .set_to_def(&Scalar::all(0.0))?;
-        to_dilate
-            .roi_mut(Rect::new(cols - 2, 2, 2, rows - 4))?
-            .set_to_def(&Scalar::all(0.0))?;
-        // For some reason dilation doesn't work on ROIs
-        imgproc::dilate(
+        dilate_padded_mask(
             to_dilate,
             &mut state.tmp_mask,
             &state.erosion_kernel_times_two,
-            DEF_ANCHOR,
-            1,
-            BORDER_CONSTANT,
-            imgproc::morphology_default_border_value()?,
         )?;
         std::mem::swap(&mut state.cleaned_grayscale_mask, &mut state.tmp_mask);
 
@@ -245,23 +271,12 @@ fn update_display(state: &mut State) -> opencv::Result<()> {
         let mask_to_randomly_sample =
             Mat::roi(mask_to_randomly_sample, state.mask_roi)?.clone_pointee();
 
-        let mut seed = [0; 32];
-        seed[0..4].copy_from_slice(&drag_origin_x.to_be_bytes());
-        seed[4..8].copy_from_slice(&drag_origin_y.to_be_bytes());
-        let mut rng = SmallRng::from_seed(seed);
-        nonzeroes = mask_to_randomly_sample
-            .data_bytes()?
-            .iter()
-            .enumerate()
-            .filter_map(|(i, &value)| {
-                if value == u8::try_from(MAX_PIXEL_VALUE).unwrap() {
-                    Some(i)
-                } else {
-                    None
-                }
-            })
-            .collect();
-        shuffled = nonzeroes.partial_shuffle(&mut rng, NUM_QVIS_PIXELS).0;
+        preview_samples = sample_selected_pixels(
+            mask_to_randomly_sample.data_bytes()?,
+            (drag_origin_x, drag_origin_y),
+            state.num_preview_samples,
+            state.preview_seed,
+        );
 
         imgproc::line(
             &mut state.displayed_img,
@@ -291,47 +306,24 @@ fn update_display(state: &mut State) -> opencv::Result<()> {
             0,
         )?;
     } else {
-        ran = false;
-        shuffled = &mut [];
+        ran = state.proposal.is_some();
+        preview_samples = Vec::new();
     }
-    imgproc::put_text(
-        &mut state.displayed_img,
-        &format!(
-            "Choose {} on {}",
-            state.work[state.current_sticker_idx]
-                .1
-                .iter()
-                .map(std::string::ToString::to_string)
-                .collect::<String>(),
-            state.work[state.current_sticker_idx].0.color
-        ),
-        Point::new(10, 40),
-        imgproc::FONT_HERSHEY_SIMPLEX,
-        1.1,
-        Scalar::all(0.0),
-        5,
-        imgproc::LINE_8,
-        false,
-    )?;
-    imgproc::put_text(
-        &mut state.displayed_img,
-        &format!(
-            "Choose {} on {}",
-            state.work[state.current_sticker_idx]
-                .1
-                .iter()
-                .map(std::string::ToString::to_string)
-                .collect::<String>(),
-            state.work[state.current_sticker_idx].0.color
-        ),
-        Point::new(10, 40),
-        imgproc::FONT_HERSHEY_SIMPLEX,
-        1.1,
-        Scalar::all(f64::from(MAX_PIXEL_VALUE)),
-        2,
-        imgproc::LINE_8,
-        false,
-    )?;
+    let current_sticker_idx = state.navigation.current();
+    let prompt = format!(
+        "Choose {} on {}{}",
+        state.work[current_sticker_idx]
+            .1
+            .iter()
+            .map(std::string::ToString::to_string)
+            .collect::<String>(),
+        state.work[current_sticker_idx].0.color,
+        match state.navigation.status(current_sticker_idx) {
+            StickerStatus::Pending => "",
+            StickerStatus::Assigned => " (redoing)",
+            StickerStatus::Skipped => " (skipped)",
+        }
+    );
     if ran {
         let cleaned_grayscale_mask_cropped =
             Mat::roi(&state.cleaned_grayscale_mask, state.mask_roi)?;
@@ -351,8 +343,7 @@ fn update_display(state: &mut State) -> opencv::Result<()> {
         // dbg!(displayed_image_data_bytes_mut.len());
         // dbg!(cols);
         // dbg!(state.img.rows() as usize + 1);
-        // dbg!(&state.samples);
-        for i in shuffled.iter().copied() {
+        for i in preview_samples {
             // dbg!(i, cols);
             // let row = i / (cols + 0);
             // let num_padding_pixels = 2 + 4 * (row - 3);
@@ -364,23 +355,71 @@ fn update_display(state: &mut State) -> opencv::Result<()> {
             ]);
         }
     }
-    highgui::imshow(WINDOW_NAME, &state.displayed_img)?;
+
+    let (x, y, width, height) = state.viewport.visible();
+    let crop = Mat::roi(&state.displayed_img, Rect::new(x, y, width, height))?;
+    let (shown_width, shown_height) = state.viewport.shown_size();
+    if (shown_width, shown_height) == (width, height) {
+        crop.copy_to(&mut state.shown_img)?;
+    } else {
+        imgproc::resize(
+            &crop,
+            &mut state.shown_img,
+            Size::new(shown_width, shown_height),
+            0.0,
+            0.0,
+            imgproc::INTER_AREA, // best for downscaling
+        )?;
+    }
+    put_outlined_text(&mut state.shown_img, &prompt, Point::new(10, 40), 1.1)?;
+    draw_progress(state)?;
+    if let Some(warning) = &state.warning {
+        put_outlined_text(&mut state.shown_img, warning, Point::new(10, 120), 0.8)?;
+    }
+    let bottom = state.shown_img.rows() - 10;
+    for (line, y) in state
+        .legend
+        .iter()
+        .rev()
+        .zip((0..).map(|i| bottom - LEGEND_LINE_HEIGHT * i))
+    {
+        put_outlined_text(&mut state.shown_img, line, Point::new(10, y), 0.7)?;
+    }
+    highgui::imshow(WINDOW_NAME, &state.shown_img)?;
     Ok(())
 }
 
-fn mouse_callback(state: &mut State, event: i32, x: i32, y: i32) -> opencv::Result<()> {
+fn mouse_callback(state: &mut State, event: i32, x: i32, y: i32, flags: i32) -> opencv::Result<()> {
     if event == highgui::EVENT_MOUSEMOVE {
-        state.maybe_xy = Some((x, y));
+        let xy = state.viewport.to_image((x, y));
+        state.maybe_xy = Some(xy);
         if state.dragging {
-            state.maybe_drag_xy = Some((x, y));
+            state.maybe_drag_xy = Some(xy);
             update_display(state)?;
         }
+    } else if event == highgui::EVENT_MOUSEWHEEL {
+        let delta = highgui::get_mouse_wheel_delta(flags)?;
+        if delta != 0 {
+            zoom_callback(state, delta > 0, (x, y))?;
+        }
     }
 
     Ok(())
 }
 
+/// Zoom in or out by a step, keeping the pixel at `point` of the window where it is
+fn zoom_callback(state: &mut State, zoom_in: bool, point: (i32, i32)) -> opencv::Result<()> {
+    let factor = if zoom_in {
+        ZOOM_STEP
+    } else {
+        ZOOM_STEP.recip()
+    };
+    state.viewport.zoom_by(factor, point);
+    update_display(state)
+}
+
 fn erosion_kernel_trackbar_callback(state: &mut State, pos: i32) -> opencv::Result<()> {
+    state.erosion_size = pos;
     state.erosion_kernel =
         imgproc::get_structuring_element_def(EROSION_KERNEL_MORPH_SHAPE, Size::new(pos, pos))?;
     state.erosion_kernel_times_two = imgproc::get_structuring_element_def(
@@ -391,6 +430,12 @@ fn erosion_kernel_trackbar_callback(state: &mut State, pos: i32) -> opencv::Resu
     Ok(())
 }
 
+fn preview_samples_trackbar_callback(state: &mut State, pos: i32) -> opencv::Result<()> {
+    state.num_preview_samples = usize::try_from(pos).unwrap_or(0);
+    update_display(state)?;
+    Ok(())
+}
+
 fn light_tolerance_trackbar_callback(state: &mut State, pos: i32) -> opencv::Result<()> {
     state.upper_flood_fill_diff = pos;
     update_display(state)?;
@@ -404,44 +449,278 @@ fn submit_button_callback(state: &mut State) -> opencv::Result<()> {
         state.pixel_assignment.len()
     );
 
+    let current_sticker_idx = state.navigation.current();
+    // Forget the previous assignment if the sticker is being redone
+    clear_sticker(&mut state.pixel_assignment, current_sticker_idx);
+
     let h = cleaned_grayscale_mask_cropped.rows();
     let w = cleaned_grayscale_mask_cropped.cols();
-    let mut count = 0;
+    let mut selected = Vec::new();
     for y in 0..h {
         for x in 0..w {
             let value = *cleaned_grayscale_mask_cropped.at_2d::<u8>(y, x)?;
-            let idx = usize::try_from(y * w + x).unwrap();
             if i32::from(value) == MAX_PIXEL_VALUE {
-                count += 1;
-                state.pixel_assignment[idx] = Pixel::Sticker(state.current_sticker_idx);
+                selected.push(usize::try_from(y * w + x).unwrap());
             }
         }
     }
 
+    let overlapping = overlapping_pixels(&selected, &state.pixel_assignment, current_sticker_idx);
+    state.warning = if overlapping.is_empty() {
+        None
+    } else {
+        let warning = format!(
+            "{} pixels of sticker {} were already assigned; {}",
+            overlapping.len(),
+            current_sticker_idx,
+            if STEAL_OVERLAPPING_PIXELS {
+                "reassigned them"
+            } else {
+                "left them be"
+            }
+        );
+        leptos::logging::warn!("{warning}");
+        Some(warning)
+    };
+    if !STEAL_OVERLAPPING_PIXELS {
+        // Both lists are sorted
+        let mut overlapping = overlapping.iter().peekable();
+        selected.retain(|idx| overlapping.next_if_eq(&idx).is_none());
+    }
+
+    for &idx in &selected {
+        state.pixel_assignment[idx] = Pixel::Sticker(current_sticker_idx);
+    }
+
     leptos::logging::log!(
         "Assigned {} pixels to sticker {}",
-        count,
-        state.current_sticker_idx
+        selected.len(),
+        current_sticker_idx
     );
 
-    state.current_sticker_idx += 1;
-    if state.current_sticker_idx == state.work.len() {
-        state.ui = UIState::Finished;
+    state.navigation.assign();
+    finish_if_done(state);
+    state.maybe_drag_origin = None;
+    // Move on to the next proposal so that the user can keep confirming them
+    if let Some(proposal) = state.proposal {
+        state.proposals.remove(proposal);
+        state.proposal = (!state.proposals.is_empty()).then(|| proposal % state.proposals.len());
+        select_proposal(state)?;
     }
+    update_display(state)?;
+
+    Ok(())
+}
+
+/// Select the next region that looks like a sticker, proposing regions first if there isn't one selected already. Pixels that are already assigned are left out of the proposals.
+fn propose_callback(state: &mut State) -> opencv::Result<()> {
+    state.proposal = match state.proposal {
+        Some(proposal) => Some((proposal + 1) % state.proposals.len()),
+        None => {
+            let pixels = state
+                .img
+                .data_typed::<Vec3b>()?
+                .iter()
+                .map(|pixel| pixel.0)
+                .collect::<Vec<_>>();
+            let max_size = pixels.len() * usize::try_from(PROPOSAL_MAX_FRACTION.0).unwrap()
+                / usize::try_from(PROPOSAL_MAX_FRACTION.1).unwrap();
+
+            state.proposals = propose_regions(
+                &pixels,
+                usize::try_from(state.img.cols()).unwrap(),
+                state.surface.proposal_tolerance(),
+                &(usize::try_from(MIN_SAMPLES).unwrap()..=max_size),
+            );
+            state.proposals.retain(|region| {
+                region
+                    .iter()
+                    .all(|&idx| matches!(state.pixel_assignment[idx], Pixel::Unassigned))
+            });
+            leptos::logging::log!("Proposed {} stickers", state.proposals.len());
+
+            (!state.proposals.is_empty()).then_some(0)
+        }
+    };
+
     state.maybe_drag_origin = None;
+    select_proposal(state)?;
     update_display(state)?;
 
     Ok(())
 }
 
+/// Put the selected proposal into the mask that assigning a sticker reads the selection from
+fn select_proposal(state: &mut State) -> opencv::Result<()> {
+    let Some(proposal) = state.proposal else {
+        return Ok(());
+    };
+
+    Mat::roi_mut(&mut state.cleaned_grayscale_mask, state.mask_roi)?
+        .set_to_def(&Scalar::all(0.0))?;
+    Mat::roi_mut(&mut state.eroded_grayscale_mask, state.mask_roi)?
+        .set_to_def(&Scalar::all(0.0))?;
+
+    let w = usize::try_from(state.img.cols()).unwrap();
+    for &idx in &state.proposals[proposal] {
+        let x = i32::try_from(idx % w).unwrap();
+        let y = i32::try_from(idx / w).unwrap();
+        *state
+            .cleaned_grayscale_mask
+            .at_2d_mut::<u8>(y + MASK_PADDING, x + MASK_PADDING)? =
+            MAX_PIXEL_VALUE.try_into().unwrap();
+    }
+
+    Ok(())
+}
+
+fn skip_callback(state: &mut State) -> opencv::Result<()> {
+    leptos::logging::log!("Skipped sticker {}", state.navigation.current());
+
+    state.navigation.skip();
+    state.warning = None;
+    finish_if_done(state);
+    state.maybe_drag_origin = None;
+    update_display(state)?;
+
+    Ok(())
+}
+
+fn navigate_callback(state: &mut State, forwards: bool) -> opencv::Result<()> {
+    if forwards {
+        state.navigation.next();
+    } else {
+        state.navigation.previous();
+    }
+    state.maybe_drag_origin = None;
+    update_display(state)?;
+
+    Ok(())
+}
+
+fn finish_if_done(state: &mut State) {
+    if !state.navigation.is_finished() {
+        return;
+    }
+
+    let unassigned = state.navigation.unassigned();
+    if !unassigned.is_empty() {
+        leptos::logging::log!("Stickers left unassigned: {unassigned:?}");
+    }
+
+    let summary = AssignmentSummary::new(&state.pixel_assignment, state.work.len());
+    for (sticker, count) in summary.sticker_pixels.iter().enumerate() {
+        leptos::logging::log!("Sticker {sticker}: {count} pixels");
+    }
+    leptos::logging::log!(
+        "{} pixels unassigned, {} pixels used for white balance",
+        summary.unassigned_pixels,
+        summary.white_balance_pixels
+    );
+    let sparse = summary.sparse_stickers(MIN_SAMPLES.try_into().unwrap());
+    if !sparse.is_empty() {
+        leptos::logging::warn!(
+            "Stickers with fewer than {MIN_SAMPLES} pixels, which may be recognized poorly: {sparse:?}"
+        );
+    }
+
+    state.ui = UIState::Finished;
+}
+
 fn restart_button_callback(state: &mut State) -> opencv::Result<()> {
-    state.current_sticker_idx = 0;
+    state.navigation.restart();
+    state.warning = None;
+    state.proposals.clear();
+    state.proposal = None;
     state.pixel_assignment.fill(Pixel::Unassigned);
     state.maybe_drag_origin = None;
     update_display(state)?;
     Ok(())
 }
 
+/// Save the progress so far, so that it can be resumed if the UI fails. Failing to save is only logged, since the UI can carry on without it.
+fn save_snapshot(state: &State, autosave: &Autosave) {
+    let snapshot = AssignmentSnapshot {
+        current_sticker: state.navigation.current(),
+        statuses: state.navigation.statuses().to_vec(),
+        pixel_assignment: state.pixel_assignment.clone(),
+    };
+
+    if let Err(e) = snapshot.save(&autosave.path) {
+        leptos::logging::warn!(
+            "Failed to save the assignment progress to {}: {e}",
+            autosave.path.display()
+        );
+    }
+}
+
+/// Load the progress saved by a previous run of the UI, if there is any and it is for an image and puzzle of the same size
+fn load_snapshot(
+    autosave: &Autosave,
+    num_pixels: usize,
+    num_stickers: usize,
+) -> Option<AssignmentSnapshot> {
+    match AssignmentSnapshot::load(&autosave.path) {
+        Ok(Some(snapshot))
+            if snapshot.pixel_assignment.len() == num_pixels
+                && snapshot.statuses.len() == num_stickers =>
+        {
+            Some(snapshot)
+        }
+        Ok(Some(_)) => {
+            leptos::logging::warn!(
+                "Ignoring the assignment progress saved in {}, which is for a different image or puzzle",
+                autosave.path.display()
+            );
+            None
+        }
+        Ok(None) => None,
+        Err(e) => {
+            leptos::logging::warn!(
+                "Failed to load the assignment progress from {}: {e}",
+                autosave.path.display()
+            );
+            None
+        }
+    }
+}
+
+/// Load the trackbar settings saved by a previous run of the UI, or the defaults for `surface` if there aren't any
+fn load_params(params_path: Option<&Path>, surface: Surface) -> AssignmentParams {
+    let defaults = surface.default_params();
+    let Some(path) = params_path else {
+        return defaults;
+    };
+
+    match AssignmentParams::load(path) {
+        Ok(Some(params)) => params,
+        Ok(None) => defaults,
+        Err(e) => {
+            leptos::logging::warn!(
+                "Failed to load the assignment settings from {}: {e}",
+                path.display()
+            );
+            defaults
+        }
+    }
+}
+
+/// Save the trackbar settings for the next run of the UI. Failing to save is only logged, since the assignment itself is done.
+fn save_params(state: &State, params_path: &Path) {
+    let params = AssignmentParams {
+        erosion_size: state.erosion_size,
+        upper_diff: state.upper_flood_fill_diff,
+        preview_samples: i32::try_from(state.num_preview_samples).unwrap_or(i32::MAX),
+    };
+
+    if let Err(e) = params.save(params_path) {
+        leptos::logging::warn!(
+            "Failed to save the assignment settings to {}: {e}",
+            params_path.display()
+        );
+    }
+}
+
 fn toggle_dragging(state: &mut State) {
     if state.dragging {
         state.dragging = false;
@@ -456,48 +735,76 @@ fn toggle_dragging(state: &mut State) {
         }
         state.maybe_drag_xy = Some((x, y));
         state.dragging = true;
+        // Dragging out a selection overrides the proposal
+        state.proposal = None;
     }
 }
 
-/// Displays a UI for assignment the stickers of a `PuzzleGeometry`
+/// A single sticker to assign again in an assignment that is otherwise finished, for when the sticker is recognized badly because of where its pixels were picked
+#[derive(Debug, Clone)]
+pub struct RedoSticker {
+    /// The finished assignment, which must be for the same image
+    pub pixel_assignment: Box<[Pixel]>,
+    pub sticker: usize,
+}
+
+/// Displays a UI for assignment the stickers of a `PuzzleGeometry` in the image at `image_path`, using `key_bindings` for the keyboard shortcuts. The UI closes early and returns `None` once `is_cancelled` returns true.
+///
+/// If `autosave` is given, the progress is saved to its path every interval and when the UI fails or is cancelled, and the next run of the UI picks up where it left off. The saved progress is deleted once the assignment is finished.
+///
+/// If `params_path` is given, the trackbars start where they were left the last time an assignment was finished with the same path, and where they are left is saved there once this assignment is finished. Otherwise, and until anything is saved there, they start from the defaults for `surface`, which also decides how loosely stickers are proposed.
+///
+/// The image is assigned at its full resolution, so that the assignment lines up with the frames of the same camera that are recognized with it. Images too large to show quickly are shown downscaled as an overview, and the mouse wheel and the zoom keys magnify the part of the image under the pointer for selecting pixels precisely.
+///
+/// If `seed` is given, the pixels that are previewed for each drag are picked the same way on every run, for reproducing what was seen while debugging. Otherwise they are seeded from entropy.
+///
+/// If `redo` is given, only its sticker is assigned: the sticker's pixels are cleared, every other sticker keeps its pixels, and the UI finishes as soon as the sticker is assigned or skipped. The progress isn't autosaved in that case, since it would take the place of the progress of a whole assignment.
 ///
 /// # Errors
 ///
 /// This function will return an `OpenCV` error.
 pub fn pixel_assignment_ui(
     puzzle_geometry: &PuzzleGeometry,
-) -> Result<Box<[Pixel]>, opencv::Error> {
+    image_path: &Path,
+    key_bindings: KeyBindings,
+    autosave: Option<&Autosave>,
+    params_path: Option<&Path>,
+    surface: Surface,
+    redo: Option<RedoSticker>,
+    seed: Option<u64>,
+    is_cancelled: &mut dyn FnMut() -> bool,
+) -> Result<Option<Box<[Pixel]>>, opencv::Error> {
     highgui::named_window(
         WINDOW_NAME,
         highgui::WINDOW_NORMAL | highgui::WINDOW_KEEPRATIO | highgui::WINDOW_GUI_EXPANDED,
     )?;
 
-    let mut img = imgcodecs::imread_def("input.jpg")?;
+    let img = imgcodecs::imread_def(&image_path.to_string_lossy())?;
+    if img.empty() {
+        return Err(opencv::Error::new(
+            opencv::core::StsError,
+            format!("Couldn't read the image {}", image_path.display()),
+        ));
+    }
 
     let w = img.cols();
     let h = img.rows();
-    let mut pixel_count = w * h;
+    let pixel_count = w.checked_mul(h).ok_or_else(|| {
+        opencv::Error::new(
+            opencv::core::StsError,
+            format!("The image {} has too many pixels", image_path.display()),
+        )
+    })?;
 
     if pixel_count > MAX_PIXEL_COUNT {
-        let scale = (f64::from(MAX_PIXEL_COUNT) / f64::from(pixel_count)).sqrt();
-        #[allow(clippy::cast_possible_truncation)]
-        let new_w = (f64::from(w) * scale).round() as i32;
-        #[allow(clippy::cast_possible_truncation)]
-        let new_h = (f64::from(h) * scale).round() as i32;
-        pixel_count = new_w * new_h;
-        let mut resized = Mat::default();
-        imgproc::resize(
-            &img,
-            &mut resized,
-            Size::new(new_w, new_h),
-            0.0,
-            0.0,
-            imgproc::INTER_AREA, // best for downscaling
-        )?;
-        img = resized;
+        leptos::logging::log!(
+            "The image has {pixel_count} pixels, so it is shown downscaled to {MAX_PIXEL_COUNT} until it is zoomed in on"
+        );
     }
 
     let displayed_img = Mat::zeros(img.rows(), img.cols(), CV_8UC3)?.to_mat()?;
+    let shown_img = Mat::default();
+    let viewport = Viewport::new(w, h, MAX_PIXEL_COUNT);
     let grayscale_mask = Mat::zeros(img.rows() + 2, img.cols() + 2, CV_8UC1)?.to_mat()?;
     let cleaned_grayscale_mask = grayscale_mask.clone();
     let eroded_grayscale_mask = grayscale_mask.clone();
@@ -506,17 +813,56 @@ pub fn pixel_assignment_ui(
     let erosion_kernel = Mat::default();
     let erosion_kernel_times_two = Mat::default();
 
-    let pixel_assignment = vec![
-        Pixel::Unassigned;
-        pixel_count.try_into().map_err(|e| opencv::Error::new(
-            opencv::core::StsError,
-            format!("Too many pixels: {e}"),
-        ))?
-    ]
-    .into_boxed_slice();
-
+    let pixel_count = usize::try_from(pixel_count)
+        .map_err(|e| opencv::Error::new(opencv::core::StsError, format!("Too many pixels: {e}")))?;
     let work = puzzle_geometry.stickers().to_vec();
 
+    let autosave = autosave.filter(|_| redo.is_none());
+
+    let (pixel_assignment, navigation) = if let Some(redo) = redo {
+        if redo.pixel_assignment.len() != pixel_count || redo.sticker >= work.len() {
+            return Err(opencv::Error::new(
+                opencv::core::StsError,
+                format!(
+                    "Can't redo sticker {} of an assignment of {} pixels in an image of {pixel_count} pixels",
+                    redo.sticker,
+                    redo.pixel_assignment.len()
+                ),
+            ));
+        }
+
+        let mut pixel_assignment = redo.pixel_assignment;
+        let cleared = clear_sticker(&mut pixel_assignment, redo.sticker);
+        leptos::logging::log!(
+            "Redoing sticker {}, which had {cleared} pixels",
+            redo.sticker
+        );
+
+        (
+            pixel_assignment,
+            StickerNavigation::redo(work.len(), redo.sticker),
+        )
+    } else {
+        match autosave.and_then(|autosave| load_snapshot(autosave, pixel_count, work.len())) {
+            Some(snapshot) => {
+                leptos::logging::log!(
+                    "Resuming the assignment from sticker {}",
+                    snapshot.current_sticker
+                );
+                (
+                    snapshot.pixel_assignment,
+                    StickerNavigation::resume(snapshot.statuses, snapshot.current_sticker),
+                )
+            }
+            None => (
+                vec![Pixel::Unassigned; pixel_count].into_boxed_slice(),
+                StickerNavigation::new(work.len()),
+            ),
+        }
+    };
+
+    let params = load_params(params_path, surface);
+
     let state = Arc::new(Mutex::new(State {
         img,
         tmp_mask,
@@ -526,15 +872,26 @@ pub fn pixel_assignment_ui(
         erosion_kernel,
         erosion_kernel_times_two,
         displayed_img,
+        shown_img,
+        viewport,
         mask_roi,
         pixel_assignment,
         work,
-        current_sticker_idx: 0,
+        navigation,
+        erosion_size: 0,
         upper_flood_fill_diff: 0,
+        num_preview_samples: 0,
+        preview_seed: seed.unwrap_or_else(rand::random),
         maybe_drag_origin: None,
         maybe_drag_xy: None,
         maybe_xy: None,
         dragging: false,
+        legend: key_bindings.legend(),
+        show_overlay: true,
+        warning: None,
+        surface,
+        proposals: Vec::new(),
+        proposal: None,
         ui: UIState::Assigning,
     }));
 
@@ -542,10 +899,10 @@ pub fn pixel_assignment_ui(
         let state = Arc::clone(&state);
         highgui::set_mouse_callback(
             WINDOW_NAME,
-            Some(Box::new(move |event, x, y, _flags| {
+            Some(Box::new(move |event, x, y, flags| {
                 #[allow(clippy::missing_panics_doc)]
                 let mut state = state.lock().unwrap();
-                if let Err(e) = mouse_callback(&mut state, event, x, y) {
+                if let Err(e) = mouse_callback(&mut state, event, x, y, flags) {
                     state.ui = UIState::OpenCVError(e);
                 }
             })),
@@ -557,7 +914,7 @@ pub fn pixel_assignment_ui(
             EROSION_SIZE_TRACKBAR_NAME,
             WINDOW_NAME,
             None,
-            EROSION_SIZE_TRACKBAR_MINDEFMAX[2],
+            EROSION_SIZE_TRACKBAR_MINMAX[1],
             Some(Box::new(move |pos| {
                 #[allow(clippy::missing_panics_doc)]
                 let mut state = state.lock().unwrap();
@@ -569,12 +926,15 @@ pub fn pixel_assignment_ui(
         highgui::set_trackbar_pos(
             EROSION_SIZE_TRACKBAR_NAME,
             WINDOW_NAME,
-            EROSION_SIZE_TRACKBAR_MINDEFMAX[1],
+            params.erosion_size.clamp(
+                EROSION_SIZE_TRACKBAR_MINMAX[0],
+                EROSION_SIZE_TRACKBAR_MINMAX[1],
+            ),
         )?;
         highgui::set_trackbar_min(
             EROSION_SIZE_TRACKBAR_NAME,
             WINDOW_NAME,
-            EROSION_SIZE_TRACKBAR_MINDEFMAX[0],
+            EROSION_SIZE_TRACKBAR_MINMAX[0],
         )?;
     }
     {
@@ -583,7 +943,7 @@ pub fn pixel_assignment_ui(
             UPPER_DIFF_TRACKBAR_NAME,
             WINDOW_NAME,
             None,
-            UPPER_DIFF_TRACKBAR_MINDEFMAX[2],
+            UPPER_DIFF_TRACKBAR_MINMAX[1],
             Some(Box::new(move |pos| {
                 #[allow(clippy::missing_panics_doc)]
                 let mut state = state.lock().unwrap();
@@ -595,12 +955,43 @@ pub fn pixel_assignment_ui(
         highgui::set_trackbar_pos(
             UPPER_DIFF_TRACKBAR_NAME,
             WINDOW_NAME,
-            UPPER_DIFF_TRACKBAR_MINDEFMAX[1],
+            params
+                .upper_diff
+                .clamp(UPPER_DIFF_TRACKBAR_MINMAX[0], UPPER_DIFF_TRACKBAR_MINMAX[1]),
         )?;
         highgui::set_trackbar_min(
             UPPER_DIFF_TRACKBAR_NAME,
             WINDOW_NAME,
-            UPPER_DIFF_TRACKBAR_MINDEFMAX[0],
+            UPPER_DIFF_TRACKBAR_MINMAX[0],
+        )?;
+    }
+    {
+        let state = Arc::clone(&state);
+        highgui::create_trackbar(
+            PREVIEW_SAMPLES_TRACKBAR_NAME,
+            WINDOW_NAME,
+            None,
+            PREVIEW_SAMPLES_TRACKBAR_MINMAX[1],
+            Some(Box::new(move |pos| {
+                #[allow(clippy::missing_panics_doc)]
+                let mut state = state.lock().unwrap();
+                if let Err(e) = preview_samples_trackbar_callback(&mut state, pos) {
+                    state.ui = UIState::OpenCVError(e);
+                }
+            })),
+        )?;
+        highgui::set_trackbar_pos(
+            PREVIEW_SAMPLES_TRACKBAR_NAME,
+            WINDOW_NAME,
+            params.preview_samples.clamp(
+                PREVIEW_SAMPLES_TRACKBAR_MINMAX[0],
+                PREVIEW_SAMPLES_TRACKBAR_MINMAX[1],
+            ),
+        )?;
+        highgui::set_trackbar_min(
+            PREVIEW_SAMPLES_TRACKBAR_NAME,
+            WINDOW_NAME,
+            PREVIEW_SAMPLES_TRACKBAR_MINMAX[0],
         )?;
     }
     {
@@ -623,21 +1014,40 @@ pub fn pixel_assignment_ui(
         update_display(&mut state)?;
     }
 
-    let mut in_toggle_dragging = false;
+    let mut key_dispatcher = KeyDispatcher::new(key_bindings);
+    let mut last_saved = Instant::now();
     loop {
-        const D: i32 = 100;
-        const R: i32 = 114;
-        const S: i32 = 115;
-
         {
             #[allow(clippy::missing_panics_doc)]
             let state = state.lock().unwrap();
             match &state.ui {
                 UIState::Finished => {
+                    if let Some(autosave) = autosave
+                        && let Err(e) = std::fs::remove_file(&autosave.path)
+                        && e.kind() != std::io::ErrorKind::NotFound
+                    {
+                        leptos::logging::warn!(
+                            "Failed to delete the saved assignment progress in {}: {e}",
+                            autosave.path.display()
+                        );
+                    }
+                    if let Some(params_path) = params_path {
+                        save_params(&state, params_path);
+                    }
+                    if let Err(missing) =
+                        qvis::validate_assignment(&state.pixel_assignment, puzzle_geometry)
+                    {
+                        leptos::logging::warn!(
+                            "Stickers with no pixels, which can't be recognized: {missing:?}"
+                        );
+                    }
                     highgui::destroy_all_windows()?;
-                    break Ok(state.pixel_assignment.clone());
+                    break Ok(Some(state.pixel_assignment.clone()));
                 }
                 UIState::OpenCVError(e) => {
+                    if let Some(autosave) = autosave {
+                        save_snapshot(&state, autosave);
+                    }
                     highgui::destroy_all_windows()?;
                     break Err(opencv::Error::new(
                         e.code,
@@ -646,30 +1056,55 @@ pub fn pixel_assignment_ui(
                 }
                 UIState::Assigning => (),
             }
+
+            if let Some(autosave) = autosave
+                && last_saved.elapsed() >= autosave.interval
+            {
+                save_snapshot(&state, autosave);
+                last_saved = Instant::now();
+            }
         }
 
-        let key = highgui::wait_key(1000 / 30)?;
+        if is_cancelled() {
+            if let Some(autosave) = autosave {
+                #[allow(clippy::missing_panics_doc)]
+                save_snapshot(&state.lock().unwrap(), autosave);
+            }
+            highgui::destroy_all_windows()?;
+            break Ok(None);
+        }
+
+        // Errors are left for the top of the loop, which saves the progress before closing the UI
+        let key = highgui::wait_key(1000 / 30);
         {
             #[allow(clippy::missing_panics_doc)]
             let mut state = state.lock().unwrap();
-            match key {
-                D => {
-                    in_toggle_dragging = false;
-                    submit_button_callback(&mut state)?;
-                }
-                R => {
-                    in_toggle_dragging = false;
-                    restart_button_callback(&mut state)?;
+            let result = key.and_then(|key| match key_dispatcher.dispatch(key) {
+                Some(Action::Assign) => submit_button_callback(&mut state),
+                Some(Action::Restart) => restart_button_callback(&mut state),
+                Some(Action::ToggleDragging) => {
+                    toggle_dragging(&mut state);
+                    Ok(())
                 }
-                S => {
-                    if !in_toggle_dragging {
-                        toggle_dragging(&mut state);
-                        in_toggle_dragging = true;
-                    }
+                Some(Action::Propose) => propose_callback(&mut state),
+                Some(Action::Skip) => skip_callback(&mut state),
+                Some(Action::Previous) => navigate_callback(&mut state, false),
+                Some(Action::Next) => navigate_callback(&mut state, true),
+                Some(Action::ToggleOverlay) => {
+                    state.show_overlay = !state.show_overlay;
+                    update_display(&mut state)
                 }
-                _ => {
-                    in_toggle_dragging = false;
+                Some(action @ (Action::ZoomIn | Action::ZoomOut)) => {
+                    // Zoom in on the pointer, or on the middle of what is shown if the pointer hasn't been over the window
+                    let (x, y, width, height) = state.viewport.visible();
+                    let pointer = state.maybe_xy.unwrap_or((x + width / 2, y + height / 2));
+                    let point = state.viewport.to_shown(pointer);
+                    zoom_callback(&mut state, action == Action::ZoomIn, point)
                 }
+                None => Ok(()),
+            });
+            if let Err(e) = result {
+                state.ui = UIState::OpenCVError(e);
             }
         }
     }
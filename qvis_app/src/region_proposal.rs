@@ -0,0 +1,149 @@
+//! Proposing where the stickers are in an image so that the assignment UI can pre-select them, which saves flood filling each of them by hand. Stickers are patches of nearly uniform color separated by the darker plastic between them, so the proposals are the connected regions of similar color that are about the right size to be a sticker.
+
+use std::{collections::VecDeque, ops::RangeInclusive};
+
+/// Split an image into regions of neighbouring pixels whose color differs by at most `tolerance` in every channel, and return the regions whose number of pixels is in `sizes`. Pixels are given in row-major order, `width` to a row, and are neighbours if they share an edge. Chains of similar pixels are joined, so a gradual shading across a sticker doesn't split it.
+///
+/// Each region is a sorted list of the indices of its pixels, and the regions are sorted by their first pixel so that they come in roughly the order that they are read in.
+pub fn propose_regions(
+    pixels: &[[u8; 3]],
+    width: usize,
+    tolerance: u8,
+    sizes: &RangeInclusive<usize>,
+) -> Vec<Vec<usize>> {
+    if width == 0 {
+        return Vec::new();
+    }
+
+    let similar = |a: usize, b: usize| {
+        pixels[a]
+            .iter()
+            .zip(&pixels[b])
+            .all(|(a, b)| a.abs_diff(*b) <= tolerance)
+    };
+
+    let mut visited = vec![false; pixels.len()];
+    let mut regions = Vec::new();
+
+    // Scanning in order finds every region at its first pixel, so they come out sorted
+    for start in 0..pixels.len() {
+        if visited[start] {
+            continue;
+        }
+
+        let mut region = grow_region(pixels.len(), width, start, &mut visited, similar);
+
+        if sizes.contains(&region.len()) {
+            region.sort_unstable();
+            regions.push(region);
+        }
+    }
+
+    regions
+}
+
+/// Find the region of an image of `len` pixels, `width` to a row, that is connected to `start` by pixels that `joins` each other, where `joins(a, b)` says whether pixel `b` joins the region through its neighbour `a`. Pixels that are already `visited` aren't added to the region, and the pixels of the region are marked as visited. The region is returned in the order that it was found in, starting with `start`.
+pub(crate) fn grow_region(
+    len: usize,
+    width: usize,
+    start: usize,
+    visited: &mut [bool],
+    joins: impl Fn(usize, usize) -> bool,
+) -> Vec<usize> {
+    let mut queue = VecDeque::new();
+    let mut region = Vec::new();
+
+    visited[start] = true;
+    queue.push_back(start);
+
+    while let Some(idx) = queue.pop_front() {
+        region.push(idx);
+
+        let (x, y) = (idx % width, idx / width);
+        let neighbours = [
+            (x > 0).then(|| idx - 1),
+            (x + 1 < width).then_some(idx + 1),
+            (y > 0).then(|| idx - width),
+            Some(idx + width).filter(|v| *v < len),
+        ];
+
+        for neighbour in neighbours.into_iter().flatten() {
+            if !visited[neighbour] && joins(idx, neighbour) {
+                visited[neighbour] = true;
+                queue.push_back(neighbour);
+            }
+        }
+    }
+
+    region
+}
+
+#[cfg(test)]
+mod tests {
+    use super::propose_regions;
+
+    const SQUARE: usize = 10;
+    const GAP: usize = 2;
+    const SIDE: usize = 3 * SQUARE + 4 * GAP;
+
+    /// A 3x3 grid of colored squares with dark lines between them, like a face of a cube, where every square is shaded slightly from left to right
+    fn grid() -> Vec<[u8; 3]> {
+        let colors: [[u8; 3]; 9] = [
+            [255, 255, 255],
+            [0, 0, 200],
+            [0, 160, 0],
+            [200, 0, 0],
+            [0, 120, 255],
+            [0, 220, 220],
+            [255, 255, 255],
+            [0, 160, 0],
+            [0, 0, 200],
+        ];
+
+        (0..SIDE * SIDE)
+            .map(|idx| {
+                let (x, y) = (idx % SIDE, idx / SIDE);
+                let cell = |v: usize| {
+                    let v = v.checked_sub(GAP)?;
+                    (v % (SQUARE + GAP) < SQUARE).then_some(v / (SQUARE + GAP))
+                };
+
+                match (cell(x), cell(y)) {
+                    (Some(column), Some(row)) if column < 3 && row < 3 => {
+                        let shade = u8::try_from(x % (SQUARE + GAP)).unwrap();
+                        colors[row * 3 + column].map(|v| v.saturating_sub(shade))
+                    }
+                    _ => [20, 20, 20],
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn grid_of_stickers() {
+        let image = grid();
+        let regions = propose_regions(&image, SIDE, 3, &(30..=SQUARE * SQUARE * 2));
+
+        // The dark lines are all one region, which is too big to be a sticker
+        assert_eq!(regions.len(), 9);
+        for (i, region) in regions.iter().enumerate() {
+            let (column, row) = (i % 3, i / 3);
+            let x0 = GAP + column * (SQUARE + GAP);
+            let y0 = GAP + row * (SQUARE + GAP);
+
+            let expected = (y0..y0 + SQUARE)
+                .flat_map(|y| (x0..x0 + SQUARE).map(move |x| y * SIDE + x))
+                .collect::<Vec<_>>();
+            assert_eq!(*region, expected, "{i}");
+        }
+
+        // Without tolerance for the shading, every column of a square is its own region and too small
+        assert!(propose_regions(&image, SIDE, 0, &(30..=SQUARE * SQUARE * 2)).is_empty());
+
+        // Squares of the same color don't merge through the dark lines, even with a tolerance that joins the shading
+        let regions = propose_regions(&image, SIDE, 20, &(1..=SQUARE * SQUARE * 2));
+        assert_eq!(regions.len(), 9);
+
+        assert!(propose_regions(&[], 0, 3, &(1..=10)).is_empty());
+    }
+}
@@ -10,12 +10,64 @@ use serde::{Deserialize, Serialize};
 mod ssr_imports {
     pub use leptos::logging::log;
     pub use leptos_ws::ChannelSignal;
-    pub use qvis::Pixel;
-    pub use std::sync::Mutex;
+    pub use std::sync::{Arc, Mutex, PoisonError};
 }
 
+/// Sends requests to the sticker assignment UI on the main thread
+#[cfg(feature = "ssr")]
+pub type PixelAssignmentUiTx =
+    std::sync::mpsc::Sender<crate::assignment_requests::AssignmentRequest>;
+
 pub const TAKE_PICTURE_CHANNEL: &str = "take_picture_channel";
 
+/// How long `take_picture` waits for the client to reply before assuming that it has disconnected, and how many times it asks. Asking again helps if the message to the client was lost, for example because its connection was being reestablished.
+#[cfg(feature = "ssr")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientReplyTimeout {
+    /// How long to wait for each request
+    pub timeout: std::time::Duration,
+    /// How many times to send the request before giving up
+    pub attempts: u32,
+}
+
+#[cfg(feature = "ssr")]
+impl Default for ClientReplyTimeout {
+    fn default() -> Self {
+        ClientReplyTimeout {
+            timeout: std::time::Duration::from_secs(30),
+            attempts: 2,
+        }
+    }
+}
+
+/// Where the server saves the sticker assignment made in the `OpenCV` UI
+#[cfg(feature = "ssr")]
+const PIXEL_ASSIGNMENT_FILE: &str = "pixel_assignment.txt";
+
+/// Where the server saves the calibrated model, as written by `qvis::CVProcessor::calibration_text`. The browser calibrates with its own assignment and uploads the model with `upload_calibration`, and saving a new assignment deletes it, since a model is only good for the assignment that it was calibrated with.
+#[cfg(feature = "ssr")]
+const CALIBRATION_FILE: &str = "calibration.json";
+
+/// The name of the puzzle that the server is configured to recognize, as understood by `puzzle_theory::puzzle_geometry::parsing::puzzle`
+#[derive(Debug, Clone)]
+pub struct PuzzleName(pub String);
+
+impl PuzzleName {
+    /// The geometry of the puzzle with this name
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `puzzle` doesn't know a puzzle by this name. `puzzle` panics in that case, so the panic is caught and turned into the error, which is why this should be checked once up front rather than in the browser, where panics abort.
+    pub fn geometry(
+        &self,
+    ) -> Result<std::sync::Arc<puzzle_theory::puzzle_geometry::PuzzleGeometry>, String> {
+        std::panic::catch_unwind(|| {
+            puzzle_theory::puzzle_geometry::parsing::puzzle(&self.0).into_inner()
+        })
+        .map_err(|_| format!("There is no puzzle called {:?}", self.0))
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum TakePictureMessage {
     // Request
@@ -23,6 +75,85 @@ pub enum TakePictureMessage {
     // Response
     PermutationResult(Permutation),
     NeedsStickerAssignment,
+    // Sent by either side when the request failed, with the reason
+    Failed(String),
+}
+
+/// How far along the server is in being set up to recognize the puzzle
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CalibrationStatus {
+    /// No stickers have been assigned
+    Uncalibrated,
+    /// The stickers have been assigned but the model hasn't been calibrated
+    AssignedButUncalibrated,
+    /// The puzzle can be recognized
+    Ready,
+}
+
+impl CalibrationStatus {
+    /// Derive the status from which of the files are saved. A model is useless without the assignment that it was calibrated for, so the assignment comes first.
+    #[must_use]
+    pub fn from_saved(assignment_saved: bool, calibration_saved: bool) -> CalibrationStatus {
+        match (assignment_saved, calibration_saved) {
+            (false, _) => CalibrationStatus::Uncalibrated,
+            (true, false) => CalibrationStatus::AssignedButUncalibrated,
+            (true, true) => CalibrationStatus::Ready,
+        }
+    }
+}
+
+#[server(
+  endpoint = "puzzle_name",
+  input = GetUrl,
+  output = Json
+)]
+pub async fn puzzle_name() -> Result<String, ServerFnError> {
+    let PuzzleName(name) = use_context::<PuzzleName>()
+        .ok_or_else(|| ServerFnError::new("The server has no puzzle configured"))?;
+
+    Ok(name)
+}
+
+/// What the faces of the configured puzzle are made of, which the browser recognizes the puzzle with the inference parameters of
+#[server(
+  endpoint = "surface",
+  input = GetUrl,
+  output = Json
+)]
+pub async fn surface() -> Result<crate::assignment_params::Surface, ServerFnError> {
+    Ok(use_context::<crate::assignment_params::Surface>().unwrap_or_default())
+}
+
+/// Every sticker that assigning the pixels of the configured puzzle asks for, in order, so that the client can show what the assignment involves before it starts
+#[server(
+  endpoint = "assignment_plan",
+  input = GetUrl,
+  output = Json
+)]
+pub async fn assignment_plan() -> Result<Vec<qvis::StickerToAssign>, ServerFnError> {
+    let puzzle_geometry = use_context::<PuzzleName>()
+        .ok_or_else(|| ServerFnError::new("The server has no puzzle configured"))?
+        .geometry()
+        .map_err(ServerFnError::new)?;
+
+    Ok(qvis::assignment_plan(&puzzle_geometry))
+}
+
+#[server(
+  endpoint = "calibration_status",
+  input = GetUrl,
+  output = Json
+)]
+pub async fn calibration_status() -> Result<CalibrationStatus, ServerFnError> {
+    let saved = |path: &str| {
+        std::fs::exists(path)
+            .map_err(|e| ServerFnError::new(format!("Failed to check for {path}: {e}")))
+    };
+
+    Ok(CalibrationStatus::from_saved(
+        saved(PIXEL_ASSIGNMENT_FILE)?,
+        saved(CALIBRATION_FILE)?,
+    ))
 }
 
 #[server(
@@ -34,57 +165,204 @@ pub async fn take_picture() -> Result<Permutation, ServerFnError> {
     use ssr_imports::*;
 
     let channel = ChannelSignal::new(TAKE_PICTURE_CHANNEL).map_err(ServerFnError::new)?;
-    let pixel_assignment_ui_tx =
-        use_context::<std::sync::mpsc::Sender<tokio::sync::oneshot::Sender<Box<[Pixel]>>>>()
-            .unwrap();
+    let pixel_assignment_ui_tx = use_context::<PixelAssignmentUiTx>()
+        .ok_or_else(|| ServerFnError::new("The sticker assignment UI isn't available"))?;
+
+    let client_reply_timeout = use_context::<ClientReplyTimeout>().unwrap_or_default();
 
-    let (response_tx, response_rx) = tokio::sync::oneshot::channel();
-    let response_tx = Mutex::new(Some(response_tx));
+    let (response_tx, response_rx) =
+        tokio::sync::oneshot::channel::<Result<Permutation, ServerFnError>>();
+    // Shared with the handler so that it can be disarmed once the request is over
+    let response_tx = Arc::new(Mutex::new(Some(response_tx)));
+    let (replied_tx, mut replied_rx) = tokio::sync::oneshot::channel();
+    let replied_tx = Mutex::new(Some(replied_tx));
 
+    let handler_response_tx = Arc::clone(&response_tx);
     channel
         .on_server(move |message: &TakePictureMessage| {
             log!("Recieved message {message:#?}");
+            if let Some(replied_tx) = replied_tx
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .take()
+            {
+                let _ = replied_tx.send(());
+            }
+            let take_response_tx = || {
+                let response_tx = handler_response_tx
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .take();
+                if response_tx.is_none() {
+                    warn!("Received {message:?} after the request was already over");
+                }
+                response_tx
+            };
             match message {
                 TakePictureMessage::PermutationResult(permutation) => {
-                    response_tx.lock().unwrap().take().expect("Expected to send only one response").send(permutation.clone()).unwrap();
+                    if let Some(response_tx) = take_response_tx() {
+                        // The request is gone if the receiver was dropped, so there's nobody to tell
+                        let _ = response_tx.send(Ok(permutation.clone()));
+                    }
                 }
                 TakePictureMessage::NeedsStickerAssignment => {
+                    let Some(response_tx) = take_response_tx() else {
+                        return;
+                    };
                     let pixel_assignment_ui_tx = pixel_assignment_ui_tx.clone();
-                    let response_tx = response_tx
-                        .lock()
-                        .unwrap()
-                        .take()
-                        .expect("Expected to send only one response");
 
                     tokio::task::spawn(async move {
-                        let (pixel_assignment_done_tx, pixel_assignment_done_rx) = tokio::sync::oneshot::channel();
-
-                        pixel_assignment_ui_tx
-                            .send(pixel_assignment_done_tx)
-                            .unwrap();
-                        let pixel_assignment = pixel_assignment_done_rx.await.unwrap();
-                        std::fs::write(
-                            "pixel_assignment.txt",
-                            format!("{pixel_assignment:?}"),
-                        ).unwrap();
-                        response_tx
-                            .send(todo!())
-                            .unwrap();
+                        let mut response_tx = response_tx;
+                        let result = tokio::select! {
+                            result = assign_stickers(pixel_assignment_ui_tx) => result,
+                            // Dropping the assignment future cancels the request to the UI
+                            () = response_tx.closed() => return,
+                        };
+                        let _ = response_tx.send(result);
                     });
-                    // todo!()
+                }
+                TakePictureMessage::Failed(reason) => {
+                    if let Some(response_tx) = take_response_tx() {
+                        let _ = response_tx.send(Err(ServerFnError::new(reason)));
+                    }
                 }
                 TakePictureMessage::TakePicture => {
-                    warn!("Received TakePictureMessage::TakePicture on server, which should not happen");
+                    warn!("Received {message:?} on server, which should not happen");
                 }
             }
         })
         .map_err(ServerFnError::new)?;
 
-    channel
-        .send_message(TakePictureMessage::TakePicture)
+    // Only the client's first reply is timed; sticker assignment after that can take as long as it needs
+    let result = match await_client_reply(
+        || {
+            channel
+                .send_message(TakePictureMessage::TakePicture)
+                .map_err(|e| e.to_string())
+        },
+        &mut replied_rx,
+        client_reply_timeout,
+    )
+    .await
+    {
+        Ok(()) => response_rx.await.map_err(ServerFnError::new).flatten(),
+        Err(e) => Err(ServerFnError::new(e)),
+    };
+
+    if let Err(e) = &result {
+        // A reply that arrives after giving up must not start anything, like a sticker assignment that nobody is waiting for
+        response_tx
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        log!("Failed to take a picture: {e}");
+        // Best effort; the client may be the reason that it failed
+        let _ = channel.send_message(TakePictureMessage::Failed(e.to_string()));
+    }
+
+    result
+}
+
+/// Send a request to the client with `send` and wait for `replied` to say that the client replied, sending the request again if the client doesn't reply in time, up to the number of attempts in `timeout`
+///
+/// # Errors
+///
+/// Returns a description of what went wrong if the request couldn't be sent or the client never replied.
+#[cfg(feature = "ssr")]
+async fn await_client_reply(
+    mut send: impl FnMut() -> Result<(), String>,
+    replied: &mut tokio::sync::oneshot::Receiver<()>,
+    timeout: ClientReplyTimeout,
+) -> Result<(), String> {
+    for _ in 0..timeout.attempts {
+        send()?;
+
+        match tokio::time::timeout(timeout.timeout, &mut *replied).await {
+            Ok(Ok(())) => return Ok(()),
+            Ok(Err(e)) => return Err(e.to_string()),
+            Err(_) => {}
+        }
+    }
+
+    Err("The client didn't reply; it may have disconnected".to_owned())
+}
+
+/// Have the user assign stickers in the `OpenCV` UI and save the assignment
+#[cfg(feature = "ssr")]
+async fn assign_stickers(
+    pixel_assignment_ui_tx: PixelAssignmentUiTx,
+) -> Result<Permutation, ServerFnError> {
+    let (request, pending) = crate::assignment_requests::AssignmentRequest::new();
+
+    pixel_assignment_ui_tx
+        .send(request)
+        .map_err(|_| ServerFnError::new("The sticker assignment UI isn't running"))?;
+    let pixel_assignment = pending.finished().await.map_err(ServerFnError::new)?;
+    std::fs::write(PIXEL_ASSIGNMENT_FILE, format!("{pixel_assignment:?}"))
+        .map_err(|e| ServerFnError::new(format!("Failed to save the sticker assignment: {e}")))?;
+    forget_calibration()?;
+
+    Err(ServerFnError::new(
+        "Stickers assigned; take another picture to recognize the puzzle",
+    ))
+}
+
+/// Save a sticker assignment made in the browser, in place of assigning the stickers in the `OpenCV` UI
+#[server(
+  endpoint = "upload_assignment",
+  input = Json,
+  output = Json
+)]
+pub async fn upload_assignment(
+    assignment: crate::assignment_upload::AssignmentUpload,
+) -> Result<(), ServerFnError> {
+    let puzzle_geometry = use_context::<PuzzleName>()
+        .ok_or_else(|| ServerFnError::new("The server has no puzzle configured"))?
+        .geometry()
+        .map_err(ServerFnError::new)?;
+
+    let pixel_assignment = assignment
+        .into_pixels(&puzzle_geometry)
         .map_err(ServerFnError::new)?;
+    if let Err(missing) = qvis::validate_assignment(&pixel_assignment, &puzzle_geometry) {
+        warn!("Stickers with no pixels, which can't be recognized: {missing:?}");
+    }
+    std::fs::write(PIXEL_ASSIGNMENT_FILE, format!("{pixel_assignment:?}"))
+        .map_err(|e| ServerFnError::new(format!("Failed to save the sticker assignment: {e}")))?;
+    forget_calibration()?;
+
+    Ok(())
+}
+
+/// Save the model that the browser calibrated with the assignment that it uploaded, which is what makes the puzzle count as calibrated
+#[server(
+  endpoint = "upload_calibration",
+  input = Json,
+  output = Json
+)]
+pub async fn upload_calibration(calibration: String) -> Result<(), ServerFnError> {
+    if !std::fs::exists(PIXEL_ASSIGNMENT_FILE)
+        .map_err(|e| ServerFnError::new(format!("Failed to check for the assignment: {e}")))?
+    {
+        return Err(ServerFnError::new(
+            "There is no sticker assignment for the calibration to be of",
+        ));
+    }
+
+    std::fs::write(CALIBRATION_FILE, calibration)
+        .map_err(|e| ServerFnError::new(format!("Failed to save the calibration: {e}")))
+}
 
-    response_rx.await.map_err(ServerFnError::new)
+/// Delete the saved model, which was calibrated with an assignment that has just been replaced
+#[cfg(feature = "ssr")]
+fn forget_calibration() -> Result<(), ServerFnError> {
+    match std::fs::remove_file(CALIBRATION_FILE) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(ServerFnError::new(format!(
+            "Failed to delete the calibration of the old assignment: {e}"
+        ))),
+    }
 }
 
 // #[server(
@@ -93,3 +371,101 @@ pub async fn take_picture() -> Result<Permutation, ServerFnError> {
 //   output = Json
 // )]
 // pub async fn pixel_assignment() -> Result<Permutation, ServerFnError> {
+
+#[cfg(test)]
+mod tests {
+    use super::{CalibrationStatus, PuzzleName};
+
+    #[test]
+    fn puzzle_geometry() {
+        assert!(PuzzleName("3x3".to_owned()).geometry().is_ok());
+        assert!(
+            PuzzleName("a cube with seven faces".to_owned())
+                .geometry()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn status_from_saved_files() {
+        assert_eq!(
+            CalibrationStatus::from_saved(false, false),
+            CalibrationStatus::Uncalibrated
+        );
+        // A model without the assignment that it was calibrated for can't be used
+        assert_eq!(
+            CalibrationStatus::from_saved(false, true),
+            CalibrationStatus::Uncalibrated
+        );
+        assert_eq!(
+            CalibrationStatus::from_saved(true, false),
+            CalibrationStatus::AssignedButUncalibrated
+        );
+        assert_eq!(
+            CalibrationStatus::from_saved(true, true),
+            CalibrationStatus::Ready
+        );
+    }
+
+    #[cfg(feature = "ssr")]
+    #[tokio::test]
+    async fn client_reply_timeout() {
+        use super::{ClientReplyTimeout, await_client_reply};
+        use std::time::Duration;
+        use tokio::sync::oneshot;
+
+        let timeout = ClientReplyTimeout {
+            timeout: Duration::from_millis(20),
+            attempts: 2,
+        };
+
+        // The client never replies
+        let (_replied_tx, mut replied_rx) = oneshot::channel::<()>();
+        let mut sent = 0;
+        let result = await_client_reply(
+            || {
+                sent += 1;
+                Ok(())
+            },
+            &mut replied_rx,
+            timeout,
+        )
+        .await;
+        assert!(result.is_err());
+        assert_eq!(sent, 2);
+
+        // The first request is lost, and the client replies to the second
+        let (replied_tx, mut replied_rx) = oneshot::channel();
+        let mut replied_tx = Some(replied_tx);
+        let mut sent = 0;
+        let result = await_client_reply(
+            || {
+                sent += 1;
+                if sent == 2 {
+                    replied_tx.take().unwrap().send(()).unwrap();
+                }
+                Ok(())
+            },
+            &mut replied_rx,
+            timeout,
+        )
+        .await;
+        assert_eq!(result, Ok(()));
+        assert_eq!(sent, 2);
+
+        // Failing to send isn't retried
+        let (_replied_tx, mut replied_rx) = oneshot::channel::<()>();
+        let mut sent = 0;
+        let result = await_client_reply(
+            || {
+                sent += 1;
+                Err("disconnected".to_owned())
+            },
+            &mut replied_rx,
+            timeout,
+        )
+        .await;
+        assert_eq!(result, Err("disconnected".to_owned()));
+        assert_eq!(sent, 1);
+    }
+}
@@ -0,0 +1,128 @@
+//! The settings of the server, which are all read from environment variables when it starts. Reading them goes through a function that looks up a variable rather than the environment itself, so that they can be read from something else in the tests.
+
+use std::{ffi::OsString, path::PathBuf, time::Duration};
+
+use crate::assignment_params::Surface;
+
+/// The puzzle that the server recognizes when `QVIS_PUZZLE` isn't set
+pub const DEFAULT_PUZZLE: &str = "3x3";
+/// The image that stickers are assigned in when `QVIS_ASSIGNMENT_IMAGE` isn't set
+pub const DEFAULT_ASSIGNMENT_IMAGE: &str = "input.jpg";
+/// How often the sticker assignment UI saves its progress when `QVIS_AUTOSAVE_SECS` isn't set
+pub const DEFAULT_AUTOSAVE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How the server was configured to run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ServerSettings {
+    /// The puzzle to recognize, from `QVIS_PUZZLE`, using any name that `puzzle` understands
+    pub puzzle: String,
+    /// The image to assign stickers in, from `QVIS_ASSIGNMENT_IMAGE`
+    pub assignment_image: PathBuf,
+    /// How often the progress of the sticker assignment is saved, from `QVIS_AUTOSAVE_SECS` in seconds
+    pub autosave_interval: Duration,
+    /// How long to wait for the client to reply to a request to take a picture, from `QVIS_CLIENT_REPLY_TIMEOUT_SECS` in seconds, or `None` to use the default
+    pub client_reply_timeout: Option<Duration>,
+    /// Whether the puzzle is stickerless, which is selected by setting `QVIS_STICKERLESS` to anything
+    pub surface: Surface,
+    /// The seed of the randomness of the sticker assignment UI, from `QVIS_SEED`, or `None` to seed it from entropy
+    pub seed: Option<u64>,
+}
+
+impl ServerSettings {
+    /// Read the settings from the environment of the server
+    pub fn from_env() -> ServerSettings {
+        ServerSettings::read(|name| std::env::var_os(name))
+    }
+
+    /// Read the settings from the variables that `var` looks up. Variables that aren't set, or numbers that aren't whole, fall back to their defaults.
+    pub fn read(var: impl Fn(&str) -> Option<OsString>) -> ServerSettings {
+        let number = |name| {
+            var(name)
+                .and_then(|v| v.into_string().ok())
+                .and_then(|v| v.parse().ok())
+        };
+        let secs = |name| number(name).map(Duration::from_secs);
+
+        ServerSettings {
+            puzzle: var("QVIS_PUZZLE")
+                .and_then(|v| v.into_string().ok())
+                .unwrap_or_else(|| DEFAULT_PUZZLE.to_owned()),
+            assignment_image: PathBuf::from(
+                var("QVIS_ASSIGNMENT_IMAGE").unwrap_or_else(|| DEFAULT_ASSIGNMENT_IMAGE.into()),
+            ),
+            autosave_interval: secs("QVIS_AUTOSAVE_SECS").unwrap_or(DEFAULT_AUTOSAVE_INTERVAL),
+            client_reply_timeout: secs("QVIS_CLIENT_REPLY_TIMEOUT_SECS"),
+            surface: if var("QVIS_STICKERLESS").is_some() {
+                Surface::Stickerless
+            } else {
+                Surface::Stickers
+            },
+            seed: number("QVIS_SEED"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ffi::OsString, path::PathBuf, time::Duration};
+
+    use super::{
+        DEFAULT_ASSIGNMENT_IMAGE, DEFAULT_AUTOSAVE_INTERVAL, DEFAULT_PUZZLE, ServerSettings,
+    };
+    use crate::assignment_params::Surface;
+
+    fn read(vars: &[(&str, &str)]) -> ServerSettings {
+        ServerSettings::read(|name| {
+            vars.iter()
+                .find(|(var, _)| *var == name)
+                .map(|(_, value)| OsString::from(value))
+        })
+    }
+
+    #[test]
+    fn defaults() {
+        assert_eq!(
+            read(&[]),
+            ServerSettings {
+                puzzle: DEFAULT_PUZZLE.to_owned(),
+                assignment_image: PathBuf::from(DEFAULT_ASSIGNMENT_IMAGE),
+                autosave_interval: DEFAULT_AUTOSAVE_INTERVAL,
+                client_reply_timeout: None,
+                surface: Surface::Stickers,
+                seed: None,
+            }
+        );
+    }
+
+    #[test]
+    fn set() {
+        assert_eq!(
+            read(&[
+                ("QVIS_PUZZLE", "2x2"),
+                ("QVIS_ASSIGNMENT_IMAGE", "pictures/cube.png"),
+                ("QVIS_AUTOSAVE_SECS", "5"),
+                ("QVIS_CLIENT_REPLY_TIMEOUT_SECS", "90"),
+                ("QVIS_STICKERLESS", ""),
+                ("QVIS_SEED", "1234"),
+            ]),
+            ServerSettings {
+                puzzle: "2x2".to_owned(),
+                assignment_image: PathBuf::from("pictures/cube.png"),
+                autosave_interval: Duration::from_secs(5),
+                client_reply_timeout: Some(Duration::from_secs(90)),
+                surface: Surface::Stickerless,
+                seed: Some(1234),
+            }
+        );
+
+        // Numbers that can't be read are ignored rather than stopping the server
+        let settings = read(&[
+            ("QVIS_AUTOSAVE_SECS", "soon"),
+            ("QVIS_CLIENT_REPLY_TIMEOUT_SECS", "-1"),
+            ("QVIS_SEED", "0.5"),
+        ]);
+        assert_eq!(settings.autosave_interval, DEFAULT_AUTOSAVE_INTERVAL);
+        assert_eq!(settings.client_reply_timeout, None);
+        assert_eq!(settings.seed, None);
+    }
+}
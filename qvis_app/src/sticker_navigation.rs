@@ -0,0 +1,226 @@
+//! Keeps track of which sticker the assignment UI is asking for, so that stickers can be skipped and revisited in any order.
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum StickerStatus {
+    /// The sticker hasn't been assigned or skipped yet
+    Pending,
+    Assigned,
+    /// The user chose not to assign the sticker, for example because it is occluded
+    Skipped,
+}
+
+#[derive(Debug)]
+pub struct StickerNavigation {
+    statuses: Vec<StickerStatus>,
+    current: usize,
+}
+
+impl StickerNavigation {
+    /// Start at the first of `num_stickers` stickers, with none of them assigned
+    ///
+    /// # Panics
+    ///
+    /// This method panics if there are no stickers.
+    pub fn new(num_stickers: usize) -> StickerNavigation {
+        assert!(num_stickers > 0, "There must be a sticker to assign");
+
+        StickerNavigation {
+            statuses: vec![StickerStatus::Pending; num_stickers],
+            current: 0,
+        }
+    }
+
+    /// Pick up where a previous navigation left off, at `current` with the stickers having the given statuses
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `current` isn't one of the stickers.
+    pub fn resume(statuses: Vec<StickerStatus>, current: usize) -> StickerNavigation {
+        assert!(current < statuses.len(), "The current sticker must exist");
+
+        StickerNavigation { statuses, current }
+    }
+
+    /// Assign only `sticker` again, with every other sticker kept as it was assigned. The navigation is finished as soon as the sticker is assigned, which makes redoing a sticker that recognizes badly quick.
+    ///
+    /// # Panics
+    ///
+    /// This method panics if `sticker` isn't one of the `num_stickers` stickers.
+    pub fn redo(num_stickers: usize, sticker: usize) -> StickerNavigation {
+        assert!(sticker < num_stickers, "The sticker to redo must exist");
+
+        let mut statuses = vec![StickerStatus::Assigned; num_stickers];
+        statuses[sticker] = StickerStatus::Pending;
+
+        StickerNavigation {
+            statuses,
+            current: sticker,
+        }
+    }
+
+    /// The status of every sticker
+    pub fn statuses(&self) -> &[StickerStatus] {
+        &self.statuses
+    }
+
+    /// The sticker that is currently being assigned
+    pub fn current(&self) -> usize {
+        self.current
+    }
+
+    pub fn status(&self, sticker: usize) -> StickerStatus {
+        self.statuses[sticker]
+    }
+
+    /// Mark the current sticker as assigned and move on to the next pending sticker
+    pub fn assign(&mut self) {
+        self.statuses[self.current] = StickerStatus::Assigned;
+        self.advance();
+    }
+
+    /// Leave the current sticker unassigned and move on to the next pending sticker. Skipping a sticker that was already assigned keeps its assignment.
+    pub fn skip(&mut self) {
+        if self.statuses[self.current] == StickerStatus::Pending {
+            self.statuses[self.current] = StickerStatus::Skipped;
+        }
+        self.advance();
+    }
+
+    /// Move to the previous sticker, regardless of its status, to redo it
+    pub fn previous(&mut self) {
+        self.current = (self.current + self.statuses.len() - 1) % self.statuses.len();
+    }
+
+    /// Move to the next sticker, regardless of its status, to redo it
+    pub fn next(&mut self) {
+        self.current = (self.current + 1) % self.statuses.len();
+    }
+
+    /// Whether every sticker has been either assigned or skipped
+    pub fn is_finished(&self) -> bool {
+        !self.statuses.contains(&StickerStatus::Pending)
+    }
+
+    /// The stickers that haven't been assigned
+    pub fn unassigned(&self) -> Vec<usize> {
+        (0..self.statuses.len())
+            .filter(|&sticker| self.statuses[sticker] != StickerStatus::Assigned)
+            .collect()
+    }
+
+    /// Forget everything and start over from the first sticker
+    pub fn restart(&mut self) {
+        self.statuses.fill(StickerStatus::Pending);
+        self.current = 0;
+    }
+
+    /// Move to the first pending sticker after the current one, wrapping around. Stays put if there are no pending stickers.
+    fn advance(&mut self) {
+        let len = self.statuses.len();
+        if let Some(next) = (1..=len)
+            .map(|offset| (self.current + offset) % len)
+            .find(|&sticker| self.statuses[sticker] == StickerStatus::Pending)
+        {
+            self.current = next;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{StickerNavigation, StickerStatus};
+
+    #[test]
+    fn in_order() {
+        let mut navigation = StickerNavigation::new(3);
+
+        for sticker in 0..3 {
+            assert!(!navigation.is_finished());
+            assert_eq!(navigation.current(), sticker);
+            navigation.assign();
+        }
+
+        assert!(navigation.is_finished());
+        assert!(navigation.unassigned().is_empty());
+    }
+
+    #[test]
+    fn skip_and_revisit() {
+        let mut navigation = StickerNavigation::new(4);
+
+        navigation.assign();
+        navigation.skip();
+        assert_eq!(navigation.current(), 2);
+        assert_eq!(navigation.status(1), StickerStatus::Skipped);
+
+        // Go back and redo the first sticker, which returns to the pending ones afterwards
+        navigation.previous();
+        navigation.previous();
+        assert_eq!(navigation.current(), 0);
+        navigation.assign();
+        assert_eq!(navigation.current(), 2);
+
+        navigation.assign();
+        assert!(!navigation.is_finished());
+        assert_eq!(navigation.current(), 3);
+        navigation.assign();
+
+        assert!(navigation.is_finished());
+        assert_eq!(navigation.unassigned(), [1]);
+
+        // Skipped stickers can still be assigned later
+        navigation.next();
+        navigation.next();
+        assert_eq!(navigation.current(), 1);
+        navigation.assign();
+        assert!(navigation.unassigned().is_empty());
+    }
+
+    #[test]
+    fn wrapping() {
+        let mut navigation = StickerNavigation::new(3);
+
+        navigation.previous();
+        assert_eq!(navigation.current(), 2);
+        navigation.next();
+        assert_eq!(navigation.current(), 0);
+
+        // Assigning the last sticker wraps around to the pending stickers before it
+        navigation.previous();
+        navigation.assign();
+        assert_eq!(navigation.current(), 0);
+
+        // Skipping an assigned sticker keeps it assigned
+        navigation.previous();
+        navigation.skip();
+        assert_eq!(navigation.status(2), StickerStatus::Assigned);
+
+        navigation.restart();
+        assert_eq!(navigation.current(), 0);
+        assert_eq!(navigation.unassigned(), [0, 1, 2]);
+    }
+
+    #[test]
+    fn redo() {
+        let mut navigation = StickerNavigation::redo(5, 3);
+        assert_eq!(navigation.current(), 3);
+        assert!(!navigation.is_finished());
+        assert_eq!(navigation.unassigned(), [3]);
+
+        // Looking around doesn't lose track of the sticker being redone
+        navigation.next();
+        assert_eq!(navigation.current(), 4);
+        navigation.previous();
+        navigation.assign();
+        assert!(navigation.is_finished());
+        assert!(navigation.unassigned().is_empty());
+
+        // Skipping it leaves it unassigned
+        let mut navigation = StickerNavigation::redo(5, 0);
+        navigation.skip();
+        assert!(navigation.is_finished());
+        assert_eq!(navigation.unassigned(), [0]);
+    }
+}
@@ -1,45 +1,99 @@
-use crate::server_fns::TakePictureMessage;
+use crate::{
+    assignment_params::Surface, browser_assignment::CapturedFrame,
+    browser_recognition::recognize_rgba, camera_choice::CameraChoice, frame_freeze::FrameFreeze,
+    server_fns::TakePictureMessage,
+};
 use leptos::{ev::canplay, html, prelude::*};
 use leptos_use::{
     FacingMode, UseEventListenerOptions, UseUserMediaOptions, UseUserMediaReturn,
-    VideoTrackConstraints, use_event_listener_with_options, use_user_media_with_options,
+    VideoTrackConstraints, core::MaybeRwSignal, use_event_listener_with_options,
+    use_user_media_with_options,
 };
 use log::{info, warn};
-use puzzle_theory::puzzle_geometry::parsing::puzzle;
-use qvis::CVProcessor;
-use wasm_bindgen::JsCast;
-use web_sys::{CanvasRenderingContext2d, js_sys};
+use puzzle_theory::permutations::Permutation;
+use qvis::{CVProcessor, Pixel};
+use wasm_bindgen::{JsCast, JsValue};
+use web_sys::{
+    CanvasRenderingContext2d, DomException, HtmlCanvasElement, HtmlVideoElement, js_sys,
+};
 
 const WIDTH: u32 = 350;
 
+/// The name to download a captured frame as, which records the state that was recognized in it so that the frame can be used as labeled calibration or evaluation data
+fn frame_file_name(state: &Permutation) -> String {
+    let cycles = state
+        .cycles()
+        .iter()
+        .map(|cycle| {
+            cycle
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join("_")
+        })
+        .collect::<Vec<_>>()
+        .join("-");
+
+    if cycles.is_empty() {
+        "qvis-solved.png".to_owned()
+    } else {
+        format!("qvis-{cycles}.png")
+    }
+}
+
+/// Draw the current frame of the video onto the canvas and read it back, or only read back the frame that is already on the canvas if `draw` is false
+fn capture_frame(
+    video: &HtmlVideoElement,
+    canvas: &HtmlCanvasElement,
+    draw: bool,
+) -> Result<CapturedFrame, JsValue> {
+    let ctx = canvas
+        .get_context("2d")?
+        .ok_or_else(|| JsValue::from_str("The canvas has no 2d context"))?
+        .dyn_into::<CanvasRenderingContext2d>()?;
+    let (width, height) = (canvas.width(), canvas.height());
+
+    if draw {
+        ctx.draw_image_with_html_video_element_and_dw_and_dh(
+            video,
+            0.0,
+            0.0,
+            width.into(),
+            height.into(),
+        )?;
+    }
+    let image_data = ctx.get_image_data(0.0, 0.0, width.into(), height.into())?;
+
+    Ok(CapturedFrame::from_rgba8(width, height, &image_data.data()))
+}
+
+/// The options for asking the browser for `camera`
+fn media_options(camera: CameraChoice, enabled: RwSignal<bool>) -> UseUserMediaOptions {
+    let options = UseUserMediaOptions::default().enabled(MaybeRwSignal::from(enabled));
+
+    match camera {
+        CameraChoice::Environment => {
+            options.video(VideoTrackConstraints::default().facing_mode(FacingMode::Environment))
+        }
+        CameraChoice::User => {
+            options.video(VideoTrackConstraints::default().facing_mode(FacingMode::User))
+        }
+        CameraChoice::Any => options.video(true),
+    }
+}
+
+/// Play `camera` in the video, calling `on_unavailable` with the camera to try instead if the browser can't open it
 #[component]
-pub fn Video(
-    take_picture_resp: Callback<TakePictureMessage>,
-    take_picture_command: ReadSignal<()>,
+fn CameraStream(
+    camera: CameraChoice,
+    video_ref: NodeRef<html::Video>,
+    enabled: RwSignal<bool>,
+    on_unavailable: Callback<CameraChoice>,
 ) -> impl IntoView {
-    let video_ref = NodeRef::<html::Video>::new();
-    let canvas_ref = NodeRef::<html::Canvas>::new();
-    let UseUserMediaReturn {
-        stream,
-        enabled,
-        set_enabled,
-        ..
-    } = use_user_media_with_options(
-        UseUserMediaOptions::default()
-            .video(VideoTrackConstraints::default().facing_mode(FacingMode::Environment)), // .enabled((enabled, set_enabled).into()),
-    );
-
-    let puzzle_geometry = puzzle("3x3").into_inner();
-    let mut cv: Option<CVProcessor> = None;
-    let mut ctx: Option<CanvasRenderingContext2d> = None;
+    let UseUserMediaReturn { stream, .. } =
+        use_user_media_with_options(media_options(camera, enabled));
 
     Effect::new(move |_| {
-        // let media = use_window()
-        //     .navigator()
-        //     .ok_or_else(|| JsValue::from_str("Failed to access window.navigator"))
-        //     .and_then(|n| n.media_devices())
-        //     .unwrap();
-
         let binding = stream.read();
         let maybe_stream = match binding.as_ref() {
             Some(Ok(s)) => {
@@ -47,7 +101,17 @@ pub fn Video(
                 Some(s)
             }
             Some(Err(e)) => {
-                warn!("Failed to get media stream: {e:?}");
+                let error = e
+                    .dyn_ref::<DomException>()
+                    .map(DomException::name)
+                    .unwrap_or_default();
+                match camera.fallback(&error) {
+                    Some(next) => {
+                        info!("Couldn't open the {camera:?} camera ({error}); trying {next:?}");
+                        on_unavailable.run(next);
+                    }
+                    None => warn!("Failed to get media stream: {e:?}"),
+                }
                 None
             }
             None => {
@@ -60,9 +124,47 @@ pub fn Video(
             v.set_src_object(maybe_stream);
         }
     });
+}
+
+#[component]
+pub fn Video(
+    take_picture_resp: Callback<TakePictureMessage>,
+    take_picture_command: ReadSignal<()>,
+    /// Called with a frame to assign the stickers on in the browser
+    on_capture: Callback<CapturedFrame>,
+    /// The name of the puzzle, once the server has said what it is
+    puzzle_name: Signal<Option<String>>,
+    /// What the faces of the puzzle are made of, once the server has said
+    surface: Signal<Option<Surface>>,
+    /// The sticker assignment made in the browser, if there is one. Pictures are then recognized in the browser, starting with a picture of the solved puzzle to calibrate with.
+    pixel_assignment: ReadSignal<Option<Box<[Pixel]>>>,
+    /// Called with the calibration, as written by `CVProcessor::calibration_text`, once the browser has calibrated with a picture of the solved puzzle
+    on_calibrated: Callback<String>,
+) -> impl IntoView {
+    let video_ref = NodeRef::<html::Video>::new();
+    let canvas_ref = NodeRef::<html::Canvas>::new();
+    let download_ref = NodeRef::<html::A>::new();
+    // The state recognized in the frame on the canvas, if it has one
+    let (recognized, set_recognized) = signal(None::<Permutation>);
+    // Whether the camera is on, which is kept across falling back to another camera
+    let enabled = RwSignal::new(false);
+    let (camera, set_camera) = signal(CameraChoice::Environment);
+    let (freeze, set_freeze) = signal(FrameFreeze::Live);
+    // Whether a frame has been drawn to the canvas, which is what freezing holds still
+    let (has_frame, set_has_frame) = signal(false);
+
+    let mut cv: Option<CVProcessor> = None;
+    let mut ctx: Option<CanvasRenderingContext2d> = None;
+    // Whether the assignment changed since the processor was made, so that it has to be made and calibrated again
+    let (assignment_changed, set_assignment_changed) = signal(false);
+
+    Effect::new(move |_| {
+        pixel_assignment.track();
+        set_assignment_changed.set(true);
+    });
 
     let toggle_enabled = move |_| {
-        set_enabled.update(|e| *e = !*e);
+        enabled.update(|e| *e = !*e);
     };
 
     Effect::watch(
@@ -71,13 +173,31 @@ pub fn Video(
             let canvas_ref = canvas_ref.get_untracked().unwrap();
             let video_ref = video_ref.get_untracked().unwrap();
 
-            let Some(ref cv) = cv else {
+            if assignment_changed.get_untracked() {
+                cv = None;
+                set_assignment_changed.set(false);
+            }
+            if cv.is_none() && pixel_assignment.with_untracked(Option::is_none) {
                 take_picture_resp.run(TakePictureMessage::NeedsStickerAssignment);
                 return;
+            }
+            // The server is told right away so that it doesn't wait for a reply that won't come
+            let fail = |reason: &str| {
+                let reason = format!("Couldn't recognize the puzzle: {reason}");
+                warn!("{reason}");
+                take_picture_resp.run(TakePictureMessage::Failed(reason));
+            };
+            let Some(puzzle_name) = puzzle_name.get_untracked() else {
+                fail("the server hasn't said which puzzle it is");
+                return;
+            };
+            let Some(surface) = surface.get_untracked() else {
+                fail("the server hasn't said what it is made of");
+                return;
             };
 
             if !enabled.get() {
-                set_enabled.set(true);
+                enabled.set(true);
             }
 
             let ctx = ctx.get_or_insert_with(|| {
@@ -91,14 +211,18 @@ pub fn Video(
                     .unwrap()
             });
 
-            ctx.draw_image_with_html_video_element_and_dw_and_dh(
-                &video_ref,
-                0.0,
-                0.0,
-                canvas_ref.width().into(),
-                canvas_ref.height().into(),
-            )
-            .unwrap();
+            // A frozen frame is recognized again as it is
+            if freeze.get_untracked().is_live() {
+                ctx.draw_image_with_html_video_element_and_dw_and_dh(
+                    &video_ref,
+                    0.0,
+                    0.0,
+                    canvas_ref.width().into(),
+                    canvas_ref.height().into(),
+                )
+                .unwrap();
+                set_has_frame.set(true);
+            }
 
             let image_data = ctx
                 .get_image_data(
@@ -111,19 +235,32 @@ pub fn Video(
             let data = &*image_data.data();
 
             info!("Captured image data length: {}", data.len());
-            let pixels = data
-                .chunks_exact(4)
-                .map(|rgba| {
-                    let [r, g, b, _] = rgba.try_into().unwrap();
-                    (
-                        f64::from(r) / 255.0,
-                        f64::from(g) / 255.0,
-                        f64::from(b) / 255.0,
-                    )
-                })
-                .collect::<Vec<_>>()
-                .into_boxed_slice();
-            let permutation = cv.process_image(pixels).0;
+            let calibrating = cv.is_none();
+            let recognized = pixel_assignment.with_untracked(|pixel_assignment| {
+                recognize_rgba(
+                    &mut cv,
+                    &puzzle_name,
+                    surface,
+                    pixel_assignment.as_deref(),
+                    data,
+                )
+            });
+            let permutation = match recognized {
+                Some(Ok(permutation)) => permutation,
+                Some(Err(err)) => {
+                    fail(&err.to_string());
+                    return;
+                }
+                None => {
+                    take_picture_resp.run(TakePictureMessage::NeedsStickerAssignment);
+                    return;
+                }
+            };
+            if calibrating && let Some(cv) = &cv {
+                info!("Calibrated with this picture of the solved puzzle");
+                on_calibrated.run(cv.calibration_text());
+            }
+            set_recognized.set(Some(permutation.clone()));
             take_picture_resp.run(TakePictureMessage::PermutationResult(permutation));
         },
         false,
@@ -153,7 +290,55 @@ pub fn Video(
         UseEventListenerOptions::default().once(true),
     );
 
+    let download_frame = move |_| {
+        let Some(state) = recognized.get_untracked() else {
+            return;
+        };
+        let canvas_ref = canvas_ref.get_untracked().unwrap();
+        let download_ref = download_ref.get_untracked().unwrap();
+
+        match canvas_ref.to_data_url_with_type("image/png") {
+            Ok(url) => {
+                // Safari only downloads from a link that is in the document, so a hidden one is clicked rather than a detached one
+                download_ref.set_href(&url);
+                download_ref.set_download(&frame_file_name(&state));
+                download_ref.click();
+            }
+            Err(e) => warn!("Failed to encode the frame: {e:?}"),
+        }
+    };
+
+    let assign_stickers = move |_| {
+        let canvas_ref = canvas_ref.get_untracked().unwrap();
+        let video_ref = video_ref.get_untracked().unwrap();
+
+        let live = freeze.get_untracked().is_live();
+        match capture_frame(&video_ref, &canvas_ref, live) {
+            Ok(frame) => {
+                if live {
+                    set_has_frame.set(true);
+                }
+                on_capture.run(frame);
+            }
+            Err(e) => warn!("Failed to capture a frame: {e:?}"),
+        }
+    };
+
     view! {
+      // Opening another camera means asking the browser again, so the stream is started over whenever the camera changes
+      {move || {
+        view! {
+          <CameraStream
+            camera=camera.get()
+            video_ref
+            enabled
+            on_unavailable=Callback::new(move |next| set_camera.set(next))
+          />
+        }
+      }}
+      {move || {
+        camera.get().notice().map(|notice| view! { <p class="self-center text-amber-300">{notice}</p> })
+      }}
       <div class="flex gap-4 justify-around">
         <video
           node_ref=video_ref
@@ -165,5 +350,23 @@ pub fn Video(
         />
         <canvas node_ref=canvas_ref class="flex-1 min-w-0 border-2 border-amber-300 max-w-[400px]" />
       </div>
+      <button
+        class="self-center px-2 border-2 border-gray-300 disabled:opacity-50"
+        disabled=move || recognized.get().is_none()
+        on:click=download_frame
+      >
+        "Download frame"
+      </button>
+      <button
+        class="self-center px-2 border-2 border-gray-300 disabled:opacity-50"
+        disabled=move || !has_frame.get()
+        on:click=move |_| set_freeze.update(|freeze| *freeze = freeze.toggle(has_frame.get_untracked()))
+      >
+        {move || freeze.get().button_label()}
+      </button>
+      <button class="self-center px-2 border-2 border-gray-300" on:click=assign_stickers>
+        "Assign stickers here"
+      </button>
+      <a node_ref=download_ref class="hidden" />
     }
 }
@@ -0,0 +1,195 @@
+//! Zooming the sticker assignment UI in on part of the image, so that stickers and the thin gaps between them can be selected precisely in an image with far more pixels than the screen. The image, and so the assignment, stays at its full resolution, which is the resolution of the frames that are recognized with the assignment; only how much of it the window shows changes, and a crop with too many pixels to show quickly is shown downscaled as an overview.
+
+/// How much each step of zooming in magnifies the image
+pub const ZOOM_STEP: f64 = 1.5;
+/// The most that the image can be magnified
+pub const MAX_ZOOM: f64 = 16.;
+
+/// The part of the image that the window shows. What is shown is a crop of the image, which the window scales to fit. The crop is shown at the image's own scale unless it has more pixels than can be shown at once, in which case it is downscaled to that many, so a point of what is shown is an offset from the corner of the crop in pixels of the downscaled crop.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Viewport {
+    width: i32,
+    height: i32,
+    max_shown_pixels: i32,
+    zoom: f64,
+    // The corner of the crop, in pixels of the image
+    x: i32,
+    y: i32,
+}
+
+impl Viewport {
+    /// Show the whole of an image of `width` by `height` pixels, downscaling it to show at most `max_shown_pixels` at once
+    pub fn new(width: i32, height: i32, max_shown_pixels: i32) -> Viewport {
+        Viewport {
+            width,
+            height,
+            max_shown_pixels,
+            zoom: 1.,
+            x: 0,
+            y: 0,
+        }
+    }
+
+    /// How many times the image is magnified, which is one when the whole image is shown
+    pub fn zoom(&self) -> f64 {
+        self.zoom
+    }
+
+    /// The width and height of the crop at the current zoom, which is never empty
+    #[allow(clippy::cast_possible_truncation)]
+    fn size(&self) -> (i32, i32) {
+        let shrink = |v: i32| ((f64::from(v) / self.zoom).round() as i32).clamp(1, v.max(1));
+        (shrink(self.width), shrink(self.height))
+    }
+
+    /// The crop of the image that is shown, as its corner and size `(x, y, width, height)` in pixels of the image. It always lies within the image.
+    pub fn visible(&self) -> (i32, i32, i32, i32) {
+        let (width, height) = self.size();
+        (self.x, self.y, width, height)
+    }
+
+    /// The width and height that the crop is shown at, which is its own size unless that has more than `max_shown_pixels` pixels
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn shown_size(&self) -> (i32, i32) {
+        let (width, height) = self.size();
+        let scale = (f64::from(self.max_shown_pixels) / (f64::from(width) * f64::from(height)))
+            .sqrt()
+            .min(1.);
+        let shrink = |v: i32| ((f64::from(v) * scale).round() as i32).clamp(1, v);
+        (shrink(width), shrink(height))
+    }
+
+    /// The offset from the corner of the crop, in pixels of the image, of `point` of what is shown
+    #[allow(clippy::cast_possible_truncation)]
+    fn crop_offset(&self, (x, y): (i32, i32)) -> (i32, i32) {
+        let (width, height) = self.size();
+        let (shown_width, shown_height) = self.shown_size();
+        let scale = |v: i32, size: i32, shown: i32| {
+            (i64::from(v) * i64::from(size) / i64::from(shown)) as i32
+        };
+        (scale(x, width, shown_width), scale(y, height, shown_height))
+    }
+
+    /// The pixel of the image that is at `point` of what is shown
+    pub fn to_image(&self, point: (i32, i32)) -> (i32, i32) {
+        let (x, y) = self.crop_offset(point);
+        (self.x + x, self.y + y)
+    }
+
+    /// The point of what is shown that the pixel of the image at `point` is at, which is outside of what is shown if the pixel isn't in the crop
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn to_shown(&self, (x, y): (i32, i32)) -> (i32, i32) {
+        let (width, height) = self.size();
+        let (shown_width, shown_height) = self.shown_size();
+        let scale = |v: i32, size: i32, shown: i32| {
+            (i64::from(v) * i64::from(shown) / i64::from(size)) as i32
+        };
+        (
+            scale(x - self.x, width, shown_width),
+            scale(y - self.y, height, shown_height),
+        )
+    }
+
+    /// Magnify the image by another `factor`, which zooms out if it is less than one, keeping the pixel at `point` of what is shown under it so that zooming in on a sticker doesn't lose it. The zoom stays between one and `MAX_ZOOM`, and the crop is moved back inside the image if it would stick out of it.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn zoom_by(&mut self, factor: f64, point: (i32, i32)) {
+        let (old_width, old_height) = self.size();
+        let offset = self.crop_offset(point);
+        let (image_x, image_y) = (self.x + offset.0, self.y + offset.1);
+
+        self.zoom = (self.zoom * factor).clamp(1., MAX_ZOOM);
+        let (width, height) = self.size();
+
+        // The pixel stays the same fraction of the way across the crop
+        let corner = |pixel: i32, offset: i32, old: i32, new: i32, limit: i32| {
+            let offset = (f64::from(offset) * f64::from(new) / f64::from(old)).round() as i32;
+            (pixel - offset).clamp(0, limit - new)
+        };
+        self.x = corner(image_x, offset.0, old_width, width, self.width);
+        self.y = corner(image_y, offset.1, old_height, height, self.height);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MAX_ZOOM, Viewport};
+
+    /// More pixels than any image in these tests has, so that they are never downscaled
+    const UNLIMITED: i32 = i32::MAX;
+
+    #[test]
+    fn whole_image() {
+        let viewport = Viewport::new(1000, 500, UNLIMITED);
+        assert_eq!(viewport.zoom(), 1.);
+        assert_eq!(viewport.visible(), (0, 0, 1000, 500));
+        assert_eq!(viewport.to_image((10, 20)), (10, 20));
+    }
+
+    #[test]
+    fn zoom_keeps_the_point() {
+        let mut viewport = Viewport::new(1000, 500, UNLIMITED);
+
+        // Zooming in on the middle keeps the middle in the middle
+        viewport.zoom_by(2., (500, 250));
+        assert_eq!(viewport.visible(), (250, 125, 500, 250));
+        assert_eq!(viewport.to_image((250, 125)), (500, 250));
+        assert_eq!(viewport.to_image((0, 0)), (250, 125));
+
+        // Zooming in on the corner of what is shown keeps that pixel in the corner
+        viewport.zoom_by(2., (0, 0));
+        assert_eq!(viewport.visible(), (250, 125, 250, 125));
+        assert_eq!(viewport.to_image((0, 0)), (250, 125));
+
+        // And zooming back out puts it back
+        viewport.zoom_by(0.5, (0, 0));
+        assert_eq!(viewport.visible(), (250, 125, 500, 250));
+    }
+
+    #[test]
+    fn stays_inside() {
+        let mut viewport = Viewport::new(1000, 500, UNLIMITED);
+
+        viewport.zoom_by(4., (999, 499));
+        assert_eq!(viewport.visible(), (749, 374, 250, 125));
+        assert_eq!(viewport.to_image((249, 124)), (998, 498));
+
+        // Keeping the corner of what is shown under the pointer would leave the crop hanging off the image
+        viewport.zoom_by(0.5, (0, 0));
+        assert_eq!(viewport.visible(), (500, 250, 500, 250));
+
+        viewport.zoom_by(1000., (0, 0));
+        assert_eq!(viewport.zoom(), MAX_ZOOM);
+        let (x, y, width, height) = viewport.visible();
+        assert_eq!((width, height), (63, 31));
+        assert!(x + width <= 1000 && y + height <= 500);
+
+        // Zooming out too far shows the whole image
+        viewport.zoom_by(0.001, (10, 10));
+        assert_eq!(viewport.zoom(), 1.);
+        assert_eq!(viewport.visible(), (0, 0, 1000, 500));
+    }
+
+    #[test]
+    fn overview() {
+        // Only a quarter of the image can be shown at once
+        let mut viewport = Viewport::new(4000, 2000, 2_000_000);
+        assert_eq!(viewport.visible(), (0, 0, 4000, 2000));
+        assert_eq!(viewport.shown_size(), (2000, 1000));
+        assert_eq!(viewport.to_image((0, 0)), (0, 0));
+        assert_eq!(viewport.to_image((1000, 500)), (2000, 1000));
+        assert_eq!(viewport.to_image((1999, 999)), (3998, 1998));
+        assert_eq!(viewport.to_shown((2000, 1000)), (1000, 500));
+
+        // Zoomed in far enough, the crop is shown at the image's own scale
+        viewport.zoom_by(2., (1000, 500));
+        assert_eq!(viewport.visible(), (1000, 500, 2000, 1000));
+        assert_eq!(viewport.shown_size(), (2000, 1000));
+        assert_eq!(viewport.to_image((1000, 500)), (2000, 1000));
+        assert_eq!(viewport.to_shown((2000, 1000)), (1000, 500));
+
+        viewport.zoom_by(2., (0, 0));
+        assert_eq!(viewport.visible(), (1000, 500, 1000, 500));
+        assert_eq!(viewport.shown_size(), (1000, 500));
+        assert_eq!(viewport.to_image((10, 20)), (1010, 520));
+    }
+}